@@ -0,0 +1,70 @@
+//! An api token store that keeps tokens in memory
+
+use super::{ApiToken, ApiTokenStore};
+use std::{
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+};
+use twilight_model::id::UserId;
+
+/// An api token store that keeps tokens in memory, losing them when the server restarts
+#[derive(Debug, Default)]
+pub struct MemoryApiTokenStore {
+    /// The tokens, keyed by their hash
+    tokens: RwLock<HashMap<String, ApiToken>>,
+}
+
+#[rocket::async_trait]
+impl ApiTokenStore for MemoryApiTokenStore {
+    async fn insert(&self, token: ApiToken) -> anyhow::Result<()> {
+        self.tokens
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(token.hash.clone(), token);
+
+        Ok(())
+    }
+
+    async fn get_by_hash(&self, hash: &str) -> anyhow::Result<Option<ApiToken>> {
+        Ok(self
+            .tokens
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(hash)
+            .cloned())
+    }
+
+    async fn touch(&self, id: &str, last_used_at: u64) -> anyhow::Result<()> {
+        if let Some(token) = self
+            .tokens
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values_mut()
+            .find(|token| token.id == id)
+        {
+            token.last_used_at = Some(last_used_at);
+        }
+
+        Ok(())
+    }
+
+    async fn user_tokens(&self, user_id: UserId) -> anyhow::Result<Vec<ApiToken>> {
+        Ok(self
+            .tokens
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|token| token.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn remove(&self, user_id: UserId, id: &str) -> anyhow::Result<bool> {
+        let mut tokens = self.tokens.write().unwrap_or_else(PoisonError::into_inner);
+        let count = tokens.len();
+
+        tokens.retain(|_, token| !(token.user_id == user_id && token.id == id));
+
+        Ok(tokens.len() != count)
+    }
+}
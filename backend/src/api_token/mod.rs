@@ -0,0 +1,161 @@
+//! Personal api tokens, letting scripts and bots call the graphql api without a browser
+//!
+//! Tokens are only ever shown once when they are created, the server only keeps a hash of them
+//! in an [`ApiTokenStore`].
+
+use crate::{
+    config::Config,
+    session::{random_key, unix_timestamp},
+};
+use anyhow::anyhow;
+use juniper::GraphQLEnum;
+use sha2::{Digest, Sha256};
+use std::{fmt::Debug, str::FromStr, sync::Arc};
+use twilight_model::id::{GuildId, UserId};
+
+pub mod memory;
+pub mod sqlite;
+
+pub use memory::MemoryApiTokenStore;
+pub use sqlite::SqliteApiTokenStore;
+
+/// The prefix of every api token, to make them easy to recognize
+const TOKEN_PREFIX: &str = "stfu_";
+
+/// The most api tokens that a user can have
+pub const MAX_USER_TOKENS: usize = 25;
+
+/// A shared handle to the api token store used by the server
+pub type ApiTokens = Arc<dyn ApiTokenStore>;
+
+/// An action that an api token can be allowed to perform.
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TokenAction {
+    /// Read information about guilds, channels and members.
+    Read,
+    /// Mute members in voice channels.
+    Mute,
+    /// Unmute members in voice channels.
+    Unmute,
+}
+
+impl TokenAction {
+    /// The name of the action when stored
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenAction::Read => "read",
+            TokenAction::Mute => "mute",
+            TokenAction::Unmute => "unmute",
+        }
+    }
+}
+
+impl FromStr for TokenAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(TokenAction::Read),
+            "mute" => Ok(TokenAction::Mute),
+            "unmute" => Ok(TokenAction::Unmute),
+            _ => Err(anyhow!("Unknown token action {}", s)),
+        }
+    }
+}
+
+/// A personal api token of a user
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    /// Public id of the token, used to list and revoke tokens.
+    pub id: String,
+    /// Hash of the token itself, the token is never stored.
+    pub hash: String,
+    /// The id of the user who the token acts on behalf of
+    pub user_id: UserId,
+    /// Name given to the token by the user
+    pub name: String,
+    /// The guilds that the token can be used in
+    pub guilds: Vec<GuildId>,
+    /// The actions that the token can perform
+    pub actions: Vec<TokenAction>,
+    /// The seconds since the unix epoch that this token was created
+    pub created_at: u64,
+    /// The seconds since the unix epoch that this token was last used, if ever
+    pub last_used_at: Option<u64>,
+}
+
+impl ApiToken {
+    /// Create a new api token for the user
+    ///
+    /// Returns the information about the token to store along with the token itself, which
+    /// needs to be handed to the user since it can not be recovered later.
+    #[must_use]
+    pub fn create(
+        user_id: UserId,
+        name: String,
+        guilds: Vec<GuildId>,
+        actions: Vec<TokenAction>,
+    ) -> (Self, String) {
+        let token = format!("{}{}", TOKEN_PREFIX, random_key());
+
+        (
+            ApiToken {
+                id: random_key(),
+                hash: hash_token(&token),
+                user_id,
+                name,
+                guilds,
+                actions,
+                created_at: unix_timestamp(),
+                last_used_at: None,
+            },
+            token,
+        )
+    }
+
+    /// If the token is allowed to perform the action in the guild
+    #[must_use]
+    pub fn allows(&self, guild_id: GuildId, action: TokenAction) -> bool {
+        self.guilds.contains(&guild_id) && self.actions.contains(&action)
+    }
+}
+
+/// Hash an api token for storage and lookup
+#[must_use]
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Storage for the api tokens of users
+#[rocket::async_trait]
+pub trait ApiTokenStore: Debug + Send + Sync {
+    /// Save a newly created token
+    async fn insert(&self, token: ApiToken) -> anyhow::Result<()>;
+
+    /// Get a token by the hash of the token
+    async fn get_by_hash(&self, hash: &str) -> anyhow::Result<Option<ApiToken>>;
+
+    /// Mark the token with the id as used at the given time
+    async fn touch(&self, id: &str, last_used_at: u64) -> anyhow::Result<()>;
+
+    /// Get all of the tokens of a user
+    async fn user_tokens(&self, user_id: UserId) -> anyhow::Result<Vec<ApiToken>>;
+
+    /// Remove a token of the user by its id, returning if a token was removed
+    async fn remove(&self, user_id: UserId, id: &str) -> anyhow::Result<bool>;
+}
+
+/// Create the api token store described by the config
+///
+/// Tokens are stored in the sqlite database at `api_token_database` if one is configured,
+/// otherwise they are kept in memory and lost on restart.
+///
+/// # Errors
+/// If the sqlite database could not be opened
+pub fn create_api_token_store(config: &Config) -> anyhow::Result<ApiTokens> {
+    Ok(match &config.api_token_database {
+        Some(path) => Arc::new(SqliteApiTokenStore::open(path)?),
+        None => Arc::new(MemoryApiTokenStore::default()),
+    })
+}
@@ -0,0 +1,165 @@
+//! An api token store that persists tokens in a sqlite database
+
+use super::{ApiToken, ApiTokenStore};
+use crate::database::{from_sql_integer, to_sql_integer, Database};
+use rusqlite::{params, OptionalExtension, Row};
+use std::path::Path;
+use twilight_model::id::{GuildId, UserId};
+
+/// The statements to run to setup the database
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS api_tokens (
+    hash TEXT PRIMARY KEY NOT NULL,
+    id TEXT NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    guilds TEXT NOT NULL,
+    actions TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER
+);
+CREATE INDEX IF NOT EXISTS api_tokens_user_id ON api_tokens (user_id);
+";
+
+/// The columns of the api tokens table, in the order that `token_from_row` expects them
+const COLUMNS: &str = "hash, id, user_id, name, guilds, actions, created_at, last_used_at";
+
+/// An api token store that persists tokens in a sqlite database, surviving restarts
+#[derive(Debug, Clone)]
+pub struct SqliteApiTokenStore {
+    /// The api token database
+    database: Database,
+}
+
+impl SqliteApiTokenStore {
+    /// Open, and create if needed, the api token database at the path
+    ///
+    /// # Errors
+    /// If the database could not be opened or setup
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            database: Database::open(path, SCHEMA)?,
+        })
+    }
+}
+
+/// Read a token from a row made up of `COLUMNS`
+///
+/// Guilds and actions are stored as comma separated lists.
+fn token_from_row(row: &Row<'_>) -> rusqlite::Result<ApiToken> {
+    let guilds: String = row.get(4)?;
+    let actions: String = row.get(5)?;
+
+    Ok(ApiToken {
+        hash: row.get(0)?,
+        id: row.get(1)?,
+        user_id: UserId(from_sql_integer(row.get(2)?)),
+        name: row.get(3)?,
+        guilds: guilds
+            .split(',')
+            .filter_map(|id| id.parse().ok().map(GuildId))
+            .collect(),
+        actions: actions
+            .split(',')
+            .filter_map(|action| action.parse().ok())
+            .collect(),
+        created_at: from_sql_integer(row.get(6)?),
+        last_used_at: row.get::<_, Option<i64>>(7)?.map(from_sql_integer),
+    })
+}
+
+#[rocket::async_trait]
+impl ApiTokenStore for SqliteApiTokenStore {
+    async fn insert(&self, token: ApiToken) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    &format!(
+                        "INSERT INTO api_tokens ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                        COLUMNS
+                    ),
+                    params![
+                        token.hash,
+                        token.id,
+                        to_sql_integer(token.user_id.0),
+                        token.name,
+                        token
+                            .guilds
+                            .iter()
+                            .map(|id| id.to_string())
+                            .collect::<Vec<_>>()
+                            .join(","),
+                        token
+                            .actions
+                            .iter()
+                            .map(|action| action.as_str())
+                            .collect::<Vec<_>>()
+                            .join(","),
+                        to_sql_integer(token.created_at),
+                        token.last_used_at.map(to_sql_integer),
+                    ],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_by_hash(&self, hash: &str) -> anyhow::Result<Option<ApiToken>> {
+        let hash = hash.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .query_row(
+                        &format!("SELECT {} FROM api_tokens WHERE hash = ?1", COLUMNS),
+                        params![hash],
+                        token_from_row,
+                    )
+                    .optional()
+            })
+            .await
+    }
+
+    async fn touch(&self, id: &str, last_used_at: u64) -> anyhow::Result<()> {
+        let id = id.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    "UPDATE api_tokens SET last_used_at = ?2 WHERE id = ?1",
+                    params![id, to_sql_integer(last_used_at)],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn user_tokens(&self, user_id: UserId) -> anyhow::Result<Vec<ApiToken>> {
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .prepare(&format!(
+                        "SELECT {} FROM api_tokens WHERE user_id = ?1",
+                        COLUMNS
+                    ))?
+                    .query_map(params![to_sql_integer(user_id.0)], token_from_row)?
+                    .collect()
+            })
+            .await
+    }
+
+    async fn remove(&self, user_id: UserId, id: &str) -> anyhow::Result<bool> {
+        let id = id.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                Ok(connection.execute(
+                    "DELETE FROM api_tokens WHERE user_id = ?1 AND id = ?2",
+                    params![to_sql_integer(user_id.0), id],
+                )? > 0)
+            })
+            .await
+    }
+}
@@ -1,77 +1,39 @@
 //! Structures and other tools used for authentication
 
-use crate::{config::Config, create_http_client};
+use crate::{
+    api_token::{hash_token, ApiToken, ApiTokens},
+    config::Config,
+    create_http_client,
+    session::{unix_timestamp, Session, Sessions},
+};
 use anyhow::anyhow;
-use log::warn;
+use log::{error, warn};
+use reqwest::Client as ReqwestClient;
 use rocket::{
-    http::{Cookie, Status},
+    http::{Cookie, CookieJar, Status},
     request::{FromRequest, Outcome},
 };
-use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
 use twilight_http::Client as HttpClient;
 use twilight_model::id::UserId;
-use twilight_oauth2::request::access_token_exchange::AccessTokenExchangeResponse;
-
-/// The cookie containing oauth authentication information for a user
-///
-/// The cookie can be derived from an `AccessTokenExchangeResponse`
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OauthCookie {
-    /// Access token to be used when making requests to the API on the user's
-    /// behalf.
-    pub access_token: String,
-    /// Number of seconds from issuing that the access token is valid.
-    ///
-    /// After this duration, the refresh token must be exchanged for another
-    /// access token and refresh token pair.
-    pub expires_in: u64,
-    /// Refresh token to use to exchange for another access token and refresh
-    /// token pair.
-    pub refresh_token: String,
-    /// The seconds since the unix epoch that this oauth token was created
-    pub created_at: u64,
-    /// The user's id
-    pub user_id: UserId,
-}
 
-impl OauthCookie {
-    /// Create an instance of the cookie from the response and make a request to get the user id
-    ///
-    /// # Errors
-    /// If the request to get the user's id fails
-    pub async fn create(
-        AccessTokenExchangeResponse {
-            access_token,
-            expires_in,
-            refresh_token,
-            ..
-        }: AccessTokenExchangeResponse,
-        config: &Config,
-    ) -> Result<Self, twilight_http::Error> {
-        Ok(OauthCookie {
-            user_id: create_http_client(format!("Bearer {}", access_token), config)
-                .current_user()
-                .await?
-                .id,
-            access_token,
-            expires_in,
-            refresh_token,
-            created_at: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_secs(),
-        })
-    }
-}
+/// The seconds between updates of the last used time of a session or api token
+const TOUCH_INTERVAL: u64 = 60;
 
 /// An authenticated oauth user
 #[derive(Debug)]
 pub struct OauthUser {
     /// The http client to request information from discord
     pub http: HttpClient,
-    /// The authentication information stored in the cookie for the user
-    pub cookie: OauthCookie,
+    /// The session of the user, found from their cookie
+    pub session: Session,
+}
+
+/// Remove the authentication cookie from the browser
+pub fn remove_auth_cookie(cookies: &CookieJar<'_>, config: &Config) {
+    let mut cookie = Cookie::named(config.auth_cookie_name.clone());
+    cookie.set_domain(config.auth_cookie_domain.clone());
+
+    cookies.remove(cookie);
 }
 
 #[rocket::async_trait]
@@ -79,43 +41,167 @@ impl<'a, 'r> FromRequest<'a, 'r> for OauthUser {
     type Error = anyhow::Error;
 
     async fn from_request(request: &'a rocket::Request<'r>) -> Outcome<Self, Self::Error> {
-        let config: &Config = match request.managed_state() {
-            Some(config) => config,
-            None => {
+        let (config, sessions, reqwest): (&Config, &Sessions, &ReqwestClient) = match (
+            request.managed_state(),
+            request.managed_state(),
+            request.managed_state(),
+        ) {
+            (Some(config), Some(sessions), Some(reqwest)) => (config, sessions, reqwest),
+            _ => {
                 return Outcome::Failure((
                     Status::InternalServerError,
-                    anyhow!("Config was not mounted on the rocket"),
+                    anyhow!("Config, session store or http client was not mounted on the rocket"),
                 ))
             }
         };
         let cookies = request.cookies();
 
-        let cookie = cookies.get_private(&config.auth_cookie_name);
+        let hash = match cookies.get(&config.auth_cookie_name) {
+            Some(cookie) => hash_token(cookie.value()),
+            None => return Outcome::Forward(()),
+        };
+
+        match sessions.get_by_hash(&hash).await {
+            Ok(Some(session)) if !session.is_expired() => {
+                // FIXME: Auto refresh if time is neigh
 
-        if let Some(cookie) = cookie {
-            match serde_json::from_str::<OauthCookie>(cookie.value()) {
-                Ok(cookie) => {
-                    // FIXME: Auto refresh if time is neigh
+                let now = unix_timestamp();
+                if now.saturating_sub(session.last_used_at) >= TOUCH_INTERVAL {
+                    if let Err(e) = sessions.touch(&hash, now).await {
+                        warn!("Failed to update the last use of a session. {}", e);
+                    }
+                }
 
-                    Outcome::Success(OauthUser {
-                        http: create_http_client(format!("Bearer {}", cookie.access_token), config),
-                        cookie,
-                    })
+                Outcome::Success(OauthUser {
+                    http: create_http_client(
+                        format!("Bearer {}", session.access_token),
+                        config,
+                        reqwest,
+                    ),
+                    session,
+                })
+            }
+            Ok(session) => {
+                // Remove cookie if the session is unknown or has expired
+                if session.is_some() {
+                    if let Err(e) = sessions.remove(&hash).await {
+                        warn!("Failed to remove an expired session. {}", e);
+                    }
                 }
-                Err(e) => {
-                    warn!("Received malformed cookie. Clearing it. {}", e);
 
-                    // Remove cookie if malformed
-                    let mut cookie = Cookie::named(config.auth_cookie_name.clone());
-                    cookie.set_domain(config.auth_cookie_domain.clone());
+                remove_auth_cookie(cookies, config);
+
+                Outcome::Forward(())
+            }
+            Err(e) => {
+                error!("Failed to lookup session. {}", e);
+
+                Outcome::Failure((Status::InternalServerError, e))
+            }
+        }
+    }
+}
+
+/// The user agent of the browser making the request, if it sent one
+#[derive(Debug)]
+pub struct UserAgent(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for UserAgent {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'a rocket::Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(UserAgent(
+            request
+                .headers()
+                .get_one("User-Agent")
+                .map(ToOwned::to_owned),
+        ))
+    }
+}
+
+/// A user authenticated with a personal api token
+#[derive(Debug)]
+pub struct ApiTokenUser {
+    /// The api token sent with the request
+    pub token: ApiToken,
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for ApiTokenUser {
+    type Error = anyhow::Error;
 
-                    cookies.remove_private(cookie);
+    async fn from_request(request: &'a rocket::Request<'r>) -> Outcome<Self, Self::Error> {
+        let api_tokens: &ApiTokens = match request.managed_state() {
+            Some(api_tokens) => api_tokens,
+            None => {
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    anyhow!("Api token store was not mounted on the rocket"),
+                ))
+            }
+        };
 
-                    Outcome::Forward(())
+        let token = match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        {
+            Some(token) => token.trim(),
+            None => return Outcome::Forward(()),
+        };
+
+        match api_tokens.get_by_hash(&hash_token(token)).await {
+            Ok(Some(token)) => {
+                let now = unix_timestamp();
+                if token.last_used_at.map_or(true, |last_used_at| {
+                    now.saturating_sub(last_used_at) >= TOUCH_INTERVAL
+                }) {
+                    if let Err(e) = api_tokens.touch(&token.id, now).await {
+                        warn!("Failed to update the last use of an api token. {}", e);
+                    }
                 }
+
+                Outcome::Success(ApiTokenUser { token })
             }
-        } else {
-            Outcome::Forward(())
+            Ok(None) => Outcome::Failure((Status::Unauthorized, anyhow!("Unknown api token"))),
+            Err(e) => {
+                error!("Failed to lookup api token. {}", e);
+
+                Outcome::Failure((Status::InternalServerError, e))
+            }
+        }
+    }
+}
+
+/// The user making a request, authenticated either through oauth or with an api token
+#[derive(Debug)]
+pub enum Viewer {
+    /// A user logged in through oauth in their browser
+    Oauth(OauthUser),
+    /// A script or bot using a personal api token of a user
+    ApiToken(ApiTokenUser),
+}
+
+impl Viewer {
+    /// The id of the user that the request is made on behalf of
+    #[must_use]
+    pub fn user_id(&self) -> UserId {
+        match self {
+            Viewer::Oauth(user) => user.session.user_id,
+            Viewer::ApiToken(user) => user.token.user_id,
         }
     }
 }
+
+impl From<OauthUser> for Viewer {
+    fn from(user: OauthUser) -> Self {
+        Viewer::Oauth(user)
+    }
+}
+
+impl From<ApiTokenUser> for Viewer {
+    fn from(user: ApiTokenUser) -> Self {
+        Viewer::ApiToken(user)
+    }
+}
@@ -0,0 +1,159 @@
+//! Helpers for building urls to images hosted on the discord CDN
+
+use anyhow::{bail, Context};
+use juniper::GraphQLEnum;
+
+/// The base url of the discord CDN
+pub const DISCORD_CDN: &str = "https://cdn.discordapp.com";
+
+/// The image sizes that the CDN is able to serve
+const IMAGE_SIZES: &[i32] = &[16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+/// A format that images can be requested in from the CDN.
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// A PNG image.
+    Png,
+    /// A JPEG image.
+    Jpeg,
+    /// A WebP image.
+    Webp,
+    /// A GIF image, only available for animated images.
+    Gif,
+}
+
+impl ImageFormat {
+    /// The file extension used by the CDN for this format
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Gif => "gif",
+        }
+    }
+}
+
+/// Build the url to an image on the CDN from its hash
+///
+/// Animated images are served as a gif unless another format is requested, and requests for a
+/// gif of a still image fall back to a png.
+///
+/// # Errors
+/// If the size is not one that the CDN can serve
+pub fn image_url(
+    path: &str,
+    hash: &str,
+    format: Option<ImageFormat>,
+    size: Option<i32>,
+) -> anyhow::Result<String> {
+    let animated = hash.starts_with("a_");
+
+    let format = match format {
+        Some(ImageFormat::Gif) if !animated => ImageFormat::Png,
+        Some(format) => format,
+        None if animated => ImageFormat::Gif,
+        None => ImageFormat::Png,
+    };
+
+    with_size(
+        format!("{}/{}/{}.{}", DISCORD_CDN, path, hash, format.extension()),
+        size,
+    )
+}
+
+/// Build the url to the default avatar of a user without an avatar, based on their discriminator
+///
+/// Default avatars are only available as pngs.
+///
+/// # Errors
+/// If the discriminator is not a number or the size is not one that the CDN can serve
+pub fn default_avatar_url(discriminator: &str, size: Option<i32>) -> anyhow::Result<String> {
+    let discriminator: u16 = discriminator
+        .parse()
+        .context("User discriminator was not a number")?;
+
+    with_size(
+        format!("{}/embed/avatars/{}.png", DISCORD_CDN, discriminator % 5),
+        size,
+    )
+}
+
+/// Append the size query parameter to the url if a size was requested
+fn with_size(url: String, size: Option<i32>) -> anyhow::Result<String> {
+    match size {
+        None => Ok(url),
+        Some(size) if IMAGE_SIZES.contains(&size) => Ok(format!("{}?size={}", url, size)),
+        Some(size) => bail!(
+            "Invalid image size {}, must be a power of two between 16 and 4096",
+            size
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn animated_images_default_to_gifs() {
+        assert_eq!(
+            image_url("avatars/1", "a_hash", None, None).unwrap(),
+            "https://cdn.discordapp.com/avatars/1/a_hash.gif"
+        );
+        assert_eq!(
+            image_url("avatars/1", "a_hash", Some(ImageFormat::Webp), None).unwrap(),
+            "https://cdn.discordapp.com/avatars/1/a_hash.webp"
+        );
+    }
+
+    #[test]
+    fn still_images_are_never_gifs() {
+        assert_eq!(
+            image_url("icons/1", "hash", None, None).unwrap(),
+            "https://cdn.discordapp.com/icons/1/hash.png"
+        );
+        assert_eq!(
+            image_url("icons/1", "hash", Some(ImageFormat::Gif), None).unwrap(),
+            "https://cdn.discordapp.com/icons/1/hash.png"
+        );
+        assert_eq!(
+            image_url("icons/1", "hash", Some(ImageFormat::Jpeg), None).unwrap(),
+            "https://cdn.discordapp.com/icons/1/hash.jpg"
+        );
+    }
+
+    #[test]
+    fn default_avatars_follow_the_discriminator() {
+        assert_eq!(
+            default_avatar_url("0001", None).unwrap(),
+            "https://cdn.discordapp.com/embed/avatars/1.png"
+        );
+        assert_eq!(
+            default_avatar_url("1337", None).unwrap(),
+            "https://cdn.discordapp.com/embed/avatars/2.png"
+        );
+        assert_eq!(
+            default_avatar_url("9995", None).unwrap(),
+            "https://cdn.discordapp.com/embed/avatars/0.png"
+        );
+        assert!(default_avatar_url("abcd", None).is_err());
+    }
+
+    #[test]
+    fn only_sizes_the_cdn_serves_are_allowed() {
+        assert_eq!(
+            image_url("icons/1", "hash", None, Some(16)).unwrap(),
+            "https://cdn.discordapp.com/icons/1/hash.png?size=16"
+        );
+        assert_eq!(
+            default_avatar_url("0001", Some(4096)).unwrap(),
+            "https://cdn.discordapp.com/embed/avatars/1.png?size=4096"
+        );
+
+        for size in &[0, 8, 100, 8192, -64] {
+            assert!(image_url("icons/1", "hash", None, Some(*size)).is_err());
+        }
+    }
+}
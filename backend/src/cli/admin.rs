@@ -0,0 +1,283 @@
+//! Administration commands that act on discord from the terminal
+
+use crate::{
+    api_token::create_api_token_store,
+    config::{self, Config},
+    consts::{GATEWAY_INTENTS, OAUTH_REDIRECT_URLS},
+    create_http_client, create_reqwest_client, create_webhook_client,
+    data_source::{LiveDataSource, RedisCache, SharedDataSource},
+    floor::create_floor_store,
+    graphql::mute_channel,
+    invite,
+    notification::{create_notification_store, MuteNotifier},
+    persisted_queries::PersistedQueries,
+    schedule::create_schedule_store,
+    session::create_session_store,
+    webhook::{create_webhook_store, WebhookSender},
+};
+use anyhow::{anyhow, bail, Context};
+use async_std::{future::timeout, stream::StreamExt};
+use std::{fmt::Display, sync::Arc, time::Duration};
+use twilight_cache_inmemory::InMemoryCache;
+use twilight_gateway::shard::ShardBuilder;
+use twilight_model::{
+    channel::{Channel, GuildChannel},
+    id::ChannelId,
+};
+use twilight_oauth2::Client as OauthClient;
+
+/// How long to wait for the gateway to send the guild of a channel
+const GUILD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Print the outcome of a check, returning if it passed
+fn report<T, E: Display>(name: &str, result: Result<T, E>) -> bool {
+    match result {
+        Ok(_) => {
+            println!("ok      {}", name);
+            true
+        }
+        Err(error) => {
+            println!("FAILED  {}: {}", name, error);
+            false
+        }
+    }
+}
+
+/// Check that the environment holds a valid config, that the bot token works and that the
+/// redirect url, secret key and redis cache are usable, printing the outcome of every check
+///
+/// # Errors
+/// If any of the checks failed
+pub async fn check_config() -> anyhow::Result<()> {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            report("environment", Err(format!("{:#}", error)));
+            bail!("The config is invalid");
+        }
+    };
+    report("environment", Ok::<_, String>(()));
+
+    let reqwest = create_reqwest_client(&config);
+    let http_config = report(
+        "http config",
+        reqwest.as_ref().map_err(|error| format!("{:#}", error)),
+    );
+
+    let checks = [
+        http_config,
+        // The http client can not be created to check the token with an invalid http config
+        match &reqwest {
+            Ok(reqwest) => report(
+                "discord token",
+                create_http_client(&config.token, &config, reqwest)
+                    .current_user()
+                    .await,
+            ),
+            Err(_) => false,
+        },
+        report(
+            "client secret",
+            if config.client_secret.is_empty() {
+                Err("CLIENT_SECRET is empty")
+            } else {
+                Ok(())
+            },
+        ),
+        report(
+            "redirect url",
+            if OAUTH_REDIRECT_URLS.contains(&config.redirect_url.as_str()) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} is not one of the allowed redirect urls: {}",
+                    config.redirect_url,
+                    OAUTH_REDIRECT_URLS.join(", ")
+                ))
+            },
+        ),
+        report(
+            "secret key",
+            if config::has_secret_key() {
+                config::rocket_figment()
+                    .extract::<rocket::Config>()
+                    .map_err(|error| format!("The rocket config is invalid: {}", error))
+            } else {
+                Err("ROCKET_SECRET_KEY is not set".to_owned())
+            },
+        ),
+        report(
+            "session store",
+            create_session_store(&config).map_err(|error| format!("{:#}", error)),
+        ),
+        report(
+            "api token store",
+            create_api_token_store(&config).map_err(|error| format!("{:#}", error)),
+        ),
+        report(
+            "webhook store",
+            create_webhook_store(&config).map_err(|error| format!("{:#}", error)),
+        ),
+        report(
+            "notification store",
+            create_notification_store(&config).map_err(|error| format!("{:#}", error)),
+        ),
+        report(
+            "floor store",
+            create_floor_store(&config).map_err(|error| format!("{:#}", error)),
+        ),
+        report(
+            "schedule store",
+            create_schedule_store(&config).map_err(|error| format!("{:#}", error)),
+        ),
+        report(
+            "persisted queries",
+            PersistedQueries::load(&config, None).map_err(|error| format!("{:#}", error)),
+        ),
+        report("redis cache", check_redis(&config).await),
+    ];
+
+    if checks.iter().all(|passed| *passed) {
+        Ok(())
+    } else {
+        bail!("The config is invalid")
+    }
+}
+
+/// Check that the redis server of the shared cache can be reached, if one is configured
+async fn check_redis(config: &Config) -> Result<(), String> {
+    match RedisCache::from_config(config) {
+        Ok(Some(redis)) => redis
+            .connect()
+            .await
+            .map(|_| ())
+            .map_err(|error| format!("{:#}", error)),
+        Ok(None) => Ok(()),
+        Err(error) => Err(format!("{:#}", error)),
+    }
+}
+
+/// Server mute or unmute everyone in a voice channel in the name of the bot, printing how many
+/// users were changed
+///
+/// The bot connects to the gateway until the guild of the channel is received, so that the
+/// voice states in the channel are known. As with a mute made through the api, the channel is
+/// taken out of speaker mode, and the webhooks and mute messages of the guild are sent before
+/// returning.
+///
+/// # Errors
+/// If the configured stores could not be opened, the channel is not a voice channel the bot can
+/// see, or the guild was not received in time
+pub async fn set_channel_mute(config: &Config, channel_id: u64, mute: bool) -> anyhow::Result<()> {
+    let channel_id = ChannelId(channel_id);
+    let http = create_http_client(&config.token, config, &create_reqwest_client(config)?);
+
+    let webhooks = WebhookSender::new(
+        create_webhook_store(config)?,
+        create_webhook_client(config)?,
+        config.webhook_internal_addresses,
+    );
+    let notifications = MuteNotifier::new(
+        create_notification_store(config)?,
+        RedisCache::from_config(config)?,
+    );
+    let floors = create_floor_store(config)?;
+    let bot_id = http.current_user().await?.id;
+
+    let guild_id = match http.channel(channel_id).await? {
+        Some(Channel::Guild(GuildChannel::Voice(channel))) => channel
+            .guild_id
+            .ok_or_else(|| anyhow!("Channel {} is not in a guild", channel_id))?,
+        Some(_) => bail!("Channel {} is not a voice channel", channel_id),
+        None => bail!("Channel {} does not exist", channel_id),
+    };
+
+    let mut shard = ShardBuilder::new(&config.token, GATEWAY_INTENTS)
+        .http_client(http.clone())
+        .build();
+    shard.start().await?;
+
+    let cache = InMemoryCache::new();
+    let mut events = shard.events();
+
+    let received = timeout(GUILD_TIMEOUT, async {
+        while let Some(event) = events.next().await {
+            cache.update(&event);
+
+            if cache.guild(guild_id).is_some() {
+                return true;
+            }
+        }
+
+        false
+    })
+    .await;
+
+    let result = match received {
+        Ok(true) => mute_channel(
+            &(Arc::new(LiveDataSource::new(cache, http)) as SharedDataSource),
+            &webhooks,
+            &notifications,
+            &floors,
+            guild_id,
+            channel_id,
+            bot_id,
+            mute,
+        )
+        .await
+        .map_err(|error| anyhow!("{}", error.message())),
+        Ok(false) => Err(anyhow!(
+            "The gateway closed before guild {} was received",
+            guild_id
+        )),
+        Err(_) => Err(anyhow!("Timed out waiting for guild {}", guild_id)),
+    };
+
+    shard.shutdown();
+
+    let changed = result?;
+    webhooks.wait_idle().await;
+    notifications.wait_idle().await;
+
+    println!(
+        "{} {} users in channel {}",
+        if mute { "Muted" } else { "Unmuted" },
+        changed.len(),
+        channel_id
+    );
+
+    Ok(())
+}
+
+/// Print the id and name of every guild that the bot is in
+///
+/// # Errors
+/// If the guilds could not be fetched from discord
+pub async fn guilds(config: &Config) -> anyhow::Result<()> {
+    let guilds = create_http_client(&config.token, config, &create_reqwest_client(config)?)
+        .current_user_guilds()
+        .await
+        .context("Failed to fetch the guilds of the bot")?;
+
+    for guild in guilds {
+        println!("{}\t{}", guild.id, guild.name);
+    }
+
+    Ok(())
+}
+
+/// Print a link to invite the bot to a guild
+///
+/// # Errors
+/// If the application of the bot could not be fetched from discord
+pub async fn invite_url(config: &Config) -> anyhow::Result<()> {
+    let application = create_http_client(&config.token, config, &create_reqwest_client(config)?)
+        .current_user_application()
+        .await
+        .context("Failed to fetch the application of the bot")?;
+    let oauth = OauthClient::new(application.id, &config.client_secret, OAUTH_REDIRECT_URLS)?;
+
+    println!("{}", invite::invite_url(&oauth, None));
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! The command line interface of the backend
+
+use anyhow::bail;
+use std::{path::PathBuf, str::FromStr};
+use structopt::StructOpt;
+
+pub mod admin;
+#[cfg(debug_assertions)]
+pub mod query;
+pub mod schema;
+
+/// An elaborate, over-engineered solution to shutting my friends up
+#[derive(StructOpt, Debug)]
+#[structopt(name = "stfu-backend")]
+pub struct Cli {
+    /// The command to run, the server is run if none is given
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// A command of the backend
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Run the server along with its own connection to the gateway
+    Serve,
+    /// Connect to the gateway, filling the redis cache at `REDIS_URL` for api servers to read
+    /// and making the member updates that they send
+    Gateway,
+    /// Run the server without connecting to the gateway, talking to a gateway worker through
+    /// the redis server at `REDIS_URL` instead
+    Api,
+    /// Print the graphql schema, without connecting to discord
+    Schema {
+        /// Format to print the schema in, `sdl` or `json` introspection
+        #[structopt(long, default_value = "sdl")]
+        format: SchemaFormat,
+        /// File to write the schema to instead of stdout
+        #[structopt(long, short)]
+        out: Option<PathBuf>,
+    },
+    /// Check that the configuration is valid and that the bot can log in to discord
+    CheckConfig,
+    /// Server mute everyone in a voice channel
+    Mute {
+        /// Id of the voice channel
+        channel_id: u64,
+    },
+    /// Remove the server mute of everyone in a voice channel
+    Unmute {
+        /// Id of the voice channel
+        channel_id: u64,
+    },
+    /// List the guilds that the bot is in
+    Guilds,
+    /// Print a link to invite the bot to a guild with the permissions it needs
+    InviteUrl,
+    /// Run a graphql query against a fixture of discord data, without connecting to discord
+    ///
+    /// Only built in debug mode, as the user it runs as can do anything in the fixture guilds.
+    #[cfg(debug_assertions)]
+    Query {
+        /// Json file with the bot user and the guilds to serve, guilds are in the shape of
+        /// `GUILD_CREATE` events
+        #[structopt(long)]
+        fixture: String,
+        /// Id of the user to run the query as, who can do anything in the fixture guilds
+        #[structopt(long)]
+        user: u64,
+        /// Json object with the variables of the query
+        #[structopt(long)]
+        variables: Option<String>,
+        /// The graphql query to run
+        query: String,
+    },
+}
+
+/// A format that the schema can be printed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaFormat {
+    /// The graphql schema definition language
+    Sdl,
+    /// The json result of an introspection query
+    Json,
+}
+
+impl FromStr for SchemaFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sdl" => Ok(SchemaFormat::Sdl),
+            "json" => Ok(SchemaFormat::Json),
+            _ => bail!("Unknown schema format {}, expected sdl or json", s),
+        }
+    }
+}
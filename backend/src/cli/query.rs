@@ -0,0 +1,107 @@
+//! Running graphql queries against a fixture of discord data
+
+use crate::{
+    api_token::{ApiToken, MemoryApiTokenStore, TokenAction},
+    auth::{ApiTokenUser, Viewer},
+    data_source::{FixtureDataSource, SharedDataSource},
+    floor::MemoryFloorStore,
+    graphql::{create_schema, DiscordContext, GraphQLContext},
+    guild_cache::UserGuildCache,
+    notification::{MemoryNotificationStore, MuteNotifier},
+    rate_limit::{Rate, RateLimiter},
+    schedule::{MemoryScheduleStore, Scheduler},
+    session::MemorySessionStore,
+    webhook::{MemoryWebhookStore, WebhookClient, WebhookSender},
+};
+use anyhow::{bail, Context};
+use juniper::{http::GraphQLRequest, InputValue};
+use std::{sync::Arc, time::Duration};
+use twilight_model::id::{ApplicationId, GuildId, UserId};
+use twilight_oauth2::Client as OauthClient;
+
+/// Run a graphql query against the fixture as the user, printing the response and any member
+/// updates that it made
+///
+/// # Errors
+/// If the fixture could not be loaded, the variables are not json or the query failed
+pub async fn query(
+    fixture: &str,
+    user_id: u64,
+    variables: Option<&str>,
+    query: &str,
+) -> anyhow::Result<()> {
+    let data = Arc::new(FixtureDataSource::load(fixture)?);
+    let context = offline_context(data.clone(), UserId(user_id), data.guild_ids().to_vec());
+
+    let variables = variables
+        .map(serde_json::from_str::<InputValue>)
+        .transpose()
+        .context("The variables are not valid json")?;
+
+    let response = GraphQLRequest::new(query.to_owned(), None, variables)
+        .execute(&create_schema(), &context)
+        .await;
+
+    println!("{}", serde_json::to_string_pretty(&response)?);
+
+    for update in data.updates() {
+        eprintln!(
+            "{} user {} in guild {}",
+            if update.mute { "Muted" } else { "Unmuted" },
+            update.user_id,
+            update.guild_id
+        );
+    }
+
+    if response.is_ok() {
+        Ok(())
+    } else {
+        bail!("The query failed")
+    }
+}
+
+/// A context that never connects to discord, reading from the data source as the user
+///
+/// The user acts through an api token that can do anything in the given guilds, so that none of
+/// the resolvers need an oauth session.
+pub(crate) fn offline_context(
+    data: SharedDataSource,
+    user_id: UserId,
+    guilds: Vec<GuildId>,
+) -> GraphQLContext {
+    GraphQLContext::new(
+        DiscordContext {
+            data,
+            oauth: Arc::new(
+                OauthClient::new(ApplicationId(1), "", &[])
+                    .expect("An oauth client with no redirect urls is always valid"),
+            ),
+            user_guilds: Arc::new(UserGuildCache::new(Duration::default())),
+            rate_limiter: Arc::new(RateLimiter::new(Rate::UNLIMITED, Rate::UNLIMITED)),
+        },
+        Arc::new(MemorySessionStore::default()),
+        Arc::new(MemoryApiTokenStore::default()),
+        // Webhooks registered offline are never stored, so nothing is ever sent to them
+        WebhookSender::new(
+            Arc::new(MemoryWebhookStore::default()),
+            WebhookClient::Proxied(reqwest::Client::new()),
+            false,
+        ),
+        MuteNotifier::new(Arc::new(MemoryNotificationStore::default()), None),
+        Arc::new(MemoryFloorStore::default()),
+        // The scheduler is never started offline, so schedules created here never run
+        Scheduler::new(Arc::new(MemoryScheduleStore::default())),
+        Viewer::ApiToken(ApiTokenUser {
+            token: ApiToken {
+                id: String::new(),
+                hash: String::new(),
+                user_id,
+                name: String::from("offline"),
+                guilds,
+                actions: vec![TokenAction::Read, TokenAction::Mute, TokenAction::Unmute],
+                created_at: 0,
+                last_used_at: None,
+            },
+        }),
+    )
+}
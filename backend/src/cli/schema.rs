@@ -0,0 +1,86 @@
+//! Printing the graphql schema
+
+use crate::{
+    cli::{query::offline_context, SchemaFormat},
+    data_source::FixtureDataSource,
+    graphql::{create_schema, GraphQLContext, Schema},
+};
+use anyhow::{anyhow, Context};
+use graphql_parser::schema::{Definition, TypeDefinition};
+use juniper::IntrospectionFormat;
+use std::{fs, path::Path, sync::Arc};
+use twilight_model::id::UserId;
+
+/// Print the graphql schema in the format, to the file if one is given
+///
+/// # Errors
+/// If introspecting the schema failed or the file could not be written
+pub fn schema(format: SchemaFormat, out: Option<&Path>) -> anyhow::Result<()> {
+    let schema = create_schema();
+
+    let printed = match format {
+        SchemaFormat::Sdl => schema_sdl(&schema),
+        SchemaFormat::Json => schema_json(&schema)?,
+    };
+
+    match out {
+        Some(path) => fs::write(path, printed)
+            .with_context(|| format!("Failed to write the schema to {}", path.display()))?,
+        None => print!("{}", printed),
+    }
+
+    Ok(())
+}
+
+/// Print the schema in the schema definition language, with the types sorted by name so the
+/// output is stable
+#[must_use]
+pub fn schema_sdl(schema: &Schema) -> String {
+    /// The name of a definition, the schema definition sorts first
+    fn name<'a>(definition: &Definition<'a, &'a str>) -> &'a str {
+        match definition {
+            Definition::SchemaDefinition(_) | Definition::TypeExtension(_) => "",
+            Definition::DirectiveDefinition(directive) => directive.name,
+            Definition::TypeDefinition(definition) => match definition {
+                TypeDefinition::Scalar(scalar) => scalar.name,
+                TypeDefinition::Object(object) => object.name,
+                TypeDefinition::Interface(interface) => interface.name,
+                TypeDefinition::Union(union) => union.name,
+                TypeDefinition::Enum(enumeration) => enumeration.name,
+                TypeDefinition::InputObject(input) => input.name,
+            },
+        }
+    }
+
+    let mut document = schema.as_parser_document();
+    document.definitions.sort_by_key(name);
+
+    document.to_string()
+}
+
+/// Print the result of an introspection query on the schema as json
+///
+/// # Errors
+/// If the introspection query failed
+pub fn schema_json(schema: &Schema) -> anyhow::Result<String> {
+    let (introspection, _errors) = juniper::introspect(
+        schema,
+        &introspection_context(),
+        IntrospectionFormat::default(),
+    )
+    .map_err(|e| anyhow!("Failed to introspect the schema: {:?}", e))?;
+
+    Ok(serde_json::to_string_pretty(&introspection)?)
+}
+
+/// A context for running introspection queries
+///
+/// Introspection never looks at the context, so it reads from an empty fixture and needs no
+/// configuration.
+fn introspection_context() -> GraphQLContext {
+    offline_context(
+        Arc::new(FixtureDataSource::default()),
+        UserId(0),
+        Vec::new(),
+    )
+}
@@ -1,6 +1,91 @@
 //! The configuration for the app
+//!
+//! The app and rocket share one layered config. Each layer overrides the ones before it:
+//!
+//! 1. Defaults
+//! 2. A TOML file, `Stfu.toml` or the file in `STFU_CONFIG`, with a `[default]` section and a
+//!    section for each profile, such as `[dev]` and `[prod]`
+//! 3. Environment variables named after the keys of the config, `ROCKET_` prefixed ones for
+//!    rocket
+//! 4. Secrets read from the files named by the `DISCORD_TOKEN_FILE`, `CLIENT_SECRET_FILE` and
+//!    `ROCKET_SECRET_KEY_FILE` environment variables, for docker secrets
+//!
+//! The profile is picked with `STFU_PROFILE`, and is `dev` in debug builds and `prod` in
+//! release builds otherwise.
 
+use anyhow::{anyhow, bail, Context};
+use reqwest::Url;
+use rocket::figment::{
+    self,
+    providers::{Env, Format, Toml},
+    value::{Dict, Map, Value},
+    Figment, Metadata, Profile, Provider,
+};
 use serde::Deserialize;
+use std::{env, fs};
+
+/// The environment variable holding the path of the config file
+const CONFIG_FILE_VAR: &str = "STFU_CONFIG";
+
+/// The config file read when no other file is given
+const DEFAULT_CONFIG_FILE: &str = "Stfu.toml";
+
+/// The host of the discord api, which the stand-in api replaces
+const DISCORD_HOST: &str = "discord.com";
+
+/// The environment variable holding the profile to use
+const PROFILE_VAR: &str = "STFU_PROFILE";
+
+/// The profile used when no other profile is given
+const DEFAULT_PROFILE: &str = if cfg!(debug_assertions) {
+    "dev"
+} else {
+    "prod"
+};
+
+/// The environment variables that name a file to read a secret from, and the key that the
+/// secret sets
+const SECRET_FILES: &[(&str, &str)] = &[
+    ("DISCORD_TOKEN_FILE", "discord_token"),
+    ("CLIENT_SECRET_FILE", "client_secret"),
+    ("ROCKET_SECRET_KEY_FILE", "secret_key"),
+];
+
+/// The keys of the config, which are the only environment variables read into it
+const CONFIG_KEYS: &[&str] = &[
+    "discord_token",
+    "client_secret",
+    "redirect_url",
+    "auth_cookie_name",
+    "auth_cookie_domain",
+    "session_database",
+    "api_token_database",
+    "webhook_database",
+    "webhook_internal_addresses",
+    "notification_database",
+    "floor_database",
+    "schedule_database",
+    "user_guild_cache_ttl",
+    "persisted_queries",
+    "strict_persisted_queries",
+    "max_query_depth",
+    "max_query_complexity",
+    "max_batch_size",
+    "mutation_user_burst",
+    "mutation_user_per_minute",
+    "mutation_guild_burst",
+    "mutation_guild_per_minute",
+    "graphiql",
+    "egress_proxy",
+    "extra_ca_certs",
+    "http_timeout",
+    "http_connect_timeout",
+    "discord_api_url",
+    "redis_url",
+    "redis_key_prefix",
+    "proxy_url",
+    "proxy_cert_path",
+];
 
 /// The configuration for the application
 #[derive(Deserialize, Debug, Clone)]
@@ -16,10 +101,321 @@ pub struct Config {
     pub auth_cookie_name: String,
     /// Domain to set the auth cookie for
     pub auth_cookie_domain: String,
-    /// Proxy url to use
+    /// Path to the sqlite database to store sessions in, sessions are kept in memory if unset.
+    /// Like the other databases, it has to be set for api servers.
+    pub session_database: Option<String>,
+    /// Path to the sqlite database to store api tokens in, tokens are kept in memory if unset
+    pub api_token_database: Option<String>,
+    /// Path to the sqlite database to store webhooks and their deliveries in, webhooks are kept
+    /// in memory if unset
+    pub webhook_database: Option<String>,
+    /// Allow webhooks to be sent to loopback, private and link-local addresses, which are
+    /// refused by default so that webhooks can not reach services inside the network of the
+    /// server. Only meant for development and tests.
+    #[serde(default)]
+    pub webhook_internal_addresses: bool,
+    /// Path to the sqlite database to store the mute notification settings of guilds in,
+    /// settings are kept in memory if unset
+    pub notification_database: Option<String>,
+    /// Path to the sqlite database to store the speakers and speaking queues of voice channels
+    /// in, they are kept in memory if unset. It has to be set for the gateway worker and the api
+    /// servers, which share it to keep members who join a channel in speaker mode muted.
+    pub floor_database: Option<String>,
+    /// Path to the sqlite database to store scheduled mutes and the timezones of guilds in, they
+    /// are kept in memory if unset. Api servers sharing a redis cache share it as well, so that
+    /// they see the same schedules and only one of them makes each run.
+    pub schedule_database: Option<String>,
+    /// Seconds that a user's guild list is cached for before it is fetched again
+    #[serde(default = "default_user_guild_cache_ttl")]
+    pub user_guild_cache_ttl: u64,
+    /// Path to the registry of persisted queries generated from the frontend
+    pub persisted_queries: Option<String>,
+    /// Reject any query that is not in the persisted query registry
+    #[serde(default)]
+    pub strict_persisted_queries: bool,
+    /// The deepest that fields can be nested in a query
+    #[serde(default = "default_max_query_depth")]
+    pub max_query_depth: usize,
+    /// The highest estimated cost that a query, or all the queries of a batch, can have
+    #[serde(default = "default_max_query_complexity")]
+    pub max_query_complexity: usize,
+    /// The most queries that can be sent in one batch
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    /// The most mutations that a user can make in a burst, 0 disables the limit
+    #[serde(default = "default_mutation_user_burst")]
+    pub mutation_user_burst: u32,
+    /// The mutations that a user can make every minute after using up their burst
+    #[serde(default = "default_mutation_user_per_minute")]
+    pub mutation_user_per_minute: u32,
+    /// The most mutations that can be made in a guild in a burst, 0 disables the limit
+    #[serde(default = "default_mutation_guild_burst")]
+    pub mutation_guild_burst: u32,
+    /// The mutations that can be made in a guild every minute after using up its burst
+    #[serde(default = "default_mutation_guild_per_minute")]
+    pub mutation_guild_per_minute: u32,
+    /// Serve the graphiql IDE
+    #[serde(default = "default_graphiql")]
+    pub graphiql: bool,
+    /// Proxy that all requests to discord are sent through
+    ///
+    /// Webhooks are sent through it too, which resolves their hosts itself, so it should also
+    /// refuse to connect to internal addresses.
+    pub egress_proxy: Option<String>,
+    /// Paths to extra PEM certificates to trust, such as the certificate of the egress proxy
+    #[serde(default)]
+    pub extra_ca_certs: Vec<String>,
+    /// Seconds that a request to discord can take before it is given up on
+    #[serde(default = "default_http_timeout")]
+    pub http_timeout: u64,
+    /// Seconds that connecting to discord can take before it is given up on
+    #[serde(default = "default_http_connect_timeout")]
+    pub http_connect_timeout: u64,
+    /// Url of a stand-in for the discord api, such as the mock server of the integration tests.
+    /// Every request to discord is sent to it over plain http instead, so it can not be combined
+    /// with an egress proxy.
+    pub discord_api_url: Option<String>,
+    /// Url of the redis server that the gateway worker and the api servers talk through. The
+    /// worker fills the discord cache and makes member updates for the api servers.
+    pub redis_url: Option<String>,
+    /// Prefix of the redis keys, so multiple deployments can share a redis server
+    #[serde(default = "default_redis_key_prefix")]
+    pub redis_key_prefix: String,
+    /// Url of the mitm proxy to debug requests with, replacing the egress proxy
     #[cfg(feature = "mitm_proxy")]
     pub proxy_url: String,
-    /// Proxy certificate path to use
+    /// Path to the certificate of the mitm proxy
     #[cfg(feature = "mitm_proxy")]
     pub proxy_cert_path: String,
 }
+
+impl Config {
+    /// The layered config that the app config is read from, with the environment variables
+    /// named after its keys included
+    #[must_use]
+    pub fn figment() -> Figment {
+        rocket_figment()
+            .merge(Env::raw().only(CONFIG_KEYS).global())
+            .merge(SecretFiles)
+    }
+
+    /// Read the config from the config file, environment and secret files
+    ///
+    /// # Errors
+    /// If a required key is missing or a key could not be parsed, naming the key
+    pub fn load() -> anyhow::Result<Self> {
+        let config: Self = Self::figment()
+            .extract()
+            .map_err(|error| anyhow!("Invalid configuration: {}", error))?;
+
+        config.discord_api()?;
+
+        Ok(config)
+    }
+
+    /// The stand-in for the discord api, if one is configured
+    ///
+    /// # Errors
+    /// If the url is malformed, or an egress proxy is configured as well. The stand-in api is
+    /// reached as an http proxy, so requests to it can not go through the egress proxy too.
+    pub fn discord_api(&self) -> anyhow::Result<Option<Url>> {
+        let url = match &self.discord_api_url {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+
+        if self.egress_proxy.is_some() {
+            bail!("DISCORD_API_URL and EGRESS_PROXY can not both be set");
+        }
+
+        let url = Url::parse(url).context("DISCORD_API_URL is malformed")?;
+        if url.host_str().is_none() {
+            bail!("DISCORD_API_URL has to have a host");
+        }
+
+        Ok(Some(url))
+    }
+
+    /// Make sure that nothing is kept in memory that has to be shared by the api servers reading
+    /// the same redis cache, as each of them would only see its own copy
+    ///
+    /// The databases have to be on storage that every api server and the gateway worker can
+    /// reach.
+    ///
+    /// # Errors
+    /// If any of the databases is not set, naming them
+    pub fn require_shared_databases(&self) -> anyhow::Result<()> {
+        let missing: Vec<&str> = [
+            ("SESSION_DATABASE", &self.session_database),
+            ("API_TOKEN_DATABASE", &self.api_token_database),
+            ("WEBHOOK_DATABASE", &self.webhook_database),
+            ("NOTIFICATION_DATABASE", &self.notification_database),
+            ("FLOOR_DATABASE", &self.floor_database),
+            ("SCHEDULE_DATABASE", &self.schedule_database),
+        ]
+        .iter()
+        .filter(|(_, path)| path.is_none())
+        .map(|(name, _)| *name)
+        .collect();
+
+        if !missing.is_empty() {
+            bail!(
+                "{} have to be set for api servers, which share their stores with each other",
+                missing.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Point a url of the discord api at the stand-in api, if one is configured
+    ///
+    /// Urls of other hosts are left alone.
+    #[must_use]
+    pub fn discord_url(&self, url: String) -> String {
+        let (mut parsed, base) = match (Url::parse(&url), self.discord_api()) {
+            (Ok(parsed), Ok(Some(base))) if parsed.host_str() == Some(DISCORD_HOST) => {
+                (parsed, base)
+            }
+            _ => return url,
+        };
+
+        // Both schemes are special, so switching between them always succeeds
+        if parsed.set_scheme(base.scheme()).is_err()
+            || parsed.set_host(base.host_str()).is_err()
+            || parsed.set_port(base.port()).is_err()
+        {
+            return url;
+        }
+
+        parsed.into_string()
+    }
+}
+
+/// The layered config that rocket is configured from
+///
+/// Only `ROCKET_` prefixed environment variables are included, so that unrelated variables such
+/// as `PORT` do not change rocket's config.
+#[must_use]
+pub fn rocket_figment() -> Figment {
+    Figment::from(rocket::Config::default())
+        .merge(sources())
+        .select(Profile::from_env_or(PROFILE_VAR, DEFAULT_PROFILE))
+}
+
+/// If a secret key for rocket was given, instead of falling back to the default
+#[must_use]
+pub fn has_secret_key() -> bool {
+    sources()
+        .select(Profile::from_env_or(PROFILE_VAR, DEFAULT_PROFILE))
+        .find_value("secret_key")
+        .is_ok()
+}
+
+/// The config file, `ROCKET_` environment variables and secret files, without any defaults
+fn sources() -> Figment {
+    Figment::new()
+        .merge(Toml::file(Env::var_or(CONFIG_FILE_VAR, DEFAULT_CONFIG_FILE)).nested())
+        .merge(Env::prefixed("ROCKET_").global())
+        .merge(SecretFiles)
+}
+
+/// A provider reading secrets from the files named by the environment variables in
+/// `SECRET_FILES`
+///
+/// `DISCORD_TOKEN_FILE` sets `discord_token`, and `ROCKET_SECRET_KEY_FILE` sets rocket's
+/// `secret_key`. Other `*_FILE` variables, such as `SSL_CERT_FILE`, are left alone. Trailing
+/// newlines are removed from the secrets.
+#[derive(Debug, Clone, Copy)]
+struct SecretFiles;
+
+impl Provider for SecretFiles {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("secret files")
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        let mut secrets = Dict::new();
+
+        for &(var, key) in SECRET_FILES {
+            let path = match env::var(var) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+
+            let secret = fs::read_to_string(&path).map_err(|error| {
+                format!("failed to read {} from the file {}: {}", key, path, error)
+            })?;
+
+            secrets.insert(
+                key.to_owned(),
+                Value::from(secret.trim_end_matches(&['\r', '\n'][..]).to_owned()),
+            );
+        }
+
+        let mut data = Map::new();
+        data.insert(Profile::Global, secrets);
+
+        Ok(data)
+    }
+}
+
+/// The default time to live of a cached user guild list, 5 minutes
+const fn default_user_guild_cache_ttl() -> u64 {
+    5 * 60
+}
+
+/// The default maximum query depth, enough for every query of the frontend
+const fn default_max_query_depth() -> usize {
+    10
+}
+
+/// The default maximum estimated query cost, enough to show a guild with full voice channels
+const fn default_max_query_complexity() -> usize {
+    50_000
+}
+
+/// The default maximum batch size, more than the frontend ever batches
+const fn default_max_batch_size() -> usize {
+    10
+}
+
+/// By default a user can make a burst of 5 mutations
+const fn default_mutation_user_burst() -> u32 {
+    5
+}
+
+/// By default a user can make a mutation every 2 seconds
+const fn default_mutation_user_per_minute() -> u32 {
+    30
+}
+
+/// By default a guild can see a burst of 10 mutations
+const fn default_mutation_guild_burst() -> u32 {
+    10
+}
+
+/// By default a guild can see a mutation every second
+const fn default_mutation_guild_per_minute() -> u32 {
+    60
+}
+
+/// Graphiql is served unless disabled
+const fn default_graphiql() -> bool {
+    true
+}
+
+/// Requests to discord time out after 30 seconds by default
+const fn default_http_timeout() -> u64 {
+    30
+}
+
+/// Connecting to discord times out after 10 seconds by default
+const fn default_http_connect_timeout() -> u64 {
+    10
+}
+
+/// Redis keys are prefixed with the name of the app by default
+fn default_redis_key_prefix() -> String {
+    "stfu".to_owned()
+}
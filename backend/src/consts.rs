@@ -1,5 +1,6 @@
 //! Constants shared across the program
 
+use twilight_model::gateway::Intents;
 use twilight_oauth2::Scope;
 use twilight_permission_calculator::prelude::Permissions; // TODO: change once v2 hits
 
@@ -19,3 +20,17 @@ pub const OAUTH_REDIRECT_URLS: &[&str] = &[
     "http://localhost:8000/oauth/authorize",
     "https://stfu-backend.dusterthefirst.com/oauth/authorize",
 ];
+
+/// The amount of nodes returned in a page of a connection if not otherwise specified
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// The maximum amount of nodes that can be requested in a page of a connection
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// The gateway intents that the bot needs to keep its cache up to date
+pub const GATEWAY_INTENTS: Intents = Intents::from_bits_truncate(
+    Intents::GUILDS.bits()
+        | Intents::GUILD_VOICE_STATES.bits()
+        | Intents::GUILD_MEMBERS.bits()
+        | Intents::GUILD_PRESENCES.bits(),
+);
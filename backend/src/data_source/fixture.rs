@@ -0,0 +1,198 @@
+//! A data source serving a fixture loaded from json, without connecting to discord
+//!
+//! Release builds only use the empty fixture that the schema is printed with, as the query
+//! command that loads fixtures is only built in debug mode.
+#![cfg_attr(not(debug_assertions), allow(dead_code))]
+
+use super::{cache_lookups, DataSource};
+use anyhow::Context;
+use serde::Deserialize;
+use std::{
+    fs,
+    sync::{Arc, Mutex, PoisonError},
+};
+use twilight_cache_inmemory::{
+    model::{CachedGuild, CachedMember, CachedPresence},
+    InMemoryCache,
+};
+use twilight_model::{
+    channel::{embed::Embed, GuildChannel},
+    gateway::payload::{GuildCreate, UserUpdate, VoiceStateUpdate},
+    guild::{Guild, Role},
+    id::{ChannelId, GuildId, MessageId, RoleId, UserId},
+    user::{CurrentUser, User},
+    voice::VoiceState,
+};
+
+/// The contents of a fixture file
+///
+/// Guilds are in the same shape as the `GUILD_CREATE` events sent by the gateway, and voice
+/// states need their `guild_id` set.
+#[derive(Deserialize, Debug)]
+struct Fixture {
+    /// The bot user
+    user: Option<CurrentUser>,
+    /// The guilds that the bot is in
+    #[serde(default)]
+    guilds: Vec<Guild>,
+}
+
+/// A member update made through a fixture data source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemberMuteUpdate {
+    /// The guild of the member
+    pub guild_id: GuildId,
+    /// The updated user
+    pub user_id: UserId,
+    /// If the member was muted or unmuted
+    pub mute: bool,
+}
+
+/// A message sent through a fixture data source
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentEmbed {
+    /// The id given to the message
+    pub message_id: MessageId,
+    /// The channel the message was sent to
+    pub channel_id: ChannelId,
+    /// The embed of the message, after any edits
+    pub embed: Embed,
+}
+
+/// A data source serving a fixed set of guilds, recording updates instead of sending them to
+/// discord
+///
+/// Updates are applied to the voice states of the fixture, so later lookups see them.
+#[derive(Debug, Default)]
+pub struct FixtureDataSource {
+    /// The cache holding the fixture
+    cache: InMemoryCache,
+    /// The ids of the guilds in the fixture
+    guild_ids: Vec<GuildId>,
+    /// The member updates made, in order
+    updates: Mutex<Vec<MemberMuteUpdate>>,
+    /// The messages sent, in order
+    messages: Mutex<Vec<SentEmbed>>,
+}
+
+impl FixtureDataSource {
+    /// Load a fixture from its json
+    ///
+    /// # Errors
+    /// If the json is not a valid fixture
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let fixture: Fixture = serde_json::from_str(json).context("The fixture is invalid")?;
+        let cache = InMemoryCache::new();
+        let guild_ids = fixture.guilds.iter().map(|guild| guild.id).collect();
+
+        if let Some(user) = fixture.user {
+            cache.update(&UserUpdate(user));
+        }
+
+        for guild in fixture.guilds {
+            cache.update(&GuildCreate(guild));
+        }
+
+        Ok(Self {
+            cache,
+            guild_ids,
+            updates: Mutex::default(),
+            messages: Mutex::default(),
+        })
+    }
+
+    /// Load a fixture from a json file
+    ///
+    /// # Errors
+    /// If the file could not be read or is not a valid fixture
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        Self::from_json(
+            &fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the fixture {}", path))?,
+        )
+    }
+
+    /// The ids of the guilds in the fixture
+    #[must_use]
+    pub fn guild_ids(&self) -> &[GuildId] {
+        &self.guild_ids
+    }
+
+    /// The member updates made so far, in order
+    #[must_use]
+    pub fn updates(&self) -> Vec<MemberMuteUpdate> {
+        self.updates
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The messages sent so far, in order
+    #[must_use]
+    pub fn messages(&self) -> Vec<SentEmbed> {
+        self.messages
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[rocket::async_trait]
+impl DataSource for FixtureDataSource {
+    cache_lookups!();
+
+    async fn update_member_mute(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        mute: bool,
+    ) -> anyhow::Result<()> {
+        if let Some(state) = self.cache.voice_state(user_id, guild_id) {
+            self.cache.update(&VoiceStateUpdate(VoiceState {
+                mute,
+                ..VoiceState::clone(&state)
+            }));
+        }
+
+        self.updates
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(MemberMuteUpdate {
+                guild_id,
+                user_id,
+                mute,
+            });
+
+        Ok(())
+    }
+
+    async fn send_embed(&self, channel_id: ChannelId, embed: Embed) -> anyhow::Result<MessageId> {
+        let mut messages = self.messages.lock().unwrap_or_else(PoisonError::into_inner);
+        let message_id = MessageId(messages.len() as u64 + 1);
+
+        messages.push(SentEmbed {
+            message_id,
+            channel_id,
+            embed,
+        });
+
+        Ok(message_id)
+    }
+
+    async fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        embed: Embed,
+    ) -> anyhow::Result<()> {
+        let mut messages = self.messages.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let message = messages
+            .iter_mut()
+            .find(|message| message.message_id == message_id && message.channel_id == channel_id)
+            .context("The message does not exist")?;
+        message.embed = embed;
+
+        Ok(())
+    }
+}
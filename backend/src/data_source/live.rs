@@ -0,0 +1,71 @@
+//! A data source backed by the gateway cache and the discord api
+
+use super::{cache_lookups, DataSource};
+use std::sync::Arc;
+use twilight_cache_inmemory::{
+    model::{CachedGuild, CachedMember, CachedPresence},
+    InMemoryCache,
+};
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    channel::{embed::Embed, GuildChannel},
+    guild::Role,
+    id::{ChannelId, GuildId, MessageId, RoleId, UserId},
+    user::{CurrentUser, User},
+    voice::VoiceState,
+};
+
+/// A data source that reads from the cache kept up to date by the gateway, and makes updates
+/// through the discord api
+#[derive(Debug, Clone)]
+pub struct LiveDataSource {
+    /// The discord cache connected to the gateway
+    cache: InMemoryCache,
+    /// The discord http client for rest calls
+    http: HttpClient,
+}
+
+impl LiveDataSource {
+    /// Create a data source from a cache that is kept up to date and a client logged in as the bot
+    #[must_use]
+    pub fn new(cache: InMemoryCache, http: HttpClient) -> Self {
+        Self { cache, http }
+    }
+}
+
+#[rocket::async_trait]
+impl DataSource for LiveDataSource {
+    cache_lookups!();
+
+    async fn update_member_mute(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        mute: bool,
+    ) -> anyhow::Result<()> {
+        self.http
+            .update_guild_member(guild_id, user_id)
+            .mute(mute)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn send_embed(&self, channel_id: ChannelId, embed: Embed) -> anyhow::Result<MessageId> {
+        Ok(self.http.create_message(channel_id).embed(embed)?.await?.id)
+    }
+
+    async fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        embed: Embed,
+    ) -> anyhow::Result<()> {
+        self.http
+            .update_message(channel_id, message_id)
+            .embed(Some(embed))?
+            .await?;
+
+        Ok(())
+    }
+}
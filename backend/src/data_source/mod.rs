@@ -0,0 +1,157 @@
+//! The source of the discord data that the graphql api serves
+//!
+//! Resolvers only ever go through a [`DataSource`], so the graphql layer can be run against the
+//! live gateway cache, the cache shared through redis by a gateway worker, or against a fixture
+//! loaded from json.
+
+use std::{fmt::Debug, sync::Arc};
+use twilight_cache_inmemory::model::{CachedGuild, CachedMember, CachedPresence};
+use twilight_model::{
+    channel::{embed::Embed, GuildChannel},
+    guild::Role,
+    id::{ChannelId, GuildId, MessageId, RoleId, UserId},
+    user::{CurrentUser, User},
+    voice::VoiceState,
+};
+
+/// Implement the lookups of [`DataSource`] by delegating them to the `cache` field, an
+/// `InMemoryCache`
+///
+/// This is defined before the implementations are declared so that it is in scope for them.
+macro_rules! cache_lookups {
+    () => {
+        fn current_user(&self) -> Option<Arc<CurrentUser>> {
+            self.cache.current_user()
+        }
+
+        fn guild(&self, guild_id: GuildId) -> Option<Arc<CachedGuild>> {
+            self.cache.guild(guild_id)
+        }
+
+        fn guild_channels(&self, guild_id: GuildId) -> Option<Vec<ChannelId>> {
+            self.cache
+                .guild_channels(guild_id)
+                .map(|ids| ids.into_iter().collect())
+        }
+
+        fn guild_channel(&self, channel_id: ChannelId) -> Option<Arc<GuildChannel>> {
+            self.cache.guild_channel(channel_id)
+        }
+
+        fn guild_members(&self, guild_id: GuildId) -> Option<Vec<UserId>> {
+            self.cache
+                .guild_members(guild_id)
+                .map(|ids| ids.into_iter().collect())
+        }
+
+        fn member(&self, guild_id: GuildId, user_id: UserId) -> Option<Arc<CachedMember>> {
+            self.cache.member(guild_id, user_id)
+        }
+
+        fn guild_roles(&self, guild_id: GuildId) -> Option<Vec<RoleId>> {
+            self.cache
+                .guild_roles(guild_id)
+                .map(|ids| ids.into_iter().collect())
+        }
+
+        fn role(&self, role_id: RoleId) -> Option<Arc<Role>> {
+            self.cache.role(role_id)
+        }
+
+        fn presence(&self, guild_id: GuildId, user_id: UserId) -> Option<Arc<CachedPresence>> {
+            self.cache.presence(guild_id, user_id)
+        }
+
+        fn user(&self, user_id: UserId) -> Option<Arc<User>> {
+            self.cache.user(user_id)
+        }
+
+        fn voice_state(&self, user_id: UserId, guild_id: GuildId) -> Option<Arc<VoiceState>> {
+            self.cache.voice_state(user_id, guild_id)
+        }
+
+        fn voice_channel_states(&self, channel_id: ChannelId) -> Option<Vec<Arc<VoiceState>>> {
+            self.cache.voice_channel_states(channel_id)
+        }
+    };
+}
+
+pub mod fixture;
+pub mod live;
+pub mod redis_cache;
+
+pub use fixture::FixtureDataSource;
+pub use live::LiveDataSource;
+pub use redis_cache::{RedisCache, RedisDataSource};
+
+/// A shared handle to the data source used by the server
+pub type SharedDataSource = Arc<dyn DataSource>;
+
+/// Lookups of guilds, channels, members, roles and voice states, along with the member updates
+/// and messages that the bot makes
+#[rocket::async_trait]
+pub trait DataSource: Debug + Send + Sync {
+    /// Get the bot user
+    fn current_user(&self) -> Option<Arc<CurrentUser>>;
+
+    /// Get a guild that the bot is in
+    fn guild(&self, guild_id: GuildId) -> Option<Arc<CachedGuild>>;
+
+    /// Get the ids of the channels in a guild
+    fn guild_channels(&self, guild_id: GuildId) -> Option<Vec<ChannelId>>;
+
+    /// Get a channel of a guild
+    fn guild_channel(&self, channel_id: ChannelId) -> Option<Arc<GuildChannel>>;
+
+    /// Get the ids of the members of a guild
+    fn guild_members(&self, guild_id: GuildId) -> Option<Vec<UserId>>;
+
+    /// Get a member of a guild
+    fn member(&self, guild_id: GuildId, user_id: UserId) -> Option<Arc<CachedMember>>;
+
+    /// Get the ids of the roles in a guild
+    fn guild_roles(&self, guild_id: GuildId) -> Option<Vec<RoleId>>;
+
+    /// Get a role of a guild
+    fn role(&self, role_id: RoleId) -> Option<Arc<Role>>;
+
+    /// Get the presence of a member of a guild
+    fn presence(&self, guild_id: GuildId, user_id: UserId) -> Option<Arc<CachedPresence>>;
+
+    /// Get a user
+    fn user(&self, user_id: UserId) -> Option<Arc<User>>;
+
+    /// Get the voice state of a user in a guild
+    fn voice_state(&self, user_id: UserId, guild_id: GuildId) -> Option<Arc<VoiceState>>;
+
+    /// Get the voice states of the users in a voice channel
+    fn voice_channel_states(&self, channel_id: ChannelId) -> Option<Vec<Arc<VoiceState>>>;
+
+    /// Server mute or unmute a member of a guild
+    ///
+    /// # Errors
+    /// If the member could not be updated
+    async fn update_member_mute(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        mute: bool,
+    ) -> anyhow::Result<()>;
+
+    /// Send a message with an embed to a channel, returning the id of the message
+    ///
+    /// # Errors
+    /// If the message could not be sent
+    async fn send_embed(&self, channel_id: ChannelId, embed: Embed) -> anyhow::Result<MessageId>;
+
+    /// Replace the embed of a message that the bot sent
+    ///
+    /// # Errors
+    /// If the message could not be edited
+    async fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        embed: Embed,
+    ) -> anyhow::Result<()>;
+}
@@ -0,0 +1,1497 @@
+//! A discord cache shared through redis, so that any number of api servers can serve the
+//! guilds seen by a single gateway worker
+//!
+//! The gateway worker writes each guild to redis whenever it changes, and publishes the change.
+//! A guild is stored with its channels and roles under one key, while its members, presences
+//! and voice states are fields of a hash each, so that the frequent events about a single user
+//! only write and publish that user. Servers keep a copy of the cache in memory, so lookups
+//! never wait on redis, and reload only the parts named by the published changes.
+//!
+//! Servers never talk to the gateway themselves. Member updates and messages are pushed to a
+//! queue in redis for the gateway worker to make, and the voice state changes they cause come
+//! back through the published changes like any other.
+
+use super::{DataSource, LiveDataSource};
+use crate::{
+    config::Config,
+    rate_limit::Rate,
+    session::{random_key, unix_timestamp},
+};
+use anyhow::{anyhow, bail, Context};
+use async_std::{
+    future::timeout,
+    stream::{Stream, StreamExt},
+    task,
+};
+use futures::lock::Mutex as AsyncMutex;
+use redis::{
+    aio::{Connection, PubSub},
+    AsyncCommands, Client, Pipeline, Script,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    fmt::{self, Debug, Formatter},
+    mem,
+    sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use twilight_cache_inmemory::{
+    model::{CachedGuild, CachedMember, CachedPresence},
+    InMemoryCache,
+};
+use twilight_http::Client as HttpClient;
+use twilight_model::{
+    channel::{embed::Embed, Channel, GuildChannel},
+    gateway::{event::Event, presence::UserOrId},
+    guild::Role,
+    id::{ChannelId, GuildId, MessageId, RoleId, UserId},
+    user::{CurrentUser, User},
+    voice::VoiceState,
+};
+
+/// How long the gateway worker collects events for before writing the parts of the cache they
+/// changed, so that a burst of events about the same user only writes the user once
+const WRITE_DELAY: Duration = Duration::from_millis(100);
+
+/// How long to wait before reconnecting to redis after losing the connection
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Seconds that a server waits for the gateway worker to run a command, after which the command
+/// is given up on
+const COMMAND_TIMEOUT: usize = 30;
+
+/// The most connections that a server keeps open for its commands while none are running, the
+/// rest being closed once their command is done
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+/// Take a token from each of the token buckets in `KEYS` if all of them have one, otherwise
+/// returning the seconds until they all do
+///
+/// A bucket is a hash of the tokens it held when it was last updated, and the time in seconds
+/// that it was. The first argument is the current time in seconds, followed by the burst and the
+/// tokens added back every minute of each bucket. Missing buckets are full, and buckets expire
+/// once they would be full again.
+const TAKE_TOKENS_SCRIPT: &str = r"
+local now = tonumber(ARGV[1])
+local tokens = {}
+local wait = 0
+
+for i, key in ipairs(KEYS) do
+    local burst = tonumber(ARGV[i * 2])
+    local per_second = tonumber(ARGV[i * 2 + 1]) / 60
+    local bucket = redis.call('HMGET', key, 'tokens', 'updated_at')
+
+    tokens[i] = burst
+    if bucket[1] then
+        local elapsed = math.max(0, now - tonumber(bucket[2]))
+        tokens[i] = math.min(burst, tonumber(bucket[1]) + elapsed * per_second)
+    end
+
+    if tokens[i] < 1 then
+        wait = math.max(wait, (1 - tokens[i]) / per_second)
+    end
+end
+
+if wait > 0 then
+    return tostring(wait)
+end
+
+for i, key in ipairs(KEYS) do
+    local burst = tonumber(ARGV[i * 2])
+    local per_second = tonumber(ARGV[i * 2 + 1]) / 60
+
+    redis.call('HSET', key, 'tokens', tostring(tokens[i] - 1), 'updated_at', tostring(now))
+    redis.call('PEXPIRE', key, math.ceil((burst - tokens[i] + 1) / per_second * 1000))
+end
+
+return false
+";
+
+/// Set the field `ARGV[1]` of the hash in `KEYS[1]` to `ARGV[2]`, unless the field is already
+/// set or the hash has `ARGV[3]` fields, returning 1 if the field was set
+const WRITE_HASH_VALUE_SCRIPT: &str = r"
+if redis.call('HLEN', KEYS[1]) >= tonumber(ARGV[3]) then
+    return 0
+end
+
+return redis.call('HSETNX', KEYS[1], ARGV[1], ARGV[2])
+";
+
+/// A part of the cache that changed and has to be written again
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheChange {
+    /// The bot user
+    CurrentUser,
+    /// A guild, along with its channels and roles, or everything about it if it was removed
+    Guild(GuildId),
+    /// Every member, presence and voice state of a guild
+    GuildMembers(GuildId),
+    /// A member of a guild
+    Member(GuildId, UserId),
+    /// The presence of a member of a guild
+    Presence(GuildId, UserId),
+    /// The voice state of a member of a guild
+    VoiceState(GuildId, UserId),
+}
+
+impl CacheChange {
+    /// The parts of the cache that a gateway event changes
+    #[must_use]
+    pub fn of(event: &Event) -> Vec<Self> {
+        match event {
+            Event::Ready(_) | Event::UserUpdate(_) => vec![Self::CurrentUser],
+            Event::GuildCreate(guild) => {
+                vec![Self::Guild(guild.0.id), Self::GuildMembers(guild.0.id)]
+            }
+            Event::GuildUpdate(guild) => vec![Self::Guild(guild.0.id)],
+            Event::GuildDelete(guild) => vec![Self::Guild(guild.id)],
+            Event::ChannelCreate(channel) => channel_guild(&channel.0)
+                .map(Self::Guild)
+                .into_iter()
+                .collect(),
+            Event::ChannelUpdate(channel) => channel_guild(&channel.0)
+                .map(Self::Guild)
+                .into_iter()
+                .collect(),
+            Event::ChannelDelete(channel) => channel_guild(&channel.0)
+                .map(Self::Guild)
+                .into_iter()
+                .collect(),
+            Event::RoleCreate(role) => vec![Self::Guild(role.guild_id)],
+            Event::RoleUpdate(role) => vec![Self::Guild(role.guild_id)],
+            Event::RoleDelete(role) => vec![Self::Guild(role.guild_id)],
+            Event::MemberAdd(member) => vec![Self::Member(member.0.guild_id, member.0.user.id)],
+            Event::MemberUpdate(member) => vec![Self::Member(member.guild_id, member.user.id)],
+            Event::MemberRemove(member) => vec![
+                Self::Member(member.guild_id, member.user.id),
+                Self::Presence(member.guild_id, member.user.id),
+                Self::VoiceState(member.guild_id, member.user.id),
+            ],
+            // Chunks only arrive in bulk while the members of a guild are being requested
+            Event::MemberChunk(chunk) => vec![Self::GuildMembers(chunk.guild_id)],
+            Event::PresenceUpdate(presence) => {
+                let user_id = match &presence.user {
+                    UserOrId::User(user) => user.id,
+                    UserOrId::UserId { id } => *id,
+                };
+
+                vec![Self::Presence(presence.guild_id, user_id)]
+            }
+            Event::VoiceStateUpdate(state) => state
+                .0
+                .guild_id
+                .map(|guild_id| Self::VoiceState(guild_id, state.0.user_id))
+                .into_iter()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The guild that a channel is in, if it is a guild channel
+fn channel_guild(channel: &Channel) -> Option<GuildId> {
+    match channel {
+        Channel::Guild(GuildChannel::Category(channel)) => channel.guild_id,
+        Channel::Guild(GuildChannel::Text(channel)) => channel.guild_id,
+        Channel::Guild(GuildChannel::Voice(channel)) => channel.guild_id,
+        _ => None,
+    }
+}
+
+/// Queue replacing a hash with json values keyed by user ids
+fn write_hash<T: Serialize>(
+    pipe: &mut Pipeline,
+    key: &str,
+    values: impl Iterator<Item = (UserId, Arc<T>)>,
+) -> anyhow::Result<()> {
+    let fields = values
+        .map(|(user_id, value)| Ok((user_id.0, serde_json::to_string(&*value)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    pipe.del(key).ignore();
+    if !fields.is_empty() {
+        pipe.hset_multiple(key, &fields).ignore();
+    }
+
+    Ok(())
+}
+
+/// Queue writing a json value to the field of a hash keyed by a user id, or removing the field
+/// if there is no value
+fn write_field<T: Serialize>(
+    pipe: &mut Pipeline,
+    key: &str,
+    user_id: UserId,
+    value: Option<Arc<T>>,
+) -> anyhow::Result<()> {
+    match value {
+        Some(value) => pipe
+            .hset(key, user_id.0, serde_json::to_string(&*value)?)
+            .ignore(),
+        None => pipe.hdel(key, user_id.0).ignore(),
+    };
+
+    Ok(())
+}
+
+/// Set the value kept for a user, or remove it if there is no value
+fn set_field<T>(values: &mut HashMap<UserId, Arc<T>>, user_id: UserId, value: Option<T>) {
+    match value {
+        Some(value) => values.insert(user_id, Arc::new(value)),
+        None => values.remove(&user_id),
+    };
+}
+
+/// A command pushed by a server for the gateway worker to run
+#[derive(Serialize, Deserialize, Debug)]
+struct Command {
+    /// The key of the list that the outcome is pushed to
+    reply_to: String,
+    /// The seconds since the unix epoch after which the server has given up on the command
+    expires_at: u64,
+    /// What the gateway worker should do
+    action: CommandAction,
+}
+
+/// A request to discord that a server has the gateway worker make
+#[derive(Serialize, Deserialize, Debug)]
+enum CommandAction {
+    /// Server mute or unmute a member of a guild
+    MemberMute {
+        /// The guild of the member
+        guild_id: GuildId,
+        /// The user to update
+        user_id: UserId,
+        /// If the member should be muted or unmuted
+        mute: bool,
+    },
+    /// Send a message with an embed to a channel
+    SendEmbed {
+        /// The channel to send the message to
+        channel_id: ChannelId,
+        /// The embed of the message
+        embed: Embed,
+    },
+    /// Replace the embed of a message
+    EditEmbed {
+        /// The channel of the message
+        channel_id: ChannelId,
+        /// The message to edit
+        message_id: MessageId,
+        /// The new embed of the message
+        embed: Embed,
+    },
+}
+
+impl CommandAction {
+    /// Make the request to discord, returning the id of the message sent, if any
+    async fn run(self, http: &HttpClient) -> anyhow::Result<Option<MessageId>> {
+        match self {
+            CommandAction::MemberMute {
+                guild_id,
+                user_id,
+                mute,
+            } => {
+                http.update_guild_member(guild_id, user_id)
+                    .mute(mute)
+                    .await?;
+
+                Ok(None)
+            }
+            CommandAction::SendEmbed { channel_id, embed } => {
+                let message = http.create_message(channel_id).embed(embed)?.await?;
+
+                Ok(Some(message.id))
+            }
+            CommandAction::EditEmbed {
+                channel_id,
+                message_id,
+                embed,
+            } => {
+                http.update_message(channel_id, message_id)
+                    .embed(Some(embed))?
+                    .await?;
+
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The outcome of a command, with the id of the message sent if any, or the error as a message
+type CommandOutcome = Result<Option<MessageId>, String>;
+
+/// A guild as it is stored in redis, without its members, presences and voice states
+#[derive(Serialize, Deserialize, Debug)]
+struct GuildSnapshot {
+    /// The guild
+    guild: CachedGuild,
+    /// The channels of the guild
+    channels: Vec<GuildChannel>,
+    /// The roles of the guild
+    roles: Vec<Role>,
+}
+
+impl GuildSnapshot {
+    /// Take a snapshot of a guild in a data source, if the guild is in it
+    fn capture(data: &dyn DataSource, guild_id: GuildId) -> Option<Self> {
+        let guild = data.guild(guild_id)?;
+
+        Some(Self {
+            guild: CachedGuild::clone(&guild),
+            channels: data
+                .guild_channels(guild_id)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|channel_id| data.guild_channel(channel_id))
+                .map(|channel| GuildChannel::clone(&channel))
+                .collect(),
+            roles: data
+                .guild_roles(guild_id)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|role_id| data.role(role_id))
+                .map(|role| Role::clone(&role))
+                .collect(),
+        })
+    }
+}
+
+/// The members, presences and voice states of a guild copied from redis, by their user id
+#[derive(Debug, Default)]
+struct MirroredUsers {
+    /// The members of the guild
+    members: HashMap<UserId, Arc<CachedMember>>,
+    /// The presences of the members
+    presences: HashMap<UserId, Arc<CachedPresence>>,
+    /// The voice states of the users in the voice channels of the guild
+    voice_states: HashMap<UserId, Arc<VoiceState>>,
+}
+
+/// A guild copied from redis, indexed for lookups
+#[derive(Debug)]
+struct MirroredGuild {
+    /// The guild
+    guild: Arc<CachedGuild>,
+    /// The channels of the guild
+    channels: HashMap<ChannelId, Arc<GuildChannel>>,
+    /// The roles of the guild
+    roles: HashMap<RoleId, Arc<Role>>,
+    /// The members, presences and voice states of the guild
+    users: MirroredUsers,
+}
+
+impl MirroredGuild {
+    /// Index a guild read from redis, along with its users
+    fn new(snapshot: GuildSnapshot, users: MirroredUsers) -> Self {
+        Self {
+            guild: Arc::new(snapshot.guild),
+            channels: snapshot
+                .channels
+                .into_iter()
+                .map(|channel| (channel.id(), Arc::new(channel)))
+                .collect(),
+            roles: snapshot
+                .roles
+                .into_iter()
+                .map(|role| (role.id, Arc::new(role)))
+                .collect(),
+            users,
+        }
+    }
+}
+
+/// The copy of the shared cache kept by a server
+#[derive(Debug, Default)]
+struct Mirror {
+    /// The bot user
+    current_user: Option<Arc<CurrentUser>>,
+    /// The guilds that the bot is in
+    guilds: HashMap<GuildId, MirroredGuild>,
+}
+
+/// Connections to redis that the commands of a server reuse, since a mass mute runs a command
+/// for every member
+///
+/// Waiting for the outcome of a command blocks its connection, so commands cannot share a single
+/// multiplexed connection.
+#[derive(Default)]
+struct ConnectionPool {
+    /// The connections that no command is using
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl ConnectionPool {
+    /// Take a connection that no command is using, if there is one
+    fn take(&self) -> Option<Connection> {
+        self.idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop()
+    }
+
+    /// Keep a connection that a command is done with for the next commands, unless enough are
+    /// kept already
+    fn put(&self, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(connection);
+        }
+    }
+}
+
+impl Debug for ConnectionPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field(
+                "idle",
+                &self
+                    .idle
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .len(),
+            )
+            .finish()
+    }
+}
+
+/// The redis server that the cache is shared through
+#[derive(Debug, Clone)]
+pub struct RedisCache {
+    /// The client of the redis server
+    client: Client,
+    /// The prefix of every key used, so multiple deployments can share a redis server
+    prefix: String,
+    /// The connections reused by the commands pushed to the gateway worker, and by claims
+    pool: Arc<ConnectionPool>,
+}
+
+impl RedisCache {
+    /// Create a handle to the shared cache on a redis server, without connecting to it yet
+    ///
+    /// # Errors
+    /// If the url is not a valid redis url
+    pub fn open(url: &str, prefix: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: Client::open(url).context("The redis url is invalid")?,
+            prefix: prefix.to_owned(),
+            pool: Arc::default(),
+        })
+    }
+
+    /// Create a handle to the shared cache described by the config, if one is configured
+    ///
+    /// # Errors
+    /// If the redis url is not a valid redis url
+    pub fn from_config(config: &Config) -> anyhow::Result<Option<Self>> {
+        config
+            .redis_url
+            .as_ref()
+            .map(|url| Self::open(url, &config.redis_key_prefix))
+            .transpose()
+    }
+
+    /// Open a new connection to the redis server
+    ///
+    /// # Errors
+    /// If the redis server could not be reached
+    pub async fn connect(&self) -> anyhow::Result<Connection> {
+        self.client
+            .get_async_connection()
+            .await
+            .context("Failed to connect to redis")
+    }
+
+    /// The full name of a key
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.prefix, name)
+    }
+
+    /// The key holding the snapshot of a guild
+    fn guild_key(&self, guild_id: GuildId) -> String {
+        self.key(&format!("guild:{}", guild_id))
+    }
+
+    /// The key of the hash holding the members of a guild by their user id
+    fn members_key(&self, guild_id: GuildId) -> String {
+        self.key(&format!("guild:{}:members", guild_id))
+    }
+
+    /// The key of the hash holding the presences of the members of a guild by their user id
+    fn presences_key(&self, guild_id: GuildId) -> String {
+        self.key(&format!("guild:{}:presences", guild_id))
+    }
+
+    /// The key of the hash holding the voice states of a guild by their user id
+    fn voice_states_key(&self, guild_id: GuildId) -> String {
+        self.key(&format!("guild:{}:voice_states", guild_id))
+    }
+
+    /// Every key holding a part of a guild
+    fn guild_keys(&self, guild_id: GuildId) -> [String; 4] {
+        [
+            self.guild_key(guild_id),
+            self.members_key(guild_id),
+            self.presences_key(guild_id),
+            self.voice_states_key(guild_id),
+        ]
+    }
+
+    /// Keep the shared cache up to date with the events of a gateway connection, until the
+    /// events run out, handing every event to `on_event` once the cache is updated with it
+    ///
+    /// Anything left in the cache by an earlier worker is removed first. Failed writes are
+    /// retried along with the changes of the next events.
+    ///
+    /// # Errors
+    /// If the cache could not be cleared
+    pub async fn fill(
+        &self,
+        cache: InMemoryCache,
+        http: HttpClient,
+        mut events: impl Stream<Item = Event> + Unpin,
+        on_event: impl Fn(&Event),
+    ) -> anyhow::Result<()> {
+        let data = LiveDataSource::new(cache.clone(), http);
+
+        let mut connection = self.connect().await?;
+        self.clear(&mut connection).await?;
+
+        let mut connection = Some(connection);
+        let mut pending = HashSet::new();
+
+        while let Some(event) = events.next().await {
+            let deadline = Instant::now() + WRITE_DELAY;
+            let mut next = Some(event);
+
+            while let Some(event) = next {
+                cache.update(&event);
+                on_event(&event);
+                pending.extend(CacheChange::of(&event));
+
+                next = match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) => timeout(remaining, events.next()).await.ok().flatten(),
+                    None => None,
+                };
+            }
+
+            if let Err(error) = self
+                .write_pending(&mut connection, &data, &mut pending)
+                .await
+            {
+                log::error!("Failed to write to the redis cache: {:#}", error);
+                connection = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write every pending change, reconnecting first if there is no connection
+    async fn write_pending(
+        &self,
+        connection: &mut Option<Connection>,
+        data: &dyn DataSource,
+        pending: &mut HashSet<CacheChange>,
+    ) -> anyhow::Result<()> {
+        if connection.is_none() {
+            *connection = Some(self.connect().await?);
+        }
+        let connection = connection.as_mut().expect("Connected above");
+
+        for change in pending.clone() {
+            self.write(connection, data, change).await?;
+            pending.remove(&change);
+        }
+
+        Ok(())
+    }
+
+    /// Write a part of the cache from a data source and publish the change
+    async fn write(
+        &self,
+        connection: &mut Connection,
+        data: &dyn DataSource,
+        change: CacheChange,
+    ) -> anyhow::Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        match change {
+            CacheChange::CurrentUser => {
+                match data.current_user() {
+                    Some(user) => pipe
+                        .set(self.key("current_user"), serde_json::to_string(&*user)?)
+                        .ignore(),
+                    None => pipe.del(self.key("current_user")).ignore(),
+                };
+            }
+            CacheChange::Guild(guild_id) => {
+                match GuildSnapshot::capture(data, guild_id) {
+                    Some(snapshot) => pipe
+                        .set(self.guild_key(guild_id), serde_json::to_string(&snapshot)?)
+                        .ignore()
+                        .sadd(self.key("guilds"), guild_id.0)
+                        .ignore(),
+                    None => pipe
+                        .del(&self.guild_keys(guild_id)[..])
+                        .ignore()
+                        .srem(self.key("guilds"), guild_id.0)
+                        .ignore(),
+                };
+            }
+            CacheChange::GuildMembers(guild_id) => {
+                let member_ids = data.guild_members(guild_id).unwrap_or_default();
+
+                write_hash(
+                    &mut pipe,
+                    &self.members_key(guild_id),
+                    member_ids.iter().filter_map(|&user_id| {
+                        data.member(guild_id, user_id)
+                            .map(|member| (user_id, member))
+                    }),
+                )?;
+                write_hash(
+                    &mut pipe,
+                    &self.presences_key(guild_id),
+                    member_ids.iter().filter_map(|&user_id| {
+                        data.presence(guild_id, user_id)
+                            .map(|presence| (user_id, presence))
+                    }),
+                )?;
+                write_hash(
+                    &mut pipe,
+                    &self.voice_states_key(guild_id),
+                    data.guild_channels(guild_id)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|channel_id| data.voice_channel_states(channel_id))
+                        .flatten()
+                        .map(|state| (state.user_id, state)),
+                )?;
+            }
+            CacheChange::Member(guild_id, user_id) => write_field(
+                &mut pipe,
+                &self.members_key(guild_id),
+                user_id,
+                data.member(guild_id, user_id),
+            )?,
+            CacheChange::Presence(guild_id, user_id) => write_field(
+                &mut pipe,
+                &self.presences_key(guild_id),
+                user_id,
+                data.presence(guild_id, user_id),
+            )?,
+            CacheChange::VoiceState(guild_id, user_id) => write_field(
+                &mut pipe,
+                &self.voice_states_key(guild_id),
+                user_id,
+                data.voice_state(user_id, guild_id),
+            )?,
+        }
+
+        pipe.publish(self.key("changes"), serde_json::to_string(&change)?)
+            .ignore()
+            .query_async(connection)
+            .await
+            .context("Failed to write the change")
+    }
+
+    /// Remove everything from the cache, publishing the removals
+    async fn clear(&self, connection: &mut Connection) -> anyhow::Result<()> {
+        let guild_ids: Vec<u64> = connection.smembers(self.key("guilds")).await?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        for guild_id in guild_ids {
+            pipe.del(&self.guild_keys(GuildId(guild_id))[..])
+                .ignore()
+                .publish(
+                    self.key("changes"),
+                    serde_json::to_string(&CacheChange::Guild(GuildId(guild_id)))?,
+                )
+                .ignore();
+        }
+
+        pipe.del(self.key("guilds"))
+            .ignore()
+            .del(self.key("current_user"))
+            .ignore()
+            .publish(
+                self.key("changes"),
+                serde_json::to_string(&CacheChange::CurrentUser)?,
+            )
+            .ignore()
+            .query_async(connection)
+            .await
+            .context("Failed to clear the redis cache")
+    }
+
+    /// Read a json value from a key
+    async fn read<T: DeserializeOwned>(
+        connection: &mut Connection,
+        key: String,
+    ) -> anyhow::Result<Option<T>> {
+        let json: Option<String> = connection.get(&key).await?;
+
+        json.map(|json| {
+            serde_json::from_str(&json).with_context(|| format!("The cached {} is invalid", key))
+        })
+        .transpose()
+    }
+
+    /// Read a json value from a field of a hash, keyed by a user id
+    async fn read_field<T: DeserializeOwned>(
+        connection: &mut Connection,
+        key: String,
+        user_id: UserId,
+    ) -> anyhow::Result<Option<T>> {
+        let json: Option<String> = connection.hget(&key, user_id.0).await?;
+
+        json.map(|json| {
+            serde_json::from_str(&json)
+                .with_context(|| format!("The cached {} of {} is invalid", key, user_id))
+        })
+        .transpose()
+    }
+
+    /// Read every json value in a hash, keyed by user ids
+    async fn read_hash<T: DeserializeOwned>(
+        connection: &mut Connection,
+        key: String,
+    ) -> anyhow::Result<HashMap<UserId, Arc<T>>> {
+        let fields: HashMap<u64, String> = connection.hgetall(&key).await?;
+
+        fields
+            .into_iter()
+            .map(|(user_id, json)| {
+                let value = serde_json::from_str(&json)
+                    .with_context(|| format!("The cached {} of {} is invalid", key, user_id))?;
+
+                Ok((UserId(user_id), Arc::new(value)))
+            })
+            .collect()
+    }
+
+    /// Read the members, presences and voice states of a guild
+    async fn read_users(
+        &self,
+        connection: &mut Connection,
+        guild_id: GuildId,
+    ) -> anyhow::Result<MirroredUsers> {
+        Ok(MirroredUsers {
+            members: Self::read_hash(connection, self.members_key(guild_id)).await?,
+            presences: Self::read_hash(connection, self.presences_key(guild_id)).await?,
+            voice_states: Self::read_hash(connection, self.voice_states_key(guild_id)).await?,
+        })
+    }
+
+    /// Read the whole cache
+    async fn read_all(&self, connection: &mut Connection) -> anyhow::Result<Mirror> {
+        let guild_ids: Vec<u64> = connection.smembers(self.key("guilds")).await?;
+
+        let mut mirror = Mirror {
+            current_user: Self::read(connection, self.key("current_user"))
+                .await?
+                .map(Arc::new),
+            guilds: HashMap::new(),
+        };
+
+        for guild_id in guild_ids.into_iter().map(GuildId) {
+            if let Some(snapshot) =
+                Self::read::<GuildSnapshot>(connection, self.guild_key(guild_id)).await?
+            {
+                let users = self.read_users(connection, guild_id).await?;
+                mirror
+                    .guilds
+                    .insert(guild_id, MirroredGuild::new(snapshot, users));
+            }
+        }
+
+        Ok(mirror)
+    }
+
+    /// Read a changed part of the cache into a mirror
+    ///
+    /// The users of a guild are only read along with the guild the first time it is seen, and
+    /// changes to the users of a guild that is not mirrored yet are skipped until then.
+    async fn apply(
+        &self,
+        connection: &mut Connection,
+        mirror: &RwLock<Mirror>,
+        change: CacheChange,
+    ) -> anyhow::Result<()> {
+        match change {
+            CacheChange::CurrentUser => {
+                let user = Self::read(connection, self.key("current_user")).await?;
+
+                mirror
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .current_user = user.map(Arc::new);
+            }
+            CacheChange::Guild(guild_id) => {
+                let snapshot = match Self::read::<GuildSnapshot>(
+                    connection,
+                    self.guild_key(guild_id),
+                )
+                .await?
+                {
+                    Some(snapshot) => snapshot,
+                    None => {
+                        mirror
+                            .write()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .guilds
+                            .remove(&guild_id);
+                        return Ok(());
+                    }
+                };
+
+                // A guild that is mirrored already keeps its users, which change on their own
+                {
+                    let mut mirror = mirror.write().unwrap_or_else(PoisonError::into_inner);
+
+                    if let Some(guild) = mirror.guilds.get_mut(&guild_id) {
+                        let users = mem::take(&mut guild.users);
+                        *guild = MirroredGuild::new(snapshot, users);
+                        return Ok(());
+                    }
+                }
+
+                let users = self.read_users(connection, guild_id).await?;
+                mirror
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .guilds
+                    .insert(guild_id, MirroredGuild::new(snapshot, users));
+            }
+            CacheChange::GuildMembers(guild_id) => {
+                if !Self::is_mirrored(mirror, guild_id) {
+                    return Ok(());
+                }
+
+                let users = self.read_users(connection, guild_id).await?;
+                if let Some(guild) = mirror
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .guilds
+                    .get_mut(&guild_id)
+                {
+                    guild.users = users;
+                }
+            }
+            CacheChange::Member(guild_id, user_id) => {
+                if Self::is_mirrored(mirror, guild_id) {
+                    let member =
+                        Self::read_field(connection, self.members_key(guild_id), user_id).await?;
+                    Self::update_users(mirror, guild_id, |users| {
+                        set_field(&mut users.members, user_id, member)
+                    });
+                }
+            }
+            CacheChange::Presence(guild_id, user_id) => {
+                if Self::is_mirrored(mirror, guild_id) {
+                    let presence =
+                        Self::read_field(connection, self.presences_key(guild_id), user_id).await?;
+                    Self::update_users(mirror, guild_id, |users| {
+                        set_field(&mut users.presences, user_id, presence)
+                    });
+                }
+            }
+            CacheChange::VoiceState(guild_id, user_id) => {
+                if Self::is_mirrored(mirror, guild_id) {
+                    let state =
+                        Self::read_field(connection, self.voice_states_key(guild_id), user_id)
+                            .await?;
+                    Self::update_users(mirror, guild_id, |users| {
+                        set_field(&mut users.voice_states, user_id, state)
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// If a guild is in a mirror
+    fn is_mirrored(mirror: &RwLock<Mirror>, guild_id: GuildId) -> bool {
+        mirror
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .guilds
+            .contains_key(&guild_id)
+    }
+
+    /// Update the users of a guild in a mirror, if the guild is still in it
+    fn update_users(
+        mirror: &RwLock<Mirror>,
+        guild_id: GuildId,
+        update: impl FnOnce(&mut MirroredUsers),
+    ) {
+        if let Some(guild) = mirror
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .guilds
+            .get_mut(&guild_id)
+        {
+            update(&mut guild.users);
+        }
+    }
+
+    /// Subscribe to the changes of the cache and read the whole cache into a mirror, returning
+    /// the subscription and a connection to read the changes with
+    async fn subscribe(&self, mirror: &RwLock<Mirror>) -> anyhow::Result<(PubSub, Connection)> {
+        let mut pubsub = self.connect().await?.into_pubsub();
+        pubsub.subscribe(self.key("changes")).await?;
+
+        // Read the cache after subscribing, so that no change made in between is missed
+        let mut connection = self.connect().await?;
+        let cache = self.read_all(&mut connection).await?;
+        *mirror.write().unwrap_or_else(PoisonError::into_inner) = cache;
+
+        Ok((pubsub, connection))
+    }
+
+    /// Apply the changes published to a subscription to a mirror, until the subscription fails
+    async fn follow(
+        &self,
+        pubsub: &mut PubSub,
+        connection: &mut Connection,
+        mirror: &RwLock<Mirror>,
+    ) -> anyhow::Result<()> {
+        let mut messages = Box::pin(pubsub.on_message());
+
+        while let Some(message) = messages.next().await {
+            let change = serde_json::from_str(&message.get_payload::<String>()?)
+                .context("Received an invalid cache change")?;
+
+            self.apply(connection, mirror, change).await?;
+        }
+
+        bail!("The subscription to the cache changes closed")
+    }
+
+    /// Have the gateway worker server mute or unmute a member, waiting for the outcome
+    ///
+    /// # Errors
+    /// If redis could not be reached, the worker did not make the update in time, or the update
+    /// failed
+    pub async fn update_member_mute(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        mute: bool,
+    ) -> anyhow::Result<()> {
+        self.run_command(CommandAction::MemberMute {
+            guild_id,
+            user_id,
+            mute,
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Have the gateway worker send a message with an embed to a channel, waiting for the id of
+    /// the message
+    ///
+    /// # Errors
+    /// If redis could not be reached, the worker did not send the message in time, or sending
+    /// it failed
+    pub async fn send_embed(
+        &self,
+        channel_id: ChannelId,
+        embed: Embed,
+    ) -> anyhow::Result<MessageId> {
+        self.run_command(CommandAction::SendEmbed { channel_id, embed })
+            .await?
+            .context("The gateway worker did not send back the id of the message")
+    }
+
+    /// Have the gateway worker replace the embed of a message, waiting for the outcome
+    ///
+    /// # Errors
+    /// If redis could not be reached, the worker did not edit the message in time, or editing
+    /// it failed
+    pub async fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        embed: Embed,
+    ) -> anyhow::Result<()> {
+        self.run_command(CommandAction::EditEmbed {
+            channel_id,
+            message_id,
+            embed,
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Push a command for the gateway worker to run, and wait for its outcome
+    ///
+    /// A connection is only reused once the command went through on it, so that a broken
+    /// connection is dropped instead.
+    async fn run_command(&self, action: CommandAction) -> anyhow::Result<Option<MessageId>> {
+        let mut connection = self.pooled_connection().await?;
+        let reply_to = self.key(&format!("reply:{}", random_key()));
+
+        let command = Command {
+            reply_to: reply_to.clone(),
+            expires_at: unix_timestamp() + COMMAND_TIMEOUT as u64,
+            action,
+        };
+
+        connection
+            .lpush::<_, _, ()>(self.key("commands"), serde_json::to_string(&command)?)
+            .await?;
+
+        let reply: Option<(String, String)> = connection.brpop(&reply_to, COMMAND_TIMEOUT).await?;
+        self.pool.put(connection);
+
+        match reply {
+            Some((_, outcome)) => serde_json::from_str::<CommandOutcome>(&outcome)
+                .context("Received an invalid command outcome")?
+                .map_err(|error| anyhow!(error)),
+            None => bail!("The gateway worker did not run the command in time"),
+        }
+    }
+
+    /// Claim a name until it expires, so that only one of the servers sharing the redis server
+    /// acts on it
+    ///
+    /// # Returns
+    /// If the name was claimed, which it is not if another server claimed it first
+    ///
+    /// # Errors
+    /// If redis could not be reached
+    pub async fn claim(&self, name: &str, expires_in: Duration) -> anyhow::Result<bool> {
+        let mut connection = self.pooled_connection().await?;
+
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(self.key(&format!("claim:{}", name)))
+            .arg(unix_timestamp())
+            .arg("NX")
+            .arg("EX")
+            .arg(expires_in.as_secs())
+            .query_async(&mut connection)
+            .await?;
+        self.pool.put(connection);
+
+        Ok(claimed.is_some())
+    }
+
+    /// Read a json value that a server wrote to the key with the name
+    ///
+    /// # Errors
+    /// If redis could not be reached or the value is invalid
+    pub async fn read_value<T: DeserializeOwned>(&self, name: &str) -> anyhow::Result<Option<T>> {
+        let mut connection = self.pooled_connection().await?;
+        let value = Self::read(&mut connection, self.key(name)).await?;
+        self.pool.put(connection);
+
+        Ok(value)
+    }
+
+    /// Write a json value to the key with the name, for every server to read until it expires
+    ///
+    /// # Errors
+    /// If redis could not be reached
+    pub async fn write_value<T: Serialize + ?Sized>(
+        &self,
+        name: &str,
+        value: &T,
+        expires_in: Duration,
+    ) -> anyhow::Result<()> {
+        let json = serde_json::to_string(value)?;
+
+        let mut connection = self.pooled_connection().await?;
+        connection
+            .set_ex::<_, _, ()>(self.key(name), json, expires_in.as_secs().try_into()?)
+            .await?;
+        self.pool.put(connection);
+
+        Ok(())
+    }
+
+    /// Remove the value of the key with the name
+    ///
+    /// # Errors
+    /// If redis could not be reached
+    pub async fn remove_value(&self, name: &str) -> anyhow::Result<()> {
+        let mut connection = self.pooled_connection().await?;
+        connection.del::<_, ()>(self.key(name)).await?;
+        self.pool.put(connection);
+
+        Ok(())
+    }
+
+    /// Read a field of the hash with the name
+    ///
+    /// # Errors
+    /// If redis could not be reached
+    pub async fn read_hash_value(&self, name: &str, field: &str) -> anyhow::Result<Option<String>> {
+        let mut connection = self.pooled_connection().await?;
+        let value = connection.hget(self.key(name), field).await?;
+        self.pool.put(connection);
+
+        Ok(value)
+    }
+
+    /// Set a field of the hash with the name, unless the field is already set or the hash holds
+    /// the most fields it may
+    ///
+    /// # Returns
+    /// If the field was set
+    ///
+    /// # Errors
+    /// If redis could not be reached
+    pub async fn write_hash_value(
+        &self,
+        name: &str,
+        field: &str,
+        value: &str,
+        max_fields: usize,
+    ) -> anyhow::Result<bool> {
+        let mut connection = self.pooled_connection().await?;
+        let written = Script::new(WRITE_HASH_VALUE_SCRIPT)
+            .key(self.key(name))
+            .arg(field)
+            .arg(value)
+            .arg(max_fields)
+            .invoke_async(&mut connection)
+            .await?;
+        self.pool.put(connection);
+
+        Ok(written)
+    }
+
+    /// Take a token from each of the rate limited buckets, if all of them have one to spare
+    ///
+    /// The buckets are shared by every server, and named by the caller.
+    ///
+    /// # Returns
+    /// How long until all of the buckets have a token, or `None` if the tokens were taken
+    ///
+    /// # Errors
+    /// If redis could not be reached
+    pub async fn take_tokens(
+        &self,
+        buckets: &[(String, Rate)],
+    ) -> anyhow::Result<Option<Duration>> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+
+        let script = Script::new(TAKE_TOKENS_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation.arg(now);
+        for (name, rate) in buckets {
+            invocation
+                .key(self.key(&format!("rate_limit:{}", name)))
+                .arg(rate.burst)
+                .arg(rate.per_minute);
+        }
+
+        let mut connection = self.pooled_connection().await?;
+        let wait: Option<String> = invocation
+            .invoke_async(&mut connection)
+            .await
+            .context("Failed to take from the rate limits")?;
+        self.pool.put(connection);
+
+        wait.map(|wait| {
+            wait.parse()
+                .map(Duration::from_secs_f64)
+                .context("The wait time of the rate limits is invalid")
+        })
+        .transpose()
+    }
+
+    /// Release a claim before it expires, so that the other servers can claim the name again
+    ///
+    /// # Errors
+    /// If redis could not be reached
+    pub async fn release(&self, name: &str) -> anyhow::Result<()> {
+        self.remove_value(&format!("claim:{}", name)).await
+    }
+
+    /// Take an idle connection from the pool, or open a new one if there are none
+    ///
+    /// # Errors
+    /// If the redis server could not be reached
+    async fn pooled_connection(&self) -> anyhow::Result<Connection> {
+        match self.pool.take() {
+            Some(connection) => Ok(connection),
+            None => self.connect().await,
+        }
+    }
+
+    /// Run the commands pushed by servers through the discord api, reconnecting whenever the
+    /// connection to redis is lost
+    pub async fn run_commands(&self, http: HttpClient) {
+        loop {
+            if let Err(error) = self.take_commands(&http).await {
+                log::error!("Lost the connection to the command queue: {:#}", error);
+            }
+
+            task::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Run the commands pushed by servers until the connection to redis fails
+    async fn take_commands(&self, http: &HttpClient) -> anyhow::Result<()> {
+        let mut queue = self.connect().await?;
+        let replies = Arc::new(AsyncMutex::new(self.connect().await?));
+
+        loop {
+            let (_, command): (String, String) = queue.brpop(self.key("commands"), 0).await?;
+
+            let command: Command = match serde_json::from_str(&command) {
+                Ok(command) => command,
+                Err(error) => {
+                    log::error!("Received an invalid command: {}", error);
+                    continue;
+                }
+            };
+
+            // The server is no longer waiting, so the request would come as a surprise
+            if command.expires_at <= unix_timestamp() {
+                log::warn!("Dropping an expired command: {:?}", command.action);
+                continue;
+            }
+
+            let http = http.clone();
+            let replies = replies.clone();
+            task::spawn(async move {
+                let Command {
+                    reply_to, action, ..
+                } = command;
+
+                let outcome: CommandOutcome = action
+                    .run(&http)
+                    .await
+                    .map_err(|error| format!("{:#}", error));
+
+                if let Err(error) = Self::reply(&replies, &reply_to, &outcome).await {
+                    log::error!("Failed to send the outcome of a command: {:#}", error);
+                }
+            });
+        }
+    }
+
+    /// Push the outcome of a command to the server that is waiting for it
+    async fn reply(
+        connection: &AsyncMutex<Connection>,
+        reply_to: &str,
+        outcome: &CommandOutcome,
+    ) -> anyhow::Result<()> {
+        redis::pipe()
+            .lpush(reply_to, serde_json::to_string(outcome)?)
+            .ignore()
+            // Nobody reads the outcome if the server gave up on it, so do not keep it around
+            .expire(reply_to, COMMAND_TIMEOUT)
+            .ignore()
+            .query_async::<_, ()>(&mut *connection.lock().await)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// A data source that reads from the cache shared through redis by the gateway worker, and has
+/// the gateway worker make its updates
+#[derive(Debug, Clone)]
+pub struct RedisDataSource {
+    /// The copy of the shared cache, kept up to date in the background
+    mirror: Arc<RwLock<Mirror>>,
+    /// The redis server shared with the gateway worker
+    redis: RedisCache,
+}
+
+impl RedisDataSource {
+    /// Read the shared cache and keep following its changes in the background, reconnecting
+    /// whenever the connection to redis is lost
+    ///
+    /// # Errors
+    /// If the shared cache could not be read
+    pub async fn connect(redis: RedisCache) -> anyhow::Result<Self> {
+        let mirror = Arc::new(RwLock::new(Mirror::default()));
+        let subscription = redis.subscribe(&mirror).await?;
+
+        {
+            let mirror = mirror.clone();
+            let redis = redis.clone();
+            task::spawn(async move {
+                let mut subscription = Ok(subscription);
+
+                loop {
+                    match subscription {
+                        Ok((mut pubsub, mut connection)) => {
+                            if let Err(error) =
+                                redis.follow(&mut pubsub, &mut connection, &mirror).await
+                            {
+                                log::error!("Lost the connection to the redis cache: {:#}", error);
+                            }
+                        }
+                        Err(error) => {
+                            log::error!("Failed to reconnect to the redis cache: {:#}", error);
+                        }
+                    }
+
+                    task::sleep(RECONNECT_DELAY).await;
+                    subscription = redis.subscribe(&mirror).await;
+                }
+            });
+        }
+
+        Ok(Self { mirror, redis })
+    }
+
+    /// Lock the copy of the shared cache for reading
+    fn mirror(&self) -> RwLockReadGuard<'_, Mirror> {
+        self.mirror.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Find the first value that a lookup returns for any guild
+    fn find<T>(&self, lookup: impl FnMut(&MirroredGuild) -> Option<T>) -> Option<T> {
+        self.mirror().guilds.values().find_map(lookup)
+    }
+}
+
+#[rocket::async_trait]
+impl DataSource for RedisDataSource {
+    fn current_user(&self) -> Option<Arc<CurrentUser>> {
+        self.mirror().current_user.clone()
+    }
+
+    fn guild(&self, guild_id: GuildId) -> Option<Arc<CachedGuild>> {
+        self.mirror()
+            .guilds
+            .get(&guild_id)
+            .map(|guild| guild.guild.clone())
+    }
+
+    fn guild_channels(&self, guild_id: GuildId) -> Option<Vec<ChannelId>> {
+        self.mirror()
+            .guilds
+            .get(&guild_id)
+            .map(|guild| guild.channels.keys().copied().collect())
+    }
+
+    fn guild_channel(&self, channel_id: ChannelId) -> Option<Arc<GuildChannel>> {
+        self.find(|guild| guild.channels.get(&channel_id).cloned())
+    }
+
+    fn guild_members(&self, guild_id: GuildId) -> Option<Vec<UserId>> {
+        self.mirror()
+            .guilds
+            .get(&guild_id)
+            .map(|guild| guild.users.members.keys().copied().collect())
+    }
+
+    fn member(&self, guild_id: GuildId, user_id: UserId) -> Option<Arc<CachedMember>> {
+        self.mirror()
+            .guilds
+            .get(&guild_id)?
+            .users
+            .members
+            .get(&user_id)
+            .cloned()
+    }
+
+    fn guild_roles(&self, guild_id: GuildId) -> Option<Vec<RoleId>> {
+        self.mirror()
+            .guilds
+            .get(&guild_id)
+            .map(|guild| guild.roles.keys().copied().collect())
+    }
+
+    fn role(&self, role_id: RoleId) -> Option<Arc<Role>> {
+        self.find(|guild| guild.roles.get(&role_id).cloned())
+    }
+
+    fn presence(&self, guild_id: GuildId, user_id: UserId) -> Option<Arc<CachedPresence>> {
+        self.mirror()
+            .guilds
+            .get(&guild_id)?
+            .users
+            .presences
+            .get(&user_id)
+            .cloned()
+    }
+
+    fn user(&self, user_id: UserId) -> Option<Arc<User>> {
+        self.find(|guild| {
+            guild
+                .users
+                .members
+                .get(&user_id)
+                .map(|member| member.user.clone())
+        })
+    }
+
+    fn voice_state(&self, user_id: UserId, guild_id: GuildId) -> Option<Arc<VoiceState>> {
+        self.mirror()
+            .guilds
+            .get(&guild_id)?
+            .users
+            .voice_states
+            .get(&user_id)
+            .cloned()
+    }
+
+    fn voice_channel_states(&self, channel_id: ChannelId) -> Option<Vec<Arc<VoiceState>>> {
+        let states: Vec<_> = self.find(|guild| {
+            if guild.channels.contains_key(&channel_id) {
+                Some(
+                    guild
+                        .users
+                        .voice_states
+                        .values()
+                        .filter(|state| state.channel_id == Some(channel_id))
+                        .cloned()
+                        .collect(),
+                )
+            } else {
+                None
+            }
+        })?;
+
+        // Like the in memory cache, channels without anyone in them have no states
+        if states.is_empty() {
+            None
+        } else {
+            Some(states)
+        }
+    }
+
+    async fn update_member_mute(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        mute: bool,
+    ) -> anyhow::Result<()> {
+        self.redis.update_member_mute(guild_id, user_id, mute).await
+    }
+
+    async fn send_embed(&self, channel_id: ChannelId, embed: Embed) -> anyhow::Result<MessageId> {
+        self.redis.send_embed(channel_id, embed).await
+    }
+
+    async fn edit_embed(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        embed: Embed,
+    ) -> anyhow::Result<()> {
+        self.redis.edit_embed(channel_id, message_id, embed).await
+    }
+}
@@ -0,0 +1,78 @@
+//! Shared helpers for the stores that persist their data in a sqlite database
+
+use anyhow::Context;
+use async_std::task;
+use rusqlite::Connection;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+/// How long to wait for another process to finish writing to a database before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection to a sqlite database that can be shared between tasks
+#[derive(Debug, Clone)]
+pub struct Database {
+    /// The connection to the database
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    /// Open, and create if needed, the database at the path and run the schema on it
+    ///
+    /// The schema should only create tables and indices if they do not already exist.
+    ///
+    /// # Errors
+    /// If the database could not be opened or setup
+    pub fn open(path: impl AsRef<Path>, schema: &str) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open the database at {}", path.display()))?;
+
+        // The gateway worker and the api servers share databases, so wait for the writes of the
+        // other processes instead of failing right away
+        connection
+            .busy_timeout(BUSY_TIMEOUT)
+            .with_context(|| format!("Failed to setup the database at {}", path.display()))?;
+        connection
+            .execute_batch(schema)
+            .with_context(|| format!("Failed to setup the database at {}", path.display()))?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Run a closure with the connection on a thread where it is allowed to block
+    ///
+    /// # Errors
+    /// If the closure returns an error
+    pub async fn with_connection<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let connection = self.connection.clone();
+
+        task::spawn_blocking(move || f(&connection.lock().unwrap_or_else(PoisonError::into_inner)))
+            .await
+            .context("Failed to query the database")
+    }
+}
+
+/// Convert an unsigned integer to be stored in sqlite, which only has signed integers
+///
+/// Snowflakes and timestamps both fit within 63 bits so they can be stored as is
+#[allow(clippy::cast_possible_wrap)]
+#[must_use]
+pub fn to_sql_integer(value: u64) -> i64 {
+    value as i64
+}
+
+/// Convert an integer stored by `to_sql_integer` back into an unsigned integer
+#[allow(clippy::cast_sign_loss)]
+#[must_use]
+pub fn from_sql_integer(value: i64) -> u64 {
+    value as u64
+}
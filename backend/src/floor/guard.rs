@@ -0,0 +1,80 @@
+//! Keeping members who join a voice channel in speaker mode from talking
+
+use super::Floors;
+use crate::data_source::SharedDataSource;
+use async_std::task;
+use twilight_model::{
+    id::{ChannelId, GuildId, UserId},
+    voice::VoiceState,
+};
+
+/// Mutes the members who join a voice channel in speaker mode without holding its floor, as
+/// giving the floor only mutes the members who are in the channel at the time
+#[derive(Debug, Clone)]
+pub struct FloorGuard {
+    /// Source of the users, and what mutes them
+    data: SharedDataSource,
+    /// The floors of the voice channels in speaker mode
+    floors: Floors,
+}
+
+impl FloorGuard {
+    /// Create a guard for the voice channels in speaker mode in the store
+    #[must_use]
+    pub fn new(data: SharedDataSource, floors: Floors) -> Self {
+        Self { data, floors }
+    }
+
+    /// Check a voice state received from the gateway in the background, muting the member if
+    /// they are in a voice channel in speaker mode without holding its floor
+    pub fn voice_state_updated(&self, state: &VoiceState) {
+        let (guild_id, channel_id) = match (state.guild_id, state.channel_id) {
+            (Some(guild_id), Some(channel_id)) if !state.mute => (guild_id, channel_id),
+            _ => return,
+        };
+        let user_id = state.user_id;
+        let guard = self.clone();
+
+        task::spawn(async move {
+            if let Err(error) = guard.enforce(guild_id, channel_id, user_id).await {
+                log::error!(
+                    "Failed to mute {} for joining channel {} in speaker mode: {:#}",
+                    user_id,
+                    channel_id,
+                    error
+                );
+            }
+        });
+    }
+
+    /// Mute an unmuted member of a voice channel if the channel is in speaker mode and the
+    /// member does not hold its floor
+    ///
+    /// # Errors
+    /// If the floor store fails, or the member could not be muted
+    async fn enforce(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> anyhow::Result<()> {
+        let floor = match self.floors.floor(channel_id).await? {
+            Some(floor) => floor,
+            None => return Ok(()),
+        };
+
+        // Bots are left alone, like they are when the floor is given
+        let is_bot = self.data.user(user_id).map_or(false, |user| user.bot);
+        if floor.speakers.contains(&user_id) || is_bot {
+            return Ok(());
+        }
+
+        log::info!(
+            "Muting {} for joining channel {} in speaker mode",
+            user_id,
+            channel_id
+        );
+
+        self.data.update_member_mute(guild_id, user_id, true).await
+    }
+}
@@ -0,0 +1,88 @@
+//! A floor store that keeps floors in memory
+
+use super::{Floor, FloorStore, QueueEntry};
+use std::{
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+};
+use twilight_model::id::{ChannelId, UserId};
+
+/// A floor store that keeps floors and speaking queues in memory, losing them when the server
+/// restarts
+#[derive(Debug, Default)]
+pub struct MemoryFloorStore {
+    /// The floors of the voice channels in speaker mode
+    floors: RwLock<HashMap<ChannelId, Floor>>,
+    /// The speaking queues of voice channels, in the order that the members joined them
+    queues: RwLock<HashMap<ChannelId, Vec<QueueEntry>>>,
+}
+
+#[rocket::async_trait]
+impl FloorStore for MemoryFloorStore {
+    async fn floor(&self, channel_id: ChannelId) -> anyhow::Result<Option<Floor>> {
+        Ok(self
+            .floors
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&channel_id)
+            .cloned())
+    }
+
+    async fn set_floor(&self, floor: Floor) -> anyhow::Result<()> {
+        self.floors
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(floor.channel_id, floor);
+
+        Ok(())
+    }
+
+    async fn remove_floor(&self, channel_id: ChannelId) -> anyhow::Result<Option<Floor>> {
+        Ok(self
+            .floors
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&channel_id))
+    }
+
+    async fn queue(&self, channel_id: ChannelId) -> anyhow::Result<Vec<QueueEntry>> {
+        Ok(self
+            .queues
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&channel_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn join_queue(&self, entry: QueueEntry) -> anyhow::Result<bool> {
+        let mut queues = self.queues.write().unwrap_or_else(PoisonError::into_inner);
+        let queue = queues.entry(entry.channel_id).or_default();
+
+        if queue.iter().any(|queued| queued.user_id == entry.user_id) {
+            Ok(false)
+        } else {
+            queue.push(entry);
+            Ok(true)
+        }
+    }
+
+    async fn leave_queue(&self, channel_id: ChannelId, user_id: UserId) -> anyhow::Result<bool> {
+        let mut queues = self.queues.write().unwrap_or_else(PoisonError::into_inner);
+
+        let queue = match queues.get_mut(&channel_id) {
+            Some(queue) => queue,
+            None => return Ok(false),
+        };
+
+        let length = queue.len();
+        queue.retain(|queued| queued.user_id != user_id);
+        let removed = queue.len() != length;
+
+        if queue.is_empty() {
+            queues.remove(&channel_id);
+        }
+
+        Ok(removed)
+    }
+}
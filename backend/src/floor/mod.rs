@@ -0,0 +1,102 @@
+//! Speaker mode, where everyone in a voice channel is muted except the members given the floor
+//!
+//! The members holding the floor of a channel are kept as a [`Floor`] in a [`FloorStore`], so
+//! that the floor can be passed on, by unmuting the new speakers and muting the old ones, and
+//! handed back to everyone at the end. Members waiting for their turn to speak are kept in the
+//! same store as the [`QueueEntry`]s of the channel's speaking queue. Members who join a channel
+//! after its floor was given are muted by the [`FloorGuard`].
+
+use crate::config::Config;
+use anyhow::bail;
+use std::{fmt::Debug, sync::Arc};
+use twilight_model::id::{ChannelId, GuildId, UserId};
+
+pub mod guard;
+pub mod memory;
+pub mod sqlite;
+
+pub use guard::FloorGuard;
+pub use memory::MemoryFloorStore;
+pub use sqlite::SqliteFloorStore;
+
+/// A shared handle to the floor store used by the server
+pub type Floors = Arc<dyn FloorStore>;
+
+/// The members who are allowed to talk in a voice channel in speaker mode
+#[derive(Debug, Clone)]
+pub struct Floor {
+    /// The voice channel in speaker mode
+    pub channel_id: ChannelId,
+    /// The guild of the voice channel
+    pub guild_id: GuildId,
+    /// The ids of the members who hold the floor
+    pub speakers: Vec<UserId>,
+    /// The id of the user who last gave the floor
+    pub given_by: UserId,
+    /// The seconds since the unix epoch that the floor was last given
+    pub given_at: u64,
+}
+
+/// A member waiting in the speaking queue of a voice channel
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    /// The voice channel of the queue
+    pub channel_id: ChannelId,
+    /// The guild of the voice channel
+    pub guild_id: GuildId,
+    /// The id of the member waiting to speak
+    pub user_id: UserId,
+    /// The seconds since the unix epoch that the member joined the queue
+    pub joined_at: u64,
+}
+
+/// Storage for the floors of the voice channels in speaker mode, and their speaking queues
+#[rocket::async_trait]
+pub trait FloorStore: Debug + Send + Sync {
+    /// Get the floor of a voice channel, if it is in speaker mode
+    async fn floor(&self, channel_id: ChannelId) -> anyhow::Result<Option<Floor>>;
+
+    /// Save the floor of a voice channel, replacing any earlier floor
+    async fn set_floor(&self, floor: Floor) -> anyhow::Result<()>;
+
+    /// Remove and return the floor of a voice channel, if it was in speaker mode
+    async fn remove_floor(&self, channel_id: ChannelId) -> anyhow::Result<Option<Floor>>;
+
+    /// Get the speaking queue of a voice channel, in the order that the members joined it
+    async fn queue(&self, channel_id: ChannelId) -> anyhow::Result<Vec<QueueEntry>>;
+
+    /// Add a member to the end of the speaking queue of a voice channel, returning false if
+    /// they were already in it
+    async fn join_queue(&self, entry: QueueEntry) -> anyhow::Result<bool>;
+
+    /// Remove a member from the speaking queue of a voice channel, returning if they were in it
+    async fn leave_queue(&self, channel_id: ChannelId, user_id: UserId) -> anyhow::Result<bool>;
+}
+
+/// Create the floor store described by the config
+///
+/// Floors are stored in the sqlite database at `floor_database` if one is configured, otherwise
+/// they are kept in memory and lost on restart.
+///
+/// # Errors
+/// If the sqlite database could not be opened
+pub fn create_floor_store(config: &Config) -> anyhow::Result<Floors> {
+    Ok(match &config.floor_database {
+        Some(path) => Arc::new(SqliteFloorStore::open(path)?),
+        None => Arc::new(MemoryFloorStore::default()),
+    })
+}
+
+/// Create the floor store of a gateway worker or an api server, which have to share their floors
+/// so that the worker keeps members who join a channel in speaker mode muted
+///
+/// # Errors
+/// If `floor_database` is not set, as floors kept in memory are only seen by one process, or the
+/// sqlite database could not be opened
+pub fn create_shared_floor_store(config: &Config) -> anyhow::Result<Floors> {
+    if config.floor_database.is_none() {
+        bail!("FLOOR_DATABASE has to be set for the gateway worker and the api servers to share the floors of voice channels");
+    }
+
+    create_floor_store(config)
+}
@@ -0,0 +1,191 @@
+//! A floor store that persists floors in a sqlite database
+
+use super::{Floor, FloorStore, QueueEntry};
+use crate::database::{from_sql_integer, to_sql_integer, Database};
+use rusqlite::{params, OptionalExtension, Row};
+use std::path::Path;
+use twilight_model::id::{ChannelId, GuildId, UserId};
+
+/// The statements to run to setup the database
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS floors (
+    channel_id INTEGER PRIMARY KEY NOT NULL,
+    guild_id INTEGER NOT NULL,
+    speakers TEXT NOT NULL,
+    given_by INTEGER NOT NULL,
+    given_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS speaking_queue (
+    channel_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    guild_id INTEGER NOT NULL,
+    joined_at INTEGER NOT NULL,
+    PRIMARY KEY (channel_id, user_id)
+);
+";
+
+/// The columns of the floors table, in the order that `floor_from_row` expects them
+const COLUMNS: &str = "channel_id, guild_id, speakers, given_by, given_at";
+
+/// The columns of the speaking queue table, in the order that `entry_from_row` expects them
+const QUEUE_COLUMNS: &str = "channel_id, user_id, guild_id, joined_at";
+
+/// A floor store that persists floors and speaking queues in a sqlite database, surviving
+/// restarts
+#[derive(Debug, Clone)]
+pub struct SqliteFloorStore {
+    /// The floor database
+    database: Database,
+}
+
+impl SqliteFloorStore {
+    /// Open, and create if needed, the floor database at the path
+    ///
+    /// # Errors
+    /// If the database could not be opened or setup
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            database: Database::open(path, SCHEMA)?,
+        })
+    }
+}
+
+/// Read a floor from a row made up of `COLUMNS`
+///
+/// Speakers are stored as a comma separated list.
+fn floor_from_row(row: &Row<'_>) -> rusqlite::Result<Floor> {
+    let speakers: String = row.get(2)?;
+
+    Ok(Floor {
+        channel_id: ChannelId(from_sql_integer(row.get(0)?)),
+        guild_id: GuildId(from_sql_integer(row.get(1)?)),
+        speakers: speakers
+            .split(',')
+            .filter_map(|id| id.parse().ok().map(UserId))
+            .collect(),
+        given_by: UserId(from_sql_integer(row.get(3)?)),
+        given_at: from_sql_integer(row.get(4)?),
+    })
+}
+
+/// Read a queue entry from a row made up of `QUEUE_COLUMNS`
+fn entry_from_row(row: &Row<'_>) -> rusqlite::Result<QueueEntry> {
+    Ok(QueueEntry {
+        channel_id: ChannelId(from_sql_integer(row.get(0)?)),
+        user_id: UserId(from_sql_integer(row.get(1)?)),
+        guild_id: GuildId(from_sql_integer(row.get(2)?)),
+        joined_at: from_sql_integer(row.get(3)?),
+    })
+}
+
+#[rocket::async_trait]
+impl FloorStore for SqliteFloorStore {
+    async fn floor(&self, channel_id: ChannelId) -> anyhow::Result<Option<Floor>> {
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .query_row(
+                        &format!("SELECT {} FROM floors WHERE channel_id = ?1", COLUMNS),
+                        params![to_sql_integer(channel_id.0)],
+                        floor_from_row,
+                    )
+                    .optional()
+            })
+            .await
+    }
+
+    async fn set_floor(&self, floor: Floor) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                let speakers = floor
+                    .speakers
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                connection.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO floors ({}) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        COLUMNS
+                    ),
+                    params![
+                        to_sql_integer(floor.channel_id.0),
+                        to_sql_integer(floor.guild_id.0),
+                        speakers,
+                        to_sql_integer(floor.given_by.0),
+                        to_sql_integer(floor.given_at),
+                    ],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn remove_floor(&self, channel_id: ChannelId) -> anyhow::Result<Option<Floor>> {
+        self.database
+            .with_connection(move |connection| {
+                let floor = connection
+                    .query_row(
+                        &format!("SELECT {} FROM floors WHERE channel_id = ?1", COLUMNS),
+                        params![to_sql_integer(channel_id.0)],
+                        floor_from_row,
+                    )
+                    .optional()?;
+
+                connection.execute(
+                    "DELETE FROM floors WHERE channel_id = ?1",
+                    params![to_sql_integer(channel_id.0)],
+                )?;
+
+                Ok(floor)
+            })
+            .await
+    }
+
+    async fn queue(&self, channel_id: ChannelId) -> anyhow::Result<Vec<QueueEntry>> {
+        self.database
+            .with_connection(move |connection| {
+                // Members who joined in the same second keep the order they were inserted in
+                connection
+                    .prepare(&format!(
+                        "SELECT {} FROM speaking_queue WHERE channel_id = ?1 ORDER BY joined_at, rowid",
+                        QUEUE_COLUMNS
+                    ))?
+                    .query_map(params![to_sql_integer(channel_id.0)], entry_from_row)?
+                    .collect()
+            })
+            .await
+    }
+
+    async fn join_queue(&self, entry: QueueEntry) -> anyhow::Result<bool> {
+        self.database
+            .with_connection(move |connection| {
+                Ok(connection.execute(
+                    &format!(
+                        "INSERT OR IGNORE INTO speaking_queue ({}) VALUES (?1, ?2, ?3, ?4)",
+                        QUEUE_COLUMNS
+                    ),
+                    params![
+                        to_sql_integer(entry.channel_id.0),
+                        to_sql_integer(entry.user_id.0),
+                        to_sql_integer(entry.guild_id.0),
+                        to_sql_integer(entry.joined_at),
+                    ],
+                )? > 0)
+            })
+            .await
+    }
+
+    async fn leave_queue(&self, channel_id: ChannelId, user_id: UserId) -> anyhow::Result<bool> {
+        self.database
+            .with_connection(move |connection| {
+                Ok(connection.execute(
+                    "DELETE FROM speaking_queue WHERE channel_id = ?1 AND user_id = ?2",
+                    params![to_sql_integer(channel_id.0), to_sql_integer(user_id.0)],
+                )? > 0)
+            })
+            .await
+    }
+}
@@ -1,57 +1,392 @@
 //! The definitions for the graphql api
 
 use anyhow::Context as _;
+use chrono_tz::Tz;
 use futures::future::join_all;
 use juniper::{
-    graphql_object, graphql_value, Context, EmptySubscription, FieldError, FieldResult, RootNode,
-    Value,
+    graphql_object, graphql_value, Context, EmptySubscription, FieldError, FieldResult,
+    GraphQLEnum, GraphQLInputObject, GraphQLObject, RootNode, Value,
 };
+use serde_json::json;
 use std::{
-    collections::HashSet,
+    cmp::Reverse,
     convert::{TryFrom, TryInto},
     fmt::Debug,
     iter,
     ops::Deref,
     sync::{mpsc, Arc},
 };
-use twilight_cache_inmemory::{model::CachedGuild, model::CachedMember, InMemoryCache};
-use twilight_gateway::Shard;
-use twilight_http::Client as HttpClient;
+use twilight_cache_inmemory::model::{CachedGuild, CachedMember, CachedPresence};
 use twilight_model::{
     channel::{self, GuildChannel},
-    guild::Permissions,
+    gateway::presence::{self, ActivityType, Status},
+    guild::{self, Permissions},
     id::{ChannelId, GuildId, RoleId, UserId},
-    user,
+    user::{self, CurrentUserGuild},
     voice::VoiceState,
 };
 use twilight_oauth2::Client as OauthClient;
 use twilight_permission_calculator::Calculator;
 
-use crate::{auth::OauthUser, consts::REQUIRED_PERMISSIONS};
+use crate::{
+    api_token::{ApiToken, ApiTokens, TokenAction, MAX_USER_TOKENS},
+    auth::{OauthUser, Viewer},
+    cdn::{self, ImageFormat},
+    consts::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE, REQUIRED_PERMISSIONS},
+    data_source::{DataSource, SharedDataSource},
+    floor::{Floor, Floors, QueueEntry},
+    guild_cache::UserGuildCache,
+    invite,
+    loader::{Loader, Memo},
+    notification::{MuteNotifier, NotificationSettings, NotificationTarget},
+    rate_limit::RateLimiter,
+    schedule::{Recurrence, Schedule, ScheduleAction, Scheduler, MAX_GUILD_SCHEDULES},
+    session::{unix_timestamp, Session, Sessions},
+    webhook::{Webhook, WebhookDelivery, WebhookEvent, WebhookSender, MAX_GUILD_WEBHOOKS},
+};
 
 /// The juniper context to provide access to the user and discord api
 #[derive(Debug)]
 pub struct GraphQLContext {
     /// The nested discord context
     pub discord: DiscordContext,
-    /// The user who is authenticated with oauth
-    pub user: OauthUser,
+    /// The store of the sessions of all users
+    pub sessions: Sessions,
+    /// The store of the api tokens of all users
+    pub api_tokens: ApiTokens,
+    /// The webhooks of all guilds, and what sends events to them
+    pub webhooks: WebhookSender,
+    /// The mute notification settings of all guilds, and what posts the notifications
+    pub notifications: MuteNotifier,
+    /// The floors of the voice channels in speaker mode
+    pub floors: Floors,
+    /// The scheduler of the scheduled mutes
+    pub schedules: Scheduler,
+    /// The user who is making the request
+    pub user: Viewer,
+    /// The loaders used to deduplicate lookups made while resolving the request
+    loaders: Loaders,
 }
 impl Context for GraphQLContext {}
 
+/// The per request loaders for the values that the resolvers look up
+#[derive(Debug, Default)]
+struct Loaders {
+    /// Guild members, keyed by their guild and user id
+    members: Loader<(GuildId, UserId), Arc<CachedMember>>,
+    /// Guild roles
+    roles: Loader<RoleId, Arc<guild::Role>>,
+    /// Guild channels
+    channels: Loader<ChannelId, Arc<GuildChannel>>,
+    /// The guilds that the oauth user is in
+    user_guilds: Memo<Arc<Vec<CurrentUserGuild>>>,
+    /// The oauth user
+    current_user: Memo<Arc<user::CurrentUser>>,
+}
+
+impl GraphQLContext {
+    /// Create the context for a single request
+    #[must_use]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        discord: DiscordContext,
+        sessions: Sessions,
+        api_tokens: ApiTokens,
+        webhooks: WebhookSender,
+        notifications: MuteNotifier,
+        floors: Floors,
+        schedules: Scheduler,
+        user: Viewer,
+    ) -> Self {
+        Self {
+            discord,
+            sessions,
+            api_tokens,
+            webhooks,
+            notifications,
+            floors,
+            schedules,
+            user,
+            loaders: Loaders::default(),
+        }
+    }
+
+    /// Get the user who is logged in through oauth
+    ///
+    /// # Errors
+    /// If the request was made with an api token instead
+    pub fn oauth_user(&self) -> FieldResult<&OauthUser> {
+        match &self.user {
+            Viewer::Oauth(user) => Ok(user),
+            Viewer::ApiToken(_) => Err(FieldError::new(
+                "This can only be done while logged in through the browser, not with an api token",
+                Value::null(),
+            )),
+        }
+    }
+
+    /// Check that the user is allowed to perform the action in the guild
+    ///
+    /// Users logged in through oauth can do anything that their discord permissions allow,
+    /// while api tokens are limited to the guilds and actions they were created for.
+    ///
+    /// # Errors
+    /// If the request was made with an api token that does not allow the action
+    pub fn authorize(&self, guild_id: GuildId, action: TokenAction) -> FieldResult<()> {
+        match &self.user {
+            Viewer::ApiToken(user) if !user.token.allows(guild_id, action) => Err(FieldError::new(
+                "Permission denied: the api token is not allowed to perform that action",
+                graphql_value!({ "action": (action.as_str()) }),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the oauth user can manage the guild, by owning it or having the administrator
+    /// or manage guild permission
+    ///
+    /// This reads the user's cached guild list, which can be out of date for a few minutes, so
+    /// it is only used to show the settings of a guild. Changes to them are checked with
+    /// [`authorize_management_change`](Self::authorize_management_change) instead.
+    ///
+    /// # Errors
+    /// If the request was made with an api token, the user can not manage the guild, or the
+    /// user's guilds were not cached and the request to discord fails
+    pub async fn authorize_management(&self, guild_id: GuildId) -> FieldResult<()> {
+        let manageable = self.user_guilds().await?.iter().any(|guild| {
+            guild.id == guild_id
+                && (guild.owner
+                    || guild
+                        .permissions
+                        .intersects(Permissions::ADMINISTRATOR | Permissions::MANAGE_GUILD))
+        });
+
+        if manageable {
+            Ok(())
+        } else {
+            Err(FieldError::new(
+                "Permission denied: managing the guild requires the administrator or manage guild permission",
+                Value::null(),
+            ))
+        }
+    }
+
+    /// Check that the oauth user can still manage the guild before changing its settings, from
+    /// the member and roles kept up to date by the gateway
+    ///
+    /// # Errors
+    /// If the request was made with an api token, or the user can not manage the guild
+    pub fn authorize_management_change(&self, guild_id: GuildId) -> FieldResult<()> {
+        let user_id = self.oauth_user()?.session.user_id;
+
+        authorize_guild_manager(self.discord.data.as_ref(), guild_id, user_id).map(drop)
+    }
+
+    /// Get a webhook of the guild by its id, after checking that the user can manage the guild
+    ///
+    /// # Errors
+    /// If the user can not manage the guild, or the guild has no webhook with the id
+    pub async fn guild_webhook(&self, guild_id: GuildId, id: &str) -> FieldResult<Webhook> {
+        self.authorize_management_change(guild_id)?;
+
+        Ok(self
+            .webhooks
+            .store()
+            .guild_webhooks(guild_id)
+            .await?
+            .into_iter()
+            .find(|webhook| webhook.id == id)
+            .context("Webhook does not exist on the guild")?)
+    }
+
+    /// Take a token from the rate limits of the user, and of the guild if the mutation acts on one
+    ///
+    /// # Errors
+    /// If the user or the guild has made too many mutations recently, or the rate limits shared
+    /// through redis could not be reached
+    pub async fn rate_limit(&self, guild_id: Option<GuildId>) -> FieldResult<()> {
+        self.discord
+            .rate_limiter
+            .check(self.user.user_id(), guild_id)
+            .await
+    }
+
+    /// Lookup a member of a guild in the cache
+    pub fn member(&self, guild_id: GuildId, user_id: UserId) -> Option<Arc<CachedMember>> {
+        self.loaders
+            .members
+            .load((guild_id, user_id), |(guild_id, user_id)| {
+                self.discord.data.member(guild_id, user_id)
+            })
+    }
+
+    /// Lookup many members of a guild in the cache at once, skipping any that are not cached
+    pub fn members(
+        &self,
+        guild_id: GuildId,
+        user_ids: impl IntoIterator<Item = UserId>,
+    ) -> Vec<Arc<CachedMember>> {
+        self.loaders
+            .members
+            .load_many(
+                user_ids.into_iter().map(|user_id| (guild_id, user_id)),
+                |keys| {
+                    keys.iter()
+                        .filter_map(|&(guild_id, user_id)| {
+                            self.discord
+                                .data
+                                .member(guild_id, user_id)
+                                .map(|member| ((guild_id, user_id), member))
+                        })
+                        .collect()
+                },
+            )
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// Server mute or unmute everyone in a voice channel, giving the speakers the opposite state,
+    /// and forget the members loaded so far so the rest of the request sees the change
+    ///
+    /// # Returns
+    /// Id's of users whose mute status was changed
+    ///
+    /// # Errors
+    /// If the voice states of the channel could not be read
+    pub async fn update_voice_states(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        mute: bool,
+        speakers: &[UserId],
+    ) -> FieldResult<Vec<UserId>> {
+        let changed = mass_update_voice_state(
+            self.discord.data.as_ref(),
+            channel_id,
+            guild_id,
+            mute,
+            speakers,
+        )
+        .await;
+        self.loaders.members.clear();
+
+        changed
+    }
+
+    /// Lookup a role in the cache
+    pub fn role(&self, role_id: RoleId) -> Option<Arc<guild::Role>> {
+        self.loaders
+            .roles
+            .load(role_id, |role_id| self.discord.data.role(role_id))
+    }
+
+    /// Lookup a guild channel in the cache
+    pub fn guild_channel(&self, channel_id: ChannelId) -> Option<Arc<GuildChannel>> {
+        self.loaders.channels.load(channel_id, |channel_id| {
+            self.discord.data.guild_channel(channel_id)
+        })
+    }
+
+    /// Get the guilds that the oauth user is in from the user guild cache, only looking them up
+    /// once per request
+    ///
+    /// # Errors
+    /// If the request was made with an api token instead, or the guilds were not cached and the
+    /// request to discord fails
+    pub async fn user_guilds(&self) -> FieldResult<Arc<Vec<CurrentUserGuild>>> {
+        let user = self.oauth_user()?;
+
+        Ok(self
+            .loaders
+            .user_guilds
+            .get_or_try_load(|| self.discord.user_guilds.get(user))
+            .await?)
+    }
+
+    /// Get the guilds that both the user and the bot are in
+    ///
+    /// The bot's guilds come from the gateway cache, so only the user's guilds need to be
+    /// looked up. Api tokens only see the guilds that they can read.
+    ///
+    /// # Errors
+    /// If the user's guilds were not cached and the request to discord fails
+    pub async fn shared_guilds(&self) -> FieldResult<Vec<Guild>> {
+        let guild_ids: Vec<GuildId> = match &self.user {
+            Viewer::Oauth(_) => self
+                .user_guilds()
+                .await?
+                .iter()
+                .map(|guild| guild.id)
+                .collect(),
+            Viewer::ApiToken(user) => user
+                .token
+                .guilds
+                .iter()
+                .copied()
+                .filter(|&guild_id| user.token.allows(guild_id, TokenAction::Read))
+                .collect(),
+        };
+
+        Ok(guild_ids
+            .into_iter()
+            .filter_map(|guild_id| self.discord.data.guild(guild_id).map(Guild::from))
+            .collect())
+    }
+
+    /// Get the guilds that the oauth user can manage, but the bot is not in
+    ///
+    /// # Errors
+    /// If the request was made with an api token, or the user's guilds were not cached and the
+    /// request to discord fails
+    pub async fn manageable_guilds(&self) -> FieldResult<Vec<ManageableGuild>> {
+        Ok(self
+            .user_guilds()
+            .await?
+            .iter()
+            .filter(|guild| {
+                guild.owner
+                    || guild
+                        .permissions
+                        .intersects(Permissions::ADMINISTRATOR | Permissions::MANAGE_GUILD)
+            })
+            .filter(|guild| self.discord.data.guild(guild.id).is_none())
+            .map(|guild| ManageableGuild {
+                guild: guild.clone(),
+                invite_url: invite::invite_url(&self.discord.oauth, Some(guild.id)),
+            })
+            .collect())
+    }
+
+    /// Get information on the oauth user, only asking discord once per request
+    ///
+    /// # Errors
+    /// If the request was made with an api token or the request to discord fails
+    pub async fn current_user(&self) -> FieldResult<Arc<user::CurrentUser>> {
+        let user = self.oauth_user()?;
+
+        Ok(self
+            .loaders
+            .current_user
+            .get_or_try_load(|| async { user.http.current_user().await.map(Arc::new) })
+            .await
+            .context("Unable to get information on the current oauth user")?)
+    }
+}
+
 #[derive(Debug, Clone)]
 /// The juniper context to provide access to the discord api and bot
 ///
 /// This context derives clone since it is just 4 pointers, it can be cloned rather effortlessly
 pub struct DiscordContext {
-    /// The discord cache connected to the gateway
-    pub cache: InMemoryCache,
-    /// The shard, connected to the gateway
-    pub shard: Shard,
-    /// The discord http client for rest calls
-    pub http: HttpClient,
+    /// The source of guilds, channels, members and voice states, and of member updates
+    pub data: SharedDataSource,
     /// The discord oauth client for authentication
     pub oauth: Arc<OauthClient>,
+    /// The cache of the guilds that each oauth user is in
+    pub user_guilds: Arc<UserGuildCache>,
+    /// The rate limiter for mutations
+    pub rate_limiter: Arc<RateLimiter>,
 }
 
 /// A macro to create transparent wrappers of non graphql types for use with juniper
@@ -140,6 +475,28 @@ transparent_wrapper! {
     pub struct Member(Arc<CachedMember>);
     /// A current user, either the bot or an oauth user.
     pub struct CurrentUser(Arc<user::CurrentUser>);
+    /// A discord guild role.
+    pub struct Role(Arc<guild::Role>);
+    /// The presence of a guild member.
+    pub struct Presence(Arc<CachedPresence>);
+    /// An activity that a user is partaking in.
+    pub struct Activity(presence::Activity);
+    /// A login session of the oauth user.
+    pub struct UserSession(Session);
+    /// A personal api token of the oauth user.
+    pub struct UserApiToken(ApiToken);
+    /// A webhook that events in a guild are sent to.
+    pub struct GuildWebhook(Webhook);
+    /// An event sent to a webhook.
+    pub struct GuildWebhookDelivery(WebhookDelivery);
+    /// Where a guild has messages about its mutes posted.
+    pub struct GuildNotificationSettings(NotificationSettings);
+    /// The members allowed to talk in a voice channel in speaker mode.
+    pub struct VoiceChannelFloor(Floor);
+    /// A member waiting in the speaking queue of a voice channel.
+    pub struct QueuedSpeaker(QueueEntry);
+    /// A mute or unmute of a voice channel that runs at set times.
+    pub struct ChannelSchedule(Schedule);
 }
 
 // Create the wrapper types around enum variants
@@ -152,8 +509,283 @@ transparent_wrapper! {
     use enum type GuildChannel::Voice(channel::VoiceChannel) else "Channel is not a voice channel";
 }
 
+/// A macro to create relay style connections for paginating over lists of nodes
+macro_rules! connection {
+    (
+        $(
+            $(#[$outer:meta])*
+            pub struct $connection:ident($edge:ident<$node:ty>);
+        )*
+    ) => {
+        $(
+            #[derive(Clone, Debug)]
+            $(#[$outer])*
+            pub struct $connection {
+                edges: Vec<$edge>,
+                page_info: PageInfo,
+                total_count: usize,
+            }
+
+            /// An edge in a connection, pairing a node with its cursor.
+            #[derive(Clone, Debug)]
+            pub struct $edge {
+                cursor: String,
+                node: $node,
+            }
+
+            impl $connection {
+                /// Create a page of the connection from all of the nodes in it
+                fn new(
+                    nodes: Vec<$node>,
+                    cursor: impl Fn(&$node) -> u64,
+                    first: Option<i32>,
+                    after: Option<String>,
+                ) -> FieldResult<Self> {
+                    let (edges, page_info, total_count) = paginate(nodes, cursor, first, after)?;
+
+                    Ok(Self {
+                        edges: edges
+                            .into_iter()
+                            .map(|(cursor, node)| $edge { cursor, node })
+                            .collect(),
+                        page_info,
+                        total_count,
+                    })
+                }
+            }
+
+            #[graphql_object(Context = GraphQLContext)]
+            impl $connection {
+                /// Edges in the current page.
+                fn edges(&self) -> Vec<$edge> {
+                    self.edges.clone()
+                }
+
+                /// Nodes in the current page, without their cursors.
+                fn nodes(&self) -> Vec<$node> {
+                    self.edges.iter().map(|edge| edge.node.clone()).collect()
+                }
+
+                /// Information to aid in pagination.
+                fn page_info(&self) -> PageInfo {
+                    self.page_info.clone()
+                }
+
+                /// Total amount of nodes across all pages.
+                fn total_count(&self) -> FieldResult<i32> {
+                    Ok(self.total_count.try_into()?)
+                }
+            }
+
+            #[graphql_object(Context = GraphQLContext)]
+            impl $edge {
+                /// Opaque cursor pointing to this edge, for use with the `after` argument.
+                fn cursor(&self) -> &str {
+                    self.cursor.as_str()
+                }
+
+                /// Node at the end of the edge.
+                fn node(&self) -> $node {
+                    self.node.clone()
+                }
+            }
+        )*
+    };
+}
+
+/// Information about the current page of a connection.
+#[derive(GraphQLObject, Clone, Debug)]
+pub struct PageInfo {
+    /// If there are more nodes after this page.
+    has_next_page: bool,
+    /// If there are nodes before this page.
+    has_previous_page: bool,
+    /// Cursor of the first edge in this page.
+    start_cursor: Option<String>,
+    /// Cursor of the last edge in this page.
+    end_cursor: Option<String>,
+}
+
+/// Sort the nodes by their cursor and take the requested page from them
+///
+/// Returns the nodes in the page along with their cursors, the page info and the total amount
+/// of nodes
+fn paginate<T>(
+    mut nodes: Vec<T>,
+    cursor: impl Fn(&T) -> u64,
+    first: Option<i32>,
+    after: Option<String>,
+) -> FieldResult<(Vec<(String, T)>, PageInfo, usize)> {
+    let first = first
+        .map_or(Ok(DEFAULT_PAGE_SIZE), usize::try_from)
+        .context("Page size can not be negative")?;
+
+    if first > MAX_PAGE_SIZE {
+        return Err(FieldError::new(
+            format!(
+                "Requested page is too large, at most {} nodes can be requested at once",
+                MAX_PAGE_SIZE
+            ),
+            Value::null(),
+        ));
+    }
+
+    let after = after
+        .map(|after| after.parse::<u64>())
+        .transpose()
+        .context("Invalid cursor")?;
+
+    nodes.sort_by_key(&cursor);
+
+    let total_count = nodes.len();
+    let start = after.map_or(0, |after| {
+        nodes
+            .iter()
+            .position(|node| cursor(node) > after)
+            .unwrap_or(total_count)
+    });
+    let has_next_page = total_count - start > first;
+
+    let edges: Vec<_> = nodes
+        .into_iter()
+        .skip(start)
+        .take(first)
+        .map(|node| (cursor(&node).to_string(), node))
+        .collect();
+
+    let page_info = PageInfo {
+        has_next_page,
+        has_previous_page: start > 0,
+        start_cursor: edges.first().map(|(cursor, _)| cursor.clone()),
+        end_cursor: edges.last().map(|(cursor, _)| cursor.clone()),
+    };
+
+    Ok((edges, page_info, total_count))
+}
+
+// Create the connection types
+connection! {
+    /// A paginated list of guild members, ordered by their ids.
+    pub struct MemberConnection(MemberEdge<Member>);
+    /// A paginated list of voice states, ordered by the ids of their members.
+    pub struct VoiceChannelStateConnection(VoiceChannelStateEdge<VoiceChannelState>);
+}
+
+/// Filters to narrow down a list of members, all given filters must match.
+#[derive(GraphQLInputObject, Debug, Default)]
+pub struct MemberFilter {
+    /// Only include members with the role with this id.
+    role: Option<String>,
+    /// Only include members who are, or are not, connected to a voice channel.
+    in_voice: Option<bool>,
+    /// Only include bots if `true` or only humans if `false`.
+    bot: Option<bool>,
+    /// Only include members whose username or nickname starts with this, ignoring case.
+    name_prefix: Option<String>,
+}
+
+impl MemberFilter {
+    /// If no filter was given, so every member passes
+    fn is_empty(&self) -> bool {
+        self.role.is_none()
+            && self.in_voice.is_none()
+            && self.bot.is_none()
+            && self.name_prefix.is_none()
+    }
+
+    /// Create a predicate which checks if a member of the guild passes the filter
+    fn predicate<'a>(
+        self,
+        context: &'a GraphQLContext,
+        guild_id: GuildId,
+    ) -> FieldResult<impl Fn(&CachedMember) -> bool + 'a> {
+        let MemberFilter {
+            role,
+            in_voice,
+            bot,
+            name_prefix,
+        } = self;
+
+        let role = role
+            .map(|id| id.parse().map(RoleId))
+            .transpose()
+            .context("Invalid role id")?;
+        let name_prefix = name_prefix.map(|prefix| prefix.to_lowercase());
+
+        Ok(move |member: &CachedMember| {
+            role.map_or(true, |role| member.roles.contains(&role))
+                && bot.map_or(true, |bot| member.user.bot == bot)
+                && in_voice.map_or(true, |in_voice| {
+                    context
+                        .discord
+                        .data
+                        .voice_state(member.user.id, guild_id)
+                        .is_some()
+                        == in_voice
+                })
+                && name_prefix.as_ref().map_or(true, |prefix| {
+                    iter::once(&member.user.name)
+                        .chain(member.nick.as_ref())
+                        .any(|name| name.to_lowercase().starts_with(prefix))
+                })
+        })
+    }
+}
+
 // Create juniper objects
 
+/// A role in a guild.
+#[graphql_object]
+impl Role {
+    /// Unique id of the role.
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    /// Name of the role.
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Color of the role, `0` if the role does not have a color.
+    fn color(&self) -> FieldResult<i32> {
+        Ok(self.color.try_into()?)
+    }
+
+    /// Relative position of the role, higher roles take precedence.
+    fn position(&self) -> FieldResult<i32> {
+        Ok(self.position.try_into()?)
+    }
+
+    /// Permissions granted to members with the role.
+    fn permissions(&self) -> Vec<String> {
+        permission_names(self.permissions)
+    }
+
+    /// If the role is displayed separately from online members.
+    fn hoist(&self) -> bool {
+        self.hoist
+    }
+
+    /// If the role can be mentioned by anyone.
+    fn mentionable(&self) -> bool {
+        self.mentionable
+    }
+}
+
+impl Member {
+    /// Lookup each of the member's roles in the cache, yielding `None` for roles that are not
+    /// cached.
+    fn cached_roles<'a>(
+        &'a self,
+        context: &'a GraphQLContext,
+    ) -> impl Iterator<Item = Option<Role>> + 'a {
+        self.roles
+            .iter()
+            .map(move |role_id| context.role(*role_id).map(Role::from))
+    }
+}
+
 #[graphql_object(Context = GraphQLContext)]
 /// A member of a guild.
 impl Member {
@@ -162,6 +794,21 @@ impl Member {
         self.user.avatar.as_ref()
     }
 
+    /// Url of the member's avatar, or their default avatar if they do not have one.
+    #[graphql(arguments(
+        size(description = "Size of the image in pixels, a power of two between 16 and 4096"),
+        format(description = "Format of the image, defaults to gif if animated or png otherwise"),
+    ))]
+    fn avatar_url(&self, size: Option<i32>, format: Option<ImageFormat>) -> FieldResult<String> {
+        Ok(avatar_url(
+            self.user.id,
+            self.user.avatar.as_deref(),
+            &self.user.discriminator,
+            size,
+            format,
+        )?)
+    }
+
     /// Time the member joined the guild.
     fn joined_at(&self) -> Option<&String> {
         self.joined_at.as_ref()
@@ -185,16 +832,19 @@ impl Member {
     /// Member's color, calculated from their highest, colored, role.
     fn color(&self, context: &GraphQLContext) -> FieldResult<Option<i32>> {
         Ok(self
-            .roles
-            .iter()
-            .cloned()
-            .filter_map(|role_id| context.discord.cache.role(role_id))
-            .filter(|x| x.color != 0)
-            .max_by_key(|x| x.position)
+            .cached_roles(context)
+            .flatten()
+            .filter(|role| role.color != 0)
+            .max_by_key(|role| role.position)
             .map(|role| role.color.try_into())
             .transpose()?)
     }
 
+    /// Roles assigned to the member, not including the @everyone role.
+    fn roles(&self, context: &GraphQLContext) -> Vec<Role> {
+        self.cached_roles(context).flatten().collect()
+    }
+
     /// Member's discriminator.
     fn discriminator(&self) -> &str {
         self.user.discriminator.as_str()
@@ -214,6 +864,154 @@ impl Member {
     fn bot(&self) -> bool {
         self.user.bot
     }
+
+    /// Member's presence, if they are not offline.
+    fn presence(&self, context: &GraphQLContext) -> Option<Presence> {
+        context
+            .discord
+            .data
+            .presence(self.guild_id, self.user.id)
+            .map(Presence::from)
+    }
+}
+
+/// The online status of a user.
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresenceStatus {
+    /// The user is online.
+    Online,
+    /// The user is idle.
+    Idle,
+    /// The user does not want to be disturbed.
+    DoNotDisturb,
+    /// The user is offline or invisible.
+    Offline,
+}
+
+impl From<Status> for PresenceStatus {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Online => Self::Online,
+            Status::Idle => Self::Idle,
+            Status::DoNotDisturb => Self::DoNotDisturb,
+            Status::Invisible | Status::Offline => Self::Offline,
+        }
+    }
+}
+
+/// A platform that a user can be connected to discord from.
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientPlatform {
+    /// The desktop client.
+    Desktop,
+    /// The mobile app.
+    Mobile,
+    /// The web client.
+    Web,
+}
+
+/// The kind of an activity.
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActivityKind {
+    /// Playing a game.
+    Playing,
+    /// Streaming on twitch or youtube.
+    Streaming,
+    /// Listening to music.
+    Listening,
+    /// Watching a video.
+    Watching,
+    /// A custom status.
+    Custom,
+    /// Competing in a competition.
+    Competing,
+}
+
+impl From<ActivityType> for ActivityKind {
+    fn from(kind: ActivityType) -> Self {
+        match kind {
+            ActivityType::Playing => Self::Playing,
+            ActivityType::Streaming => Self::Streaming,
+            ActivityType::Listening => Self::Listening,
+            ActivityType::Watching => Self::Watching,
+            ActivityType::Custom => Self::Custom,
+            ActivityType::Competing => Self::Competing,
+        }
+    }
+}
+
+/// The presence of a guild member.
+#[graphql_object]
+impl Presence {
+    /// Overall status of the user.
+    fn status(&self) -> PresenceStatus {
+        self.status.into()
+    }
+
+    /// Platforms that the user is currently connected from.
+    fn client_platforms(&self) -> Vec<ClientPlatform> {
+        let client_status = &self.client_status;
+
+        [
+            (ClientPlatform::Desktop, client_status.desktop),
+            (ClientPlatform::Mobile, client_status.mobile),
+            (ClientPlatform::Web, client_status.web),
+        ]
+        .iter()
+        .filter(|(_, status)| {
+            status.map_or(false, |status| {
+                PresenceStatus::from(status) != PresenceStatus::Offline
+            })
+        })
+        .map(|(platform, _)| *platform)
+        .collect()
+    }
+
+    /// Activities the user is currently partaking in.
+    fn activities(&self) -> Vec<Activity> {
+        self.activities
+            .iter()
+            .cloned()
+            .map(Activity::from)
+            .collect()
+    }
+}
+
+/// An activity that a user is partaking in.
+#[graphql_object]
+impl Activity {
+    /// Name of the activity.
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Kind of the activity.
+    fn kind(&self) -> ActivityKind {
+        self.kind.into()
+    }
+
+    /// What the user is currently doing in the activity.
+    fn details(&self) -> Option<&String> {
+        self.details.as_ref()
+    }
+
+    /// Current party status of the user in the activity, or the text of a custom status.
+    fn state(&self) -> Option<&String> {
+        self.state.as_ref()
+    }
+
+    /// Stream url of the activity, if it is a stream.
+    fn url(&self) -> Option<&String> {
+        self.url.as_ref()
+    }
+
+    /// Time the activity was started, in milliseconds since the unix epoch.
+    fn started_at(&self) -> Option<String> {
+        self.timestamps
+            .as_ref()
+            .and_then(|timestamps| timestamps.start)
+            .map(|start| start.to_string())
+    }
 }
 
 /// State of a member in a voice channel.
@@ -252,8 +1050,6 @@ impl VoiceChannelState {
     /// Member object associated with the voice state.
     fn member(&self, context: &GraphQLContext) -> FieldResult<Member> {
         Ok(context
-            .discord
-            .cache
             .member(
                 self.guild_id
                     .context("Voice channel provided was not in a guild")?,
@@ -310,8 +1106,6 @@ impl VoiceChannel {
     fn category(&self, context: &GraphQLContext) -> Option<CategoryChannel> {
         self.parent_id.and_then(|parent_id| {
             context
-                .discord
-                .cache
                 .guild_channel(parent_id)
                 .and_then(|parent| parent.try_into().ok())
         })
@@ -322,7 +1116,7 @@ impl VoiceChannel {
         &self,
         context: &GraphQLContext,
     ) -> FieldResult<Option<Vec<String>>> {
-        missing_permissions(context, self, context.user.cookie.user_id)
+        missing_permissions(context, self, context.user.user_id())
     }
 
     /// The permissions that the bot is missing in this channel. Returns `None` if the bot has enough permissions
@@ -335,7 +1129,7 @@ impl VoiceChannel {
             self,
             context
                 .discord
-                .cache
+                .data
                 .current_user()
                 .context("Unable to get information on the bot current")?
                 .id,
@@ -343,18 +1137,154 @@ impl VoiceChannel {
     }
 
     /// Voice channel states in this voice channel.
-    fn states(&self, context: &GraphQLContext) -> Vec<VoiceChannelState> {
-        context
-            .discord
-            .cache
-            .voice_channel_states(self.id)
-            .unwrap_or_default()
+    #[graphql(arguments(
+        first(description = "Maximum amount of voice states to return"),
+        after(description = "Cursor of the edge to start after"),
+        filter(description = "Filter to apply to the members of the voice states"),
+    ))]
+    fn states(
+        &self,
+        context: &GraphQLContext,
+        first: Option<i32>,
+        after: Option<String>,
+        filter: Option<MemberFilter>,
+    ) -> FieldResult<VoiceChannelStateConnection> {
+        let guild_id = self.guild_id.context("Voice channel missing guild_id")?;
+        // Members missing from the cache can only be filtered out if a filter needs them
+        let predicate = filter
+            .filter(|filter| !filter.is_empty())
+            .map(|filter| filter.predicate(context, guild_id))
+            .transpose()?;
+
+        VoiceChannelStateConnection::new(
+            context
+                .discord
+                .data
+                .voice_channel_states(self.id)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|state| {
+                    predicate.as_ref().map_or(true, |predicate| {
+                        context
+                            .member(guild_id, state.user_id)
+                            .map_or(false, |member| predicate(&member))
+                    })
+                })
+                .map(|state| state.into())
+                .collect(),
+            |state| state.user_id.0,
+            first,
+            after,
+        )
+    }
+
+    /// The members holding the floor, if the voice channel is in speaker mode. Poll this field
+    /// every few seconds to follow speaker mode live, as `FLOOR` webhooks only reach servers.
+    async fn floor(&self, context: &GraphQLContext) -> FieldResult<Option<VoiceChannelFloor>> {
+        Ok(context
+            .floors
+            .floor(self.id)
+            .await?
+            .map(VoiceChannelFloor::from))
+    }
+
+    /// The members waiting for their turn to speak, in the order that they raised their hands.
+    /// Poll this field every few seconds to follow the queue live, as `QUEUE` webhooks only
+    /// reach servers.
+    async fn queue(&self, context: &GraphQLContext) -> FieldResult<Vec<QueuedSpeaker>> {
+        Ok(context
+            .floors
+            .queue(self.id)
+            .await?
             .into_iter()
-            .map(|state| state.into())
-            .collect()
+            .map(QueuedSpeaker::from)
+            .collect())
     }
 }
 
+/// The members allowed to talk in a voice channel in speaker mode.
+#[graphql_object(Context = GraphQLContext)]
+impl VoiceChannelFloor {
+    /// Id's of the members who hold the floor.
+    fn speaker_ids(&self) -> Vec<String> {
+        self.speakers.iter().map(ToString::to_string).collect()
+    }
+
+    /// The members who hold the floor.
+    fn speakers(&self, context: &GraphQLContext) -> Vec<Member> {
+        self.speakers
+            .iter()
+            .filter_map(|&user_id| context.member(self.guild_id, user_id))
+            .map(Member::from)
+            .collect()
+    }
+
+    /// Id of the user who last gave the floor.
+    fn given_by(&self) -> String {
+        self.given_by.to_string()
+    }
+
+    /// Time the floor was last given, in seconds since the unix epoch.
+    fn given_at(&self) -> String {
+        self.given_at.to_string()
+    }
+}
+
+/// A member waiting in the speaking queue of a voice channel.
+#[graphql_object(Context = GraphQLContext)]
+impl QueuedSpeaker {
+    /// Id of the member waiting to speak.
+    fn id(&self) -> String {
+        self.user_id.to_string()
+    }
+
+    /// The member waiting to speak.
+    fn member(&self, context: &GraphQLContext) -> Option<Member> {
+        context
+            .member(self.guild_id, self.user_id)
+            .map(Member::from)
+    }
+
+    /// Time the member raised their hand, in seconds since the unix epoch.
+    fn joined_at(&self) -> String {
+        self.joined_at.to_string()
+    }
+}
+
+/// Get a voice channel of a guild that the user can mute and unmute the members of
+///
+/// # Errors
+/// If the channel is not a voice channel of the guild, or the user is missing permissions in it
+fn updatable_voice_channel(
+    context: &GraphQLContext,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> FieldResult<VoiceChannel> {
+    let channel = VoiceChannel::try_from(
+        context
+            .guild_channel(channel_id)
+            .context("Channel does not exist on the guild")?,
+    )?;
+
+    if channel.guild_id != Some(guild_id) {
+        return Err(FieldError::new(
+            "Channel does not exist on the guild",
+            Value::null(),
+        ));
+    }
+
+    if let Some(missing_perms) = missing_permissions(context, &channel, context.user.user_id())? {
+        let missing_perms = Value::List(missing_perms.into_iter().map(Value::from).collect());
+
+        return Err(FieldError::new(
+            "Permission denied: user does not have enough permissions to perform that action",
+            graphql_value!({ "missing_permissions": missing_perms }),
+        ));
+    }
+
+    Ok(channel)
+}
+
 fn missing_permissions(
     context: &GraphQLContext,
     channel: &VoiceChannel,
@@ -362,29 +1292,47 @@ fn missing_permissions(
 ) -> FieldResult<Option<Vec<String>>> {
     let guild_id = channel.guild_id.context("Voice channel missing guild_id")?;
 
-    let member = context
-        .discord
-        .cache
+    let member: Member = context
         .member(guild_id, user_id)
-        .context("Unable to get information about the user in the guild")?;
+        .context("Unable to get information about the user in the guild")?
+        .into();
+
+    let everyone_role = context.role(RoleId(guild_id.0))
+        .context("The bot was unable to get information on the @everyone role for the guild the voice channel is in")?
+        .into();
 
     let member_roles = member
-            .roles
-            .iter()
-            .map(|role_id| {
-                context.discord.cache.role(*role_id).map(|role| {
-                    (*role_id, role.permissions)
-                })
-            })
-            .chain(iter::once({
-                let role = context.discord.cache.role(RoleId(guild_id.0)).context("The bot was unable to get information on the @everyone role for the guild the voice channel is in")?;
+        .cached_roles(context)
+        .chain(iter::once(Some(everyone_role)))
+        .collect::<Option<Vec<Role>>>()
+        .context("The bot was unable to get information on its roles")?
+        .iter()
+        .map(|role| (role.id, role.permissions))
+        .collect::<Vec<_>>();
 
-                Some((role.id, role.permissions))
-            }))
-            .collect::<Option<Vec<_>>>()
-            .context("The bot was unable to get information on its roles")?;
+    let missing_perms =
+        REQUIRED_PERMISSIONS - channel_permissions(channel, user_id, &member_roles)?;
+
+    if missing_perms.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(permission_names(missing_perms)))
+    }
+}
+
+/// Get the permissions that a member with the roles, including the @everyone role, has in a
+/// voice channel
+///
+/// # Errors
+/// If the channel is not in a guild
+fn channel_permissions(
+    channel: &VoiceChannel,
+    user_id: UserId,
+    member_roles: &[(RoleId, Permissions)],
+) -> FieldResult<Permissions> {
+    let guild_id = channel.guild_id.context("Voice channel missing guild_id")?;
 
-    let permissions = Calculator::new(
+    Ok(Calculator::new(
         guild_id,
         user_id,
         member_roles
@@ -392,22 +1340,108 @@ fn missing_permissions(
             .collect::<Vec<&(RoleId, Permissions)>>()
             .as_slice(),
     )
-    .in_channel(channel.kind, channel.permission_overwrites.as_slice())?;
+    .in_channel(channel.kind, channel.permission_overwrites.as_slice())?)
+}
+
+/// Check that a user owns the guild or has the administrator or manage guild permission in it,
+/// from the cached member and roles
+///
+/// # Returns
+/// The roles of the member with their permissions, including the everyone role
+///
+/// # Errors
+/// If the guild is not cached, the user is not a member of it or can not manage it, or the
+/// roles of the user are not cached
+fn authorize_guild_manager(
+    data: &dyn DataSource,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> FieldResult<Vec<(RoleId, Permissions)>> {
+    let guild = data.guild(guild_id).context("The guild is not cached")?;
+    let denied = || {
+        FieldError::new(
+            "Permission denied: managing the guild requires the administrator or manage guild permission",
+            Value::null(),
+        )
+    };
+
+    let member_roles = data
+        .member(guild_id, user_id)
+        .ok_or_else(denied)?
+        .roles
+        .iter()
+        .copied()
+        .chain(iter::once(RoleId(guild_id.0)))
+        .map(|role_id| data.role(role_id).map(|role| (role.id, role.permissions)))
+        .collect::<Option<Vec<_>>>()
+        .context("The roles of the user are not cached")?;
+
+    let guild_permissions = member_roles
+        .iter()
+        .fold(Permissions::empty(), |all, (_, permissions)| {
+            all | *permissions
+        });
+
+    if guild.owner_id == user_id
+        || guild_permissions.intersects(Permissions::ADMINISTRATOR | Permissions::MANAGE_GUILD)
+    {
+        Ok(member_roles)
+    } else {
+        Err(denied())
+    }
+}
+
+/// Check that a user still has the permissions to make a scheduled mute or unmute of a voice
+/// channel, which are the same as those needed to schedule it: managing the guild, and muting
+/// the members of the channel
+///
+/// # Errors
+/// If the user left the guild or lost a permission, the channel is gone, or the roles of the
+/// user are not cached
+pub(crate) fn authorize_scheduled(
+    data: &dyn DataSource,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+) -> FieldResult<()> {
+    let member_roles = authorize_guild_manager(data, guild_id, user_id)?;
+    let channel = VoiceChannel::try_from(
+        data.guild_channel(channel_id)
+            .context("The voice channel does not exist anymore")?,
+    )?;
+
+    if channel.guild_id != Some(guild_id) {
+        return Err(FieldError::new(
+            "The voice channel does not exist anymore",
+            Value::null(),
+        ));
+    }
 
-    let missing_perms = REQUIRED_PERMISSIONS - permissions;
+    let missing_perms =
+        REQUIRED_PERMISSIONS - channel_permissions(&channel, user_id, &member_roles)?;
 
     if missing_perms.is_empty() {
-        Ok(None)
+        Ok(())
     } else {
-        Ok(Some(
-            format!("{:?}", missing_perms)
-                .split('|')
-                .map(|x| x.trim().to_string())
-                .collect(),
+        Err(FieldError::new(
+            "The user is missing permissions in the voice channel",
+            Value::null(),
         ))
     }
 }
 
+/// Get the names of all the permissions in the set
+fn permission_names(permissions: Permissions) -> Vec<String> {
+    if permissions.is_empty() {
+        return Vec::new();
+    }
+
+    format!("{:?}", permissions)
+        .split('|')
+        .map(|x| x.trim().to_string())
+        .collect()
+}
+
 /// A discord guild.
 #[graphql_object(Context = GraphQLContext)]
 impl Guild {
@@ -427,8 +1461,6 @@ impl Guild {
     /// Guild member object of the owner of the guild.
     fn owner(&self, context: &GraphQLContext) -> FieldResult<Member> {
         Ok(context
-            .discord
-            .cache
             .member(self.id, self.owner_id)
             .context("The guild owner was not found in the cache")?
             .into())
@@ -443,21 +1475,67 @@ impl Guild {
         self.banner.as_ref()
     }
 
+    /// Url of the guild's icon.
+    #[graphql(arguments(
+        size(description = "Size of the image in pixels, a power of two between 16 and 4096"),
+        format(description = "Format of the image, defaults to gif if animated or png otherwise"),
+    ))]
+    fn icon_url(
+        &self,
+        size: Option<i32>,
+        format: Option<ImageFormat>,
+    ) -> FieldResult<Option<String>> {
+        Ok(self
+            .icon
+            .as_ref()
+            .map(|icon| cdn::image_url(&format!("icons/{}", self.id), icon, format, size))
+            .transpose()?)
+    }
+
+    /// Url of the guild's banner.
+    #[graphql(arguments(
+        size(description = "Size of the image in pixels, a power of two between 16 and 4096"),
+        format(description = "Format of the image, defaults to gif if animated or png otherwise"),
+    ))]
+    fn banner_url(
+        &self,
+        size: Option<i32>,
+        format: Option<ImageFormat>,
+    ) -> FieldResult<Option<String>> {
+        Ok(self
+            .banner
+            .as_ref()
+            .map(|banner| cdn::image_url(&format!("banners/{}", self.id), banner, format, size))
+            .transpose()?)
+    }
+
+    /// Roles in the guild, ordered from highest to lowest.
+    fn roles(&self, context: &GraphQLContext) -> Vec<Role> {
+        let mut roles: Vec<Role> = context
+            .discord
+            .data
+            .guild_roles(self.id)
+            .map(|ids| {
+                ids.into_iter()
+                    .filter_map(|id| context.role(id).map(Role::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        roles.sort_by_key(|role| Reverse(role.position));
+
+        roles
+    }
+
     /// Voice channels in the guild.
     fn voice_channels(&self, context: &GraphQLContext) -> Vec<VoiceChannel> {
         context
             .discord
-            .cache
+            .data
             .guild_channels(self.id)
             .map(|ids| {
                 ids.into_iter()
-                    .filter_map(|id| {
-                        context
-                            .discord
-                            .cache
-                            .guild_channel(id)
-                            .and_then(|c| c.try_into().ok())
-                    })
+                    .filter_map(|id| context.guild_channel(id).and_then(|c| c.try_into().ok()))
                     .collect()
             })
             .unwrap_or_default()
@@ -471,37 +1549,49 @@ impl Guild {
         id: String,
     ) -> FieldResult<Option<VoiceChannel>> {
         Ok(context
-            .discord
-            .cache
             .guild_channel(ChannelId(id.parse().context("Invalid channel id")?))
-            .and_then(|c| c.try_into().ok()))
+            .and_then(|c| VoiceChannel::try_from(c).ok())
+            .filter(|c| c.guild_id == Some(self.id)))
     }
 
-    /// All the members in the guild.
-    fn members(&self, context: &GraphQLContext) -> Vec<Member> {
-        context
-            .discord
-            .cache
-            .guild_members(self.id)
-            .map(|ids| {
-                ids.into_iter()
-                    .filter_map(|id| {
-                        context
-                            .discord
-                            .cache
-                            .member(self.id, id)
-                            .map(|member| member.into())
-                    })
-                    .collect()
-            })
-            .unwrap_or_default()
+    /// Members in the guild.
+    #[graphql(arguments(
+        first(description = "Maximum amount of members to return"),
+        after(description = "Cursor of the edge to start after"),
+        filter(description = "Filter to apply to the members"),
+    ))]
+    fn members(
+        &self,
+        context: &GraphQLContext,
+        first: Option<i32>,
+        after: Option<String>,
+        filter: Option<MemberFilter>,
+    ) -> FieldResult<MemberConnection> {
+        let predicate = filter.unwrap_or_default().predicate(context, self.id)?;
+
+        MemberConnection::new(
+            context
+                .discord
+                .data
+                .guild_members(self.id)
+                .map(|ids| {
+                    context
+                        .members(self.id, ids)
+                        .into_iter()
+                        .filter(|member| predicate(member))
+                        .map(|member| member.into())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            |member| member.user.id.0,
+            first,
+            after,
+        )
     }
 
     /// A specific member in the guild.
     fn member(&self, context: &GraphQLContext, id: String) -> FieldResult<Option<Member>> {
         Ok(context
-            .discord
-            .cache
             .member(self.id, UserId(id.parse().context("Invalid user id")?))
             .map(|member| member.into()))
     }
@@ -509,12 +1599,22 @@ impl Guild {
     /// The current logged in user as a member of the guild.
     async fn me(&self, context: &GraphQLContext) -> FieldResult<Member> {
         Ok(context
-            .discord
-            .cache
-            .member(self.id, context.user.cookie.user_id)
+            .member(self.id, context.user.user_id())
             .map(|member| member.into())
             .context("Failed to lookup current user in cache")?)
     }
+
+    /// The IANA timezone that the scheduled mutes of the guild are evaluated in.
+    async fn timezone(&self, context: &GraphQLContext) -> FieldResult<String> {
+        Ok(context
+            .schedules
+            .store()
+            .timezone(self.id)
+            .await?
+            .unwrap_or(Tz::UTC)
+            .name()
+            .to_owned())
+    }
 }
 
 /// A current user object, different from a member since it is detached from a guild.
@@ -544,203 +1644,1429 @@ impl CurrentUser {
     fn avatar(&self) -> Option<&String> {
         self.avatar.as_ref()
     }
+
+    /// Url of the user's avatar, or their default avatar if they do not have one.
+    #[graphql(arguments(
+        size(description = "Size of the image in pixels, a power of two between 16 and 4096"),
+        format(description = "Format of the image, defaults to gif if animated or png otherwise"),
+    ))]
+    fn avatar_url(&self, size: Option<i32>, format: Option<ImageFormat>) -> FieldResult<String> {
+        Ok(avatar_url(
+            self.id,
+            self.avatar.as_deref(),
+            &self.discriminator,
+            size,
+            format,
+        )?)
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
-/// The root object for `GraphQL` queries.
-pub struct QueryRoot;
+/// Get the url of a user's avatar, falling back to their default avatar if they do not have one
+fn avatar_url(
+    user_id: UserId,
+    avatar: Option<&str>,
+    discriminator: &str,
+    size: Option<i32>,
+    format: Option<ImageFormat>,
+) -> anyhow::Result<String> {
+    match avatar {
+        Some(avatar) => cdn::image_url(&format!("avatars/{}", user_id), avatar, format, size),
+        None => cdn::default_avatar_url(discriminator, size),
+    }
+}
 
-/// The root object for GraphQL queries.
+/// A login session of the oauth user.
 #[graphql_object(Context = GraphQLContext)]
-impl QueryRoot {
-    /// Get a guild by id.
-    #[graphql(arguments(id(description = "Id of the guild to fetch")))]
-    fn guild(context: &GraphQLContext, id: String) -> FieldResult<Option<Guild>> {
-        Ok(context
-            .discord
-            .cache
-            .guild(GuildId(id.parse().context("Invalid guild id")?))
-            .map(|g| g.into()))
+impl UserSession {
+    /// Unique id of the session.
+    fn id(&self) -> &str {
+        self.id.as_str()
     }
 
-    /// Get the intersection of guilds between the logged in user and the bot.
-    async fn shared_guilds(context: &GraphQLContext) -> FieldResult<Vec<Guild>> {
-        let bot_guilds = context
-            .discord
-            .http
-            .current_user_guilds()
-            .await?
-            .into_iter()
-            .map(|x| x.id)
-            .collect::<HashSet<_>>();
-
-        let user_guilds = context
-            .user
-            .http
-            .current_user_guilds()
-            .await?
-            .into_iter()
-            .map(|x| x.id)
-            .collect::<HashSet<_>>();
+    /// Time the session was created, in seconds since the unix epoch.
+    fn created_at(&self) -> String {
+        self.created_at.to_string()
+    }
 
-        let intersection = bot_guilds.intersection(&user_guilds).cloned();
+    /// Time the session was last used, in seconds since the unix epoch.
+    fn last_used_at(&self) -> String {
+        self.last_used_at.to_string()
+    }
 
-        Ok(intersection
-            .filter_map(|id| context.discord.cache.guild(id).map(|guild| guild.into()))
-            .collect())
+    /// Time the session expires, in seconds since the unix epoch.
+    fn expires_at(&self) -> String {
+        (self.created_at + self.expires_in).to_string()
     }
 
-    /// Get information about the bot user.
-    fn bot(&self, context: &GraphQLContext) -> FieldResult<CurrentUser> {
-        Ok(context
-            .discord
-            .cache
-            .current_user()
-            .context("Unable to get information on the bot user from the cache")?
-            .into())
+    /// User agent of the browser that logged in.
+    fn user_agent(&self) -> Option<&String> {
+        self.user_agent.as_ref()
     }
 
-    /// Get information about the logged in oauth user.
-    async fn me(&self, context: &GraphQLContext) -> FieldResult<CurrentUser> {
-        Ok(Arc::new(
-            context
-                .user
-                .http
-                .current_user()
-                .await
-                .context("Unable to get information on the current oauth user")?,
-        )
-        .into())
+    /// If this is the session making the request.
+    fn current(&self, context: &GraphQLContext) -> bool {
+        context
+            .oauth_user()
+            .map_or(false, |user| user.session.id == self.id)
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-/// The root object for `GraphQL` mutations.
-pub struct MutationRoot;
+/// A personal api token of the oauth user.
+#[graphql_object]
+impl UserApiToken {
+    /// Unique id of the token.
+    fn id(&self) -> &str {
+        self.id.as_str()
+    }
 
-/// The root object for GraphQL mutations.
-#[graphql_object(Context = GraphQLContext)]
-impl MutationRoot {
-    /// Mute all users in a voice channel.
-    ///
-    /// # Returns
-    /// Id's of users who were successfully muted
-    #[graphql(arguments(
-        guild_id(description = "Id of the guild that the channel resides in",),
-        channel_id(description = "Id of the channel to mutate",),
-    ))]
-    async fn mute(
-        guild_id: String,
-        channel_id: String,
-        context: &GraphQLContext,
-    ) -> FieldResult<Vec<String>> {
-        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
-        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+    /// Name given to the token.
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
 
-        if let Some(missing_perms) = missing_permissions(
-            context,
-            &VoiceChannel::try_from(
-                context
-                    .discord
-                    .cache
-                    .guild_channel(channel_id)
-                    .context("Channel does not exist on the guild")?,
-            )?,
-            context.user.cookie.user_id,
-        )? {
-            let missing_perms = Value::List(missing_perms.into_iter().map(Value::from).collect());
+    /// Ids of the guilds the token can be used in.
+    fn guild_ids(&self) -> Vec<String> {
+        self.guilds.iter().map(ToString::to_string).collect()
+    }
 
-            Err(FieldError::new(
-                "Permission denied: user does not have enough permissions to perform that action",
-                graphql_value!({ "missing_permissions": missing_perms }),
-            ))
-        } else {
-            mass_update_voice_state(context, channel_id, guild_id, true)
-                .await
-                .map(|ids| ids.into_iter().map(|id| id.to_string()).collect())
-        }
+    /// Actions the token is allowed to perform.
+    fn actions(&self) -> Vec<TokenAction> {
+        self.actions.clone()
     }
 
-    /// Unmute all users in a voice channel.
+    /// Time the token was created, in seconds since the unix epoch.
+    fn created_at(&self) -> String {
+        self.created_at.to_string()
+    }
+
+    /// Time the token was last used, in seconds since the unix epoch.
+    fn last_used_at(&self) -> Option<String> {
+        self.last_used_at
+            .map(|last_used_at| last_used_at.to_string())
+    }
+}
+
+/// A newly created api token, along with the token itself.
+#[derive(GraphQLObject, Debug)]
+pub struct CreatedApiToken {
+    /// The token to send in the `Authorization` header as `Bearer <token>`.
     ///
-    /// # Returns
-    /// Id's of users who were successfully un-muted
-    #[graphql(arguments(
-        guild_id(description = "Id of the guild that the channel resides in",),
-        channel_id(description = "Id of the channel to mutate",),
-    ))]
-    async fn unmute(
-        guild_id: String,
-        channel_id: String,
-        context: &GraphQLContext,
-    ) -> FieldResult<Vec<String>> {
-        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
-        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+    /// This is the only time the token is shown, it can not be recovered later.
+    token: String,
+    /// Information about the token.
+    api_token: UserApiToken,
+}
 
-        if let Some(missing_perms) = missing_permissions(
-            context,
-            &VoiceChannel::try_from(
-                context
-                    .discord
-                    .cache
-                    .guild_channel(channel_id)
-                    .context("Channel does not exist on the guild")?,
-            )?,
-            context.user.cookie.user_id,
-        )? {
-            let missing_perms = Value::List(
-                missing_perms
-                    .into_iter()
-                    .map(Value::from)
-                    .collect::<Vec<_>>(),
-            );
+/// A webhook that events in a guild are sent to.
+#[graphql_object(Context = GraphQLContext)]
+impl GuildWebhook {
+    /// Unique id of the webhook.
+    fn id(&self) -> &str {
+        self.id.as_str()
+    }
 
-            Err(FieldError::new(
-                "Permission denied: user does not have enough permissions to perform that action",
-                graphql_value!({ "missing_permissions": missing_perms }),
-            ))
-        } else {
-            mass_update_voice_state(context, channel_id, guild_id, false)
-                .await
-                .map(|ids| ids.into_iter().map(|id| id.to_string()).collect())
-        }
+    /// Url that the events are posted to.
+    fn url(&self) -> &str {
+        self.url.as_str()
     }
-}
 
-async fn mass_update_voice_state(
-    context: &GraphQLContext,
-    channel_id: ChannelId,
-    guild_id: GuildId,
-    mute: bool,
-) -> FieldResult<Vec<UserId>> {
-    if let Some(states) = context.discord.cache.voice_channel_states(channel_id) {
-        let (send_muted, receive_muted) = mpsc::channel();
+    /// Events that are sent to the webhook.
+    fn events(&self) -> Vec<WebhookEvent> {
+        self.events.clone()
+    }
 
-        // Remove bots
-        let states = states
-            .into_iter()
-            .filter_map(|state| {
-                if context.discord.cache.user(state.user_id)?.bot {
-                    None
-                } else {
-                    Some(Ok(state))
-                }
-            })
-            .collect::<FieldResult<Vec<_>>>()?;
+    /// Id of the user who registered the webhook.
+    fn created_by(&self) -> String {
+        self.created_by.to_string()
+    }
 
-        for chunk in states.chunks(10) {
-            let send_muted = send_muted.clone();
+    /// Time the webhook was registered, in seconds since the unix epoch.
+    fn created_at(&self) -> String {
+        self.created_at.to_string()
+    }
 
-            join_all(chunk.iter().map(move |state| {
-                let send_muted = send_muted.clone();
+    /// The latest events sent to the webhook, newest first.
+    #[graphql(arguments(first(description = "Amount of deliveries to return")))]
+    async fn deliveries(
+        &self,
+        context: &GraphQLContext,
+        first: Option<i32>,
+    ) -> FieldResult<Vec<GuildWebhookDelivery>> {
+        let first = first.map_or(Ok(DEFAULT_PAGE_SIZE), usize::try_from)?;
 
-                async move {
-                    if state.mute != mute
-                        && context
-                            .discord
-                            .http
-                            .update_guild_member(guild_id, state.user_id)
-                            .mute(mute)
+        Ok(context
+            .webhooks
+            .store()
+            .deliveries(&self.id)
+            .await?
+            .into_iter()
+            .take(first)
+            .map(GuildWebhookDelivery::from)
+            .collect())
+    }
+}
+
+/// An event sent to a webhook.
+#[graphql_object]
+impl GuildWebhookDelivery {
+    /// Unique id of the delivery, sent in the `X-Stfu-Delivery` header.
+    fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    /// Event that was sent.
+    fn event(&self) -> WebhookEvent {
+        self.event
+    }
+
+    /// Json body that was sent.
+    fn payload(&self) -> &str {
+        self.payload.as_str()
+    }
+
+    /// Times the body has been sent, failed deliveries are retried with exponential backoff.
+    fn attempts(&self) -> FieldResult<i32> {
+        Ok(self.attempts.try_into()?)
+    }
+
+    /// Http status that the webhook responded to the last attempt with, if it responded.
+    fn status(&self) -> Option<i32> {
+        self.status.map(i32::from)
+    }
+
+    /// What went wrong with the last attempt, if it failed.
+    fn error(&self) -> Option<&String> {
+        self.error.as_ref()
+    }
+
+    /// If the webhook accepted the event.
+    fn succeeded(&self) -> bool {
+        self.succeeded
+    }
+
+    /// Time the event happened, in seconds since the unix epoch.
+    fn created_at(&self) -> String {
+        self.created_at.to_string()
+    }
+
+    /// Time the body was last sent, in seconds since the unix epoch.
+    fn last_attempt_at(&self) -> Option<String> {
+        self.last_attempt_at
+            .map(|last_attempt_at| last_attempt_at.to_string())
+    }
+}
+
+/// Where a guild has messages about its mutes posted.
+#[graphql_object]
+impl GuildNotificationSettings {
+    /// Id of the text channel that the messages are posted to, or null if they are posted to
+    /// the chat of the voice channel that was muted.
+    fn channel_id(&self) -> Option<String> {
+        match self.target {
+            NotificationTarget::TextChannel(channel_id) => Some(channel_id.to_string()),
+            NotificationTarget::VoiceChat => None,
+        }
+    }
+
+    /// Id of the user who last changed the settings.
+    fn updated_by(&self) -> String {
+        self.updated_by.to_string()
+    }
+
+    /// Time the settings were last changed, in seconds since the unix epoch.
+    fn updated_at(&self) -> String {
+        self.updated_at.to_string()
+    }
+}
+
+/// A mute or unmute of a voice channel that runs at set times.
+#[graphql_object]
+impl ChannelSchedule {
+    /// Id of the schedule.
+    fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    /// Id of the voice channel that the schedule acts on.
+    fn channel_id(&self) -> String {
+        self.channel_id.to_string()
+    }
+
+    /// What the schedule does to the voice channel.
+    fn action(&self) -> ScheduleAction {
+        self.action
+    }
+
+    /// The cron expression the schedule recurs on, or null if it runs once.
+    fn cron(&self) -> Option<&str> {
+        match &self.recurrence {
+            Recurrence::Cron(cron) => Some(cron.as_str()),
+            Recurrence::Once(_) => None,
+        }
+    }
+
+    /// Time the schedule runs at, in seconds since the unix epoch, or null if it recurs.
+    fn run_at(&self) -> Option<String> {
+        match self.recurrence {
+            Recurrence::Once(run_at) => Some(run_at.to_string()),
+            Recurrence::Cron(_) => None,
+        }
+    }
+
+    /// Time the schedule runs next, in seconds since the unix epoch, or null if it will not
+    /// run again. Schedules are stopped when the user who created them can no longer make
+    /// their action.
+    fn next_run_at(&self) -> Option<String> {
+        self.next_run_at.map(|next_run_at| next_run_at.to_string())
+    }
+
+    /// Time the schedule last ran, in seconds since the unix epoch.
+    fn last_run_at(&self) -> Option<String> {
+        self.last_run_at.map(|last_run_at| last_run_at.to_string())
+    }
+
+    /// Id of the user who created the schedule, who the mutes are made in the name of.
+    fn created_by(&self) -> String {
+        self.created_by.to_string()
+    }
+
+    /// Time the schedule was created, in seconds since the unix epoch.
+    fn created_at(&self) -> String {
+        self.created_at.to_string()
+    }
+}
+
+/// A newly registered webhook, along with the secret that its events are signed with.
+#[derive(GraphQLObject, Debug)]
+pub struct CreatedWebhook {
+    /// The secret that signs every event, as the hex HMAC-SHA256 of `<timestamp>.<body>` sent in
+    /// the `X-Stfu-Signature` header as `sha256=<signature>`, with the timestamp in the
+    /// `X-Stfu-Timestamp` header.
+    ///
+    /// This is the only time the secret is shown, it can not be recovered later.
+    secret: String,
+    /// Information about the webhook.
+    webhook: GuildWebhook,
+}
+
+/// A guild that the user can manage but the bot is not in.
+#[derive(Clone, Debug)]
+pub struct ManageableGuild {
+    /// The guild, as seen by the user
+    guild: CurrentUserGuild,
+    /// The link to invite the bot to the guild
+    invite_url: String,
+}
+
+/// A guild that the logged in user can manage but the bot is not in.
+#[graphql_object(Context = GraphQLContext)]
+impl ManageableGuild {
+    /// Guild's snowflake id.
+    fn id(&self) -> String {
+        self.guild.id.to_string()
+    }
+
+    /// Guild's name.
+    fn name(&self) -> &str {
+        self.guild.name.as_str()
+    }
+
+    /// Icon hash of the guild.
+    fn icon(&self) -> Option<&String> {
+        self.guild.icon.as_ref()
+    }
+
+    /// Url of the guild's icon.
+    #[graphql(arguments(
+        size(description = "Size of the image in pixels, a power of two between 16 and 4096"),
+        format(description = "Format of the image, defaults to gif if animated or png otherwise"),
+    ))]
+    fn icon_url(
+        &self,
+        size: Option<i32>,
+        format: Option<ImageFormat>,
+    ) -> FieldResult<Option<String>> {
+        Ok(self
+            .guild
+            .icon
+            .as_ref()
+            .map(|icon| cdn::image_url(&format!("icons/{}", self.guild.id), icon, format, size))
+            .transpose()?)
+    }
+
+    /// Link that invites the bot to the guild with the permissions it needs.
+    fn invite_url(&self) -> &str {
+        self.invite_url.as_str()
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+/// The root object for `GraphQL` queries.
+pub struct QueryRoot;
+
+/// The root object for GraphQL queries.
+#[graphql_object(Context = GraphQLContext)]
+impl QueryRoot {
+    /// Get a guild by id.
+    #[graphql(arguments(id(description = "Id of the guild to fetch")))]
+    fn guild(context: &GraphQLContext, id: String) -> FieldResult<Option<Guild>> {
+        let id = GuildId(id.parse().context("Invalid guild id")?);
+
+        context.authorize(id, TokenAction::Read)?;
+
+        Ok(context.discord.data.guild(id).map(|g| g.into()))
+    }
+
+    /// Get the intersection of guilds between the logged in user and the bot.
+    async fn shared_guilds(context: &GraphQLContext) -> FieldResult<Vec<Guild>> {
+        context.shared_guilds().await
+    }
+
+    /// Get the guilds that the logged in user can manage but the bot is not in, along with
+    /// links to invite the bot to them.
+    async fn manageable_guilds(context: &GraphQLContext) -> FieldResult<Vec<ManageableGuild>> {
+        context.manageable_guilds().await
+    }
+
+    /// Get information about the bot user.
+    fn bot(&self, context: &GraphQLContext) -> FieldResult<CurrentUser> {
+        Ok(context
+            .discord
+            .data
+            .current_user()
+            .context("Unable to get information on the bot user from the cache")?
+            .into())
+    }
+
+    /// Get information about the logged in oauth user.
+    async fn me(&self, context: &GraphQLContext) -> FieldResult<CurrentUser> {
+        Ok(context.current_user().await?.into())
+    }
+
+    /// Get all of the personal api tokens of the logged in user.
+    async fn api_tokens(&self, context: &GraphQLContext) -> FieldResult<Vec<UserApiToken>> {
+        let mut api_tokens = context
+            .api_tokens
+            .user_tokens(context.oauth_user()?.session.user_id)
+            .await?;
+
+        api_tokens.sort_by_key(|token| Reverse(token.created_at));
+
+        Ok(api_tokens.into_iter().map(UserApiToken::from).collect())
+    }
+
+    /// Get the webhooks of a guild that the logged in user can manage.
+    #[graphql(arguments(guild_id(description = "Id of the guild to get the webhooks of")))]
+    async fn webhooks(
+        &self,
+        context: &GraphQLContext,
+        guild_id: String,
+    ) -> FieldResult<Vec<GuildWebhook>> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management(guild_id).await?;
+
+        let mut webhooks = context.webhooks.store().guild_webhooks(guild_id).await?;
+        webhooks.sort_by_key(|webhook| webhook.created_at);
+
+        Ok(webhooks.into_iter().map(GuildWebhook::from).collect())
+    }
+
+    /// Get where a guild that the logged in user can manage has messages about its mutes
+    /// posted, or null if it does not.
+    #[graphql(arguments(guild_id(description = "Id of the guild to get the settings of")))]
+    async fn notification_settings(
+        &self,
+        context: &GraphQLContext,
+        guild_id: String,
+    ) -> FieldResult<Option<GuildNotificationSettings>> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management(guild_id).await?;
+
+        Ok(context
+            .notifications
+            .store()
+            .settings(guild_id)
+            .await?
+            .map(GuildNotificationSettings::from))
+    }
+
+    /// Get the scheduled mutes of a guild that the logged in user can manage, oldest first.
+    #[graphql(arguments(guild_id(description = "Id of the guild to get the schedules of")))]
+    async fn schedules(
+        &self,
+        context: &GraphQLContext,
+        guild_id: String,
+    ) -> FieldResult<Vec<ChannelSchedule>> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management(guild_id).await?;
+
+        Ok(context
+            .schedules
+            .store()
+            .guild_schedules(guild_id)
+            .await?
+            .into_iter()
+            .map(ChannelSchedule::from)
+            .collect())
+    }
+
+    /// Get all of the login sessions of the logged in user.
+    async fn sessions(&self, context: &GraphQLContext) -> FieldResult<Vec<UserSession>> {
+        let mut sessions = context
+            .sessions
+            .user_sessions(context.oauth_user()?.session.user_id)
+            .await?;
+
+        sessions.retain(|session| !session.is_expired());
+        sessions.sort_by_key(|session| Reverse(session.last_used_at));
+
+        Ok(sessions.into_iter().map(UserSession::from).collect())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+/// The root object for `GraphQL` mutations.
+pub struct MutationRoot;
+
+/// The root object for GraphQL mutations.
+#[graphql_object(Context = GraphQLContext)]
+impl MutationRoot {
+    /// Create a personal api token for scripts and bots to use on behalf of the logged in user.
+    ///
+    /// The token can never do more than the user's own discord permissions allow.
+    #[graphql(arguments(
+        name(description = "Name to remember the token by"),
+        guild_ids(description = "Ids of the guilds the token can be used in"),
+        actions(description = "Actions the token is allowed to perform"),
+    ))]
+    async fn create_api_token(
+        context: &GraphQLContext,
+        name: String,
+        guild_ids: Vec<String>,
+        actions: Vec<TokenAction>,
+    ) -> FieldResult<CreatedApiToken> {
+        let user_id = context.oauth_user()?.session.user_id;
+
+        context.rate_limit(None).await?;
+
+        if guild_ids.is_empty() || actions.is_empty() {
+            return Err(FieldError::new(
+                "An api token needs at least one guild and action",
+                Value::null(),
+            ));
+        }
+
+        let guilds = guild_ids
+            .iter()
+            .map(|id| id.parse().map(GuildId))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Invalid guild id")?;
+
+        // Permissions are checked again whenever the token is used, this only keeps tokens to
+        // guilds that the user is in
+        let user_guilds = context.user_guilds().await?;
+        if let Some(guild_id) = guilds
+            .iter()
+            .find(|&&guild_id| !user_guilds.iter().any(|guild| guild.id == guild_id))
+        {
+            return Err(FieldError::new(
+                format!("You are not in the guild {}", guild_id),
+                Value::null(),
+            ));
+        }
+
+        if context.api_tokens.user_tokens(user_id).await?.len() >= MAX_USER_TOKENS {
+            return Err(FieldError::new(
+                format!("A user can have at most {} api tokens", MAX_USER_TOKENS),
+                Value::null(),
+            ));
+        }
+
+        let (api_token, token) = ApiToken::create(user_id, name, guilds, actions);
+        context.api_tokens.insert(api_token.clone()).await?;
+
+        Ok(CreatedApiToken {
+            token,
+            api_token: api_token.into(),
+        })
+    }
+
+    /// Revoke one of the logged in user's personal api tokens.
+    ///
+    /// # Returns
+    /// If a token with the id existed
+    #[graphql(arguments(id(description = "Id of the api token to revoke")))]
+    async fn revoke_api_token(context: &GraphQLContext, id: String) -> FieldResult<bool> {
+        Ok(context
+            .api_tokens
+            .remove(context.oauth_user()?.session.user_id, &id)
+            .await?)
+    }
+
+    /// Register a webhook that events in a guild the logged in user manages are posted to.
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild to send the events of"),
+        url(description = "Public http or https url to post the events to"),
+        events(description = "Events to send to the webhook"),
+    ))]
+    async fn create_webhook(
+        context: &GraphQLContext,
+        guild_id: String,
+        url: String,
+        events: Vec<WebhookEvent>,
+    ) -> FieldResult<CreatedWebhook> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management_change(guild_id)?;
+
+        if events.is_empty() {
+            return Err(FieldError::new(
+                "A webhook needs at least one event",
+                Value::null(),
+            ));
+        }
+
+        if context
+            .webhooks
+            .store()
+            .guild_webhooks(guild_id)
+            .await?
+            .len()
+            >= MAX_GUILD_WEBHOOKS
+        {
+            return Err(FieldError::new(
+                format!("A guild can have at most {} webhooks", MAX_GUILD_WEBHOOKS),
+                Value::null(),
+            ));
+        }
+
+        context.webhooks.check_url(&url).await?;
+
+        let webhook = Webhook::create(guild_id, url, events, context.user.user_id());
+        context.webhooks.store().insert(webhook.clone()).await?;
+
+        Ok(CreatedWebhook {
+            secret: webhook.secret.clone(),
+            webhook: webhook.into(),
+        })
+    }
+
+    /// Remove a webhook from a guild that the logged in user manages, along with its deliveries.
+    ///
+    /// # Returns
+    /// If a webhook with the id existed
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the webhook belongs to"),
+        id(description = "Id of the webhook to remove"),
+    ))]
+    async fn delete_webhook(
+        context: &GraphQLContext,
+        guild_id: String,
+        id: String,
+    ) -> FieldResult<bool> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management_change(guild_id)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        Ok(context.webhooks.store().remove(guild_id, &id).await?)
+    }
+
+    /// Send a test event to a webhook, making a single attempt.
+    ///
+    /// # Returns
+    /// The delivery, describing if the webhook accepted the event
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the webhook belongs to"),
+        id(description = "Id of the webhook to test"),
+    ))]
+    async fn test_webhook(
+        context: &GraphQLContext,
+        guild_id: String,
+        id: String,
+    ) -> FieldResult<GuildWebhookDelivery> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let webhook = context.guild_webhook(guild_id, &id).await?;
+
+        context.rate_limit(Some(guild_id)).await?;
+
+        Ok(context.webhooks.send_test(&webhook).await?.into())
+    }
+
+    /// Have a message posted whenever a voice channel in a guild that the logged in user manages
+    /// is muted, saying who ran the mute and how many members it hit. The message is edited
+    /// once the channel is unmuted.
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild to post the messages of"),
+        channel_id(
+            description = "Id of the text channel to post the messages to, or null to post them to the chat of the muted voice channel"
+        ),
+    ))]
+    async fn set_notification_channel(
+        context: &GraphQLContext,
+        guild_id: String,
+        channel_id: Option<String>,
+    ) -> FieldResult<GuildNotificationSettings> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management_change(guild_id)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        let target = match channel_id {
+            Some(channel_id) => {
+                let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+
+                let is_guild_text_channel = matches!(
+                    context.guild_channel(channel_id).as_deref(),
+                    Some(GuildChannel::Text(channel)) if channel.guild_id == Some(guild_id)
+                );
+
+                if !is_guild_text_channel {
+                    return Err(FieldError::new(
+                        "Text channel does not exist on the guild",
+                        Value::null(),
+                    ));
+                }
+
+                NotificationTarget::TextChannel(channel_id)
+            }
+            None => NotificationTarget::VoiceChat,
+        };
+
+        let settings = NotificationSettings {
+            guild_id,
+            target,
+            updated_by: context.user.user_id(),
+            updated_at: unix_timestamp(),
+        };
+        context
+            .notifications
+            .store()
+            .set_settings(settings.clone())
+            .await?;
+
+        Ok(settings.into())
+    }
+
+    /// Stop posting messages about the mutes in a guild that the logged in user manages.
+    ///
+    /// # Returns
+    /// If messages were being posted
+    #[graphql(arguments(guild_id(
+        description = "Id of the guild to stop posting the messages of"
+    )))]
+    async fn disable_notifications(
+        context: &GraphQLContext,
+        guild_id: String,
+    ) -> FieldResult<bool> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management_change(guild_id)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        Ok(context
+            .notifications
+            .store()
+            .remove_settings(guild_id)
+            .await?)
+    }
+
+    /// Schedule a mute or unmute of a voice channel in a guild that the logged in user manages,
+    /// either once or recurring on a cron expression evaluated in the timezone of the guild.
+    /// The action is made in the name of the logged in user, who needs the permissions to make
+    /// it.
+    ///
+    /// Quiet hours are a pair of schedules, such as a mute on `0 22 * * MON-FRI` and an unmute
+    /// on `0 7 * * TUE-SAT` for every weekday night. Recurring schedules can run at most once
+    /// every 15 minutes, and a guild can have at most 25 schedules.
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the channel resides in"),
+        channel_id(description = "Id of the voice channel to act on"),
+        action(description = "What to do to the voice channel"),
+        cron(
+            description = "Five field cron expression, `<minute> <hour> <day of month> <month> <day of week>`, to run the action on"
+        ),
+        run_at(description = "Time to run the action once at, in seconds since the unix epoch"),
+    ))]
+    async fn create_schedule(
+        context: &GraphQLContext,
+        guild_id: String,
+        channel_id: String,
+        action: ScheduleAction,
+        cron: Option<String>,
+        run_at: Option<String>,
+    ) -> FieldResult<ChannelSchedule> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+
+        context.authorize_management_change(guild_id)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        updatable_voice_channel(context, guild_id, channel_id)?;
+
+        let recurrence = match (cron, run_at) {
+            (Some(cron), None) => Recurrence::Cron(cron.trim().to_owned()),
+            (None, Some(run_at)) => Recurrence::Once(run_at.parse().context("Invalid run time")?),
+            _ => {
+                return Err(FieldError::new(
+                    "Exactly one of cron and runAt has to be given",
+                    Value::null(),
+                ))
+            }
+        };
+
+        let store = context.schedules.store();
+
+        if store.guild_schedules(guild_id).await?.len() >= MAX_GUILD_SCHEDULES {
+            return Err(FieldError::new(
+                format!("A guild can have at most {} schedules", MAX_GUILD_SCHEDULES),
+                Value::null(),
+            ));
+        }
+
+        let timezone = store.timezone(guild_id).await?.unwrap_or(Tz::UTC);
+        let schedule = Schedule::create(
+            guild_id,
+            channel_id,
+            action,
+            recurrence,
+            timezone,
+            context.user.user_id(),
+        )
+        .map_err(|error| FieldError::new(format!("{:#}", error), Value::null()))?;
+
+        store.insert(schedule.clone()).await?;
+        context.schedules.wake();
+
+        Ok(schedule.into())
+    }
+
+    /// Remove a scheduled mute from a guild that the logged in user manages.
+    ///
+    /// # Returns
+    /// If a schedule with the id existed
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the schedule belongs to"),
+        id(description = "Id of the schedule to remove"),
+    ))]
+    async fn delete_schedule(
+        context: &GraphQLContext,
+        guild_id: String,
+        id: String,
+    ) -> FieldResult<bool> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management_change(guild_id)?;
+
+        let removed = context.schedules.store().remove(guild_id, &id).await?;
+        context.schedules.wake();
+
+        Ok(removed)
+    }
+
+    /// Set the timezone that the recurring scheduled mutes of a guild that the logged in user
+    /// manages are evaluated in, moving their next runs to match.
+    ///
+    /// # Returns
+    /// The name of the timezone
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild to set the timezone of"),
+        timezone(description = "IANA name of the timezone, such as `Europe/Amsterdam`"),
+    ))]
+    async fn set_timezone(
+        context: &GraphQLContext,
+        guild_id: String,
+        timezone: String,
+    ) -> FieldResult<String> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+
+        context.authorize_management_change(guild_id)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        let timezone: Tz = timezone.parse().map_err(|_| {
+            FieldError::new(format!("Unknown timezone {}", timezone), Value::null())
+        })?;
+
+        let store = context.schedules.store();
+        store.set_timezone(guild_id, timezone).await?;
+
+        let now = unix_timestamp();
+        for schedule in store.guild_schedules(guild_id).await? {
+            if let Recurrence::Cron(_) = schedule.recurrence {
+                store
+                    .update_runs(
+                        &schedule.id,
+                        schedule.last_run_at,
+                        schedule.next_run_after(timezone, now)?,
+                    )
+                    .await?;
+            }
+        }
+        context.schedules.wake();
+
+        Ok(timezone.name().to_owned())
+    }
+
+    /// Log out of one of the logged in user's sessions.
+    ///
+    /// # Returns
+    /// If a session with the id existed
+    #[graphql(arguments(id(description = "Id of the session to log out of")))]
+    async fn logout_session(context: &GraphQLContext, id: String) -> FieldResult<bool> {
+        Ok(context
+            .sessions
+            .remove_by_id(context.oauth_user()?.session.user_id, &id)
+            .await?)
+    }
+
+    /// Log out of every session of the logged in user, including the current one.
+    ///
+    /// # Returns
+    /// The amount of sessions that were logged out of
+    async fn logout_everywhere(context: &GraphQLContext) -> FieldResult<i32> {
+        let user_id = context.oauth_user()?.session.user_id;
+
+        context.discord.user_guilds.invalidate(user_id).await?;
+
+        Ok(context
+            .sessions
+            .remove_user_sessions(user_id)
+            .await?
+            .try_into()?)
+    }
+
+    /// Fetch the guilds that the logged in user is in from discord again, instead of waiting for
+    /// the cached list to expire.
+    ///
+    /// # Returns
+    /// The intersection of guilds between the logged in user and the bot
+    async fn refresh_guilds(context: &GraphQLContext) -> FieldResult<Vec<Guild>> {
+        context.rate_limit(None).await?;
+
+        let guilds = context
+            .discord
+            .user_guilds
+            .refresh(context.oauth_user()?)
+            .await?;
+
+        Ok(guilds
+            .iter()
+            .filter_map(|guild| context.discord.data.guild(guild.id).map(Guild::from))
+            .collect())
+    }
+
+    /// Mute all users in a voice channel.
+    ///
+    /// # Returns
+    /// Id's of users who were successfully muted
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the channel resides in",),
+        channel_id(description = "Id of the channel to mutate",),
+    ))]
+    async fn mute(
+        guild_id: String,
+        channel_id: String,
+        context: &GraphQLContext,
+    ) -> FieldResult<Vec<String>> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+
+        context.authorize(guild_id, TokenAction::Mute)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        updatable_voice_channel(context, guild_id, channel_id)?;
+
+        Ok(set_channel_mute(context, guild_id, channel_id, true)
+            .await?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect())
+    }
+
+    /// Unmute all users in a voice channel.
+    ///
+    /// # Returns
+    /// Id's of users who were successfully un-muted
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the channel resides in",),
+        channel_id(description = "Id of the channel to mutate",),
+    ))]
+    async fn unmute(
+        guild_id: String,
+        channel_id: String,
+        context: &GraphQLContext,
+    ) -> FieldResult<Vec<String>> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+
+        context.authorize(guild_id, TokenAction::Unmute)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        updatable_voice_channel(context, guild_id, channel_id)?;
+
+        Ok(set_channel_mute(context, guild_id, channel_id, false)
+            .await?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect())
+    }
+
+    /// Put a voice channel in speaker mode, muting everyone in it except the speakers, who are
+    /// unmuted. Giving the floor to other members of a channel already in speaker mode passes it
+    /// on, unmuting the new speakers and muting the old ones.
+    ///
+    /// # Returns
+    /// The new floor of the channel
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the channel resides in",),
+        channel_id(description = "Id of the channel to mutate",),
+        speaker_ids(description = "Id's of the members in the channel to give the floor to",),
+    ))]
+    async fn give_floor(
+        guild_id: String,
+        channel_id: String,
+        speaker_ids: Vec<String>,
+        context: &GraphQLContext,
+    ) -> FieldResult<VoiceChannelFloor> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+        let speakers = speaker_ids
+            .iter()
+            .map(|id| Ok(UserId(id.parse().context("Invalid speaker id")?)))
+            .collect::<FieldResult<Vec<_>>>()?;
+
+        context.authorize(guild_id, TokenAction::Mute)?;
+        context.authorize(guild_id, TokenAction::Unmute)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        updatable_voice_channel(context, guild_id, channel_id)?;
+
+        if speakers.is_empty() {
+            return Err(FieldError::new(
+                "The floor has to be given to at least one member",
+                Value::null(),
+            ));
+        }
+
+        let states = context
+            .discord
+            .data
+            .voice_channel_states(channel_id)
+            .unwrap_or_default();
+
+        if let Some(speaker_id) = speakers
+            .iter()
+            .find(|&&speaker_id| !states.iter().any(|state| state.user_id == speaker_id))
+        {
+            return Err(FieldError::new(
+                "Speaker is not in the voice channel",
+                graphql_value!({ "speaker_id": (speaker_id.to_string()) }),
+            ));
+        }
+
+        Ok(pass_floor(context, guild_id, channel_id, speakers)
+            .await?
+            .into())
+    }
+
+    /// Take a voice channel out of speaker mode, unmuting everyone in it. This is the same as
+    /// unmuting the channel.
+    ///
+    /// # Returns
+    /// Id's of users who were successfully un-muted
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the channel resides in",),
+        channel_id(description = "Id of the channel to mutate",),
+    ))]
+    async fn release_floor(
+        guild_id: String,
+        channel_id: String,
+        context: &GraphQLContext,
+    ) -> FieldResult<Vec<String>> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+
+        context.authorize(guild_id, TokenAction::Unmute)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        updatable_voice_channel(context, guild_id, channel_id)?;
+
+        Ok(set_channel_mute(context, guild_id, channel_id, false)
+            .await?
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect())
+    }
+
+    /// Join the speaking queue of the voice channel that the user is in, to ask for a turn to
+    /// speak.
+    ///
+    /// # Returns
+    /// The speaking queue of the channel
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the channel resides in",),
+        channel_id(description = "Id of the channel to join the speaking queue of",),
+    ))]
+    async fn raise_hand(
+        guild_id: String,
+        channel_id: String,
+        context: &GraphQLContext,
+    ) -> FieldResult<Vec<QueuedSpeaker>> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+        let user_id = context.user.user_id();
+
+        context.authorize(guild_id, TokenAction::Read)?;
+        // Raising a hand only affects the user, so the guild's budget is left to moderators
+        context.rate_limit(None).await?;
+
+        let in_channel = context
+            .discord
+            .data
+            .voice_state(user_id, guild_id)
+            .map_or(false, |state| state.channel_id == Some(channel_id));
+
+        if !in_channel {
+            return Err(FieldError::new(
+                "You have to be in the voice channel to join its speaking queue",
+                Value::null(),
+            ));
+        }
+
+        let joined = context
+            .floors
+            .join_queue(QueueEntry {
+                channel_id,
+                guild_id,
+                user_id,
+                joined_at: unix_timestamp(),
+            })
+            .await?;
+
+        let queue = if joined {
+            queue_changed(context, guild_id, channel_id).await?
+        } else {
+            context.floors.queue(channel_id).await?
+        };
+
+        Ok(queue.into_iter().map(QueuedSpeaker::from).collect())
+    }
+
+    /// Leave the speaking queue of a voice channel. Moderators can also remove other members
+    /// from the queue.
+    ///
+    /// # Returns
+    /// If the member was in the queue
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the channel resides in",),
+        channel_id(description = "Id of the channel to leave the speaking queue of",),
+        user_id(description = "Id of the member to remove from the queue, defaults to the user",),
+    ))]
+    async fn lower_hand(
+        guild_id: String,
+        channel_id: String,
+        user_id: Option<String>,
+        context: &GraphQLContext,
+    ) -> FieldResult<bool> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+        let user_id = match user_id {
+            Some(user_id) => UserId(user_id.parse().context("Invalid user id")?),
+            None => context.user.user_id(),
+        };
+
+        context.authorize(guild_id, TokenAction::Read)?;
+        context.rate_limit(None).await?;
+
+        if user_id != context.user.user_id() {
+            context.authorize(guild_id, TokenAction::Mute)?;
+            updatable_voice_channel(context, guild_id, channel_id)?;
+        }
+
+        let left = context.floors.leave_queue(channel_id, user_id).await?;
+
+        if left {
+            queue_changed(context, guild_id, channel_id).await?;
+        }
+
+        Ok(left)
+    }
+
+    /// Give the floor of a voice channel to the next member in its speaking queue, unmuting them
+    /// and muting everyone else, including the previous speaker. Members who left the channel
+    /// while waiting are skipped and removed from the queue.
+    ///
+    /// # Returns
+    /// The new floor of the channel
+    #[graphql(arguments(
+        guild_id(description = "Id of the guild that the channel resides in",),
+        channel_id(description = "Id of the channel to mutate",),
+    ))]
+    async fn advance_queue(
+        guild_id: String,
+        channel_id: String,
+        context: &GraphQLContext,
+    ) -> FieldResult<VoiceChannelFloor> {
+        let guild_id = GuildId(guild_id.parse().context("Invalid guild id")?);
+        let channel_id = ChannelId(channel_id.parse().context("Invalid channel id")?);
+
+        context.authorize(guild_id, TokenAction::Mute)?;
+        context.authorize(guild_id, TokenAction::Unmute)?;
+        context.rate_limit(Some(guild_id)).await?;
+
+        updatable_voice_channel(context, guild_id, channel_id)?;
+
+        let states = context
+            .discord
+            .data
+            .voice_channel_states(channel_id)
+            .unwrap_or_default();
+
+        // Members who left the channel while waiting are skipped, up to the first one still in it
+        let queue = context.floors.queue(channel_id).await?;
+        let next = queue
+            .iter()
+            .position(|entry| states.iter().any(|state| state.user_id == entry.user_id));
+        let removed = &queue[..next.map_or(queue.len(), |next| next + 1)];
+
+        // The queue is only changed once the floor was passed, so a failure loses nobody's turn
+        let floor = match next {
+            Some(next) => {
+                Some(pass_floor(context, guild_id, channel_id, vec![queue[next].user_id]).await?)
+            }
+            None => None,
+        };
+
+        for entry in removed {
+            context
+                .floors
+                .leave_queue(channel_id, entry.user_id)
+                .await?;
+        }
+
+        if !removed.is_empty() {
+            queue_changed(context, guild_id, channel_id).await?;
+        }
+
+        floor.map(VoiceChannelFloor::from).ok_or_else(|| {
+            FieldError::new(
+                "Nobody in the voice channel is waiting to speak",
+                Value::null(),
+            )
+        })
+    }
+}
+
+/// Give the floor of a voice channel to the speakers, unmuting them and muting everyone else,
+/// and let webhooks know who holds it
+///
+/// The floor is saved before anyone is unmuted, so that the new speakers are not muted again for
+/// joining the channel without holding its floor.
+///
+/// # Errors
+/// If the voice states of the channel could not be read, or the floor store fails
+async fn pass_floor(
+    context: &GraphQLContext,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    speakers: Vec<UserId>,
+) -> FieldResult<Floor> {
+    let floor = Floor {
+        channel_id,
+        guild_id,
+        speakers,
+        given_by: context.user.user_id(),
+        given_at: unix_timestamp(),
+    };
+    context.floors.set_floor(floor.clone()).await?;
+
+    let changed = context
+        .update_voice_states(guild_id, channel_id, true, &floor.speakers)
+        .await?;
+    let (unmuted, muted): (Vec<_>, Vec<_>) = changed
+        .into_iter()
+        .partition(|id| floor.speakers.contains(id));
+
+    context.webhooks.dispatch(
+        guild_id,
+        WebhookEvent::Floor,
+        floor_event(&floor, floor.given_by, &muted, &unmuted),
+    );
+
+    Ok(floor)
+}
+
+/// Let webhooks know that the speaking queue of a voice channel changed
+///
+/// # Returns
+/// The speaking queue of the channel
+///
+/// # Errors
+/// If the floor store fails
+async fn queue_changed(
+    context: &GraphQLContext,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> FieldResult<Vec<QueueEntry>> {
+    let queue = context.floors.queue(channel_id).await?;
+
+    context.webhooks.dispatch(
+        guild_id,
+        WebhookEvent::Queue,
+        json!({
+            "channel_id": channel_id.to_string(),
+            "actor_id": context.user.user_id().to_string(),
+            "user_ids": queue
+                .iter()
+                .map(|entry| entry.user_id.to_string())
+                .collect::<Vec<_>>(),
+        }),
+    );
+
+    Ok(queue)
+}
+
+/// Mute or unmute everyone in a voice channel in the name of the logged in user, taking it out
+/// of speaker mode, and forget the members loaded so far so the rest of the request sees the
+/// change
+///
+/// # Returns
+/// Id's of users whose mute status was changed
+///
+/// # Errors
+/// If the voice states of the channel could not be read, or the floor store fails
+async fn set_channel_mute(
+    context: &GraphQLContext,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    mute: bool,
+) -> FieldResult<Vec<UserId>> {
+    let changed = mute_channel(
+        &context.discord.data,
+        &context.webhooks,
+        &context.notifications,
+        &context.floors,
+        guild_id,
+        channel_id,
+        context.user.user_id(),
+        mute,
+    )
+    .await;
+    context.loaders.members.clear();
+
+    changed
+}
+
+/// Mute or unmute everyone in a voice channel, taking it out of speaker mode, and let webhooks
+/// and the mute messages know
+///
+/// The floor is removed before anyone is unmuted, so that they are not muted again for being in
+/// a channel in speaker mode without holding its floor.
+///
+/// # Returns
+/// Id's of users whose mute status was changed
+///
+/// # Errors
+/// If the voice states of the channel could not be read, or the floor store fails
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn mute_channel(
+    data: &SharedDataSource,
+    webhooks: &WebhookSender,
+    notifications: &MuteNotifier,
+    floors: &Floors,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    actor_id: UserId,
+    mute: bool,
+) -> FieldResult<Vec<UserId>> {
+    let floor = floors.remove_floor(channel_id).await?;
+
+    let changed = mass_update_voice_state(data.as_ref(), channel_id, guild_id, mute, &[]).await?;
+
+    webhooks.dispatch(
+        guild_id,
+        if mute {
+            WebhookEvent::Mute
+        } else {
+            WebhookEvent::Unmute
+        },
+        voice_state_event(channel_id, actor_id, &changed),
+    );
+
+    if let Some(floor) = floor {
+        webhooks.dispatch(
+            guild_id,
+            WebhookEvent::Floor,
+            floor_event(
+                &Floor {
+                    speakers: Vec::new(),
+                    ..floor
+                },
+                actor_id,
+                &[],
+                if mute { &[] } else { &changed },
+            ),
+        );
+    }
+
+    if mute {
+        if !changed.is_empty() {
+            notifications.mute_started(data.clone(), guild_id, channel_id, actor_id, changed.len());
+        }
+    } else {
+        notifications.mute_lifted(data.clone(), channel_id, actor_id, changed.len());
+    }
+
+    Ok(changed)
+}
+
+/// Server mute or unmute every user, other than bots, in a voice channel
+///
+/// The speakers are given the opposite state, so muting a channel with speakers leaves only them
+/// able to talk.
+///
+/// # Returns
+/// Id's of users whose mute status was changed
+///
+/// # Errors
+/// If the voice states of the channel could not be read
+pub async fn mass_update_voice_state(
+    data: &dyn DataSource,
+    channel_id: ChannelId,
+    guild_id: GuildId,
+    mute: bool,
+    speakers: &[UserId],
+) -> FieldResult<Vec<UserId>> {
+    if let Some(states) = data.voice_channel_states(channel_id) {
+        let (send_muted, receive_muted) = mpsc::channel();
+
+        // Remove bots
+        let states = states
+            .into_iter()
+            .filter_map(|state| {
+                if data.user(state.user_id)?.bot {
+                    None
+                } else {
+                    Some(Ok(state))
+                }
+            })
+            .collect::<FieldResult<Vec<_>>>()?;
+
+        for chunk in states.chunks(10) {
+            let send_muted = send_muted.clone();
+
+            join_all(chunk.iter().map(move |state| {
+                let send_muted = send_muted.clone();
+
+                async move {
+                    let mute = mute != speakers.contains(&state.user_id);
+
+                    if state.mute != mute
+                        && data
+                            .update_member_mute(guild_id, state.user_id, mute)
                             .await
                             .is_ok()
                     {
@@ -757,6 +3083,37 @@ async fn mass_update_voice_state(
     }
 }
 
+/// The data of a mute or unmute event sent to webhooks
+fn voice_state_event(
+    channel_id: ChannelId,
+    actor_id: UserId,
+    member_ids: &[UserId],
+) -> serde_json::Value {
+    json!({
+        "channel_id": channel_id.to_string(),
+        "actor_id": actor_id.to_string(),
+        "member_ids": member_ids.iter().map(ToString::to_string).collect::<Vec<_>>(),
+    })
+}
+
+/// The data of a floor event sent to webhooks
+fn floor_event(
+    floor: &Floor,
+    actor_id: UserId,
+    muted_ids: &[UserId],
+    unmuted_ids: &[UserId],
+) -> serde_json::Value {
+    let ids = |ids: &[UserId]| ids.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+    json!({
+        "channel_id": floor.channel_id.to_string(),
+        "actor_id": actor_id.to_string(),
+        "speaker_ids": ids(&floor.speakers),
+        "muted_ids": ids(muted_ids),
+        "unmuted_ids": ids(unmuted_ids),
+    })
+}
+
 /// The graphql schema described in this file
 pub type Schema = RootNode<'static, QueryRoot, MutationRoot, EmptySubscription<GraphQLContext>>;
 
@@ -0,0 +1,155 @@
+//! A server side cache of the guilds that each oauth user is in
+//!
+//! Api servers keep the lists in redis instead of in memory, so that a list refreshed or
+//! forgotten by one server is refreshed or forgotten for all of them.
+
+use crate::{auth::OauthUser, config::Config, data_source::RedisCache};
+use log::warn;
+use std::{
+    collections::HashMap,
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+use twilight_model::{id::UserId, user::CurrentUserGuild};
+
+/// The guilds that a user is in, along with when they were fetched
+#[derive(Debug)]
+struct Entry {
+    /// When the guilds were fetched from discord
+    fetched_at: Instant,
+    /// The guilds that the user is in
+    guilds: Arc<Vec<CurrentUserGuild>>,
+}
+
+/// A cache of the guilds that each oauth user is in, keyed by their user id
+///
+/// Fetching the guild list uses up the rate limit of the user's token, so the list is kept
+/// around for the configured time to live before being fetched again.
+#[derive(Debug)]
+pub struct UserGuildCache {
+    /// How long a fetched guild list is valid for
+    ttl: Duration,
+    /// The cached guild lists, unless they are kept in redis
+    entries: RwLock<HashMap<UserId, Entry>>,
+    /// The redis server that the guild lists are kept in, shared with the other api servers
+    redis: Option<RedisCache>,
+}
+
+impl UserGuildCache {
+    /// Create an empty cache where guild lists live for the given time to live
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+            redis: None,
+        }
+    }
+
+    /// Create an empty cache with the time to live from the config, keeping the guild lists in
+    /// the redis server if there is one
+    #[must_use]
+    pub fn from_config(config: &Config, redis: Option<RedisCache>) -> Self {
+        Self {
+            redis,
+            ..Self::new(Duration::from_secs(config.user_guild_cache_ttl))
+        }
+    }
+
+    /// The name of the redis key holding the guild list of a user
+    fn redis_key(user_id: UserId) -> String {
+        format!("user_guilds:{}", user_id)
+    }
+
+    /// Get the guilds that the user is in, fetching them from discord if they are not cached or
+    /// the cached list has expired
+    ///
+    /// # Errors
+    /// If the guilds had to be fetched and the request to discord failed
+    pub async fn get(
+        &self,
+        user: &OauthUser,
+    ) -> Result<Arc<Vec<CurrentUserGuild>>, twilight_http::Error> {
+        if let Some(redis) = &self.redis {
+            // Redis being unreachable only costs a request to discord
+            match redis
+                .read_value(&Self::redis_key(user.session.user_id))
+                .await
+            {
+                Ok(Some(guilds)) => return Ok(Arc::new(guilds)),
+                Ok(None) => {}
+                Err(error) => warn!("Failed to read the cached guilds of a user: {:#}", error),
+            }
+
+            return self.refresh(user).await;
+        }
+
+        let cached = self
+            .entries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&user.session.user_id)
+            .filter(|entry| entry.fetched_at.elapsed() < self.ttl)
+            .map(|entry| entry.guilds.clone());
+
+        match cached {
+            Some(guilds) => Ok(guilds),
+            None => self.refresh(user).await,
+        }
+    }
+
+    /// Fetch the guilds that the user is in from discord, replacing any cached list
+    ///
+    /// # Errors
+    /// If the request to discord failed
+    pub async fn refresh(
+        &self,
+        user: &OauthUser,
+    ) -> Result<Arc<Vec<CurrentUserGuild>>, twilight_http::Error> {
+        let guilds = Arc::new(user.http.current_user_guilds().await?);
+
+        if let Some(redis) = &self.redis {
+            let written = redis
+                .write_value(&Self::redis_key(user.session.user_id), &*guilds, self.ttl)
+                .await;
+            if let Err(error) = written {
+                warn!("Failed to cache the guilds of a user: {:#}", error);
+            }
+
+            return Ok(guilds);
+        }
+
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+
+        // Drop expired lists so users who stopped visiting do not linger forever
+        let ttl = self.ttl;
+        entries.retain(|_, entry| entry.fetched_at.elapsed() < ttl);
+
+        entries.insert(
+            user.session.user_id,
+            Entry {
+                fetched_at: Instant::now(),
+                guilds: guilds.clone(),
+            },
+        );
+
+        Ok(guilds)
+    }
+
+    /// Forget the cached guilds of a user
+    ///
+    /// # Errors
+    /// If the guilds are kept in redis and it could not be reached
+    pub async fn invalidate(&self, user_id: UserId) -> anyhow::Result<()> {
+        if let Some(redis) = &self.redis {
+            return redis.remove_value(&Self::redis_key(user_id)).await;
+        }
+
+        self.entries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&user_id);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+//! Links to invite the bot to a guild
+
+use crate::consts::REQUIRED_PERMISSIONS;
+use twilight_model::id::GuildId;
+use twilight_oauth2::Client as OauthClient;
+
+/// Create a link that adds the bot to a guild with the permissions it needs
+///
+/// If a guild is given, it is selected in the prompt ahead of time.
+#[must_use]
+pub fn invite_url(oauth: &OauthClient, guild_id: Option<GuildId>) -> String {
+    let mut url = oauth.bot_authorization_url();
+    url.permissions(REQUIRED_PERMISSIONS);
+
+    if let Some(guild_id) = guild_id {
+        url.guild_id(guild_id).disable_guild_select(true);
+    }
+
+    url.build()
+}
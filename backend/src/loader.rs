@@ -0,0 +1,131 @@
+//! Per request loaders to deduplicate and batch the lookups made by the graphql resolvers
+
+use futures::lock::Mutex as AsyncMutex;
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// A memoizing loader for values keyed by `K`, meant to only live for a single request
+///
+/// Each key is fetched at most once, and the result of the fetch, including a miss, is reused
+/// for every later load of that key.
+#[derive(Debug)]
+pub struct Loader<K, V> {
+    /// The values that have already been fetched
+    values: Mutex<HashMap<K, Option<V>>>,
+}
+
+impl<K, V> Default for Loader<K, V> {
+    fn default() -> Self {
+        Self {
+            values: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Copy, V: Clone> Loader<K, V> {
+    /// Load a single value, fetching it if it has not been loaded yet
+    ///
+    /// The fetch runs without holding the lock, so it may look up other values of the loader.
+    pub fn load(&self, key: K, fetch: impl FnOnce(K) -> Option<V>) -> Option<V> {
+        if let Some(value) = self.values().get(&key) {
+            return value.clone();
+        }
+
+        let fetched = fetch(key);
+
+        self.values().entry(key).or_insert(fetched).clone()
+    }
+
+    /// Load many values at once, fetching all of the values that have not been loaded yet in a
+    /// single batch
+    ///
+    /// Keys missing from the map returned by the fetch are remembered as misses.
+    pub fn load_many(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        fetch: impl FnOnce(&[K]) -> HashMap<K, V>,
+    ) -> Vec<Option<V>> {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let missing: Vec<K> = {
+            let values = self.values();
+
+            keys.iter()
+                .filter(|key| !values.contains_key(key))
+                .copied()
+                .collect()
+        };
+
+        let mut fetched = if missing.is_empty() {
+            HashMap::new()
+        } else {
+            fetch(&missing)
+        };
+
+        let mut values = self.values();
+
+        for key in missing {
+            values.entry(key).or_insert_with(|| fetched.remove(&key));
+        }
+
+        keys.iter()
+            .map(|key| values.get(key).cloned().flatten())
+            .collect()
+    }
+
+    /// Forget every loaded value, so later loads fetch them again
+    ///
+    /// Used after a mutation changed the values, so the rest of the request does not see them
+    /// stale.
+    pub fn clear(&self) {
+        self.values().clear();
+    }
+
+    /// Lock the loaded values
+    fn values(&self) -> MutexGuard<'_, HashMap<K, Option<V>>> {
+        self.values.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A memoized value that is fetched asynchronously, meant to only live for a single request
+///
+/// Concurrent loads wait for the first fetch to finish instead of fetching the value again.
+/// Failed fetches are not remembered so that a later load can retry them.
+#[derive(Debug)]
+pub struct Memo<V> {
+    /// The value, if it has been fetched
+    value: AsyncMutex<Option<V>>,
+}
+
+impl<V> Default for Memo<V> {
+    fn default() -> Self {
+        Self {
+            value: AsyncMutex::new(None),
+        }
+    }
+}
+
+impl<V: Clone> Memo<V> {
+    /// Get the value, fetching it if it has not been fetched yet
+    ///
+    /// # Errors
+    /// If the value had to be fetched and the fetch failed
+    pub async fn get_or_try_load<F, E>(&self, fetch: impl FnOnce() -> F) -> Result<V, E>
+    where
+        F: Future<Output = Result<V, E>>,
+    {
+        let mut value = self.value.lock().await;
+
+        if let Some(value) = value.as_ref() {
+            return Ok(value.clone());
+        }
+
+        let fetched = fetch().await?;
+        *value = Some(fetched.clone());
+
+        Ok(fetched)
+    }
+}
@@ -8,102 +8,247 @@
     missing_debug_implementations,
     missing_copy_implementations
 )]
-#![cfg_attr(feature = "generate_schema", allow(unused_imports))]
 
 use anyhow::Context;
 use async_std::{stream::StreamExt, task};
+use cli::{admin, Cli, Command};
 use config::Config;
-use consts::OAUTH_REDIRECT_URLS;
+use consts::{GATEWAY_INTENTS, OAUTH_REDIRECT_URLS};
+use data_source::{LiveDataSource, RedisCache, RedisDataSource, SharedDataSource};
 use dotenv::dotenv;
+use floor::FloorGuard;
 use graphql::{create_schema, DiscordContext};
-use log::warn;
-use reqwest::Client as ReqwestClient;
-use rocket::{
-    figment::{providers::Env, Figment},
-    http::Method,
-    routes,
+use guild_cache::UserGuildCache;
+use notification::MuteNotifier;
+use persisted_queries::PersistedQueries;
+use query_limits::QueryLimits;
+use rate_limit::RateLimiter;
+use reqwest::{
+    redirect::Policy, Certificate, Client as ReqwestClient, ClientBuilder as ReqwestClientBuilder,
+    Proxy,
 };
+use rocket::{http::Method, routes};
 use rocket_cors::CorsOptions;
-use std::sync::Arc;
+use schedule::Scheduler;
+use std::{sync::Arc, time::Duration};
+use structopt::StructOpt;
 use twilight_cache_inmemory::InMemoryCache;
-use twilight_gateway::shard::ShardBuilder;
+use twilight_gateway::shard::{Shard, ShardBuilder};
 use twilight_http::{client::ClientBuilder as HttpClientBuilder, Client as HttpClient};
-use twilight_model::gateway::Intents;
+use twilight_model::gateway::event::Event;
 use twilight_oauth2::Client as OauthClient;
+use webhook::{client::PinnedClient, WebhookClient, WebhookSender};
 
+pub mod api_token;
 pub mod auth;
+pub mod cdn;
+pub mod cli;
 pub mod config;
 pub mod consts;
+pub mod data_source;
+pub mod database;
+pub mod floor;
 pub mod graphql;
+pub mod guild_cache;
+pub mod invite;
+pub mod loader;
+pub mod notification;
+pub mod persisted_queries;
+pub mod query_limits;
+pub mod rate_limit;
 pub mod routes;
+pub mod schedule;
+pub mod session;
 pub mod templates;
+pub mod webhook;
 
 #[cfg(all(feature = "mitm_proxy", not(debug_assertions)))]
-compile_error!("You cannot have the `mitm_proxy` feature enabled in release mode");
+compile_error!(
+    "You cannot have the `mitm_proxy` feature enabled in release mode, use `egress_proxy` instead"
+);
 
-/// Helper function to create a pre-configured discord http client
-pub fn create_http_client(token: impl Into<String>, _config: &Config) -> HttpClient {
-    let builder = HttpClientBuilder::new().token(token);
+/// Read a PEM certificate from a file
+fn read_certificate(path: &str) -> anyhow::Result<Certificate> {
+    let pem =
+        std::fs::read(path).with_context(|| format!("Failed to read the certificate {}", path))?;
+
+    Certificate::from_pem(&pem).with_context(|| format!("The certificate {} is invalid", path))
+}
+
+/// Apply the proxy, extra certificates and timeouts from the config to a reqwest client builder
+///
+/// # Errors
+/// If the proxy url is malformed or a certificate could not be read
+pub fn configure_reqwest(
+    mut builder: ReqwestClientBuilder,
+    config: &Config,
+) -> anyhow::Result<ReqwestClientBuilder> {
+    builder = builder
+        .timeout(Duration::from_secs(config.http_timeout))
+        .connect_timeout(Duration::from_secs(config.http_connect_timeout));
+
+    for path in &config.extra_ca_certs {
+        builder = builder.add_root_certificate(read_certificate(path)?);
+    }
 
     #[cfg(feature = "mitm_proxy")]
     {
-        warn!("Creating a http client with a connection to mitm proxy");
-
-        builder
-            .proxy(reqwest::Proxy::all(&_config.proxy_url).expect("Proxy url was malformed"))
-            .reqwest_client(
-                reqwest::ClientBuilder::new().add_root_certificate(
-                    reqwest::Certificate::from_pem(
-                        std::fs::read(&_config.proxy_cert_path)
-                            .expect("Failed to read in proxy cert")
-                            .as_slice(),
-                    )
-                    .expect("Certificate was in an invalid format"),
-                ),
-            )
-            .build()
-            .unwrap()
+        log::warn!("Creating a http client with a connection to mitm proxy");
+
+        Ok(builder
+            .proxy(Proxy::all(&config.proxy_url).context("Proxy url was malformed")?)
+            .add_root_certificate(read_certificate(&config.proxy_cert_path)?))
     }
 
     #[cfg(not(feature = "mitm_proxy"))]
-    builder.build().unwrap()
+    {
+        if let Some(api_url) = config.discord_api()? {
+            log::warn!(
+                "Sending every request to the stand-in discord api at {}",
+                api_url
+            );
+
+            builder = builder.proxy(Proxy::all(api_url).context("Discord api url was malformed")?);
+        } else if let Some(proxy_url) = &config.egress_proxy {
+            builder =
+                builder.proxy(Proxy::all(proxy_url).context("Egress proxy url was malformed")?);
+        }
+
+        Ok(builder)
+    }
+}
+
+/// Helper function to create a pre-configured discord http client
+///
+/// The reqwest client, made by [`create_reqwest_client`] once at startup, is shared by every
+/// discord http client, so the http config is only read once.
+pub fn create_http_client(
+    token: impl Into<String>,
+    config: &Config,
+    reqwest: &ReqwestClient,
+) -> HttpClient {
+    HttpClientBuilder::new()
+        .token(token)
+        .timeout(Duration::from_secs(config.http_timeout))
+        // The stand-in api is used as a proxy, which discord requests are sent to over http
+        .proxy_http(config.discord_api_url.is_some())
+        .reqwest_client(reqwest.clone())
+        .build()
+        .unwrap()
 }
 
 /// Helper function to create a pre-configured reqwest client
-#[must_use = "The created client must be used"]
-pub fn create_reqwest_client(_config: &Config) -> ReqwestClient {
-    #[cfg(feature = "mitm_proxy")]
-    {
-        warn!("Creating a http client with a connection to mitm proxy");
-
-        ReqwestClient::builder()
-            .proxy(reqwest::Proxy::all(&_config.proxy_url).expect("Proxy url was malformed"))
-            .add_root_certificate(
-                reqwest::Certificate::from_pem(
-                    std::fs::read(&_config.proxy_cert_path)
-                        .expect("Failed to read in proxy cert")
-                        .as_slice(),
-                )
-                .expect("Certificate was in an invalid format"),
-            )
-            .build()
-            .unwrap()
+///
+/// # Errors
+/// If the proxy url is malformed or a certificate could not be read
+pub fn create_reqwest_client(config: &Config) -> anyhow::Result<ReqwestClient> {
+    configure_reqwest(ReqwestClient::builder(), config)?
+        .build()
+        .context("Failed to create the http client")
+}
+
+/// Helper function to create the client that webhooks are sent with
+///
+/// Webhooks are not discord requests, so they are never sent to the stand-in discord api.
+/// Without a proxy, webhooks connect straight to the addresses their url was checked against.
+/// Redirects are not followed, as they could lead to an address that webhooks are not allowed
+/// to be sent to.
+///
+/// # Errors
+/// If the proxy url is malformed or a certificate could not be read
+pub fn create_webhook_client(config: &Config) -> anyhow::Result<WebhookClient> {
+    if config.egress_proxy.is_none() && !cfg!(feature = "mitm_proxy") {
+        return Ok(WebhookClient::Direct(PinnedClient::from_config(config)?));
     }
 
-    #[cfg(not(feature = "mitm_proxy"))]
-    ReqwestClient::new()
+    let config = Config {
+        discord_api_url: None,
+        ..config.clone()
+    };
+
+    configure_reqwest(ReqwestClient::builder().redirect(Policy::none()), &config)?
+        .build()
+        .map(WebhookClient::Proxied)
+        .context("Failed to create the webhook client")
 }
 
-#[cfg(not(feature = "generate_schema"))]
 #[async_std::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
+    pretty_env_logger::init();
 
-    let config: Config = envy::from_env().context("Missing required environment variables")?;
+    match Cli::from_args().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(Config::load()?, ServerMode::Standalone).await,
+        Command::Gateway => gateway(Config::load()?).await,
+        Command::Api => serve(Config::load()?, ServerMode::Api).await,
+        Command::Schema { format, out } => cli::schema::schema(format, out.as_deref()),
+        Command::CheckConfig => admin::check_config().await,
+        Command::Mute { channel_id } => {
+            admin::set_channel_mute(&Config::load()?, channel_id, true).await
+        }
+        Command::Unmute { channel_id } => {
+            admin::set_channel_mute(&Config::load()?, channel_id, false).await
+        }
+        Command::Guilds => admin::guilds(&Config::load()?).await,
+        Command::InviteUrl => admin::invite_url(&Config::load()?).await,
+        #[cfg(debug_assertions)]
+        Command::Query {
+            fixture,
+            user,
+            variables,
+            query,
+        } => cli::query::query(&fixture, user, variables.as_deref(), &query).await,
+    }
+}
 
-    pretty_env_logger::init();
+/// Where the server gets its discord data from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ServerMode {
+    /// Connect to the gateway in the same process
+    Standalone,
+    /// Read from the redis cache filled by the gateway worker and send updates to it, so that
+    /// restarting the server does not reconnect to the gateway
+    Api,
+}
 
-    let http = create_http_client(&config.token, &config);
+/// Run the server until it is shut down
+async fn serve(config: Config, mode: ServerMode) -> anyhow::Result<()> {
+    // Api servers share everything that has to be seen by all of them through redis and the
+    // databases
+    let redis = match mode {
+        ServerMode::Api => {
+            config.require_shared_databases()?;
+
+            Some(
+                RedisCache::from_config(&config)?
+                    .context("REDIS_URL has to be set for the api to reach the gateway worker")?,
+            )
+        }
+        ServerMode::Standalone => None,
+    };
+
+    let sessions = session::create_session_store(&config)?;
+    let api_tokens = api_token::create_api_token_store(&config)?;
+    let webhook_store = webhook::create_webhook_store(&config)?;
+    let notifications = MuteNotifier::new(
+        notification::create_notification_store(&config)?,
+        redis.clone(),
+    );
+    let floors = match mode {
+        ServerMode::Api => floor::create_shared_floor_store(&config)?,
+        ServerMode::Standalone => floor::create_floor_store(&config)?,
+    };
+    let scheduler = Scheduler::new(schedule::create_schedule_store(&config)?);
+    let persisted_queries = PersistedQueries::load(&config, redis.clone())?;
+
+    // Create the reqwest client first, so an invalid http config is reported before any request
+    let reqwest = create_reqwest_client(&config)?;
+    let http = create_http_client(&config.token, &config, &reqwest);
+    let webhooks = WebhookSender::new(
+        webhook_store,
+        create_webhook_client(&config)?,
+        config.webhook_internal_addresses,
+    );
 
     let oauth = Arc::new(OauthClient::new(
         http.current_user_application().await?.id,
@@ -111,40 +256,59 @@ async fn main() -> anyhow::Result<()> {
         OAUTH_REDIRECT_URLS,
     )?);
 
-    let mut shard = ShardBuilder::new(
-        &config.token,
-        Intents::GUILDS
-            | Intents::GUILD_VOICE_STATES
-            | Intents::GUILD_MEMBERS
-            | Intents::GUILD_PRESENCES,
-    )
-    .http_client(http.clone())
-    .build();
-    shard.start().await?;
+    let (data, shard): (SharedDataSource, _) = match &redis {
+        Some(redis) => (
+            Arc::new(RedisDataSource::connect(redis.clone()).await?),
+            None,
+        ),
+        None => {
+            let shard = start_shard(&config, &http).await?;
+            let cache = InMemoryCache::new();
+            let data: SharedDataSource = Arc::new(LiveDataSource::new(cache.clone(), http));
+            let guard = FloorGuard::new(data.clone(), floors.clone());
 
-    let cache = InMemoryCache::new();
-
-    // Startup an event loop for each event in the event stream
-    {
-        let shard = shard.clone();
-        let cache = cache.clone();
-        task::spawn(async move {
-            let mut events = shard.events();
+            // Startup an event loop for each event in the event stream
+            {
+                let shard = shard.clone();
+                task::spawn(async move {
+                    let mut events = shard.events();
 
-            while let Some(event) = events.next().await {
-                cache.update(&event);
+                    while let Some(event) = events.next().await {
+                        cache.update(&event);
+                        guard_floor(&guard, &event);
+                    }
+                });
             }
-        });
-    }
 
-    rocket::custom(Figment::from(rocket::Config::default()).merge(Env::prefixed("ROCKET_")))
-        .manage(create_reqwest_client(&config))
+            (data, Some(shard))
+        }
+    };
+
+    // Api servers claim every run through redis, as each of them runs the schedules
+    scheduler.start(
+        data.clone(),
+        webhooks.clone(),
+        notifications.clone(),
+        floors.clone(),
+        redis.clone(),
+    );
+
+    rocket::custom(config::rocket_figment())
+        .manage(reqwest)
         .manage(DiscordContext {
-            cache,
-            http,
-            shard: shard.clone(),
+            data,
             oauth,
+            user_guilds: Arc::new(UserGuildCache::from_config(&config, redis.clone())),
+            rate_limiter: Arc::new(RateLimiter::from_config(&config, redis)),
         })
+        .manage(sessions)
+        .manage(api_tokens)
+        .manage(webhooks)
+        .manage(notifications)
+        .manage(floors)
+        .manage(scheduler)
+        .manage(persisted_queries)
+        .manage(QueryLimits::from_config(&config))
         .manage(config.clone())
         .manage(create_schema())
         .mount(
@@ -153,8 +317,10 @@ async fn main() -> anyhow::Result<()> {
                 routes::graphql::graphiql,
                 routes::graphql::graphiql_no_auth,
                 routes::graphql::get_graphql_handler,
+                routes::graphql::get_graphql_api_token,
                 routes::graphql::get_graphql_no_auth,
                 routes::graphql::post_graphql_handler,
+                routes::graphql::post_graphql_api_token,
                 routes::graphql::post_graphql_no_auth,
                 routes::auth::oauth_login,
                 routes::auth::oauth_authorize,
@@ -181,60 +347,58 @@ async fn main() -> anyhow::Result<()> {
         .await?; // FIXME: Error handling
 
     // After server has shutdown
-    shard.shutdown();
+    if let Some(shard) = shard {
+        shard.shutdown();
+    }
 
     Ok(())
 }
 
-#[cfg(feature = "generate_schema")]
-#[async_std::main] // FIXME: maybe move to test or build script
-async fn main() {
-    use twilight_http::Client as HttpClient;
-    use twilight_model::id::{ApplicationId, UserId};
+/// Run the gateway until it closes, filling the redis cache for the api servers and making the
+/// member updates that they send
+async fn gateway(config: Config) -> anyhow::Result<()> {
+    let redis = RedisCache::from_config(&config)?
+        .context("REDIS_URL has to be set for the gateway to reach the api servers")?;
+    let http = create_http_client(&config.token, &config, &create_reqwest_client(&config)?);
 
-    let http = HttpClient::new("");
+    {
+        let redis = redis.clone();
+        let http = http.clone();
+        task::spawn(async move { redis.run_commands(http).await });
+    }
 
-    let shard = ShardBuilder::new("", Intents::empty())
+    // The floors are shared with the api servers through the floor database
+    let floors = floor::create_shared_floor_store(&config)?;
+    let cache = InMemoryCache::new();
+    let guard = FloorGuard::new(
+        Arc::new(LiveDataSource::new(cache.clone(), http.clone())),
+        floors,
+    );
+
+    let shard = start_shard(&config, &http).await?;
+    let result = redis
+        .fill(cache, http, shard.events(), |event| {
+            guard_floor(&guard, event)
+        })
+        .await;
+    shard.shutdown();
+
+    result
+}
+
+/// Have the floor guard check the voice state of a gateway event, if it has one
+fn guard_floor(guard: &FloorGuard, event: &Event) {
+    if let Event::VoiceStateUpdate(update) = event {
+        guard.voice_state_updated(&update.0);
+    }
+}
+
+/// Connect a shard of the bot to the gateway
+async fn start_shard(config: &Config, http: &HttpClient) -> anyhow::Result<Shard> {
+    let mut shard = ShardBuilder::new(&config.token, GATEWAY_INTENTS)
         .http_client(http.clone())
         .build();
+    shard.start().await?;
 
-    let cache = InMemoryCache::new();
-
-    let oauth = Arc::new(OauthClient::new(ApplicationId(1), "", &[]).unwrap());
-
-    let (res, _errors) = juniper::introspect(
-        &create_schema(),
-        &graphql::GraphQLContext {
-            discord: DiscordContext {
-                cache,
-                http,
-                shard,
-                oauth,
-            },
-            user: auth::OauthUser {
-                http: HttpClient::new(""),
-                cookie: auth::OauthCookie {
-                    access_token: "".into(),
-                    expires_in: 0,
-                    refresh_token: "".into(),
-                    created_at: 0,
-                    user_id: UserId(0),
-                },
-            },
-        },
-        Default::default(),
-    )
-    .unwrap();
-
-    let mut file = std::fs::File::create(format!(
-        "{}/target/graphql.json",
-        env!("CARGO_MANIFEST_DIR")
-    ))
-    .unwrap();
-
-    std::io::Write::write(
-        &mut file,
-        serde_json::to_string_pretty(&res).unwrap().as_bytes(),
-    )
-    .unwrap();
+    Ok(shard)
 }
@@ -0,0 +1,73 @@
+//! A notification store that keeps settings in memory
+
+use super::{MuteNotice, NotificationSettings, NotificationStore};
+use std::{
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+};
+use twilight_model::id::{ChannelId, GuildId};
+
+/// A notification store that keeps settings in memory, losing them when the server restarts
+#[derive(Debug, Default)]
+pub struct MemoryNotificationStore {
+    /// The settings of the guilds that opted in
+    settings: RwLock<HashMap<GuildId, NotificationSettings>>,
+    /// The messages posted about active mutes, keyed by their voice channel
+    notices: RwLock<HashMap<ChannelId, MuteNotice>>,
+}
+
+#[rocket::async_trait]
+impl NotificationStore for MemoryNotificationStore {
+    async fn settings(&self, guild_id: GuildId) -> anyhow::Result<Option<NotificationSettings>> {
+        Ok(self
+            .settings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&guild_id)
+            .cloned())
+    }
+
+    async fn set_settings(&self, settings: NotificationSettings) -> anyhow::Result<()> {
+        self.settings
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(settings.guild_id, settings);
+
+        Ok(())
+    }
+
+    async fn remove_settings(&self, guild_id: GuildId) -> anyhow::Result<bool> {
+        Ok(self
+            .settings
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&guild_id)
+            .is_some())
+    }
+
+    async fn insert_notice(&self, notice: MuteNotice) -> anyhow::Result<()> {
+        self.notices
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(notice.voice_channel_id, notice);
+
+        Ok(())
+    }
+
+    async fn notice(&self, voice_channel_id: ChannelId) -> anyhow::Result<Option<MuteNotice>> {
+        Ok(self
+            .notices
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&voice_channel_id)
+            .cloned())
+    }
+
+    async fn take_notice(&self, voice_channel_id: ChannelId) -> anyhow::Result<Option<MuteNotice>> {
+        Ok(self
+            .notices
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&voice_channel_id))
+    }
+}
@@ -0,0 +1,110 @@
+//! Messages posted to a guild when a voice channel is mass muted, so that the muted members
+//! know why
+//!
+//! Guilds opt in by choosing where the messages go in their [`NotificationSettings`]. The
+//! message posted for a mute is remembered as a [`MuteNotice`] until the channel is unmuted, when
+//! the message is edited to say that the mute was lifted.
+
+use crate::config::Config;
+use std::{fmt::Debug, sync::Arc};
+use twilight_model::id::{ChannelId, GuildId, MessageId, UserId};
+
+pub mod memory;
+pub mod notifier;
+pub mod sqlite;
+
+pub use memory::MemoryNotificationStore;
+pub use notifier::MuteNotifier;
+pub use sqlite::SqliteNotificationStore;
+
+/// A shared handle to the notification store used by the server
+pub type Notifications = Arc<dyn NotificationStore>;
+
+/// Where a guild wants the messages about mutes to be posted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationTarget {
+    /// A text channel of the guild
+    TextChannel(ChannelId),
+    /// The chat of the voice channel that was muted
+    VoiceChat,
+}
+
+impl NotificationTarget {
+    /// The channel to post the message about a mute of the voice channel to
+    #[must_use]
+    pub fn channel(self, voice_channel_id: ChannelId) -> ChannelId {
+        match self {
+            NotificationTarget::TextChannel(channel_id) => channel_id,
+            NotificationTarget::VoiceChat => voice_channel_id,
+        }
+    }
+}
+
+/// The choice of a guild to have messages posted about its mutes
+#[derive(Debug, Clone)]
+pub struct NotificationSettings {
+    /// The guild the settings are for
+    pub guild_id: GuildId,
+    /// Where the messages are posted
+    pub target: NotificationTarget,
+    /// The id of the user who last changed the settings
+    pub updated_by: UserId,
+    /// The seconds since the unix epoch that the settings were last changed
+    pub updated_at: u64,
+}
+
+/// A message posted about a voice channel being muted, kept until the channel is unmuted
+#[derive(Debug, Clone)]
+pub struct MuteNotice {
+    /// The voice channel that was muted
+    pub voice_channel_id: ChannelId,
+    /// The guild of the voice channel
+    pub guild_id: GuildId,
+    /// The channel that the message was posted to
+    pub channel_id: ChannelId,
+    /// The message that was posted
+    pub message_id: MessageId,
+    /// The id of the user who ran the mute
+    pub muted_by: UserId,
+    /// The amount of members that were muted
+    pub member_count: usize,
+    /// The seconds since the unix epoch that the mute was run
+    pub created_at: u64,
+}
+
+/// Storage for the notification settings of guilds and the messages posted about active mutes
+#[rocket::async_trait]
+pub trait NotificationStore: Debug + Send + Sync {
+    /// Get the notification settings of a guild, if it has opted in
+    async fn settings(&self, guild_id: GuildId) -> anyhow::Result<Option<NotificationSettings>>;
+
+    /// Save the notification settings of a guild, replacing any earlier settings
+    async fn set_settings(&self, settings: NotificationSettings) -> anyhow::Result<()>;
+
+    /// Remove the notification settings of a guild, returning if it had any
+    async fn remove_settings(&self, guild_id: GuildId) -> anyhow::Result<bool>;
+
+    /// Save the message posted about a mute, replacing any earlier message about the same
+    /// voice channel
+    async fn insert_notice(&self, notice: MuteNotice) -> anyhow::Result<()>;
+
+    /// Get the message posted about the active mute of a voice channel, if any
+    async fn notice(&self, voice_channel_id: ChannelId) -> anyhow::Result<Option<MuteNotice>>;
+
+    /// Remove and return the message posted about the last mute of a voice channel, if any
+    async fn take_notice(&self, voice_channel_id: ChannelId) -> anyhow::Result<Option<MuteNotice>>;
+}
+
+/// Create the notification store described by the config
+///
+/// Settings are stored in the sqlite database at `notification_database` if one is configured,
+/// otherwise they are kept in memory and lost on restart.
+///
+/// # Errors
+/// If the sqlite database could not be opened
+pub fn create_notification_store(config: &Config) -> anyhow::Result<Notifications> {
+    Ok(match &config.notification_database {
+        Some(path) => Arc::new(SqliteNotificationStore::open(path)?),
+        None => Arc::new(MemoryNotificationStore::default()),
+    })
+}
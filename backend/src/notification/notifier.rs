@@ -0,0 +1,410 @@
+//! Posting and editing the messages about mutes
+
+use super::{MuteNotice, Notifications};
+use crate::{
+    data_source::{RedisCache, SharedDataSource},
+    session::unix_timestamp,
+};
+use async_std::task;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use twilight_model::{
+    channel::embed::{Embed, EmbedField},
+    id::{ChannelId, GuildId, UserId},
+};
+
+/// The color of the message while the mute is active, red
+const MUTED_COLOR: u32 = 0x00E7_4C3C;
+
+/// The color of the message once the mute is lifted, green
+const LIFTED_COLOR: u32 = 0x002E_CC71;
+
+/// How often to check if the updates in the background are done, while waiting for them
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the claim on the message of a voice channel lasts, in case the server holding it
+/// stops before releasing it. Longer than a command to the gateway worker can take.
+const CLAIM_DURATION: Duration = Duration::from_secs(60);
+
+/// How long to wait before trying to claim the message of a voice channel again
+const CLAIM_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A change to the message about the mute of a voice channel
+#[derive(Debug)]
+enum NoticeUpdate {
+    /// Members of the voice channel were muted
+    Started {
+        /// The guild of the voice channel
+        guild_id: GuildId,
+        /// The user who ran the mute
+        muted_by: UserId,
+        /// The amount of members that were muted
+        member_count: usize,
+    },
+    /// The mute of the voice channel was lifted
+    Lifted {
+        /// The user who lifted the mute
+        lifted_by: UserId,
+        /// The amount of members that were unmuted
+        member_count: usize,
+    },
+}
+
+/// Posts messages about mutes to the guilds that opted in, and edits them when the mutes are
+/// lifted
+///
+/// The messages of a voice channel are updated one at a time in the order of the mutes and
+/// unmutes, so an unmute made right after a mute edits the message that the mute posted. Api
+/// servers claim the message through redis before updating it, so that the servers take turns,
+/// though updates made through different servers at once are made in either order.
+#[derive(Debug, Clone)]
+pub struct MuteNotifier {
+    /// The store of settings and posted messages
+    store: Notifications,
+    /// The redis server that the servers sharing the store claim messages through
+    redis: Option<RedisCache>,
+    /// The updates waiting for an earlier update of the same voice channel to be made, for every
+    /// voice channel that is being updated
+    queues: Arc<Mutex<HashMap<ChannelId, VecDeque<NoticeUpdate>>>>,
+}
+
+impl MuteNotifier {
+    /// Create a notifier for the guilds with settings in the store, taking turns with the other
+    /// servers sharing the redis server if there is one
+    #[must_use]
+    pub fn new(store: Notifications, redis: Option<RedisCache>) -> Self {
+        Self {
+            store,
+            redis,
+            queues: Arc::default(),
+        }
+    }
+
+    /// The store of settings and posted messages
+    #[must_use]
+    pub fn store(&self) -> &Notifications {
+        &self.store
+    }
+
+    /// Post a message about a voice channel being muted, in the background, if the guild opted
+    /// in
+    ///
+    /// If the channel is still muted from before, the message about that mute is edited to count
+    /// the newly muted members instead.
+    pub fn mute_started(
+        &self,
+        data: SharedDataSource,
+        guild_id: GuildId,
+        voice_channel_id: ChannelId,
+        muted_by: UserId,
+        member_count: usize,
+    ) {
+        self.queue(
+            data,
+            voice_channel_id,
+            NoticeUpdate::Started {
+                guild_id,
+                muted_by,
+                member_count,
+            },
+        );
+    }
+
+    /// Edit the message about the last mute of a voice channel to say that it was lifted, in
+    /// the background, if a message was posted
+    pub fn mute_lifted(
+        &self,
+        data: SharedDataSource,
+        voice_channel_id: ChannelId,
+        lifted_by: UserId,
+        member_count: usize,
+    ) {
+        self.queue(
+            data,
+            voice_channel_id,
+            NoticeUpdate::Lifted {
+                lifted_by,
+                member_count,
+            },
+        );
+    }
+
+    /// Wait until every update made so far is done, for processes that exit once they are done
+    pub async fn wait_idle(&self) {
+        while !self.is_idle() {
+            task::sleep(IDLE_CHECK_INTERVAL).await;
+        }
+    }
+
+    /// If no voice channel has updates left to make
+    fn is_idle(&self) -> bool {
+        self.queues
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+
+    /// Make an update to the message of a voice channel in the background, after the updates
+    /// queued before it
+    fn queue(&self, data: SharedDataSource, voice_channel_id: ChannelId, update: NoticeUpdate) {
+        {
+            let mut queues = self.queues.lock().unwrap_or_else(PoisonError::into_inner);
+
+            // The task making the earlier updates makes this one once it is done with them
+            if let Some(queue) = queues.get_mut(&voice_channel_id) {
+                queue.push_back(update);
+                return;
+            }
+
+            queues.insert(voice_channel_id, VecDeque::new());
+        }
+
+        let notifier = self.clone();
+        task::spawn(async move {
+            let mut next = Some(update);
+
+            while let Some(update) = next {
+                notifier.update(&data, voice_channel_id, update).await;
+
+                let mut queues = notifier
+                    .queues
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                next = queues
+                    .get_mut(&voice_channel_id)
+                    .and_then(VecDeque::pop_front);
+
+                if next.is_none() {
+                    queues.remove(&voice_channel_id);
+                }
+            }
+        });
+    }
+
+    /// Make an update to the message of a voice channel once it is claimed, logging any failure
+    async fn update(
+        &self,
+        data: &SharedDataSource,
+        voice_channel_id: ChannelId,
+        update: NoticeUpdate,
+    ) {
+        let claim = format!("notice:{}", voice_channel_id);
+
+        if let Some(redis) = &self.redis {
+            loop {
+                match redis.claim(&claim, CLAIM_DURATION).await {
+                    Ok(true) => break,
+                    Ok(false) => task::sleep(CLAIM_RETRY_INTERVAL).await,
+                    Err(error) => {
+                        log::error!(
+                            "Failed to claim the mute message of channel {}: {:#}",
+                            voice_channel_id,
+                            error
+                        );
+                        return;
+                    }
+                }
+            }
+        }
+
+        let result = match update {
+            NoticeUpdate::Started {
+                guild_id,
+                muted_by,
+                member_count,
+            } => {
+                self.post(data, guild_id, voice_channel_id, muted_by, member_count)
+                    .await
+            }
+            NoticeUpdate::Lifted {
+                lifted_by,
+                member_count,
+            } => {
+                self.lift(data, voice_channel_id, lifted_by, member_count)
+                    .await
+            }
+        };
+
+        if let Err(error) = result {
+            log::error!(
+                "Failed to update the mute message of channel {}: {:#}",
+                voice_channel_id,
+                error
+            );
+        }
+
+        if let Some(redis) = &self.redis {
+            if let Err(error) = redis.release(&claim).await {
+                log::error!(
+                    "Failed to release the mute message of channel {}: {:#}",
+                    voice_channel_id,
+                    error
+                );
+            }
+        }
+    }
+
+    /// Post the message about a mute, or edit the message about the mute that is still active
+    async fn post(
+        &self,
+        data: &SharedDataSource,
+        guild_id: GuildId,
+        voice_channel_id: ChannelId,
+        muted_by: UserId,
+        member_count: usize,
+    ) -> anyhow::Result<()> {
+        let settings = match self.store.settings(guild_id).await? {
+            Some(settings) => settings,
+            None => return Ok(()),
+        };
+
+        if let Some(mut notice) = self.store.notice(voice_channel_id).await? {
+            notice.member_count += member_count;
+
+            match data
+                .edit_embed(
+                    notice.channel_id,
+                    notice.message_id,
+                    muted_embed(voice_channel_id, notice.muted_by, notice.member_count),
+                )
+                .await
+            {
+                Ok(()) => return self.store.insert_notice(notice).await,
+                // The message was most likely deleted, so a new one is posted instead
+                Err(error) => log::warn!(
+                    "Failed to edit the mute message of channel {}, posting a new one: {:#}",
+                    voice_channel_id,
+                    error
+                ),
+            }
+        }
+
+        let channel_id = settings.target.channel(voice_channel_id);
+        let message_id = data
+            .send_embed(
+                channel_id,
+                muted_embed(voice_channel_id, muted_by, member_count),
+            )
+            .await?;
+
+        self.store
+            .insert_notice(MuteNotice {
+                voice_channel_id,
+                guild_id,
+                channel_id,
+                message_id,
+                muted_by,
+                member_count,
+                created_at: unix_timestamp(),
+            })
+            .await
+    }
+
+    /// Edit the message about the active mute of a voice channel to say that it was lifted
+    async fn lift(
+        &self,
+        data: &SharedDataSource,
+        voice_channel_id: ChannelId,
+        lifted_by: UserId,
+        member_count: usize,
+    ) -> anyhow::Result<()> {
+        let notice = match self.store.take_notice(voice_channel_id).await? {
+            Some(notice) => notice,
+            None => return Ok(()),
+        };
+
+        data.edit_embed(
+            notice.channel_id,
+            notice.message_id,
+            lifted_embed(&notice, lifted_by, member_count, unix_timestamp()),
+        )
+        .await
+    }
+}
+
+/// The message posted while a mute is active
+fn muted_embed(voice_channel_id: ChannelId, muted_by: UserId, member_count: usize) -> Embed {
+    embed(
+        "Voice channel muted",
+        format!(
+            "Everyone in <#{}> was server muted by <@{}>.",
+            voice_channel_id, muted_by
+        ),
+        MUTED_COLOR,
+        vec![
+            field("Muted by", format!("<@{}>", muted_by)),
+            field("Lasts", "Until the channel is unmuted".to_owned()),
+            field("Members muted", member_count.to_string()),
+        ],
+    )
+}
+
+/// The message that replaces the one posted about a mute once it is lifted
+fn lifted_embed(notice: &MuteNotice, lifted_by: UserId, member_count: usize, now: u64) -> Embed {
+    embed(
+        "Voice channel unmuted",
+        format!(
+            "The mute of <#{}> was lifted by <@{}>.",
+            notice.voice_channel_id, lifted_by
+        ),
+        LIFTED_COLOR,
+        vec![
+            field("Muted by", format!("<@{}>", notice.muted_by)),
+            field(
+                "Lasted",
+                format_duration(now.saturating_sub(notice.created_at)),
+            ),
+            field("Members muted", notice.member_count.to_string()),
+            field("Lifted by", format!("<@{}>", lifted_by)),
+            field("Members unmuted", member_count.to_string()),
+        ],
+    )
+}
+
+/// A rich embed with the title, description, color and fields
+fn embed(title: &str, description: String, color: u32, fields: Vec<EmbedField>) -> Embed {
+    Embed {
+        author: None,
+        color: Some(color),
+        description: Some(description),
+        fields,
+        footer: None,
+        image: None,
+        kind: "rich".to_owned(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some(title.to_owned()),
+        url: None,
+        video: None,
+    }
+}
+
+/// An inline field of an embed
+fn field(name: &str, value: String) -> EmbedField {
+    EmbedField {
+        inline: true,
+        name: name.to_owned(),
+        value,
+    }
+}
+
+/// Describe an amount of seconds in hours and minutes
+fn format_duration(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    let (hours, minutes) = (minutes / 60, minutes % 60);
+
+    let plural = |amount: u64, unit: &str| {
+        format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+    };
+
+    match (hours, minutes) {
+        (0, 0) => "Less than a minute".to_owned(),
+        (0, minutes) => plural(minutes, "minute"),
+        (hours, 0) => plural(hours, "hour"),
+        (hours, minutes) => format!("{} {}", plural(hours, "hour"), plural(minutes, "minute")),
+    }
+}
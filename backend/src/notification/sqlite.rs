@@ -0,0 +1,204 @@
+//! A notification store that persists settings in a sqlite database
+
+use super::{MuteNotice, NotificationSettings, NotificationStore, NotificationTarget};
+use crate::database::{from_sql_integer, to_sql_integer, Database};
+use rusqlite::{params, OptionalExtension, Row};
+use std::{convert::TryFrom, path::Path};
+use twilight_model::id::{ChannelId, GuildId, MessageId, UserId};
+
+/// The statements to run to setup the database
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS notification_settings (
+    guild_id INTEGER PRIMARY KEY NOT NULL,
+    channel_id INTEGER,
+    updated_by INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS mute_notices (
+    voice_channel_id INTEGER PRIMARY KEY NOT NULL,
+    guild_id INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    muted_by INTEGER NOT NULL,
+    member_count INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+";
+
+/// The columns of the settings table, in the order that `settings_from_row` expects them
+const SETTINGS_COLUMNS: &str = "guild_id, channel_id, updated_by, updated_at";
+
+/// The columns of the notices table, in the order that `notice_from_row` expects them
+const NOTICE_COLUMNS: &str =
+    "voice_channel_id, guild_id, channel_id, message_id, muted_by, member_count, created_at";
+
+/// A notification store that persists settings in a sqlite database, surviving restarts
+#[derive(Debug, Clone)]
+pub struct SqliteNotificationStore {
+    /// The notification database
+    database: Database,
+}
+
+impl SqliteNotificationStore {
+    /// Open, and create if needed, the notification database at the path
+    ///
+    /// # Errors
+    /// If the database could not be opened or setup
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            database: Database::open(path, SCHEMA)?,
+        })
+    }
+}
+
+/// Read settings from a row made up of `SETTINGS_COLUMNS`
+///
+/// The chat of the voice channel is stored as a missing channel id.
+fn settings_from_row(row: &Row<'_>) -> rusqlite::Result<NotificationSettings> {
+    Ok(NotificationSettings {
+        guild_id: GuildId(from_sql_integer(row.get(0)?)),
+        target: match row.get::<_, Option<i64>>(1)? {
+            Some(channel_id) => {
+                NotificationTarget::TextChannel(ChannelId(from_sql_integer(channel_id)))
+            }
+            None => NotificationTarget::VoiceChat,
+        },
+        updated_by: UserId(from_sql_integer(row.get(2)?)),
+        updated_at: from_sql_integer(row.get(3)?),
+    })
+}
+
+/// Read a notice from a row made up of `NOTICE_COLUMNS`
+fn notice_from_row(row: &Row<'_>) -> rusqlite::Result<MuteNotice> {
+    Ok(MuteNotice {
+        voice_channel_id: ChannelId(from_sql_integer(row.get(0)?)),
+        guild_id: GuildId(from_sql_integer(row.get(1)?)),
+        channel_id: ChannelId(from_sql_integer(row.get(2)?)),
+        message_id: MessageId(from_sql_integer(row.get(3)?)),
+        muted_by: UserId(from_sql_integer(row.get(4)?)),
+        member_count: usize::try_from(from_sql_integer(row.get(5)?)).unwrap_or(usize::MAX),
+        created_at: from_sql_integer(row.get(6)?),
+    })
+}
+
+#[rocket::async_trait]
+impl NotificationStore for SqliteNotificationStore {
+    async fn settings(&self, guild_id: GuildId) -> anyhow::Result<Option<NotificationSettings>> {
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .query_row(
+                        &format!(
+                            "SELECT {} FROM notification_settings WHERE guild_id = ?1",
+                            SETTINGS_COLUMNS
+                        ),
+                        params![to_sql_integer(guild_id.0)],
+                        settings_from_row,
+                    )
+                    .optional()
+            })
+            .await
+    }
+
+    async fn set_settings(&self, settings: NotificationSettings) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                let channel_id = match settings.target {
+                    NotificationTarget::TextChannel(channel_id) => Some(channel_id.0),
+                    NotificationTarget::VoiceChat => None,
+                };
+
+                connection.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO notification_settings ({}) VALUES (?1, ?2, ?3, ?4)",
+                        SETTINGS_COLUMNS
+                    ),
+                    params![
+                        to_sql_integer(settings.guild_id.0),
+                        channel_id.map(to_sql_integer),
+                        to_sql_integer(settings.updated_by.0),
+                        to_sql_integer(settings.updated_at),
+                    ],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn remove_settings(&self, guild_id: GuildId) -> anyhow::Result<bool> {
+        self.database
+            .with_connection(move |connection| {
+                Ok(connection.execute(
+                    "DELETE FROM notification_settings WHERE guild_id = ?1",
+                    params![to_sql_integer(guild_id.0)],
+                )? > 0)
+            })
+            .await
+    }
+
+    async fn insert_notice(&self, notice: MuteNotice) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO mute_notices ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                        NOTICE_COLUMNS
+                    ),
+                    params![
+                        to_sql_integer(notice.voice_channel_id.0),
+                        to_sql_integer(notice.guild_id.0),
+                        to_sql_integer(notice.channel_id.0),
+                        to_sql_integer(notice.message_id.0),
+                        to_sql_integer(notice.muted_by.0),
+                        to_sql_integer(notice.member_count as u64),
+                        to_sql_integer(notice.created_at),
+                    ],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn notice(&self, voice_channel_id: ChannelId) -> anyhow::Result<Option<MuteNotice>> {
+        self.database
+            .with_connection(move |connection| {
+                Ok(connection
+                    .query_row(
+                        &format!(
+                            "SELECT {} FROM mute_notices WHERE voice_channel_id = ?1",
+                            NOTICE_COLUMNS
+                        ),
+                        params![to_sql_integer(voice_channel_id.0)],
+                        notice_from_row,
+                    )
+                    .optional()?)
+            })
+            .await
+    }
+
+    async fn take_notice(&self, voice_channel_id: ChannelId) -> anyhow::Result<Option<MuteNotice>> {
+        self.database
+            .with_connection(move |connection| {
+                let notice = connection
+                    .query_row(
+                        &format!(
+                            "SELECT {} FROM mute_notices WHERE voice_channel_id = ?1",
+                            NOTICE_COLUMNS
+                        ),
+                        params![to_sql_integer(voice_channel_id.0)],
+                        notice_from_row,
+                    )
+                    .optional()?;
+
+                connection.execute(
+                    "DELETE FROM mute_notices WHERE voice_channel_id = ?1",
+                    params![to_sql_integer(voice_channel_id.0)],
+                )?;
+
+                Ok(notice)
+            })
+            .await
+    }
+}
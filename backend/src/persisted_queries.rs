@@ -0,0 +1,279 @@
+//! Persisted queries, letting clients send the hash of a known query instead of the query itself
+//!
+//! The registry is generated from the operations of the frontend. In strict mode it doubles as an
+//! allowlist, so that only the queries that the frontend ships with can be executed.
+//!
+//! Api servers also remember the queries registered by clients in redis, so that a query
+//! registered through one server can be sent as a hash to any of them.
+
+use crate::{config::Config, data_source::RedisCache};
+use anyhow::{bail, Context};
+use juniper::{graphql_value, FieldError};
+use log::warn;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs,
+    sync::{PoisonError, RwLock},
+};
+
+/// The most queries that clients can register on their own when not in strict mode
+const MAX_AUTOMATIC_QUERIES: usize = 1024;
+
+/// The name of the redis hash of the queries registered by clients, keyed by their hash
+const REDIS_QUERIES: &str = "persisted_queries";
+
+/// The `persistedQuery` extension of a graphql request
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PersistedQueryExtension {
+    /// The sha256 hash of the query, as lowercase hex
+    pub sha256_hash: String,
+}
+
+/// The extensions that can be sent along with a graphql request
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Extensions {
+    /// The hash of a persisted query to run
+    pub persisted_query: Option<PersistedQueryExtension>,
+}
+
+/// The reason that a query could not be resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersistedQueryError {
+    /// Only a hash was sent and no query with that hash is known
+    NotFound,
+    /// The hash that was sent does not match the query that was sent
+    HashMismatch,
+    /// The query is not in the registry and strict mode is enabled
+    NotAllowed,
+    /// The request had neither a query nor the hash of one
+    MissingQuery,
+}
+
+impl PersistedQueryError {
+    /// The machine readable code of the error
+    #[must_use]
+    pub fn code(self) -> &'static str {
+        match self {
+            PersistedQueryError::NotFound => "PERSISTED_QUERY_NOT_FOUND",
+            PersistedQueryError::HashMismatch => "PERSISTED_QUERY_HASH_MISMATCH",
+            PersistedQueryError::NotAllowed => "QUERY_NOT_ALLOWED",
+            PersistedQueryError::MissingQuery => "MISSING_QUERY",
+        }
+    }
+}
+
+impl Display for PersistedQueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // The not found message is what apollo clients look for before retrying with the query
+        match self {
+            PersistedQueryError::NotFound => write!(f, "PersistedQueryNotFound"),
+            PersistedQueryError::HashMismatch => {
+                write!(f, "The provided sha256 hash does not match the query")
+            }
+            PersistedQueryError::NotAllowed => {
+                write!(f, "Only persisted queries are allowed by this server")
+            }
+            PersistedQueryError::MissingQuery => write!(f, "The request is missing a query"),
+        }
+    }
+}
+
+impl std::error::Error for PersistedQueryError {}
+
+impl From<PersistedQueryError> for FieldError {
+    fn from(error: PersistedQueryError) -> Self {
+        FieldError::new(error, graphql_value!({ "code": (error.code()) }))
+    }
+}
+
+/// A registry of queries keyed by their hash
+#[derive(Debug)]
+pub struct PersistedQueries {
+    /// If queries that are not in the registry are rejected
+    strict: bool,
+    /// The known queries, keyed by their hash
+    queries: RwLock<HashMap<String, String>>,
+    /// The amount of queries that were loaded from the registry file
+    loaded: usize,
+    /// The redis server that queries registered by clients are shared through
+    redis: Option<RedisCache>,
+}
+
+impl PersistedQueries {
+    /// Create a registry from a list of queries
+    #[must_use]
+    pub fn new(queries: impl IntoIterator<Item = String>, strict: bool) -> Self {
+        let queries: HashMap<String, String> = queries
+            .into_iter()
+            .map(|query| (hash_query(&query), query))
+            .collect();
+
+        Self {
+            strict,
+            loaded: queries.len(),
+            queries: RwLock::new(queries),
+            redis: None,
+        }
+    }
+
+    /// Load the registry described by the config, sharing the queries registered by clients
+    /// through the redis server if there is one
+    ///
+    /// The registry file is a json object of query hashes to queries, as generated by the
+    /// `persisted-queries` script of the frontend. The hashes in the file are recomputed, so a
+    /// hand edited query can not end up under the wrong hash.
+    ///
+    /// # Errors
+    /// If the registry file could not be read or parsed, or if strict mode is enabled without a
+    /// registry to allow queries from
+    pub fn load(config: &Config, redis: Option<RedisCache>) -> anyhow::Result<Self> {
+        let path = match &config.persisted_queries {
+            Some(path) => path,
+            None if config.strict_persisted_queries => {
+                bail!("Strict persisted queries are enabled without a persisted query registry")
+            }
+            None => {
+                return Ok(Self {
+                    redis,
+                    ..Self::new(Vec::new(), false)
+                })
+            }
+        };
+
+        let queries: HashMap<String, String> = serde_json::from_str(
+            &fs::read_to_string(path)
+                .with_context(|| format!("Failed to read persisted queries from {}", path))?,
+        )
+        .with_context(|| format!("Persisted queries in {} were malformed", path))?;
+
+        for (hash, query) in &queries {
+            if hash != &hash_query(query) {
+                warn!("Persisted query {} does not match its hash", hash);
+            }
+        }
+
+        Ok(Self {
+            redis,
+            ..Self::new(
+                queries.into_iter().map(|(_, query)| query),
+                config.strict_persisted_queries,
+            )
+        })
+    }
+
+    /// Resolve the query to execute from the query and persisted query extension of a request
+    ///
+    /// Outside of strict mode, a query sent along with its hash is remembered so that later
+    /// requests can send only the hash.
+    ///
+    /// # Errors
+    /// If the query could not be resolved or is not allowed
+    pub async fn resolve(
+        &self,
+        query: Option<String>,
+        persisted: Option<&PersistedQueryExtension>,
+    ) -> Result<String, PersistedQueryError> {
+        match (query, persisted) {
+            (None, None) => Err(PersistedQueryError::MissingQuery),
+            (None, Some(persisted)) => {
+                let known = self
+                    .queries
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get(&persisted.sha256_hash)
+                    .cloned();
+
+                match known {
+                    Some(query) => Ok(query),
+                    None => self.shared(&persisted.sha256_hash).await,
+                }
+            }
+            (Some(query), persisted) => {
+                let hash = hash_query(&query);
+
+                if let Some(persisted) = persisted {
+                    if persisted.sha256_hash != hash {
+                        return Err(PersistedQueryError::HashMismatch);
+                    }
+                }
+
+                let known = self
+                    .queries
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .contains_key(&hash);
+
+                if known {
+                    Ok(query)
+                } else if self.strict {
+                    Err(PersistedQueryError::NotAllowed)
+                } else {
+                    if persisted.is_some() {
+                        self.register(hash, query.clone()).await;
+                    }
+
+                    Ok(query)
+                }
+            }
+        }
+    }
+
+    /// Look up a query that a client registered through another server, remembering it if it
+    /// was found
+    ///
+    /// # Errors
+    /// If no query with the hash was registered, or redis could not be reached, in which case
+    /// the client sends the query again
+    async fn shared(&self, hash: &str) -> Result<String, PersistedQueryError> {
+        let redis = self.redis.as_ref().ok_or(PersistedQueryError::NotFound)?;
+
+        match redis.read_hash_value(REDIS_QUERIES, hash).await {
+            Ok(Some(query)) if hash_query(&query) == hash => {
+                self.remember(hash.to_owned(), query.clone());
+
+                Ok(query)
+            }
+            Ok(_) => Err(PersistedQueryError::NotFound),
+            Err(error) => {
+                warn!("Failed to look up a shared persisted query: {:#}", error);
+
+                Err(PersistedQueryError::NotFound)
+            }
+        }
+    }
+
+    /// Remember a query sent by a client, sharing it with the other servers, unless too many
+    /// have been remembered already
+    async fn register(&self, hash: String, query: String) {
+        if let Some(redis) = &self.redis {
+            let shared = redis
+                .write_hash_value(REDIS_QUERIES, &hash, &query, MAX_AUTOMATIC_QUERIES)
+                .await;
+            if let Err(error) = shared {
+                warn!("Failed to share a persisted query: {:#}", error);
+            }
+        }
+
+        self.remember(hash, query);
+    }
+
+    /// Remember a query in memory, unless too many have been remembered already
+    fn remember(&self, hash: String, query: String) {
+        let mut queries = self.queries.write().unwrap_or_else(PoisonError::into_inner);
+
+        if queries.len() < self.loaded + MAX_AUTOMATIC_QUERIES {
+            queries.insert(hash, query);
+        }
+    }
+}
+
+/// Hash a query the same way that apollo does for persisted queries
+#[must_use]
+pub fn hash_query(query: &str) -> String {
+    format!("{:x}", Sha256::digest(query.as_bytes()))
+}
@@ -0,0 +1,316 @@
+//! Limits on the depth and estimated cost of graphql queries, checked before they are executed
+//!
+//! List fields multiply the cost of everything selected below them, so a small query that nests
+//! a few lists can end up walking the whole cache of every shared guild. The queries of a batch
+//! share the limit on the cost, so splitting a query up does not get around it.
+
+use crate::{
+    config::Config,
+    consts::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+};
+use graphql_parser::query::{
+    parse_query, Definition, Field, OperationDefinition, Selection, SelectionSet, Value,
+};
+use juniper::{graphql_value, FieldError};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+/// The reason that a query was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryLimitError {
+    /// The query nests fields deeper than allowed
+    TooDeep {
+        /// The maximum allowed depth
+        max_depth: usize,
+    },
+    /// The estimated cost of the query is higher than allowed
+    TooComplex {
+        /// The estimated cost of the query
+        complexity: usize,
+        /// The maximum allowed cost
+        max_complexity: usize,
+    },
+    /// The batch holds more requests than allowed
+    BatchTooLarge {
+        /// The maximum allowed amount of requests
+        max_batch_size: usize,
+    },
+}
+
+impl QueryLimitError {
+    /// The machine readable code of the error
+    #[must_use]
+    pub fn code(self) -> &'static str {
+        match self {
+            QueryLimitError::TooDeep { .. } => "QUERY_TOO_DEEP",
+            QueryLimitError::TooComplex { .. } => "QUERY_TOO_COMPLEX",
+            QueryLimitError::BatchTooLarge { .. } => "BATCH_TOO_LARGE",
+        }
+    }
+}
+
+impl Display for QueryLimitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryLimitError::TooDeep { max_depth } => write!(
+                f,
+                "Query nests fields deeper than the maximum depth of {}",
+                max_depth
+            ),
+            QueryLimitError::TooComplex {
+                complexity,
+                max_complexity,
+            } => write!(
+                f,
+                "Query has an estimated cost of {}, more than the maximum of {}. \
+                 Request smaller pages or fewer nested lists",
+                complexity, max_complexity
+            ),
+            QueryLimitError::BatchTooLarge { max_batch_size } => write!(
+                f,
+                "Batch holds more than the maximum of {} requests",
+                max_batch_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QueryLimitError {}
+
+impl From<QueryLimitError> for FieldError {
+    fn from(error: QueryLimitError) -> Self {
+        FieldError::new(error, graphql_value!({ "code": (error.code()) }))
+    }
+}
+
+/// The estimated amount of items returned by a list field, used to multiply the cost of the
+/// fields selected on each item
+///
+/// Connections use the page size that was asked for instead.
+fn estimated_list_size(field: &str) -> usize {
+    match field {
+        "members" | "states" => DEFAULT_PAGE_SIZE,
+        "sharedGuilds" | "manageableGuilds" | "voiceChannels" | "roles" => 25,
+        "activities" | "sessions" | "apiTokens" => 5,
+        _ => 1,
+    }
+}
+
+/// The configured limits on queries
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    /// The deepest that fields can be nested
+    pub max_depth: usize,
+    /// The highest estimated cost that a query, or all the queries of a batch, can have
+    pub max_complexity: usize,
+    /// The most requests that a batch can hold
+    pub max_batch_size: usize,
+}
+
+impl QueryLimits {
+    /// Get the limits from the config
+    #[must_use]
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_depth: config.max_query_depth,
+            max_complexity: config.max_query_complexity,
+            max_batch_size: config.max_batch_size,
+        }
+    }
+
+    /// Check every operation in the query against the limits
+    ///
+    /// Every field costs 1, plus the cost of the fields selected below it multiplied by the
+    /// amount of items it is expected to return. Introspection fields are free so that tooling
+    /// keeps working.
+    ///
+    /// # Returns
+    /// The estimated cost of the most costly operation, which is the most that executing the
+    /// query can cost
+    ///
+    /// # Errors
+    /// If an operation is too deep or too costly. Queries that fail to parse are let through,
+    /// since executing them reports a better error.
+    pub fn check(&self, query: &str) -> Result<usize, QueryLimitError> {
+        let document = match parse_query::<&str>(query) {
+            Ok(document) => document,
+            Err(_) => return Ok(0),
+        };
+
+        let mut walker = Walker {
+            max_depth: self.max_depth,
+            fragments: document
+                .definitions
+                .iter()
+                .filter_map(|definition| match definition {
+                    Definition::Fragment(fragment) => {
+                        Some((fragment.name, &fragment.selection_set))
+                    }
+                    Definition::Operation(_) => None,
+                })
+                .collect(),
+            spreads: Vec::new(),
+        };
+        let mut max_complexity = 0;
+
+        for definition in &document.definitions {
+            let selection_set = match definition {
+                Definition::Operation(OperationDefinition::SelectionSet(selection_set)) => {
+                    selection_set
+                }
+                Definition::Operation(OperationDefinition::Query(query)) => &query.selection_set,
+                Definition::Operation(OperationDefinition::Mutation(mutation)) => {
+                    &mutation.selection_set
+                }
+                Definition::Operation(OperationDefinition::Subscription(subscription)) => {
+                    &subscription.selection_set
+                }
+                Definition::Fragment(_) => continue,
+            };
+
+            let complexity = walker.cost(selection_set, 1)?;
+            self.check_complexity(complexity)?;
+
+            max_complexity = max_complexity.max(complexity);
+        }
+
+        Ok(max_complexity)
+    }
+
+    /// Check the amount of requests in a batch against the limit, before any of them are looked
+    /// at
+    ///
+    /// # Errors
+    /// If the batch holds too many requests
+    pub fn check_batch_size(&self, size: usize) -> Result<(), QueryLimitError> {
+        if size > self.max_batch_size {
+            Err(QueryLimitError::BatchTooLarge {
+                max_batch_size: self.max_batch_size,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check the summed cost of the queries of a batch, as returned by [`QueryLimits::check`],
+    /// against the limit
+    ///
+    /// # Errors
+    /// If the queries together are too costly
+    pub fn check_batch_complexity(
+        &self,
+        complexities: impl IntoIterator<Item = usize>,
+    ) -> Result<(), QueryLimitError> {
+        self.check_complexity(
+            complexities
+                .into_iter()
+                .fold(0, |total, complexity| total.saturating_add(complexity)),
+        )
+    }
+
+    /// Check an estimated cost against the limit
+    ///
+    /// # Errors
+    /// If the cost is higher than allowed
+    fn check_complexity(&self, complexity: usize) -> Result<(), QueryLimitError> {
+        if complexity > self.max_complexity {
+            Err(QueryLimitError::TooComplex {
+                complexity,
+                max_complexity: self.max_complexity,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Walks the selections of a query, adding up their cost
+struct Walker<'a, 'd> {
+    /// The deepest that fields can be nested
+    max_depth: usize,
+    /// The fragments defined in the query, by name
+    fragments: HashMap<&'d str, &'a SelectionSet<'d, &'d str>>,
+    /// The fragments currently being walked, to stop on cyclic fragments
+    spreads: Vec<&'d str>,
+}
+
+impl<'a, 'd> Walker<'a, 'd> {
+    /// The cost of a selection set whose fields are at the given depth
+    fn cost(
+        &mut self,
+        selection_set: &'a SelectionSet<'d, &'d str>,
+        depth: usize,
+    ) -> Result<usize, QueryLimitError> {
+        let mut cost = 0_usize;
+
+        for selection in &selection_set.items {
+            let selection_cost = match selection {
+                Selection::Field(field) => self.field_cost(field, depth)?,
+                Selection::InlineFragment(fragment) => self.cost(&fragment.selection_set, depth)?,
+                Selection::FragmentSpread(spread) => {
+                    let name = spread.fragment_name;
+
+                    // Unknown and cyclic fragments are rejected when the query is validated
+                    match self.fragments.get(name).copied() {
+                        Some(fragment) if !self.spreads.contains(&name) => {
+                            self.spreads.push(name);
+                            let fragment_cost = self.cost(fragment, depth);
+                            self.spreads.pop();
+
+                            fragment_cost?
+                        }
+                        _ => 0,
+                    }
+                }
+            };
+
+            cost = cost.saturating_add(selection_cost);
+        }
+
+        Ok(cost)
+    }
+
+    /// The cost of a field at the given depth, including the fields selected below it
+    fn field_cost(
+        &mut self,
+        field: &'a Field<'d, &'d str>,
+        depth: usize,
+    ) -> Result<usize, QueryLimitError> {
+        if field.name.starts_with("__") {
+            return Ok(0);
+        }
+
+        if depth > self.max_depth {
+            return Err(QueryLimitError::TooDeep {
+                max_depth: self.max_depth,
+            });
+        }
+
+        let children = self.cost(&field.selection_set, depth + 1)?;
+
+        Ok(children.saturating_mul(list_size(field)).saturating_add(1))
+    }
+}
+
+/// The amount of items that a field is expected to return
+///
+/// Pages sized by a variable are assumed to be as large as possible.
+fn list_size(field: &Field<'_, &str>) -> usize {
+    let first = field
+        .arguments
+        .iter()
+        .find(|(name, _)| *name == "first")
+        .map(|(_, value)| value);
+
+    match first {
+        Some(Value::Int(first)) => first
+            .as_i64()
+            .and_then(|first| usize::try_from(first).ok())
+            .map_or(0, |first| first.min(MAX_PAGE_SIZE)),
+        Some(Value::Null) | None => estimated_list_size(field.name),
+        Some(_) => MAX_PAGE_SIZE,
+    }
+}
@@ -0,0 +1,383 @@
+//! Token bucket rate limiting of mutations, per user and per guild
+//!
+//! Every mutation that talks to discord takes a token from the bucket of the user making it, and
+//! from the bucket of the guild it acts on, so that toggling a channel over and over can not burn
+//! through the rate limit of the bot.
+//!
+//! Api servers keep the buckets in redis instead of in memory, so that a user spreading their
+//! mutations over the servers still shares one bucket.
+
+use crate::{config::Config, data_source::RedisCache};
+use juniper::{graphql_value, FieldError, FieldResult};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    hash::Hash,
+    iter,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+use twilight_model::id::{GuildId, UserId};
+
+/// The amount of buckets that are kept before full buckets are dropped
+const PURGE_THRESHOLD: usize = 1024;
+
+/// The rate that a kind of bucket allows
+#[derive(Debug, Clone, Copy)]
+pub struct Rate {
+    /// The most tokens that a bucket can hold, allowing bursts of this size. A burst of 0
+    /// disables the limit.
+    pub burst: u32,
+    /// The amount of tokens added back to a bucket every minute
+    pub per_minute: u32,
+}
+
+impl Rate {
+    /// A rate that never limits anything
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    pub const UNLIMITED: Rate = Rate {
+        burst: 0,
+        per_minute: 0,
+    };
+
+    /// If buckets with this rate never run out
+    #[must_use]
+    pub fn is_unlimited(self) -> bool {
+        self.burst == 0 || self.per_minute == 0
+    }
+
+    /// The tokens added back to a bucket every second
+    fn per_second(self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+/// A bucket of tokens, refilled over time
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// The tokens in the bucket when it was last updated
+    tokens: f64,
+    /// When the bucket was last updated
+    updated_at: Instant,
+}
+
+impl Bucket {
+    /// The tokens in the bucket now, after refilling it since it was last updated
+    fn tokens(self, rate: Rate, now: Instant) -> f64 {
+        let refilled = now.duration_since(self.updated_at).as_secs_f64() * rate.per_second();
+
+        (self.tokens + refilled).min(f64::from(rate.burst))
+    }
+}
+
+/// The buckets for one kind of key
+#[derive(Debug)]
+struct Buckets<K> {
+    /// The rate of the buckets
+    rate: Rate,
+    /// The buckets, missing buckets are full
+    buckets: HashMap<K, Bucket>,
+}
+
+impl<K: Eq + Hash + Copy> Buckets<K> {
+    /// Create an empty set of buckets with the rate
+    fn new(rate: Rate) -> Self {
+        Self {
+            rate,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// How long until the bucket of the key has a token, or `None` if it has one now
+    fn wait_time(&self, key: K, now: Instant) -> Option<Duration> {
+        if self.rate.is_unlimited() {
+            return None;
+        }
+
+        let tokens = self
+            .buckets
+            .get(&key)
+            .map_or(f64::from(self.rate.burst), |bucket| {
+                bucket.tokens(self.rate, now)
+            });
+
+        if tokens >= 1.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - tokens) / self.rate.per_second(),
+            ))
+        }
+    }
+
+    /// Take a token from the bucket of the key, which must have been checked to have one
+    fn take(&mut self, key: K, now: Instant) {
+        if self.rate.is_unlimited() {
+            return;
+        }
+
+        if self.buckets.len() >= PURGE_THRESHOLD {
+            let rate = self.rate;
+            self.buckets
+                .retain(|_, bucket| bucket.tokens(rate, now) < f64::from(rate.burst));
+        }
+
+        let rate = self.rate;
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: f64::from(rate.burst),
+            updated_at: now,
+        });
+
+        bucket.tokens = bucket.tokens(rate, now) - 1.0;
+        bucket.updated_at = now;
+    }
+}
+
+/// The error returned when a mutation was rate limited
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimited {
+    /// How long until the mutation can be tried again
+    pub retry_after: Duration,
+}
+
+impl Display for RateLimited {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Too many requests, try again in {:.1} seconds",
+            self.retry_after.as_secs_f64()
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+impl From<RateLimited> for FieldError {
+    fn from(error: RateLimited) -> Self {
+        FieldError::new(
+            error,
+            graphql_value!({
+                "code": "RATE_LIMITED",
+                "retryAfter": (error.retry_after.as_secs_f64()),
+            }),
+        )
+    }
+}
+
+/// The buckets of users and guilds, behind a single lock so both can be taken from at once
+#[derive(Debug)]
+struct LimiterBuckets {
+    /// The buckets of each user
+    users: Buckets<UserId>,
+    /// The buckets of each guild
+    guilds: Buckets<GuildId>,
+}
+
+/// A rate limiter for mutations, with a token bucket for every user and guild
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// The buckets, unless they are kept in redis
+    state: Mutex<LimiterBuckets>,
+    /// The redis server that the buckets are kept in, shared with the other api servers
+    redis: Option<RedisCache>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter with the given rates for users and guilds
+    #[must_use]
+    pub fn new(user_rate: Rate, guild_rate: Rate) -> Self {
+        Self {
+            state: Mutex::new(LimiterBuckets {
+                users: Buckets::new(user_rate),
+                guilds: Buckets::new(guild_rate),
+            }),
+            redis: None,
+        }
+    }
+
+    /// Create a rate limiter with the rates from the config, keeping the buckets in the redis
+    /// server if there is one
+    #[must_use]
+    pub fn from_config(config: &Config, redis: Option<RedisCache>) -> Self {
+        Self {
+            redis,
+            ..Self::new(
+                Rate {
+                    burst: config.mutation_user_burst,
+                    per_minute: config.mutation_user_per_minute,
+                },
+                Rate {
+                    burst: config.mutation_guild_burst,
+                    per_minute: config.mutation_guild_per_minute,
+                },
+            )
+        }
+    }
+
+    /// Take a token for a mutation made by the user, acting on the guild if there is one
+    ///
+    /// Tokens are only taken if both the user and the guild have one to spare.
+    ///
+    /// # Errors
+    /// If the user or the guild is out of tokens, or the buckets are kept in redis and it could
+    /// not be reached
+    pub async fn check(&self, user_id: UserId, guild_id: Option<GuildId>) -> FieldResult<()> {
+        match &self.redis {
+            Some(redis) => self.check_shared(redis, user_id, guild_id).await,
+            None => Ok(self.check_local(user_id, guild_id)?),
+        }
+    }
+
+    /// Take a token from the buckets kept in redis
+    ///
+    /// # Errors
+    /// If the user or the guild is out of tokens, or redis could not be reached
+    async fn check_shared(
+        &self,
+        redis: &RedisCache,
+        user_id: UserId,
+        guild_id: Option<GuildId>,
+    ) -> FieldResult<()> {
+        let (user_rate, guild_rate) = {
+            let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+            (state.users.rate, state.guilds.rate)
+        };
+
+        let buckets: Vec<(String, Rate)> = iter::once((format!("user:{}", user_id), user_rate))
+            .chain(guild_id.map(|guild_id| (format!("guild:{}", guild_id), guild_rate)))
+            .filter(|(_, rate)| !rate.is_unlimited())
+            .collect();
+
+        if buckets.is_empty() {
+            return Ok(());
+        }
+
+        match redis.take_tokens(&buckets).await? {
+            Some(retry_after) => Err(RateLimited { retry_after }.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Take a token from the buckets kept in memory
+    ///
+    /// # Errors
+    /// If the user or the guild is out of tokens
+    fn check_local(&self, user_id: UserId, guild_id: Option<GuildId>) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let wait = state
+            .users
+            .wait_time(user_id, now)
+            .into_iter()
+            .chain(guild_id.and_then(|guild_id| state.guilds.wait_time(guild_id, now)))
+            .max();
+
+        if let Some(retry_after) = wait {
+            return Err(RateLimited { retry_after });
+        }
+
+        state.users.take(user_id, now);
+        if let Some(guild_id) = guild_id {
+            state.guilds.take(guild_id, now);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A rate of a token every second, with bursts of three
+    const RATE: Rate = Rate {
+        burst: 3,
+        per_minute: 60,
+    };
+
+    #[test]
+    fn buckets_allow_a_burst_then_wait_for_a_token() {
+        let now = Instant::now();
+        let mut buckets = Buckets::new(RATE);
+
+        for _ in 0..3 {
+            assert_eq!(buckets.wait_time(1, now), None);
+            buckets.take(1, now);
+        }
+
+        assert_eq!(buckets.wait_time(1, now), Some(Duration::from_secs(1)));
+        // Other keys have buckets of their own
+        assert_eq!(buckets.wait_time(2, now), None);
+    }
+
+    #[test]
+    fn buckets_refill_up_to_the_burst() {
+        let now = Instant::now();
+        let mut buckets = Buckets::new(RATE);
+
+        for _ in 0..3 {
+            buckets.take(1, now);
+        }
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(
+            buckets.wait_time(1, later),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(buckets.wait_time(1, now + Duration::from_secs(1)), None);
+
+        let bucket = buckets.buckets[&1];
+        assert!((bucket.tokens(RATE, now + Duration::from_secs(2)) - 2.0).abs() < f64::EPSILON);
+        assert!((bucket.tokens(RATE, now + Duration::from_secs(3600)) - 3.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn unlimited_rates_never_run_out() {
+        let now = Instant::now();
+
+        for rate in &[
+            Rate::UNLIMITED,
+            Rate {
+                burst: 0,
+                per_minute: 60,
+            },
+            Rate {
+                burst: 3,
+                per_minute: 0,
+            },
+        ] {
+            let mut buckets = Buckets::new(*rate);
+
+            for _ in 0..10 {
+                buckets.take(1, now);
+            }
+
+            assert_eq!(buckets.wait_time(1, now), None);
+            assert!(buckets.buckets.is_empty());
+        }
+    }
+
+    #[test]
+    fn limiters_take_from_the_user_and_the_guild_at_once() {
+        let limiter = RateLimiter::new(
+            Rate {
+                burst: 2,
+                per_minute: 1,
+            },
+            Rate {
+                burst: 1,
+                per_minute: 1,
+            },
+        );
+
+        assert!(limiter.check_local(UserId(1), Some(GuildId(1))).is_ok());
+        // The guild is out of tokens, so the user keeps theirs
+        assert!(limiter.check_local(UserId(1), Some(GuildId(1))).is_err());
+        assert!(limiter.check_local(UserId(2), Some(GuildId(1))).is_err());
+
+        assert!(limiter.check_local(UserId(1), Some(GuildId(2))).is_ok());
+        assert!(limiter.check_local(UserId(1), None).is_err());
+    }
+}
@@ -3,8 +3,12 @@
 #![allow(clippy::needless_pass_by_value, clippy::must_use_candidate)]
 
 use crate::{
-    auth::OauthCookie, auth::OauthUser, config::Config, consts::OAUTH_SCOPES,
-    graphql::DiscordContext, templates::HtmlRedirect,
+    auth::{remove_auth_cookie, OauthUser, UserAgent},
+    config::Config,
+    consts::OAUTH_SCOPES,
+    graphql::DiscordContext,
+    session::{Session, Sessions},
+    templates::HtmlRedirect,
 };
 use anyhow::Context;
 use reqwest::{header::HeaderMap, Client as ReqwestClient};
@@ -41,9 +45,11 @@ pub fn oauth_login(
 pub async fn oauth_authorize<'r>(
     discord: State<DiscordContext, 'r>,
     reqwest_client: State<ReqwestClient, 'r>,
+    sessions: State<Sessions, 'r>,
     config: State<Config, 'r>,
     code: String,
     state: &RawStr,
+    user_agent: UserAgent,
     cookies: &CookieJar<'r>,
 ) -> Result<HtmlRedirect, Debug<anyhow::Error>> {
     // FIXME: Better error page
@@ -54,7 +60,7 @@ pub async fn oauth_authorize<'r>(
     let request = request.scopes(OAUTH_SCOPES).build();
 
     let response = reqwest_client
-        .post(&request.url())
+        .post(&config.discord_url(request.url()))
         .headers(
             request
                 .headers
@@ -77,19 +83,23 @@ pub async fn oauth_authorize<'r>(
     let response: AccessTokenExchangeResponse =
         serde_json::from_str(&response).context("Failed to parse the response from the request")?;
 
-    cookies.add_private(
-        Cookie::build(
-            config.auth_cookie_name.clone(),
-            serde_json::to_string(
-                &OauthCookie::create(response, &config)
-                    .await
-                    .context("Unable to fetch information on the current user")?,
-            )
-            .context("Oauth cookie was unable to be serialized")?,
-        )
-        .domain(config.auth_cookie_domain.clone())
-        .same_site(SameSite::Lax)
-        .finish(),
+    let (session, secret) = Session::create(response, user_agent.0, &config, &reqwest_client)
+        .await
+        .context("Unable to fetch information on the current user")?;
+
+    sessions
+        .insert(session)
+        .await
+        .context("Unable to save the session")?;
+
+    cookies.add(
+        Cookie::build(config.auth_cookie_name.clone(), secret)
+            .domain(config.auth_cookie_domain.clone())
+            .same_site(SameSite::Lax)
+            .http_only(true)
+            // Debug builds are served over plain http during development
+            .secure(!cfg!(debug_assertions))
+            .finish(),
     );
 
     Ok(HtmlRedirect {
@@ -112,17 +122,27 @@ pub async fn oauth_authorize_failure<'r>(
 #[rocket::get("/oauth/logout?<from>")]
 pub async fn oauth_logout<'r>(
     // reqwest_client: State<ReqwestClient, 'r>,
-    _oauth: OauthUser, //TODO: Revoke url
+    oauth: OauthUser, //TODO: Revoke url
     from: &RawStr,
+    discord: State<DiscordContext, 'r>,
+    sessions: State<Sessions, 'r>,
     config: State<Config, 'r>,
     cookies: &CookieJar<'r>,
 ) -> Result<HtmlRedirect, Debug<anyhow::Error>> {
     // FIXME: Better error page
 
-    let mut cookie = Cookie::named(config.auth_cookie_name.clone());
-    cookie.set_domain(config.auth_cookie_domain.clone());
+    discord
+        .user_guilds
+        .invalidate(oauth.session.user_id)
+        .await
+        .context("Unable to forget the guilds of the user")?;
+
+    sessions
+        .remove(&oauth.session.hash)
+        .await
+        .context("Unable to remove the session")?;
 
-    cookies.remove_private(cookie);
+    remove_auth_cookie(cookies, &config);
 
     Ok(HtmlRedirect {
         url: dbg!(from.url_decode_lossy()),
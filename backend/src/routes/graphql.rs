@@ -3,74 +3,385 @@
 #![allow(clippy::needless_pass_by_value, clippy::must_use_candidate)]
 
 use crate::{
-    auth::OauthUser,
+    api_token::ApiTokens,
+    auth::{ApiTokenUser, OauthUser, Viewer},
+    config::Config,
+    floor::Floors,
     graphql::{DiscordContext, GraphQLContext, Schema},
+    notification::MuteNotifier,
+    persisted_queries::{Extensions, PersistedQueries},
+    query_limits::QueryLimits,
+    schedule::Scheduler,
+    session::Sessions,
+    webhook::WebhookSender,
 };
-use juniper_rocket_async::{graphiql_source, GraphQLRequest, GraphQLResponse};
+use juniper::{
+    http::{GraphQLBatchRequest, GraphQLRequest},
+    FieldError, InputValue,
+};
+use juniper_rocket_async::{graphiql_source, GraphQLResponse};
 use rocket::{
+    data::{self, Data, FromData, ToByteUnit},
     http::Status,
+    request::{FormItems, FromRequest, Outcome},
     response::{content::Html, Redirect},
-    uri, State,
+    tokio::io::AsyncReadExt,
+    uri, Request, State,
 };
+use serde::{de::DeserializeOwned, Deserialize};
+use std::fmt::{self, Debug, Formatter};
 
-/// The graphiql IDE
+/// The graphiql IDE, if it is enabled
 #[rocket::get("/")]
-pub fn graphiql(_user: OauthUser) -> Html<String> {
-    graphiql_source("/graphql")
+pub fn graphiql(_user: OauthUser, config: State<Config>) -> Option<Html<String>> {
+    if config.graphiql {
+        Some(graphiql_source("/graphql"))
+    } else {
+        None
+    }
 }
 
 /// A redirect to the auth if not logged in
 #[rocket::get("/", rank = 1)]
-pub fn graphiql_no_auth() -> Redirect {
-    Redirect::to(uri!(super::auth::oauth_login: "/"))
+pub fn graphiql_no_auth(config: State<Config>) -> Option<Redirect> {
+    if config.graphiql {
+        Some(Redirect::to(uri!(super::auth::oauth_login: "/")))
+    } else {
+        None
+    }
+}
+
+/// The most bytes read from the body of a graphql request, unless the `graphql` limit is set
+/// in the rocket config, as in `juniper_rocket_async`
+const BODY_LIMIT: u64 = 100 * 1024;
+
+/// A graphql request as it was sent, before its query has been resolved from the persisted
+/// queries
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingRequest {
+    /// The query to execute, which can be left out if the hash of a persisted query is sent
+    query: Option<String>,
+    /// The name of the operation in the query to execute
+    operation_name: Option<String>,
+    /// The variables of the operation
+    variables: Option<InputValue>,
+    /// The extensions of the request
+    #[serde(default)]
+    extensions: Extensions,
+}
+
+impl IncomingRequest {
+    /// A request made up of only a query, as sent in an `application/graphql` body
+    fn from_query(query: String) -> Self {
+        Self {
+            query: Some(query),
+            operation_name: None,
+            variables: None,
+            extensions: Extensions::default(),
+        }
+    }
+
+    /// Parse a request from the query string of a get request, where the variables and
+    /// extensions are json encoded
+    fn from_query_string(query_string: &str) -> Result<Self, String> {
+        /// Parse a json encoded query parameter
+        fn parse_json<T: DeserializeOwned>(name: &str, value: &str) -> Result<T, String> {
+            serde_json::from_str(value).map_err(|e| format!("Malformed {}: {}", name, e))
+        }
+
+        let mut request = IncomingRequest {
+            query: None,
+            operation_name: None,
+            variables: None,
+            extensions: Extensions::default(),
+        };
+
+        for item in FormItems::from(query_string) {
+            let (key, value) = item.key_value_decoded();
+
+            match key.as_str() {
+                "query" => request.query = Some(value),
+                "operationName" => request.operation_name = Some(value),
+                "variables" => request.variables = Some(parse_json("variables", &value)?),
+                "extensions" => request.extensions = parse_json("extensions", &value)?,
+                _ => {}
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+/// A single graphql request or a batch of them, as juniper's `GraphQLBatchRequest` but with the
+/// extensions of every request kept
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum IncomingBatch {
+    /// A single request
+    Single(IncomingRequest),
+    /// Many requests, executed in order
+    Batch(Vec<IncomingRequest>),
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for IncomingBatch {
+    type Error = String;
+
+    /// Read a single request from the query string of a get request
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        match IncomingRequest::from_query_string(request.uri().query().unwrap_or_default()) {
+            Ok(incoming) => Outcome::Success(IncomingBatch::Single(incoming)),
+            Err(e) => Outcome::Failure((Status::BadRequest, e)),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl FromData for IncomingBatch {
+    type Error = String;
+
+    /// Read a request from the body of a post request, either as json or as the bare query of an
+    /// `application/graphql` body
+    async fn from_data(request: &Request<'_>, data: Data) -> data::Outcome<Self, Self::Error> {
+        let content_type = request
+            .content_type()
+            .map(|content_type| (content_type.top().as_str(), content_type.sub().as_str()));
+        let is_json = match content_type {
+            Some(("application", "json")) => true,
+            Some(("application", "graphql")) => false,
+            _ => return data::Outcome::Forward(data),
+        };
+
+        let limit = request
+            .limits()
+            .get("graphql")
+            .unwrap_or_else(|| BODY_LIMIT.bytes());
+        let mut body = String::new();
+
+        if let Err(e) = data.open(limit).read_to_string(&mut body).await {
+            return data::Outcome::Failure((Status::InternalServerError, format!("{:?}", e)));
+        }
+
+        if is_json {
+            match serde_json::from_str(&body) {
+                Ok(batch) => data::Outcome::Success(batch),
+                Err(e) => data::Outcome::Failure((
+                    Status::BadRequest,
+                    format!("Malformed graphql request: {}", e),
+                )),
+            }
+        } else {
+            data::Outcome::Success(IncomingBatch::Single(IncomingRequest::from_query(body)))
+        }
+    }
+}
+
+/// The managed state needed to execute graphql requests
+pub struct Executor<'r> {
+    /// The schema to execute requests against
+    schema: &'r Schema,
+    /// The discord clients and cache
+    discord: &'r DiscordContext,
+    /// The session store
+    sessions: &'r Sessions,
+    /// The api token store
+    api_tokens: &'r ApiTokens,
+    /// The webhooks of guilds
+    webhooks: &'r WebhookSender,
+    /// The mute notifications of guilds
+    notifications: &'r MuteNotifier,
+    /// The floors of voice channels in speaker mode
+    floors: &'r Floors,
+    /// The scheduler of the scheduled mutes
+    schedules: &'r Scheduler,
+    /// The persisted query registry
+    persisted_queries: &'r PersistedQueries,
+    /// The limits on the depth and cost of queries
+    query_limits: &'r QueryLimits,
+}
+
+impl Debug for Executor<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // The schema does not implement debug
+        f.debug_struct("Executor")
+            .field("discord", self.discord)
+            .field("sessions", self.sessions)
+            .field("api_tokens", self.api_tokens)
+            .field("webhooks", self.webhooks)
+            .field("notifications", self.notifications)
+            .field("floors", self.floors)
+            .field("schedules", self.schedules)
+            .field("persisted_queries", self.persisted_queries)
+            .field("query_limits", self.query_limits)
+            .finish()
+    }
+}
+
+impl<'r> Executor<'r> {
+    /// Gather the managed state from the rocket, if all of it was mounted
+    fn from_managed_state(request: &Request<'r>) -> Option<Self> {
+        Some(Executor {
+            schema: request.managed_state()?,
+            discord: request.managed_state()?,
+            sessions: request.managed_state()?,
+            api_tokens: request.managed_state()?,
+            webhooks: request.managed_state()?,
+            notifications: request.managed_state()?,
+            floors: request.managed_state()?,
+            schedules: request.managed_state()?,
+            persisted_queries: request.managed_state()?,
+            query_limits: request.managed_state()?,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for Executor<'r> {
+    type Error = &'static str;
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        match Executor::from_managed_state(request) {
+            Some(executor) => Outcome::Success(executor),
+            None => Outcome::Failure((
+                Status::InternalServerError,
+                "Graphql state was not mounted on the rocket",
+            )),
+        }
+    }
+}
+
+impl Executor<'_> {
+    /// Resolve the persisted query of a request and check it against the limits, returning it
+    /// along with its estimated cost
+    ///
+    /// # Errors
+    /// If the query could not be resolved or breaks the limits
+    async fn resolve(
+        &self,
+        incoming: IncomingRequest,
+    ) -> Result<(GraphQLRequest, usize), FieldError> {
+        let query = self
+            .persisted_queries
+            .resolve(incoming.query, incoming.extensions.persisted_query.as_ref())
+            .await?;
+        let complexity = self.query_limits.check(&query)?;
+
+        Ok((
+            GraphQLRequest::new(query, incoming.operation_name, incoming.variables),
+            complexity,
+        ))
+    }
+
+    /// Resolve the persisted queries of a request, or a batch of them, and check the limits
+    /// before executing anything
+    ///
+    /// # Errors
+    /// If a query could not be resolved or the requests break the limits
+    async fn resolve_batch(&self, batch: IncomingBatch) -> Result<GraphQLBatchRequest, FieldError> {
+        match batch {
+            IncomingBatch::Single(incoming) => {
+                let (request, _) = self.resolve(incoming).await?;
+
+                Ok(GraphQLBatchRequest::Single(request))
+            }
+            IncomingBatch::Batch(incoming) => {
+                self.query_limits.check_batch_size(incoming.len())?;
+
+                let mut requests = Vec::with_capacity(incoming.len());
+                let mut complexities = Vec::with_capacity(incoming.len());
+                for incoming in incoming {
+                    let (request, complexity) = self.resolve(incoming).await?;
+                    requests.push(request);
+                    complexities.push(complexity);
+                }
+                self.query_limits.check_batch_complexity(complexities)?;
+
+                Ok(GraphQLBatchRequest::Batch(requests))
+            }
+        }
+    }
+
+    /// Execute a graphql request, or a batch of them, on behalf of the user
+    async fn execute(&self, batch: IncomingBatch, user: Viewer) -> GraphQLResponse {
+        let batch = match self.resolve_batch(batch).await {
+            Ok(batch) => batch,
+            Err(e) => return GraphQLResponse::error(e),
+        };
+
+        let context = GraphQLContext::new(
+            self.discord.clone(),
+            self.sessions.clone(),
+            self.api_tokens.clone(),
+            self.webhooks.clone(),
+            self.notifications.clone(),
+            self.floors.clone(),
+            self.schedules.clone(),
+            user,
+        );
+        let response = batch.execute(self.schema, &context).await;
+
+        let status = if response.is_ok() {
+            Status::Ok
+        } else {
+            Status::BadRequest
+        };
+
+        GraphQLResponse(
+            status,
+            serde_json::to_string(&response).expect("Graphql responses are always serializable"),
+        )
+    }
 }
 
 /// The get based graphql handler
-#[rocket::get("/graphql?<request>")]
-pub async fn get_graphql_handler<'r>(
-    discord: State<DiscordContext, 'r>,
+#[rocket::get("/graphql")]
+pub async fn get_graphql_handler(
+    executor: Executor<'_>,
     oauth: OauthUser,
-    schema: State<Schema, 'r>,
-    request: GraphQLRequest,
+    request: IncomingBatch,
 ) -> GraphQLResponse {
-    request
-        .execute(
-            &schema,
-            &GraphQLContext {
-                discord: discord.inner().clone(),
-                user: oauth,
-            },
-        )
-        .await
+    executor.execute(request, oauth.into()).await
+}
+
+/// The get based graphql handler for scripts using an api token
+#[rocket::get("/graphql", rank = 1)]
+pub async fn get_graphql_api_token(
+    executor: Executor<'_>,
+    token: ApiTokenUser,
+    request: IncomingBatch,
+) -> GraphQLResponse {
+    executor.execute(request, token.into()).await
 }
 
 /// An error code if not logged in
-#[rocket::get("/graphql?<_request>", rank = 1)]
-pub fn get_graphql_no_auth(_request: GraphQLRequest) -> Status {
+#[rocket::get("/graphql", rank = 2)]
+pub fn get_graphql_no_auth() -> Status {
     Status::Unauthorized
 }
 
 /// The post based graphql handler
 #[rocket::post("/graphql", data = "<request>")]
-pub async fn post_graphql_handler<'r>(
-    discord: State<DiscordContext, 'r>,
+pub async fn post_graphql_handler(
+    executor: Executor<'_>,
     oauth: OauthUser,
-    schema: State<Schema, 'r>,
-    request: GraphQLRequest,
+    request: IncomingBatch,
 ) -> GraphQLResponse {
-    request
-        .execute(
-            &schema,
-            &GraphQLContext {
-                discord: discord.inner().clone(),
-                user: oauth,
-            },
-        )
-        .await
+    executor.execute(request, oauth.into()).await
+}
+
+/// The post based graphql handler for scripts using an api token
+#[rocket::post("/graphql", data = "<request>", rank = 1)]
+pub async fn post_graphql_api_token(
+    executor: Executor<'_>,
+    token: ApiTokenUser,
+    request: IncomingBatch,
+) -> GraphQLResponse {
+    executor.execute(request, token.into()).await
 }
 
 /// An error code if not logged in
-#[rocket::post("/graphql", data = "<_request>", rank = 1)]
-pub fn post_graphql_no_auth(_request: GraphQLRequest) -> Status {
+#[rocket::post("/graphql", rank = 2)]
+pub fn post_graphql_no_auth() -> Status {
     Status::Unauthorized
 }
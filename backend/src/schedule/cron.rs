@@ -0,0 +1,406 @@
+//! Cron expressions describing when a recurring schedule runs
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, TimeZone, Timelike};
+use std::{str::FromStr, time};
+
+/// How many days ahead or back to look for a run, enough to find a run on the 29th of february
+const MAX_LOOKAHEAD_DAYS: u32 = 366 * 8;
+
+/// The number of minutes in a day without daylight saving changes
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// The names that can be used in place of the months, starting at january
+const MONTH_NAMES: &[&str] = &[
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+/// The names that can be used in place of the days of the week, starting at sunday
+const WEEKDAY_NAMES: &[&str] = &["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// A standard five field cron expression, `<minute> <hour> <day of month> <month> <day of week>`
+///
+/// Every field accepts `*`, single values, ranges such as `1-5`, steps such as `*/15` or
+/// `8-18/2`, and comma separated lists of those. Months and days of the week can also be named,
+/// as in `MON-FRI`, and both 0 and 7 are sunday. As in cron, if both the day of the month and the
+/// day of the week are restricted, a day matching either of them runs.
+///
+/// The shorthands `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are also accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cron {
+    /// The minutes to run at, as bits 0 to 59
+    minutes: u64,
+    /// The hours to run at, as bits 0 to 23
+    hours: u64,
+    /// The days of the month to run on, as bits 1 to 31
+    days: u64,
+    /// The months to run in, as bits 1 to 12
+    months: u64,
+    /// The days of the week to run on, as bits 0 to 6 starting at sunday
+    weekdays: u64,
+    /// If the day of the month field was restricted
+    days_restricted: bool,
+    /// If the day of the week field was restricted
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    /// The first time after the given time that the expression matches, in the same timezone
+    ///
+    /// Local times skipped by a daylight saving change never match, and local times repeated by
+    /// one match the first time they happen.
+    #[must_use]
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let timezone = after.timezone();
+        let start = (after.naive_local() + Duration::minutes(1))
+            .with_second(0)?
+            .with_nanosecond(0)?;
+        let mut date = start.date();
+
+        for _ in 0..MAX_LOOKAHEAD_DAYS {
+            if self.matches_date(date) {
+                for hour in bits(self.hours, 0, 23) {
+                    for minute in bits(self.minutes, 0, 59) {
+                        let local = date.and_hms(hour, minute, 0);
+
+                        if local < start {
+                            continue;
+                        }
+
+                        match timezone.from_local_datetime(&local) {
+                            LocalResult::Single(time) | LocalResult::Ambiguous(time, _)
+                                if time > *after =>
+                            {
+                                return Some(time)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+
+            date = date.succ_opt()?;
+        }
+
+        None
+    }
+
+    /// The last time at or before the given time that the expression matches, in the same timezone
+    ///
+    /// As with [`Cron::next_after`], local times skipped by a daylight saving change never match,
+    /// and local times repeated by one match the first time they happen.
+    #[must_use]
+    pub fn last_at_or_before<Tz: TimeZone>(&self, before: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let timezone = before.timezone();
+        // Starting a day later, as a local time repeated by a daylight saving change can come
+        // after the local time of `before` while first happening before it
+        let mut date = before.naive_local().date().succ_opt()?;
+
+        for _ in 0..MAX_LOOKAHEAD_DAYS {
+            if self.matches_date(date) {
+                for hour in bits(self.hours, 0, 23).rev() {
+                    for minute in bits(self.minutes, 0, 59).rev() {
+                        match timezone.from_local_datetime(&date.and_hms(hour, minute, 0)) {
+                            LocalResult::Single(time) | LocalResult::Ambiguous(time, _)
+                                if time <= *before =>
+                            {
+                                return Some(time)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+
+            date = date.pred_opt()?;
+        }
+
+        None
+    }
+
+    /// The shortest time between two runs on the same or following days, leaving out daylight
+    /// saving changes
+    #[must_use]
+    pub fn shortest_interval(&self) -> time::Duration {
+        let times: Vec<u32> = bits(self.hours, 0, 23)
+            .flat_map(|hour| bits(self.minutes, 0, 59).map(move |minute| hour * 60 + minute))
+            .collect();
+
+        // The first run of a day also follows the last run of the day before
+        let overnight = times
+            .first()
+            .zip(times.last())
+            .map(|(first, last)| first + MINUTES_PER_DAY - last);
+
+        let minutes = times
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .chain(overnight)
+            .min()
+            .unwrap_or(MINUTES_PER_DAY);
+
+        time::Duration::from_secs(u64::from(minutes) * 60)
+    }
+
+    /// If the expression runs on the date
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        let day = has_bit(self.days, date.day());
+        let weekday = has_bit(self.weekdays, date.weekday().num_days_from_sunday());
+
+        let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        };
+
+        day_matches && has_bit(self.months, date.month())
+    }
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expression = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let (minutes, hours, days, months, weekdays) = match *fields.as_slice() {
+            [minutes, hours, days, months, weekdays] => (minutes, hours, days, months, weekdays),
+            _ => bail!(
+                "A cron expression needs 5 fields, minute hour day-of-month month day-of-week, but {} were given",
+                fields.len()
+            ),
+        };
+
+        // Sunday can be given as both 0 and 7
+        let weekday_set =
+            parse_field(weekdays, 0, 7, WEEKDAY_NAMES, 0).context("Invalid day of week")?;
+
+        Ok(Cron {
+            minutes: parse_field(minutes, 0, 59, &[], 0).context("Invalid minute")?,
+            hours: parse_field(hours, 0, 23, &[], 0).context("Invalid hour")?,
+            days: parse_field(days, 1, 31, &[], 1).context("Invalid day of month")?,
+            months: parse_field(months, 1, 12, MONTH_NAMES, 1).context("Invalid month")?,
+            weekdays: (weekday_set | weekday_set >> 7) & 0x7F,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+}
+
+/// Parse a field of a cron expression into a set of bits, where `names` are the names of the
+/// values starting at `first_name`
+fn parse_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    first_name: u32,
+) -> anyhow::Result<u64> {
+    let value = |value: &str| -> anyhow::Result<u32> {
+        let named = (first_name..)
+            .zip(names)
+            .find(|(_, name)| name.eq_ignore_ascii_case(value));
+
+        let parsed = match named {
+            Some((named, _)) => named,
+            None => value
+                .parse()
+                .map_err(|_| anyhow!("{} is not a number", value))?,
+        };
+
+        if parsed < min || parsed > max {
+            bail!("{} is not between {} and {}", parsed, min, max);
+        }
+
+        Ok(parsed)
+    };
+
+    let mut set = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(slash) => (
+                &part[..slash],
+                part[slash + 1..]
+                    .parse()
+                    .map_err(|_| anyhow!("{} is not a valid step", &part[slash + 1..]))?,
+            ),
+            None => (part, 1),
+        };
+
+        if step == 0 {
+            bail!("The step can not be 0");
+        }
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.find('-') {
+                Some(dash) => (value(&range[..dash])?, value(&range[dash + 1..])?),
+                // A single value with a step repeats until the end of the range
+                None if part.contains('/') => (value(range)?, max),
+                None => {
+                    let single = value(range)?;
+                    (single, single)
+                }
+            },
+        };
+
+        if start > end {
+            bail!("The range {} ends before it starts", range);
+        }
+
+        for bit in (start..=end).step_by(step) {
+            set |= 1 << bit;
+        }
+    }
+
+    Ok(set)
+}
+
+/// If the bit is set
+fn has_bit(set: u64, bit: u32) -> bool {
+    set & (1 << bit) != 0
+}
+
+/// The bits that are set between min and max
+fn bits(set: u64, min: u32, max: u32) -> impl DoubleEndedIterator<Item = u32> {
+    (min..=max).filter(move |&bit| has_bit(set, bit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use chrono_tz::Europe::Amsterdam;
+
+    fn cron(expression: &str) -> Cron {
+        expression.parse().unwrap()
+    }
+
+    #[test]
+    fn shorthands_and_names_match_their_fields() {
+        assert_eq!(cron("@daily"), cron("0 0 * * *"));
+        assert_eq!(cron("@weekly"), cron("0 0 * * 7"));
+        assert_eq!(cron("0 9 * jan-MAR mon,FRI"), cron("0 9 * 1-3 1,5"));
+        assert_eq!(cron("*/20 0 * * *"), cron("0,20,40 0 * * *"));
+        assert_eq!(cron("50/5 8-12/2 * * *"), cron("50,55 8,10,12 * * *"));
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for expression in &[
+            "61 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "* * * FOO *",
+            "0 7 * *",
+            "0 7 * * * *",
+        ] {
+            assert!(expression.parse::<Cron>().is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn next_after_finds_the_next_match() {
+        let after = Utc.ymd(2021, 3, 1).and_hms(10, 7, 30);
+
+        assert_eq!(
+            cron("*/15 * * * *").next_after(&after),
+            Some(Utc.ymd(2021, 3, 1).and_hms(10, 15, 0))
+        );
+        assert_eq!(
+            cron("0 22 * * MON-FRI").next_after(&Utc.ymd(2021, 3, 5).and_hms(22, 0, 0)),
+            Some(Utc.ymd(2021, 3, 8).and_hms(22, 0, 0))
+        );
+        assert_eq!(
+            cron("0 0 29 2 *").next_after(&after),
+            Some(Utc.ymd(2024, 2, 29).and_hms(0, 0, 0))
+        );
+        assert_eq!(cron("0 0 30 2 *").next_after(&after), None);
+    }
+
+    #[test]
+    fn restricted_days_and_weekdays_both_match() {
+        // The 13th of march 2021 is a saturday, the 5th a friday
+        let after = Utc.ymd(2021, 3, 1).and_hms(0, 0, 0);
+
+        assert_eq!(
+            cron("0 0 13 * FRI").next_after(&after),
+            Some(Utc.ymd(2021, 3, 5).and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            cron("0 0 13 * *").next_after(&after),
+            Some(Utc.ymd(2021, 3, 13).and_hms(0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn times_skipped_by_daylight_saving_never_match() {
+        // Clocks in amsterdam went from 02:00 to 03:00 on the 28th of march 2021
+        let after = Amsterdam.ymd(2021, 3, 27).and_hms(12, 0, 0);
+
+        assert_eq!(
+            cron("30 2 * * *").next_after(&after),
+            Some(Amsterdam.ymd(2021, 3, 29).and_hms(2, 30, 0))
+        );
+        assert_eq!(
+            cron("30 2 * * *").last_at_or_before(&Amsterdam.ymd(2021, 3, 28).and_hms(12, 0, 0)),
+            Some(Amsterdam.ymd(2021, 3, 27).and_hms(2, 30, 0))
+        );
+    }
+
+    #[test]
+    fn times_repeated_by_daylight_saving_match_once() {
+        // Clocks in amsterdam went from 03:00 back to 02:00 on the 31st of october 2021, so
+        // 02:30 happened at both 00:30 and 01:30 utc
+        let first = Utc.ymd(2021, 10, 31).and_hms(0, 30, 0);
+        let after = Amsterdam.ymd(2021, 10, 31).and_hms(0, 0, 0);
+
+        let next = cron("30 2 * * *").next_after(&after).unwrap();
+        assert_eq!(next, first);
+        assert_eq!(
+            cron("30 2 * * *").next_after(&next),
+            Some(Amsterdam.ymd(2021, 11, 1).and_hms(2, 30, 0))
+        );
+
+        // During the repeated hour, the first 02:30 already happened
+        let repeated = Utc
+            .ymd(2021, 10, 31)
+            .and_hms(1, 45, 0)
+            .with_timezone(&Amsterdam);
+        assert_eq!(
+            cron("30 2 * * *").last_at_or_before(&repeated),
+            Some(first.with_timezone(&Amsterdam))
+        );
+    }
+
+    #[test]
+    fn last_at_or_before_includes_the_time_itself() {
+        let at = Utc.ymd(2021, 3, 2).and_hms(7, 0, 0);
+
+        assert_eq!(cron("0 7 * * *").last_at_or_before(&at), Some(at));
+        assert_eq!(
+            cron("0 22 * * *").last_at_or_before(&at),
+            Some(Utc.ymd(2021, 3, 1).and_hms(22, 0, 0))
+        );
+    }
+
+    #[test]
+    fn shortest_interval_includes_runs_on_following_days() {
+        let minutes = |expression| cron(expression).shortest_interval().as_secs() / 60;
+
+        assert_eq!(minutes("*/15 * * * *"), 15);
+        assert_eq!(minutes("0 22 * * MON-FRI"), 24 * 60);
+        assert_eq!(minutes("0,59 0,23 * * *"), 1);
+        assert_eq!(minutes("0 0,12 * * *"), 12 * 60);
+    }
+}
@@ -0,0 +1,124 @@
+//! A schedule store that keeps schedules in memory
+
+use super::{Schedule, ScheduleStore};
+use chrono_tz::Tz;
+use std::{
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+};
+use twilight_model::id::GuildId;
+
+/// A schedule store that keeps schedules in memory, losing them when the server restarts
+#[derive(Debug, Default)]
+pub struct MemoryScheduleStore {
+    /// The schedules, keyed by their id
+    schedules: RwLock<HashMap<String, Schedule>>,
+    /// The timezones that guilds chose
+    timezones: RwLock<HashMap<GuildId, Tz>>,
+}
+
+#[rocket::async_trait]
+impl ScheduleStore for MemoryScheduleStore {
+    async fn insert(&self, schedule: Schedule) -> anyhow::Result<()> {
+        self.schedules
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(schedule.id.clone(), schedule);
+
+        Ok(())
+    }
+
+    async fn guild_schedules(&self, guild_id: GuildId) -> anyhow::Result<Vec<Schedule>> {
+        let mut schedules: Vec<_> = self
+            .schedules
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|schedule| schedule.guild_id == guild_id)
+            .cloned()
+            .collect();
+
+        schedules.sort_by_key(|schedule| schedule.created_at);
+
+        Ok(schedules)
+    }
+
+    async fn remove(&self, guild_id: GuildId, id: &str) -> anyhow::Result<bool> {
+        let mut schedules = self
+            .schedules
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if schedules
+            .get(id)
+            .map_or(false, |schedule| schedule.guild_id == guild_id)
+        {
+            schedules.remove(id);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn due(&self, now: u64) -> anyhow::Result<Vec<Schedule>> {
+        let mut schedules: Vec<_> = self
+            .schedules
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|schedule| schedule.next_run_at.map_or(false, |next| next <= now))
+            .cloned()
+            .collect();
+
+        schedules.sort_by_key(|schedule| schedule.next_run_at);
+
+        Ok(schedules)
+    }
+
+    async fn next_run_at(&self) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .schedules
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter_map(|schedule| schedule.next_run_at)
+            .min())
+    }
+
+    async fn update_runs(
+        &self,
+        id: &str,
+        last_run_at: Option<u64>,
+        next_run_at: Option<u64>,
+    ) -> anyhow::Result<()> {
+        if let Some(schedule) = self
+            .schedules
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(id)
+        {
+            schedule.last_run_at = last_run_at;
+            schedule.next_run_at = next_run_at;
+        }
+
+        Ok(())
+    }
+
+    async fn timezone(&self, guild_id: GuildId) -> anyhow::Result<Option<Tz>> {
+        Ok(self
+            .timezones
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&guild_id)
+            .copied())
+    }
+
+    async fn set_timezone(&self, guild_id: GuildId, timezone: Tz) -> anyhow::Result<()> {
+        self.timezones
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(guild_id, timezone);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+//! Scheduled mutes, letting guilds mute or unmute a voice channel at a set time or on a recurring
+//! schedule, such as quiet hours every weekday night
+//!
+//! Schedules are kept in a [`ScheduleStore`] along with the time of their next run, which the
+//! [`Scheduler`] waits for. Recurring schedules are [`Cron`] expressions evaluated in the
+//! timezone configured for their guild. Runs that were missed while the server was down are
+//! caught up on when it starts again.
+
+use crate::{
+    config::Config,
+    session::{random_key, unix_timestamp},
+};
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use juniper::GraphQLEnum;
+use std::{convert::TryFrom, fmt::Debug, str::FromStr, sync::Arc, time::Duration};
+use twilight_model::id::{ChannelId, GuildId, UserId};
+
+pub mod cron;
+pub mod memory;
+pub mod scheduler;
+pub mod sqlite;
+
+pub use cron::Cron;
+pub use memory::MemoryScheduleStore;
+pub use scheduler::Scheduler;
+pub use sqlite::SqliteScheduleStore;
+
+/// The most schedules that a guild can have
+pub const MAX_GUILD_SCHEDULES: usize = 25;
+
+/// The shortest time allowed between two runs of a recurring schedule
+pub const MIN_CRON_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// A shared handle to the schedule store used by the server
+pub type Schedules = Arc<dyn ScheduleStore>;
+
+/// What a schedule does to its voice channel when it runs.
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScheduleAction {
+    /// Server mute everyone in the voice channel.
+    Mute,
+    /// Unmute everyone in the voice channel.
+    Unmute,
+}
+
+impl ScheduleAction {
+    /// The name of the action when stored
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScheduleAction::Mute => "mute",
+            ScheduleAction::Unmute => "unmute",
+        }
+    }
+}
+
+impl FromStr for ScheduleAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mute" => Ok(ScheduleAction::Mute),
+            "unmute" => Ok(ScheduleAction::Unmute),
+            _ => Err(anyhow!("Unknown schedule action {}", s)),
+        }
+    }
+}
+
+/// When a schedule runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recurrence {
+    /// Once, at the seconds since the unix epoch
+    Once(u64),
+    /// Every time the cron expression matches, in the timezone of the guild
+    Cron(String),
+}
+
+/// An action on a voice channel that runs at set times
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    /// Public id of the schedule
+    pub id: String,
+    /// The guild of the voice channel
+    pub guild_id: GuildId,
+    /// The voice channel to act on
+    pub channel_id: ChannelId,
+    /// What is done to the voice channel
+    pub action: ScheduleAction,
+    /// When the schedule runs
+    pub recurrence: Recurrence,
+    /// The seconds since the unix epoch of the next run, or `None` once a one off schedule ran
+    pub next_run_at: Option<u64>,
+    /// The seconds since the unix epoch of the last run
+    pub last_run_at: Option<u64>,
+    /// The id of the user who created the schedule
+    pub created_by: UserId,
+    /// The seconds since the unix epoch that the schedule was created
+    pub created_at: u64,
+}
+
+impl Schedule {
+    /// Create a new schedule with a random id, due at its first run in the timezone
+    ///
+    /// # Errors
+    /// If the recurrence is not a valid cron expression, runs more often than
+    /// [`MIN_CRON_INTERVAL`] allows, or never runs
+    pub fn create(
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        action: ScheduleAction,
+        recurrence: Recurrence,
+        timezone: Tz,
+        created_by: UserId,
+    ) -> anyhow::Result<Self> {
+        let created_at = unix_timestamp();
+        let next_run_at = match &recurrence {
+            Recurrence::Once(run_at) => Some(*run_at),
+            Recurrence::Cron(cron) => {
+                if cron.parse::<Cron>()?.shortest_interval() < MIN_CRON_INTERVAL {
+                    bail!(
+                        "A recurring schedule can run at most once every {} minutes",
+                        MIN_CRON_INTERVAL.as_secs() / 60
+                    );
+                }
+
+                Some(
+                    next_cron_run(cron, timezone, created_at)?
+                        .context("The cron expression never matches")?,
+                )
+            }
+        };
+
+        Ok(Schedule {
+            id: random_key(),
+            guild_id,
+            channel_id,
+            action,
+            recurrence,
+            next_run_at,
+            last_run_at: None,
+            created_by,
+            created_at,
+        })
+    }
+
+    /// The next run of the schedule after the time, in seconds since the unix epoch
+    ///
+    /// One off schedules never run again.
+    ///
+    /// # Errors
+    /// If the recurrence is not a valid cron expression
+    pub fn next_run_after(&self, timezone: Tz, after: u64) -> anyhow::Result<Option<u64>> {
+        match &self.recurrence {
+            Recurrence::Once(_) => Ok(None),
+            Recurrence::Cron(cron) => next_cron_run(cron, timezone, after),
+        }
+    }
+
+    /// The last time at or before the given time that the schedule was meant to run, in seconds
+    /// since the unix epoch, whether or not it did
+    ///
+    /// # Errors
+    /// If the recurrence is not a valid cron expression
+    pub fn last_run_at_or_before(&self, timezone: Tz, at: u64) -> anyhow::Result<Option<u64>> {
+        match &self.recurrence {
+            Recurrence::Once(run_at) => Ok(Some(*run_at).filter(|&run_at| run_at <= at)),
+            Recurrence::Cron(cron) => last_cron_run(cron, timezone, at),
+        }
+    }
+}
+
+/// The next time after the time that the cron expression matches in the timezone, in seconds
+/// since the unix epoch
+///
+/// # Errors
+/// If the cron expression is not valid
+fn next_cron_run(cron: &str, timezone: Tz, after: u64) -> anyhow::Result<Option<u64>> {
+    let cron: Cron = cron.parse()?;
+
+    Ok(cron
+        .next_after(&local_time(after, timezone)?)
+        .and_then(|next| u64::try_from(next.timestamp()).ok()))
+}
+
+/// The last time at or before the time that the cron expression matches in the timezone, in
+/// seconds since the unix epoch
+///
+/// # Errors
+/// If the cron expression is not valid
+fn last_cron_run(cron: &str, timezone: Tz, at: u64) -> anyhow::Result<Option<u64>> {
+    let cron: Cron = cron.parse()?;
+
+    Ok(cron
+        .last_at_or_before(&local_time(at, timezone)?)
+        .and_then(|last| u64::try_from(last.timestamp()).ok()))
+}
+
+/// The seconds since the unix epoch as a time in the timezone
+///
+/// # Errors
+/// If the time is too far in the future
+fn local_time(time: u64, timezone: Tz) -> anyhow::Result<DateTime<Tz>> {
+    Ok(Utc
+        .timestamp(i64::try_from(time)?, 0)
+        .with_timezone(&timezone))
+}
+
+/// Storage for schedules and the timezones that guilds evaluate them in
+#[rocket::async_trait]
+pub trait ScheduleStore: Debug + Send + Sync {
+    /// Save a new schedule
+    async fn insert(&self, schedule: Schedule) -> anyhow::Result<()>;
+
+    /// Get all of the schedules of a guild, oldest first
+    async fn guild_schedules(&self, guild_id: GuildId) -> anyhow::Result<Vec<Schedule>>;
+
+    /// Remove a schedule of a guild, returning if it existed
+    async fn remove(&self, guild_id: GuildId, id: &str) -> anyhow::Result<bool>;
+
+    /// Get the schedules whose next run is at or before the time, earliest first
+    async fn due(&self, now: u64) -> anyhow::Result<Vec<Schedule>>;
+
+    /// Get the time of the earliest next run of any schedule
+    async fn next_run_at(&self) -> anyhow::Result<Option<u64>>;
+
+    /// Record when a schedule last ran and when it runs next
+    async fn update_runs(
+        &self,
+        id: &str,
+        last_run_at: Option<u64>,
+        next_run_at: Option<u64>,
+    ) -> anyhow::Result<()>;
+
+    /// Get the timezone that a guild evaluates its schedules in, if it chose one
+    async fn timezone(&self, guild_id: GuildId) -> anyhow::Result<Option<Tz>>;
+
+    /// Save the timezone that a guild evaluates its schedules in
+    async fn set_timezone(&self, guild_id: GuildId, timezone: Tz) -> anyhow::Result<()>;
+}
+
+/// Create the schedule store described by the config
+///
+/// Schedules are stored in the sqlite database at `schedule_database` if one is configured,
+/// otherwise they are kept in memory and lost on restart.
+///
+/// # Errors
+/// If the sqlite database could not be opened
+pub fn create_schedule_store(config: &Config) -> anyhow::Result<Schedules> {
+    Ok(match &config.schedule_database {
+        Some(path) => Arc::new(SqliteScheduleStore::open(path)?),
+        None => Arc::new(MemoryScheduleStore::default()),
+    })
+}
@@ -0,0 +1,544 @@
+//! Running the schedules when they are due
+//!
+//! Every api server sharing a redis cache runs the schedules in the store, so each run is
+//! claimed through redis first and only made by the server that claims it.
+
+use super::{Schedule, ScheduleAction, Schedules};
+use crate::{
+    data_source::{RedisCache, SharedDataSource},
+    floor::Floors,
+    graphql::{authorize_scheduled, mute_channel},
+    notification::MuteNotifier,
+    session::unix_timestamp,
+    webhook::{WebhookEvent, WebhookSender},
+};
+use anyhow::anyhow;
+use async_std::{future::timeout, stream::StreamExt, task};
+use chrono_tz::Tz;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+use twilight_model::id::ChannelId;
+
+/// The shortest time to wait between checks for due schedules, so schedules that could not be
+/// moved on are not retried in a busy loop
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to put off the schedules of guilds that are not cached before checking them again,
+/// and how long after starting to wait for guilds to be cached before putting any off
+const PARK_DURATION: Duration = Duration::from_secs(60);
+
+/// The longest time to wait between checks for due schedules, in case the clock jumped
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the claim on a run lasts, long enough for the server that made it to move the
+/// schedule on
+const CLAIM_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Waits for the next run of the schedules in a store and mutes or unmutes their voice channels
+///
+/// The scheduler has to be woken whenever a schedule is created, removed or moved, so it does
+/// not sleep past the new next run.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    /// The store of schedules and guild timezones
+    store: Schedules,
+    /// Wakes the running scheduler up
+    wake: UnboundedSender<()>,
+    /// The wake ups, taken when the scheduler is started
+    receiver: Arc<Mutex<Option<UnboundedReceiver<()>>>>,
+}
+
+impl Scheduler {
+    /// Create a scheduler for the schedules in the store, which does nothing until started
+    #[must_use]
+    pub fn new(store: Schedules) -> Self {
+        let (wake, receiver) = unbounded();
+
+        Self {
+            store,
+            wake,
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+        }
+    }
+
+    /// The store of schedules and guild timezones
+    #[must_use]
+    pub fn store(&self) -> &Schedules {
+        &self.store
+    }
+
+    /// Let the scheduler know that the next run may have changed
+    pub fn wake(&self) {
+        self.wake.unbounded_send(()).ok();
+    }
+
+    /// Start running the schedules in the background
+    ///
+    /// Runs that were missed while the server was down are due right away, so they are caught up
+    /// on once the guild of their channel is cached. Only the action that was last meant to
+    /// run in every channel is applied, so a night of missed quiet hours does not mute and unmute
+    /// in a row, and the channel ends up as it would have without the missed runs.
+    ///
+    /// Servers that share a redis cache have to pass it, so they claim every run before making
+    /// it and each run is only made once.
+    pub fn start(
+        &self,
+        data: SharedDataSource,
+        webhooks: WebhookSender,
+        notifications: MuteNotifier,
+        floors: Floors,
+        redis: Option<RedisCache>,
+    ) {
+        let mut receiver = match self
+            .receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        {
+            Some(receiver) => receiver,
+            None => {
+                log::warn!("The scheduler was already started");
+                return;
+            }
+        };
+
+        let runner = Runner {
+            store: self.store.clone(),
+            data,
+            webhooks,
+            notifications,
+            floors,
+            redis,
+            started: Instant::now(),
+        };
+
+        task::spawn(async move {
+            loop {
+                if let Err(error) = runner.run_due().await {
+                    log::error!("Failed to run the due schedules: {:#}", error);
+                }
+
+                let delay = match runner.store.next_run_at().await {
+                    Ok(Some(next_run_at)) => {
+                        Duration::from_secs(next_run_at.saturating_sub(unix_timestamp()))
+                    }
+                    Ok(None) => MAX_CHECK_INTERVAL,
+                    Err(error) => {
+                        log::error!("Failed to get the next run of the schedules: {:#}", error);
+                        MAX_CHECK_INTERVAL
+                    }
+                };
+
+                // Either the next run is due or the scheduler was woken up, both mean checking again
+                if let Ok(None) = timeout(
+                    delay.max(MIN_CHECK_INTERVAL).min(MAX_CHECK_INTERVAL),
+                    receiver.next(),
+                )
+                .await
+                {
+                    // Every scheduler handle was dropped, so the server is shutting down
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// What the running scheduler needs to apply the actions of the schedules
+struct Runner {
+    /// The store of schedules and guild timezones
+    store: Schedules,
+    /// Source of the voice channels to mute and unmute
+    data: SharedDataSource,
+    /// Sends mute and unmute events to the webhooks of guilds
+    webhooks: WebhookSender,
+    /// Posts messages about mutes to the guilds that opted in
+    notifications: MuteNotifier,
+    /// The floors of voice channels in speaker mode, which muting or unmuting ends
+    floors: Floors,
+    /// The redis server shared with the other servers running the same schedules
+    redis: Option<RedisCache>,
+    /// When the scheduler started
+    started: Instant,
+}
+
+impl Runner {
+    /// Apply the schedules that are due and move them on to their next run
+    ///
+    /// # Errors
+    /// If the due schedules or the timezones of their guilds could not be read
+    async fn run_due(&self) -> anyhow::Result<()> {
+        let now = unix_timestamp();
+        let mut runs = Vec::new();
+
+        for schedule in self.store.due(now).await? {
+            if self.data.guild(schedule.guild_id).is_none() {
+                // Guilds are still being cached right after starting, so nothing is put off yet
+                if self.started.elapsed() >= PARK_DURATION {
+                    self.park(&schedule, now).await;
+                }
+
+                continue;
+            }
+
+            let timezone = self
+                .store
+                .timezone(schedule.guild_id)
+                .await?
+                .unwrap_or(Tz::UTC);
+
+            runs.push(DueRun::new(schedule, timezone, now));
+        }
+
+        let latest = latest_runs(&runs);
+
+        for run in &runs {
+            match self.claim(&run.schedule).await {
+                Ok(true) => {}
+                // Another server makes the run
+                Ok(false) => continue,
+                Err(error) => {
+                    log::error!("Failed to claim schedule {}: {:#}", run.schedule.id, error);
+                    continue;
+                }
+            }
+
+            if latest.contains(run.schedule.id.as_str()) {
+                if let Err(error) = self.authorize(&run.schedule) {
+                    self.stop(&run.schedule, &error).await;
+                    continue;
+                }
+
+                match self.apply(&run.schedule).await {
+                    Ok(()) => self.report(&run.schedule, "ran", None),
+                    Err(error) => {
+                        log::error!("Failed to run schedule {}: {:#}", run.schedule.id, error);
+                        self.report(&run.schedule, "failed", Some(&error));
+                    }
+                }
+            }
+
+            if let Err(error) = self.advance(run, now).await {
+                log::error!(
+                    "Failed to move schedule {} on: {:#}",
+                    run.schedule.id,
+                    error
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Put off a schedule of a guild that is not cached, which is either not cached yet or was
+    /// left by the bot, so it is not checked again on every check
+    ///
+    /// The missed run is still caught up on once the guild is cached again, as that only depends
+    /// on when the schedule was meant to run.
+    async fn park(&self, schedule: &Schedule, now: u64) {
+        log::debug!(
+            "Putting off schedule {} as guild {} is not cached",
+            schedule.id,
+            schedule.guild_id
+        );
+
+        if let Err(error) = self
+            .store
+            .update_runs(
+                &schedule.id,
+                schedule.last_run_at,
+                Some(now + PARK_DURATION.as_secs()),
+            )
+            .await
+        {
+            log::error!("Failed to put off schedule {}: {:#}", schedule.id, error);
+        }
+    }
+
+    /// Claim the next run of the schedule, so that no other server sharing the redis cache makes
+    /// it as well
+    ///
+    /// # Returns
+    /// If the run is made by this server
+    ///
+    /// # Errors
+    /// If redis could not be reached
+    async fn claim(&self, schedule: &Schedule) -> anyhow::Result<bool> {
+        match &self.redis {
+            Some(redis) => {
+                let name = format!(
+                    "schedule:{}:{}",
+                    schedule.id,
+                    schedule.next_run_at.unwrap_or_default()
+                );
+
+                redis.claim(&name, CLAIM_DURATION).await
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Check that the creator of the schedule can still make its action
+    ///
+    /// # Errors
+    /// If the creator lost a permission needed for the action, or left the guild
+    fn authorize(&self, schedule: &Schedule) -> anyhow::Result<()> {
+        authorize_scheduled(
+            self.data.as_ref(),
+            schedule.guild_id,
+            schedule.channel_id,
+            schedule.created_by,
+        )
+        .map_err(|error| anyhow!("{}", error.message()))
+    }
+
+    /// Stop a schedule that can not run anymore, keeping it around so the guild can see that it
+    /// stopped
+    async fn stop(&self, schedule: &Schedule, reason: &anyhow::Error) {
+        log::warn!("Stopping schedule {}: {:#}", schedule.id, reason);
+        self.report(schedule, "stopped", Some(reason));
+
+        if let Err(error) = self
+            .store
+            .update_runs(&schedule.id, schedule.last_run_at, None)
+            .await
+        {
+            log::error!("Failed to stop schedule {}: {:#}", schedule.id, error);
+        }
+    }
+
+    /// Mute or unmute the voice channel of the schedule, in the name of its creator
+    ///
+    /// # Errors
+    /// If the voice states of the channel could not be read, or the floor store fails
+    async fn apply(&self, schedule: &Schedule) -> anyhow::Result<()> {
+        mute_channel(
+            &self.data,
+            &self.webhooks,
+            &self.notifications,
+            &self.floors,
+            schedule.guild_id,
+            schedule.channel_id,
+            schedule.created_by,
+            schedule.action == ScheduleAction::Mute,
+        )
+        .await
+        .map_err(|error| anyhow!("{}", error.message()))?;
+
+        Ok(())
+    }
+
+    /// Let webhooks know how a run of the schedule went, along with why it failed or stopped
+    ///
+    /// Members muted or unmuted by the run are sent in a mute or unmute event of their own.
+    fn report(&self, schedule: &Schedule, outcome: &str, error: Option<&anyhow::Error>) {
+        self.webhooks.dispatch(
+            schedule.guild_id,
+            WebhookEvent::Schedule,
+            json!({
+                "schedule_id": schedule.id,
+                "channel_id": schedule.channel_id.to_string(),
+                "action": schedule.action.as_str(),
+                "actor_id": schedule.created_by.to_string(),
+                "outcome": outcome,
+                "error": error.map(|error| error.to_string()),
+            }),
+        );
+    }
+
+    /// Record that the schedule ran and when it runs next, in the timezone of its guild
+    ///
+    /// # Errors
+    /// If the store fails
+    async fn advance(&self, run: &DueRun, now: u64) -> anyhow::Result<()> {
+        let schedule = &run.schedule;
+
+        // A schedule that can not be moved on is stopped, so it does not run over and over
+        let next_run_at = schedule
+            .next_run_after(run.timezone, now)
+            .unwrap_or_else(|error| {
+                log::error!("Stopping schedule {}: {:#}", schedule.id, error);
+                None
+            });
+
+        self.store
+            .update_runs(&schedule.id, Some(now), next_run_at)
+            .await
+    }
+}
+
+/// A schedule that is due, along with when it was last meant to run
+struct DueRun {
+    /// The schedule that is due
+    schedule: Schedule,
+    /// The timezone of the guild of the schedule
+    timezone: Tz,
+    /// The seconds since the unix epoch of the last time the schedule was meant to run
+    meant_at: u64,
+}
+
+impl DueRun {
+    /// Find out when a schedule that is due was last meant to run
+    fn new(schedule: Schedule, timezone: Tz, now: u64) -> Self {
+        // A run was missed if the schedule is due, so this is only a fallback
+        let meant_at = schedule
+            .last_run_at_or_before(timezone, now)
+            .unwrap_or_default()
+            .or(schedule.next_run_at)
+            .unwrap_or(now);
+
+        Self {
+            schedule,
+            timezone,
+            meant_at,
+        }
+    }
+}
+
+/// The ids of the due schedules to apply, which are the ones of every channel that were meant to
+/// run last, so a channel ends up as it would have if no runs were missed
+fn latest_runs(runs: &[DueRun]) -> HashSet<&str> {
+    let mut latest: HashMap<ChannelId, &DueRun> = HashMap::new();
+
+    for run in runs {
+        let current = latest.entry(run.schedule.channel_id).or_insert(run);
+
+        // Ties go to the schedule that is due last
+        if run.meant_at >= current.meant_at {
+            *current = run;
+        }
+    }
+
+    latest
+        .values()
+        .map(|run| run.schedule.id.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::Recurrence;
+    use chrono::{TimeZone, Utc};
+    use std::convert::TryFrom;
+    use twilight_model::id::{GuildId, UserId};
+
+    /// The seconds since the unix epoch of an hour in the first week of march 2021, which starts
+    /// on a monday
+    fn at(day: u32, hour: u32) -> u64 {
+        u64::try_from(Utc.ymd(2021, 3, day).and_hms(hour, 0, 0).timestamp()).unwrap()
+    }
+
+    fn schedule(id: &str, channel_id: u64, action: ScheduleAction, cron: &str) -> Schedule {
+        Schedule {
+            id: id.to_owned(),
+            guild_id: GuildId(1),
+            channel_id: ChannelId(channel_id),
+            action,
+            recurrence: Recurrence::Cron(cron.to_owned()),
+            next_run_at: None,
+            last_run_at: None,
+            created_by: UserId(10),
+            created_at: 0,
+        }
+    }
+
+    /// The due runs of the schedules with their next run, earliest first like the store
+    fn due(schedules: Vec<(Schedule, u64)>, now: u64) -> Vec<DueRun> {
+        let mut runs: Vec<DueRun> = schedules
+            .into_iter()
+            .map(|(schedule, next_run_at)| {
+                let schedule = Schedule {
+                    next_run_at: Some(next_run_at),
+                    ..schedule
+                };
+
+                DueRun::new(schedule, Tz::UTC, now)
+            })
+            .collect();
+
+        runs.sort_by_key(|run| run.schedule.next_run_at);
+        runs
+    }
+
+    #[test]
+    fn catching_up_applies_the_action_that_was_meant_to_run_last() {
+        // Down from monday 21:00 to tuesday 23:00, missing a mute at 22:00 on both days and the
+        // unmute at 07:00 in between
+        let runs = due(
+            vec![
+                (
+                    schedule("mute", 20, ScheduleAction::Mute, "0 22 * * *"),
+                    at(1, 22),
+                ),
+                (
+                    schedule("unmute", 20, ScheduleAction::Unmute, "0 7 * * *"),
+                    at(2, 7),
+                ),
+            ],
+            at(2, 23),
+        );
+
+        assert_eq!(runs[0].meant_at, at(2, 22));
+        assert_eq!(runs[1].meant_at, at(2, 7));
+        assert_eq!(
+            latest_runs(&runs),
+            ["mute"].iter().copied().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn catching_up_applies_the_latest_action_of_every_channel() {
+        let runs = due(
+            vec![
+                (
+                    schedule("mute", 20, ScheduleAction::Mute, "0 22 * * *"),
+                    at(1, 22),
+                ),
+                (
+                    schedule("unmute", 20, ScheduleAction::Unmute, "0 7 * * *"),
+                    at(2, 7),
+                ),
+                (
+                    schedule("other", 21, ScheduleAction::Mute, "0 22 * * *"),
+                    at(1, 22),
+                ),
+            ],
+            at(2, 8),
+        );
+
+        assert_eq!(
+            latest_runs(&runs),
+            ["unmute", "other"].iter().copied().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn one_off_schedules_are_meant_to_run_at_their_time() {
+        let once = Schedule {
+            recurrence: Recurrence::Once(at(2, 6)),
+            ..schedule("once", 20, ScheduleAction::Mute, "")
+        };
+
+        let runs = due(
+            vec![
+                (once, at(2, 6)),
+                (
+                    schedule("unmute", 20, ScheduleAction::Unmute, "0 5 * * *"),
+                    at(2, 5),
+                ),
+            ],
+            at(2, 23),
+        );
+
+        assert_eq!(
+            latest_runs(&runs),
+            ["once"].iter().copied().collect::<HashSet<_>>()
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! A schedule store that persists schedules in a sqlite database
+
+use super::{Recurrence, Schedule, ScheduleStore};
+use crate::database::{from_sql_integer, to_sql_integer, Database};
+use chrono_tz::Tz;
+use rusqlite::{params, types::Type, OptionalExtension, Row};
+use std::path::Path;
+use twilight_model::id::{ChannelId, GuildId, UserId};
+
+/// The statements to run to setup the database
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS schedules (
+    id TEXT PRIMARY KEY NOT NULL,
+    guild_id INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    run_at INTEGER,
+    cron TEXT,
+    next_run_at INTEGER,
+    last_run_at INTEGER,
+    created_by INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS schedules_guild_id ON schedules (guild_id);
+CREATE INDEX IF NOT EXISTS schedules_next_run_at ON schedules (next_run_at);
+CREATE TABLE IF NOT EXISTS guild_timezones (
+    guild_id INTEGER PRIMARY KEY NOT NULL,
+    timezone TEXT NOT NULL
+);
+";
+
+/// The columns of the schedules table, in the order that `schedule_from_row` expects them
+const COLUMNS: &str =
+    "id, guild_id, channel_id, action, run_at, cron, next_run_at, last_run_at, created_by, created_at";
+
+/// A schedule store that persists schedules in a sqlite database, surviving restarts
+#[derive(Debug, Clone)]
+pub struct SqliteScheduleStore {
+    /// The schedule database
+    database: Database,
+}
+
+impl SqliteScheduleStore {
+    /// Open, and create if needed, the schedule database at the path
+    ///
+    /// # Errors
+    /// If the database could not be opened or setup
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            database: Database::open(path, SCHEMA)?,
+        })
+    }
+}
+
+/// Read a schedule from a row made up of `COLUMNS`
+///
+/// One off schedules store the time they run at, and recurring schedules their cron expression.
+fn schedule_from_row(row: &Row<'_>) -> rusqlite::Result<Schedule> {
+    let action: String = row.get(3)?;
+    let recurrence = match (row.get::<_, Option<i64>>(4)?, row.get(5)?) {
+        (Some(run_at), _) => Recurrence::Once(from_sql_integer(run_at)),
+        (None, Some(cron)) => Recurrence::Cron(cron),
+        (None, None) => {
+            return Err(rusqlite::Error::InvalidColumnType(
+                5,
+                "cron".to_owned(),
+                Type::Null,
+            ))
+        }
+    };
+
+    Ok(Schedule {
+        id: row.get(0)?,
+        guild_id: GuildId(from_sql_integer(row.get(1)?)),
+        channel_id: ChannelId(from_sql_integer(row.get(2)?)),
+        action: action.parse().map_err(|error: anyhow::Error| {
+            rusqlite::Error::FromSqlConversionFailure(3, Type::Text, error.into())
+        })?,
+        recurrence,
+        next_run_at: row.get::<_, Option<i64>>(6)?.map(from_sql_integer),
+        last_run_at: row.get::<_, Option<i64>>(7)?.map(from_sql_integer),
+        created_by: UserId(from_sql_integer(row.get(8)?)),
+        created_at: from_sql_integer(row.get(9)?),
+    })
+}
+
+#[rocket::async_trait]
+impl ScheduleStore for SqliteScheduleStore {
+    async fn insert(&self, schedule: Schedule) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                let (run_at, cron) = match schedule.recurrence {
+                    Recurrence::Once(run_at) => (Some(to_sql_integer(run_at)), None),
+                    Recurrence::Cron(cron) => (None, Some(cron)),
+                };
+
+                connection.execute(
+                    &format!(
+                        "INSERT INTO schedules ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                        COLUMNS
+                    ),
+                    params![
+                        schedule.id,
+                        to_sql_integer(schedule.guild_id.0),
+                        to_sql_integer(schedule.channel_id.0),
+                        schedule.action.as_str(),
+                        run_at,
+                        cron,
+                        schedule.next_run_at.map(to_sql_integer),
+                        schedule.last_run_at.map(to_sql_integer),
+                        to_sql_integer(schedule.created_by.0),
+                        to_sql_integer(schedule.created_at),
+                    ],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn guild_schedules(&self, guild_id: GuildId) -> anyhow::Result<Vec<Schedule>> {
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .prepare(&format!(
+                        "SELECT {} FROM schedules WHERE guild_id = ?1 ORDER BY created_at",
+                        COLUMNS
+                    ))?
+                    .query_map(params![to_sql_integer(guild_id.0)], schedule_from_row)?
+                    .collect()
+            })
+            .await
+    }
+
+    async fn remove(&self, guild_id: GuildId, id: &str) -> anyhow::Result<bool> {
+        let id = id.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                Ok(connection.execute(
+                    "DELETE FROM schedules WHERE guild_id = ?1 AND id = ?2",
+                    params![to_sql_integer(guild_id.0), id],
+                )? > 0)
+            })
+            .await
+    }
+
+    async fn due(&self, now: u64) -> anyhow::Result<Vec<Schedule>> {
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .prepare(&format!(
+                        "SELECT {} FROM schedules WHERE next_run_at <= ?1 ORDER BY next_run_at",
+                        COLUMNS
+                    ))?
+                    .query_map(params![to_sql_integer(now)], schedule_from_row)?
+                    .collect()
+            })
+            .await
+    }
+
+    async fn next_run_at(&self) -> anyhow::Result<Option<u64>> {
+        self.database
+            .with_connection(|connection| {
+                connection.query_row("SELECT MIN(next_run_at) FROM schedules", params![], |row| {
+                    Ok(row.get::<_, Option<i64>>(0)?.map(from_sql_integer))
+                })
+            })
+            .await
+    }
+
+    async fn update_runs(
+        &self,
+        id: &str,
+        last_run_at: Option<u64>,
+        next_run_at: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let id = id.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    "UPDATE schedules SET last_run_at = ?2, next_run_at = ?3 WHERE id = ?1",
+                    params![
+                        id,
+                        last_run_at.map(to_sql_integer),
+                        next_run_at.map(to_sql_integer),
+                    ],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn timezone(&self, guild_id: GuildId) -> anyhow::Result<Option<Tz>> {
+        let timezone = self
+            .database
+            .with_connection(move |connection| {
+                connection
+                    .query_row(
+                        "SELECT timezone FROM guild_timezones WHERE guild_id = ?1",
+                        params![to_sql_integer(guild_id.0)],
+                        |row| row.get::<_, String>(0),
+                    )
+                    .optional()
+            })
+            .await?;
+
+        timezone
+            .map(|timezone| timezone.parse().map_err(anyhow::Error::msg))
+            .transpose()
+    }
+
+    async fn set_timezone(&self, guild_id: GuildId, timezone: Tz) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    "INSERT OR REPLACE INTO guild_timezones (guild_id, timezone) VALUES (?1, ?2)",
+                    params![to_sql_integer(guild_id.0), timezone.name()],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+}
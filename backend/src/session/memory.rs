@@ -0,0 +1,97 @@
+//! A session store that keeps sessions in memory
+
+use super::{Session, SessionStore};
+use std::{
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+};
+use twilight_model::id::UserId;
+
+/// A session store that keeps sessions in memory, losing them when the server restarts
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    /// The sessions, keyed by the hash of their secret
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+#[rocket::async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn insert(&self, session: Session) -> anyhow::Result<()> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        // Drop expired sessions so that abandoned sessions do not linger forever
+        sessions.retain(|_, session| !session.is_expired());
+        sessions.insert(session.hash.clone(), session);
+
+        Ok(())
+    }
+
+    async fn get_by_hash(&self, hash: &str) -> anyhow::Result<Option<Session>> {
+        Ok(self
+            .sessions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(hash)
+            .cloned())
+    }
+
+    async fn touch(&self, hash: &str, last_used_at: u64) -> anyhow::Result<()> {
+        if let Some(session) = self
+            .sessions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(hash)
+        {
+            session.last_used_at = last_used_at;
+        }
+
+        Ok(())
+    }
+
+    async fn user_sessions(&self, user_id: UserId) -> anyhow::Result<Vec<Session>> {
+        Ok(self
+            .sessions
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|session| session.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn remove(&self, hash: &str) -> anyhow::Result<()> {
+        self.sessions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(hash);
+
+        Ok(())
+    }
+
+    async fn remove_by_id(&self, user_id: UserId, id: &str) -> anyhow::Result<bool> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let count = sessions.len();
+
+        sessions.retain(|_, session| !(session.user_id == user_id && session.id == id));
+
+        Ok(sessions.len() != count)
+    }
+
+    async fn remove_user_sessions(&self, user_id: UserId) -> anyhow::Result<usize> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let count = sessions.len();
+
+        sessions.retain(|_, session| session.user_id != user_id);
+
+        Ok(count - sessions.len())
+    }
+}
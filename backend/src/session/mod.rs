@@ -0,0 +1,163 @@
+//! Server side storage of the oauth sessions of logged in users
+//!
+//! The browser only ever gets handed the opaque secret of its session, while the discord
+//! tokens stay on the server in a [`SessionStore`]. Like api tokens, sessions are stored and
+//! looked up by the hash of their secret, so the secrets can not be read from the store.
+
+use crate::{api_token::hash_token, config::Config, create_http_client};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use reqwest::Client as ReqwestClient;
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use twilight_model::id::UserId;
+use twilight_oauth2::request::access_token_exchange::AccessTokenExchangeResponse;
+
+pub mod memory;
+pub mod sqlite;
+
+pub use memory::MemorySessionStore;
+pub use sqlite::SqliteSessionStore;
+
+/// The length of the randomly generated session ids and secrets
+const SESSION_KEY_LENGTH: usize = 43;
+
+/// A shared handle to the session store used by the server
+pub type Sessions = Arc<dyn SessionStore>;
+
+/// The oauth session of a logged in user
+///
+/// The session can be derived from an `AccessTokenExchangeResponse`
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Hash of the secret stored in the user's cookie to identify the session.
+    ///
+    /// The secret itself is only handed to the user's browser and never stored.
+    pub hash: String,
+    /// Public id of the session, used to list and revoke sessions.
+    pub id: String,
+    /// The user's id
+    pub user_id: UserId,
+    /// Access token to be used when making requests to the API on the user's
+    /// behalf.
+    pub access_token: String,
+    /// Number of seconds from issuing that the access token is valid.
+    ///
+    /// After this duration, the refresh token must be exchanged for another
+    /// access token and refresh token pair.
+    pub expires_in: u64,
+    /// Refresh token to use to exchange for another access token and refresh
+    /// token pair.
+    pub refresh_token: String,
+    /// The seconds since the unix epoch that this session was created
+    pub created_at: u64,
+    /// The seconds since the unix epoch that this session was last used
+    pub last_used_at: u64,
+    /// The user agent of the browser that created the session
+    pub user_agent: Option<String>,
+}
+
+impl Session {
+    /// Create a new session from the response and make a request to get the user id
+    ///
+    /// Returns the session to store along with its secret, which needs to be handed to the
+    /// user's browser since it can not be recovered later.
+    ///
+    /// # Errors
+    /// If the request to get the user's id fails
+    pub async fn create(
+        AccessTokenExchangeResponse {
+            access_token,
+            expires_in,
+            refresh_token,
+            ..
+        }: AccessTokenExchangeResponse,
+        user_agent: Option<String>,
+        config: &Config,
+        reqwest: &ReqwestClient,
+    ) -> Result<(Self, String), twilight_http::Error> {
+        let now = unix_timestamp();
+        let secret = random_key();
+
+        let session = Session {
+            hash: hash_token(&secret),
+            id: random_key(),
+            user_id: create_http_client(format!("Bearer {}", access_token), config, reqwest)
+                .current_user()
+                .await?
+                .id,
+            access_token,
+            expires_in,
+            refresh_token,
+            created_at: now,
+            last_used_at: now,
+            user_agent,
+        };
+
+        Ok((session, secret))
+    }
+
+    /// If the access token of the session has expired
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.created_at + self.expires_in <= unix_timestamp()
+    }
+}
+
+/// Storage for the sessions of logged in users
+#[rocket::async_trait]
+pub trait SessionStore: Debug + Send + Sync {
+    /// Save a newly created session
+    async fn insert(&self, session: Session) -> anyhow::Result<()>;
+
+    /// Get a session by the hash of its secret
+    async fn get_by_hash(&self, hash: &str) -> anyhow::Result<Option<Session>>;
+
+    /// Mark the session with the hash as used at the given time
+    async fn touch(&self, hash: &str, last_used_at: u64) -> anyhow::Result<()>;
+
+    /// Get all of the sessions of a user
+    async fn user_sessions(&self, user_id: UserId) -> anyhow::Result<Vec<Session>>;
+
+    /// Remove a session by the hash of its secret
+    async fn remove(&self, hash: &str) -> anyhow::Result<()>;
+
+    /// Remove a session of the user by its public id, returning if a session was removed
+    async fn remove_by_id(&self, user_id: UserId, id: &str) -> anyhow::Result<bool>;
+
+    /// Remove every session of the user, returning the amount of sessions removed
+    async fn remove_user_sessions(&self, user_id: UserId) -> anyhow::Result<usize>;
+}
+
+/// Create the session store described by the config
+///
+/// Sessions are stored in the sqlite database at `session_database` if one is configured,
+/// otherwise they are kept in memory and lost on restart.
+///
+/// # Errors
+/// If the sqlite database could not be opened
+pub fn create_session_store(config: &Config) -> anyhow::Result<Sessions> {
+    Ok(match &config.session_database {
+        Some(path) => Arc::new(SqliteSessionStore::open(path)?),
+        None => Arc::new(MemorySessionStore::default()),
+    })
+}
+
+/// Generate a random string suitable for use as an id or secret
+pub(crate) fn random_key() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SESSION_KEY_LENGTH)
+        .collect()
+}
+
+/// The seconds since the unix epoch
+#[must_use]
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
@@ -0,0 +1,181 @@
+//! A session store that persists sessions in a sqlite database
+
+use super::{Session, SessionStore};
+use crate::database::{from_sql_integer, to_sql_integer, Database};
+use rusqlite::{params, OptionalExtension, Row};
+use std::path::Path;
+use twilight_model::id::UserId;
+
+/// The statements to run to setup the database
+///
+/// Sessions used to be stored by their secret in the `sessions` table, which is dropped so that
+/// the secrets are not left readable. Users with those sessions have to log in again.
+const SCHEMA: &str = "
+DROP TABLE IF EXISTS sessions;
+CREATE TABLE IF NOT EXISTS hashed_sessions (
+    hash TEXT PRIMARY KEY NOT NULL,
+    id TEXT NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL,
+    access_token TEXT NOT NULL,
+    expires_in INTEGER NOT NULL,
+    refresh_token TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    last_used_at INTEGER NOT NULL,
+    user_agent TEXT
+);
+CREATE INDEX IF NOT EXISTS hashed_sessions_user_id ON hashed_sessions (user_id);
+";
+
+/// The columns of the sessions table, in the order that `session_from_row` expects them
+const COLUMNS: &str =
+    "hash, id, user_id, access_token, expires_in, refresh_token, created_at, last_used_at, user_agent";
+
+/// A session store that persists sessions in a sqlite database, surviving restarts
+#[derive(Debug, Clone)]
+pub struct SqliteSessionStore {
+    /// The session database
+    database: Database,
+}
+
+impl SqliteSessionStore {
+    /// Open, and create if needed, the session database at the path
+    ///
+    /// # Errors
+    /// If the database could not be opened or setup
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            database: Database::open(path, SCHEMA)?,
+        })
+    }
+}
+
+/// Read a session from a row made up of `COLUMNS`
+fn session_from_row(row: &Row<'_>) -> rusqlite::Result<Session> {
+    Ok(Session {
+        hash: row.get(0)?,
+        id: row.get(1)?,
+        user_id: UserId(from_sql_integer(row.get(2)?)),
+        access_token: row.get(3)?,
+        expires_in: from_sql_integer(row.get(4)?),
+        refresh_token: row.get(5)?,
+        created_at: from_sql_integer(row.get(6)?),
+        last_used_at: from_sql_integer(row.get(7)?),
+        user_agent: row.get(8)?,
+    })
+}
+
+#[rocket::async_trait]
+impl SessionStore for SqliteSessionStore {
+    async fn insert(&self, session: Session) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    &format!(
+                        "INSERT INTO hashed_sessions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                        COLUMNS
+                    ),
+                    params![
+                        session.hash,
+                        session.id,
+                        to_sql_integer(session.user_id.0),
+                        session.access_token,
+                        to_sql_integer(session.expires_in),
+                        session.refresh_token,
+                        to_sql_integer(session.created_at),
+                        to_sql_integer(session.last_used_at),
+                        session.user_agent,
+                    ],
+                )?;
+
+                // Drop expired sessions so that abandoned sessions do not linger forever
+                connection.execute(
+                    "DELETE FROM hashed_sessions WHERE created_at + expires_in <= ?1",
+                    params![to_sql_integer(super::unix_timestamp())],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get_by_hash(&self, hash: &str) -> anyhow::Result<Option<Session>> {
+        let hash = hash.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .query_row(
+                        &format!("SELECT {} FROM hashed_sessions WHERE hash = ?1", COLUMNS),
+                        params![hash],
+                        session_from_row,
+                    )
+                    .optional()
+            })
+            .await
+    }
+
+    async fn touch(&self, hash: &str, last_used_at: u64) -> anyhow::Result<()> {
+        let hash = hash.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    "UPDATE hashed_sessions SET last_used_at = ?2 WHERE hash = ?1",
+                    params![hash, to_sql_integer(last_used_at)],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn user_sessions(&self, user_id: UserId) -> anyhow::Result<Vec<Session>> {
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .prepare(&format!(
+                        "SELECT {} FROM hashed_sessions WHERE user_id = ?1",
+                        COLUMNS
+                    ))?
+                    .query_map(params![to_sql_integer(user_id.0)], session_from_row)?
+                    .collect()
+            })
+            .await
+    }
+
+    async fn remove(&self, hash: &str) -> anyhow::Result<()> {
+        let hash = hash.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                connection.execute("DELETE FROM hashed_sessions WHERE hash = ?1", params![hash])?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn remove_by_id(&self, user_id: UserId, id: &str) -> anyhow::Result<bool> {
+        let id = id.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                Ok(connection.execute(
+                    "DELETE FROM hashed_sessions WHERE user_id = ?1 AND id = ?2",
+                    params![to_sql_integer(user_id.0), id],
+                )? > 0)
+            })
+            .await
+    }
+
+    async fn remove_user_sessions(&self, user_id: UserId) -> anyhow::Result<usize> {
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    "DELETE FROM hashed_sessions WHERE user_id = ?1",
+                    params![to_sql_integer(user_id.0)],
+                )
+            })
+            .await
+    }
+}
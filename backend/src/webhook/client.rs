@@ -0,0 +1,208 @@
+//! The http clients that webhooks are sent with
+//!
+//! The host of a webhook url is resolved once before each attempt, to check that it only points
+//! to public addresses, and the payload is then sent to exactly those addresses. Resolving the
+//! host again when connecting would let a host that changes its addresses in between, by dns
+//! rebinding, reach services inside the network of the server.
+
+use crate::config::Config;
+use anyhow::{anyhow, Context};
+use async_std::future::timeout;
+use futures::future::{self, Ready};
+use hyper::{
+    client::{connect::dns::Name, HttpConnector},
+    service::Service,
+    Body, Client, Request,
+};
+use hyper_rustls::HttpsConnector;
+use reqwest::{Client as ReqwestClient, StatusCode, Url};
+use rustls::ClientConfig;
+use std::{
+    fmt::{self, Debug, Formatter},
+    fs::File,
+    io::{self, BufReader},
+    net::IpAddr,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
+    vec,
+};
+
+/// The reason that a request to a webhook failed
+#[derive(Debug)]
+pub struct SendError {
+    /// If the webhook did not respond in time
+    pub timed_out: bool,
+    /// What went wrong, which is only logged as it may reveal details of the network
+    pub error: anyhow::Error,
+}
+
+impl SendError {
+    /// A request that failed for any reason but a timeout
+    fn failed(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            timed_out: false,
+            error: error.into(),
+        }
+    }
+}
+
+/// How webhooks reach their urls
+#[derive(Debug, Clone)]
+pub enum WebhookClient {
+    /// Connect straight to the addresses that the url was checked to point to
+    Direct(PinnedClient),
+    /// Send every webhook through a proxy, which resolves the urls itself
+    Proxied(ReqwestClient),
+}
+
+impl WebhookClient {
+    /// If the addresses of a url are resolved by a proxy instead of the server, so that the
+    /// request can not be pinned to the addresses that were checked
+    #[must_use]
+    pub fn is_proxied(&self) -> bool {
+        matches!(self, WebhookClient::Proxied(_))
+    }
+
+    /// Post a json body to a webhook url, connecting to one of the addresses unless the
+    /// request goes through a proxy
+    ///
+    /// Redirects are never followed, as they could lead to an address that webhooks are not
+    /// allowed to be sent to.
+    ///
+    /// # Returns
+    /// The status of the response
+    ///
+    /// # Errors
+    /// If the webhook could not be reached or did not respond in time
+    pub async fn post(
+        &self,
+        url: &Url,
+        addresses: Vec<IpAddr>,
+        headers: &[(&str, String)],
+        body: String,
+    ) -> Result<StatusCode, SendError> {
+        match self {
+            WebhookClient::Direct(client) => client.post(url, addresses, headers, body).await,
+            WebhookClient::Proxied(client) => {
+                let request = headers
+                    .iter()
+                    .fold(client.post(url.clone()), |request, (name, value)| {
+                        request.header(*name, value)
+                    });
+
+                match request.body(body).send().await {
+                    Ok(response) => Ok(response.status()),
+                    Err(error) => Err(SendError {
+                        timed_out: error.is_timeout(),
+                        error: error.into(),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+/// A resolver that resolves every name to the same addresses
+#[derive(Debug, Clone)]
+struct PinnedResolver(Vec<IpAddr>);
+
+impl Service<Name> for PinnedResolver {
+    type Response = vec::IntoIter<IpAddr>;
+    type Error = io::Error;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: Name) -> Self::Future {
+        future::ready(Ok(self.0.clone().into_iter()))
+    }
+}
+
+/// A client that connects to addresses given with each request instead of resolving the host
+/// of the url, while still verifying the certificate of https urls against the host
+#[derive(Clone)]
+pub struct PinnedClient {
+    /// The tls config of https connections
+    tls: Arc<ClientConfig>,
+    /// How long a request can take, including connecting
+    timeout: Duration,
+    /// How long connecting can take
+    connect_timeout: Duration,
+}
+
+impl Debug for PinnedClient {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // The tls config does not implement debug
+        f.debug_struct("PinnedClient")
+            .field("timeout", &self.timeout)
+            .field("connect_timeout", &self.connect_timeout)
+            .finish()
+    }
+}
+
+impl PinnedClient {
+    /// Create a client with the extra certificates and timeouts from the config
+    ///
+    /// # Errors
+    /// If a certificate could not be read
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let mut tls = ClientConfig::new();
+        tls.root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        for path in &config.extra_ca_certs {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to read the certificate {}", path))?;
+
+            tls.root_store
+                .add_pem_file(&mut BufReader::new(file))
+                .map_err(|()| anyhow!("The certificate {} is invalid", path))?;
+        }
+
+        Ok(Self {
+            tls: Arc::new(tls),
+            timeout: Duration::from_secs(config.http_timeout),
+            connect_timeout: Duration::from_secs(config.http_connect_timeout),
+        })
+    }
+
+    /// Post a json body to a url, connecting to one of the addresses
+    ///
+    /// # Errors
+    /// If the url could not be reached at any of the addresses, or did not respond in time
+    async fn post(
+        &self,
+        url: &Url,
+        addresses: Vec<IpAddr>,
+        headers: &[(&str, String)],
+        body: String,
+    ) -> Result<StatusCode, SendError> {
+        let mut http = HttpConnector::new_with_resolver(PinnedResolver(addresses));
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(self.connect_timeout));
+
+        let client: Client<_, Body> =
+            Client::builder().build(HttpsConnector::from((http, self.tls.clone())));
+
+        let request = headers
+            .iter()
+            .fold(Request::post(url.as_str()), |request, (name, value)| {
+                request.header(*name, value.as_str())
+            })
+            .body(Body::from(body))
+            .map_err(SendError::failed)?;
+
+        match timeout(self.timeout, client.request(request)).await {
+            Ok(Ok(response)) => Ok(response.status()),
+            Ok(Err(error)) => Err(SendError::failed(error)),
+            Err(error) => Err(SendError {
+                timed_out: true,
+                error: error.into(),
+            }),
+        }
+    }
+}
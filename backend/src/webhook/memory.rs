@@ -0,0 +1,92 @@
+//! A webhook store that keeps webhooks in memory
+
+use super::{Webhook, WebhookDelivery, WebhookStore, DELIVERY_LOG_SIZE};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{PoisonError, RwLock},
+};
+use twilight_model::id::GuildId;
+
+/// A webhook store that keeps webhooks in memory, losing them when the server restarts
+#[derive(Debug, Default)]
+pub struct MemoryWebhookStore {
+    /// The webhooks, keyed by their id
+    webhooks: RwLock<HashMap<String, Webhook>>,
+    /// The logged deliveries, keyed by the id of their webhook
+    deliveries: RwLock<HashMap<String, Vec<WebhookDelivery>>>,
+}
+
+#[rocket::async_trait]
+impl WebhookStore for MemoryWebhookStore {
+    async fn insert(&self, webhook: Webhook) -> anyhow::Result<()> {
+        self.webhooks
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(webhook.id.clone(), webhook);
+
+        Ok(())
+    }
+
+    async fn guild_webhooks(&self, guild_id: GuildId) -> anyhow::Result<Vec<Webhook>> {
+        Ok(self
+            .webhooks
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|webhook| webhook.guild_id == guild_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn remove(&self, guild_id: GuildId, id: &str) -> anyhow::Result<bool> {
+        let mut webhooks = self
+            .webhooks
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if webhooks
+            .get(id)
+            .map_or(false, |webhook| webhook.guild_id == guild_id)
+        {
+            webhooks.remove(id);
+            self.deliveries
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(id);
+
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn record_delivery(&self, delivery: WebhookDelivery) -> anyhow::Result<()> {
+        let mut deliveries = self
+            .deliveries
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let log = deliveries.entry(delivery.webhook_id.clone()).or_default();
+
+        match log.iter_mut().find(|logged| logged.id == delivery.id) {
+            Some(logged) => *logged = delivery,
+            None => {
+                log.push(delivery);
+                log.sort_by_key(|delivery| Reverse(delivery.created_at));
+                log.truncate(DELIVERY_LOG_SIZE);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn deliveries(&self, webhook_id: &str) -> anyhow::Result<Vec<WebhookDelivery>> {
+        Ok(self
+            .deliveries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(webhook_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
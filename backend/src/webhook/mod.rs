@@ -0,0 +1,225 @@
+//! Outgoing webhooks, letting other tools such as overlays and logging bots react to what the
+//! bot does in a guild
+//!
+//! Webhooks are registered per guild in a [`WebhookStore`], and receive signed json posts from
+//! a [`WebhookSender`] for the events they subscribe to. Every delivery is kept in a log along
+//! with the outcome of its last attempt.
+
+use crate::{
+    config::Config,
+    session::{random_key, unix_timestamp},
+};
+use anyhow::anyhow;
+use hmac::{Hmac, Mac, NewMac};
+use juniper::GraphQLEnum;
+use sha2::Sha256;
+use std::{fmt::Debug, str::FromStr, sync::Arc};
+use twilight_model::id::{GuildId, UserId};
+
+pub mod client;
+pub mod memory;
+pub mod sender;
+pub mod sqlite;
+
+pub use client::WebhookClient;
+pub use memory::MemoryWebhookStore;
+pub use sender::WebhookSender;
+pub use sqlite::SqliteWebhookStore;
+
+/// The prefix of every webhook secret, to make them easy to recognize
+const SECRET_PREFIX: &str = "whsec_";
+
+/// The most deliveries that are logged for each webhook, older ones are dropped
+pub const DELIVERY_LOG_SIZE: usize = 50;
+
+/// The most webhooks that a guild can register
+pub const MAX_GUILD_WEBHOOKS: usize = 10;
+
+/// A shared handle to the webhook store used by the server
+pub type Webhooks = Arc<dyn WebhookStore>;
+
+/// An event that a webhook can receive.
+#[derive(GraphQLEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WebhookEvent {
+    /// Members of a voice channel were server muted.
+    Mute,
+    /// Members of a voice channel were unmuted.
+    Unmute,
+    /// The floor of a voice channel in speaker mode was given to other members, or released.
+    Floor,
+    /// The speaking queue of a voice channel changed.
+    Queue,
+    /// A schedule came due and muted or unmuted its voice channel, failed to, or was stopped.
+    Schedule,
+    /// A test delivery was requested, sent no matter which events the webhook subscribes to.
+    Test,
+}
+
+impl WebhookEvent {
+    /// The name of the event when stored and sent
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Mute => "mute",
+            WebhookEvent::Unmute => "unmute",
+            WebhookEvent::Floor => "floor",
+            WebhookEvent::Queue => "queue",
+            WebhookEvent::Schedule => "schedule",
+            WebhookEvent::Test => "test",
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mute" => Ok(WebhookEvent::Mute),
+            "unmute" => Ok(WebhookEvent::Unmute),
+            "floor" => Ok(WebhookEvent::Floor),
+            "queue" => Ok(WebhookEvent::Queue),
+            "schedule" => Ok(WebhookEvent::Schedule),
+            "test" => Ok(WebhookEvent::Test),
+            _ => Err(anyhow!("Unknown webhook event {}", s)),
+        }
+    }
+}
+
+/// A webhook registered in a guild
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    /// Public id of the webhook
+    pub id: String,
+    /// The guild whose events are sent to the webhook
+    pub guild_id: GuildId,
+    /// The url that events are posted to
+    pub url: String,
+    /// The secret that the payloads are signed with
+    pub secret: String,
+    /// The events that the webhook receives
+    pub events: Vec<WebhookEvent>,
+    /// The id of the user who registered the webhook
+    pub created_by: UserId,
+    /// The seconds since the unix epoch that this webhook was registered
+    pub created_at: u64,
+}
+
+impl Webhook {
+    /// Register a new webhook in the guild, with a newly generated secret
+    #[must_use]
+    pub fn create(
+        guild_id: GuildId,
+        url: String,
+        events: Vec<WebhookEvent>,
+        created_by: UserId,
+    ) -> Self {
+        Webhook {
+            id: random_key(),
+            guild_id,
+            url,
+            secret: format!("{}{}", SECRET_PREFIX, random_key()),
+            events,
+            created_by,
+            created_at: unix_timestamp(),
+        }
+    }
+
+    /// If the webhook receives the event
+    #[must_use]
+    pub fn receives(&self, event: WebhookEvent) -> bool {
+        event == WebhookEvent::Test || self.events.contains(&event)
+    }
+
+    /// Sign a payload sent at the timestamp, for the receiver to check that it came from us
+    ///
+    /// The signature is the hex encoded HMAC-SHA256 of `<timestamp>.<body>`, keyed by the secret.
+    #[must_use]
+    pub fn sign(&self, timestamp: u64, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(self.secret.as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(format!("{}.{}", timestamp, body).as_bytes());
+
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+}
+
+/// An event sent, or being sent, to a webhook, along with the outcome of its last attempt
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    /// Public id of the delivery, sent along with the payload
+    pub id: String,
+    /// The webhook that the event is sent to
+    pub webhook_id: String,
+    /// The event sent
+    pub event: WebhookEvent,
+    /// The json body sent
+    pub payload: String,
+    /// How many times the payload has been sent
+    pub attempts: u32,
+    /// The http status that the last attempt was answered with, if it was answered
+    pub status: Option<u16>,
+    /// What went wrong with the last attempt, if it failed
+    pub error: Option<String>,
+    /// If the payload was accepted by the receiver
+    pub succeeded: bool,
+    /// The seconds since the unix epoch that the event happened
+    pub created_at: u64,
+    /// The seconds since the unix epoch that the payload was last sent, if ever
+    pub last_attempt_at: Option<u64>,
+}
+
+impl WebhookDelivery {
+    /// Create a delivery of the payload to the webhook that has not been attempted yet
+    #[must_use]
+    pub fn create(webhook_id: String, event: WebhookEvent, payload: String) -> Self {
+        WebhookDelivery {
+            id: random_key(),
+            webhook_id,
+            event,
+            payload,
+            attempts: 0,
+            status: None,
+            error: None,
+            succeeded: false,
+            created_at: unix_timestamp(),
+            last_attempt_at: None,
+        }
+    }
+}
+
+/// Storage for the webhooks of guilds and the log of their deliveries
+#[rocket::async_trait]
+pub trait WebhookStore: Debug + Send + Sync {
+    /// Save a newly registered webhook
+    async fn insert(&self, webhook: Webhook) -> anyhow::Result<()>;
+
+    /// Get all of the webhooks of a guild
+    async fn guild_webhooks(&self, guild_id: GuildId) -> anyhow::Result<Vec<Webhook>>;
+
+    /// Remove a webhook of the guild by its id along with its deliveries, returning if a webhook
+    /// was removed
+    async fn remove(&self, guild_id: GuildId, id: &str) -> anyhow::Result<bool>;
+
+    /// Save a delivery, replacing the earlier state of the delivery if it was saved before
+    ///
+    /// Only the newest [`DELIVERY_LOG_SIZE`] deliveries of each webhook are kept.
+    async fn record_delivery(&self, delivery: WebhookDelivery) -> anyhow::Result<()>;
+
+    /// Get the logged deliveries of a webhook, newest first
+    async fn deliveries(&self, webhook_id: &str) -> anyhow::Result<Vec<WebhookDelivery>>;
+}
+
+/// Create the webhook store described by the config
+///
+/// Webhooks are stored in the sqlite database at `webhook_database` if one is configured,
+/// otherwise they are kept in memory and lost on restart.
+///
+/// # Errors
+/// If the sqlite database could not be opened
+pub fn create_webhook_store(config: &Config) -> anyhow::Result<Webhooks> {
+    Ok(match &config.webhook_database {
+        Some(path) => Arc::new(SqliteWebhookStore::open(path)?),
+        None => Arc::new(MemoryWebhookStore::default()),
+    })
+}
@@ -0,0 +1,436 @@
+//! Sending events to the webhooks of a guild
+
+use super::{client::WebhookClient, Webhook, WebhookDelivery, WebhookEvent, Webhooks};
+use crate::session::unix_timestamp;
+use anyhow::{bail, Context};
+use async_std::{net::ToSocketAddrs, task};
+use reqwest::{StatusCode, Url};
+use serde_json::{json, Value};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use twilight_model::id::GuildId;
+
+/// The most times that a payload is sent before the delivery is given up on
+const MAX_ATTEMPTS: u32 = 5;
+
+/// How long to wait before retrying a failed delivery for the first time, doubling every retry
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// If an address is only reachable from inside the network of the server, such as loopback,
+/// private, link-local and cloud metadata addresses
+fn is_internal(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_internal_v4(address),
+        IpAddr::V6(address) => match address.to_ipv4() {
+            // Mapped and compatible addresses reach the ipv4 address they contain
+            Some(mapped) => is_internal_v4(mapped),
+            None => is_internal_v6(address),
+        },
+    }
+}
+
+/// If an ipv4 address is only reachable from inside the network of the server
+fn is_internal_v4(address: Ipv4Addr) -> bool {
+    let [first, second, ..] = address.octets();
+
+    address.is_loopback()
+        || address.is_private()
+        // Includes the metadata service of most clouds at 169.254.169.254
+        || address.is_link_local()
+        || address.is_broadcast()
+        || address.is_multicast()
+        // This network, including the unspecified address
+        || first == 0
+        // Reserved for future use, never routed on the internet
+        || first >= 240
+        // Shared address space, which some clouds serve their metadata from
+        || (first == 100 && (64..128).contains(&second))
+        // Benchmarking, often used for internal networks
+        || (first == 198 && (second & 0xfe) == 18)
+}
+
+/// If an ipv6 address is only reachable from inside the network of the server
+fn is_internal_v6(address: Ipv6Addr) -> bool {
+    let segments = address.segments();
+    let first = segments[0];
+
+    // Nat64 and 6to4 addresses reach the ipv4 address they contain through a gateway
+    let wrapped = match segments {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] | [0x2002, high, low, ..] => {
+            Some(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))
+        }
+        _ => None,
+    };
+
+    address.is_loopback()
+        || address.is_unspecified()
+        || address.is_multicast()
+        // Unique local addresses, including the metadata service of aws at fd00:ec2::254
+        || (first & 0xfe00) == 0xfc00
+        // Link-local unicast
+        || (first & 0xffc0) == 0xfe80
+        || wrapped.map_or(false, is_internal_v4)
+}
+
+/// How often to check if the deliveries in the background are done, while waiting for them
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counts a task of a sender as running until it is dropped
+#[derive(Debug)]
+struct Running(Arc<AtomicUsize>);
+
+impl Running {
+    /// Count a task as running
+    fn start(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count.clone())
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Sends events to the webhooks that subscribe to them, logging every delivery in the store
+#[derive(Debug, Clone)]
+pub struct WebhookSender {
+    /// The store of webhooks and their deliveries
+    store: Webhooks,
+    /// The client to post the events with, which must not follow redirects
+    client: WebhookClient,
+    /// If webhooks can be sent to addresses inside the network of the server
+    allow_internal: bool,
+    /// How many deliveries are being made in the background
+    running: Arc<AtomicUsize>,
+}
+
+impl WebhookSender {
+    /// Create a sender for the webhooks in the store
+    ///
+    /// Unless `allow_internal` is set, webhooks are only sent to public addresses, so that they
+    /// can not be used to reach services inside the network of the server.
+    #[must_use]
+    pub fn new(store: Webhooks, client: WebhookClient, allow_internal: bool) -> Self {
+        Self {
+            store,
+            client,
+            allow_internal,
+            running: Arc::default(),
+        }
+    }
+
+    /// Check that a url can be sent webhooks, resolving its host to check that every address
+    /// it points to is public
+    ///
+    /// The check is made again before every attempt, as the addresses of a host can change.
+    ///
+    /// # Errors
+    /// If the url is not http or https, its host could not be resolved, or it points to an
+    /// address inside the network of the server
+    pub async fn check_url(&self, url: &str) -> anyhow::Result<()> {
+        self.resolve_url(url).await.map(drop)
+    }
+
+    /// Resolve the host of a url that webhooks are sent to, checking that every address it
+    /// points to is public
+    ///
+    /// The payload is sent to the addresses returned, so that the host can not point to a
+    /// different address by the time the server connects to it. When webhooks go through a
+    /// proxy, the proxy resolves the host and no addresses are returned.
+    ///
+    /// # Returns
+    /// The parsed url and the addresses to send to
+    ///
+    /// # Errors
+    /// If the url is not http or https, its host could not be resolved, or it points to an
+    /// address inside the network of the server
+    async fn resolve_url(&self, url: &str) -> anyhow::Result<(Url, Vec<IpAddr>)> {
+        let url = Url::parse(url).context("Invalid webhook url")?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("A webhook url has to be http or https");
+        }
+
+        if self.allow_internal && self.client.is_proxied() {
+            return Ok((url, Vec::new()));
+        }
+
+        let host = url.host_str().context("A webhook url needs a host")?;
+        let port = url
+            .port_or_known_default()
+            .context("A webhook url needs a port")?;
+
+        // The brackets around ipv6 addresses are not part of the address
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addresses: Vec<_> = (host, port)
+            .to_socket_addrs()
+            .await
+            .context("The host of the webhook url could not be resolved")?
+            .map(|address| address.ip())
+            .collect();
+
+        if addresses.is_empty() {
+            bail!("The host of the webhook url has no addresses");
+        }
+
+        if !self.allow_internal && addresses.iter().copied().any(is_internal) {
+            bail!("A webhook url has to point to a public address");
+        }
+
+        Ok((url, addresses))
+    }
+
+    /// The store of webhooks and their deliveries
+    #[must_use]
+    pub fn store(&self) -> &Webhooks {
+        &self.store
+    }
+
+    /// Send an event to every webhook of the guild that subscribes to it, in the background
+    ///
+    /// Failed deliveries are retried with exponential backoff, so the event may arrive a while
+    /// after it happened.
+    pub fn dispatch(&self, guild_id: GuildId, event: WebhookEvent, data: Value) {
+        let sender = self.clone();
+        let running = Running::start(&self.running);
+
+        task::spawn(async move {
+            let _running = running;
+            let webhooks = match sender.store.guild_webhooks(guild_id).await {
+                Ok(webhooks) => webhooks,
+                Err(error) => {
+                    log::error!("Failed to get the webhooks of {}: {:#}", guild_id, error);
+                    return;
+                }
+            };
+
+            for webhook in webhooks {
+                if webhook.receives(event) {
+                    let sender = sender.clone();
+                    let data = data.clone();
+
+                    let running = Running::start(&sender.running);
+
+                    task::spawn(async move {
+                        let _running = running;
+                        sender.deliver(&webhook, event, data).await
+                    });
+                }
+            }
+        });
+    }
+
+    /// Wait until every event dispatched so far was delivered or given up on, for processes
+    /// that exit once they are done
+    pub async fn wait_idle(&self) {
+        while self.running.load(Ordering::SeqCst) > 0 {
+            task::sleep(IDLE_CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Send a test event to the webhook, making a single attempt
+    ///
+    /// # Errors
+    /// If the delivery could not be logged, failing to reach the webhook is reported in the
+    /// delivery instead
+    pub async fn send_test(&self, webhook: &Webhook) -> anyhow::Result<WebhookDelivery> {
+        let mut delivery = Self::create_delivery(
+            webhook,
+            WebhookEvent::Test,
+            json!({ "webhook_id": webhook.id }),
+        );
+
+        self.attempt(webhook, &mut delivery).await;
+        self.store.record_delivery(delivery.clone()).await?;
+
+        Ok(delivery)
+    }
+
+    /// Send an event to the webhook, retrying until it is accepted or out of attempts
+    async fn deliver(&self, webhook: &Webhook, event: WebhookEvent, data: Value) {
+        let mut delivery = Self::create_delivery(webhook, event, data);
+        let mut delay = INITIAL_RETRY_DELAY;
+
+        loop {
+            let retry = self.attempt(webhook, &mut delivery).await;
+
+            if let Err(error) = self.store.record_delivery(delivery.clone()).await {
+                log::error!(
+                    "Failed to log webhook delivery {}: {:#}",
+                    delivery.id,
+                    error
+                );
+            }
+
+            if !retry || delivery.attempts >= MAX_ATTEMPTS {
+                if !delivery.succeeded {
+                    log::warn!(
+                        "Gave up on webhook delivery {} to {} after {} attempts",
+                        delivery.id,
+                        webhook.id,
+                        delivery.attempts
+                    );
+                }
+
+                break;
+            }
+
+            task::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    /// Create the delivery of an event to the webhook, with the json body that is sent
+    fn create_delivery(webhook: &Webhook, event: WebhookEvent, data: Value) -> WebhookDelivery {
+        let mut delivery = WebhookDelivery::create(webhook.id.clone(), event, String::new());
+
+        delivery.payload = json!({
+            "id": delivery.id,
+            "event": event.as_str(),
+            "guild_id": webhook.guild_id.to_string(),
+            "created_at": delivery.created_at,
+            "data": data,
+        })
+        .to_string();
+
+        delivery
+    }
+
+    /// Send the payload of the delivery once, recording the outcome in the delivery and
+    /// returning if it is worth trying again
+    ///
+    /// The error recorded is kept vague, as the delivery log is shown to the users who registered
+    /// the webhook, and the details are logged instead.
+    async fn attempt(&self, webhook: &Webhook, delivery: &mut WebhookDelivery) -> bool {
+        let timestamp = unix_timestamp();
+
+        delivery.attempts += 1;
+        delivery.last_attempt_at = Some(timestamp);
+
+        let (url, addresses) = match self.resolve_url(&webhook.url).await {
+            Ok(resolved) => resolved,
+            Err(error) => {
+                log::warn!("Refused to send to webhook {}: {:#}", webhook.id, error);
+
+                delivery.status = None;
+                delivery.succeeded = false;
+                delivery.error = Some("The webhook url is not allowed".to_owned());
+
+                return false;
+            }
+        };
+
+        let headers = [
+            ("Content-Type", "application/json".to_owned()),
+            ("X-Stfu-Event", delivery.event.as_str().to_owned()),
+            ("X-Stfu-Delivery", delivery.id.clone()),
+            ("X-Stfu-Timestamp", timestamp.to_string()),
+            (
+                "X-Stfu-Signature",
+                format!("sha256={}", webhook.sign(timestamp, &delivery.payload)),
+            ),
+        ];
+
+        let response = self
+            .client
+            .post(&url, addresses, &headers, delivery.payload.clone())
+            .await;
+
+        match response {
+            Ok(status) => {
+                delivery.status = Some(status.as_u16());
+                delivery.succeeded = status.is_success();
+                delivery.error = if status.is_success() {
+                    None
+                } else {
+                    Some("The webhook did not accept the event".to_owned())
+                };
+
+                // Client errors other than timeouts and rate limits will not go away on a retry
+                status.is_server_error()
+                    || status == StatusCode::REQUEST_TIMEOUT
+                    || status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(error) => {
+                log::debug!(
+                    "Failed to send to webhook {}: {:#}",
+                    webhook.id,
+                    error.error
+                );
+
+                delivery.status = None;
+                delivery.succeeded = false;
+                delivery.error = Some(
+                    if error.timed_out {
+                        "The webhook did not respond in time"
+                    } else {
+                        "The webhook could not be reached"
+                    }
+                    .to_owned(),
+                );
+
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_internal;
+
+    fn internal(address: &str) -> bool {
+        is_internal(address.parse().unwrap())
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        for address in &[
+            "1.1.1.1",
+            "198.20.0.1",
+            "2606:4700::1111",
+            "64:ff9b::101:101",
+        ] {
+            assert!(!internal(address), "{} is public", address);
+        }
+    }
+
+    #[test]
+    fn internal_ipv4_ranges_are_refused() {
+        for address in &[
+            "127.0.0.1",
+            "10.0.0.1",
+            "169.254.169.254",
+            "100.100.100.200",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "198.18.0.1",
+            "198.19.255.255",
+        ] {
+            assert!(internal(address), "{} is internal", address);
+        }
+    }
+
+    #[test]
+    fn ipv6_addresses_wrapping_internal_ipv4_are_refused() {
+        for address in &[
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::7f00:1",
+            "2002:a00:1::",
+            "2002:7f00:1::1",
+            "fd00:ec2::254",
+            "ff02::1",
+        ] {
+            assert!(internal(address), "{} is internal", address);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! A webhook store that persists webhooks in a sqlite database
+
+use super::{Webhook, WebhookDelivery, WebhookStore, DELIVERY_LOG_SIZE};
+use crate::database::{from_sql_integer, to_sql_integer, Database};
+use rusqlite::{params, types::Type, Row};
+use std::path::Path;
+use twilight_model::id::{GuildId, UserId};
+
+/// The statements to run to setup the database
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS webhooks (
+    id TEXT PRIMARY KEY NOT NULL,
+    guild_id INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    events TEXT NOT NULL,
+    created_by INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS webhooks_guild_id ON webhooks (guild_id);
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id TEXT PRIMARY KEY NOT NULL,
+    webhook_id TEXT NOT NULL,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    status INTEGER,
+    error TEXT,
+    succeeded INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    last_attempt_at INTEGER
+);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_id
+    ON webhook_deliveries (webhook_id, created_at);
+";
+
+/// The columns of the webhooks table, in the order that `webhook_from_row` expects them
+const COLUMNS: &str = "id, guild_id, url, secret, events, created_by, created_at";
+
+/// The columns of the deliveries table, in the order that `delivery_from_row` expects them
+const DELIVERY_COLUMNS: &str =
+    "id, webhook_id, event, payload, attempts, status, error, succeeded, created_at, last_attempt_at";
+
+/// A webhook store that persists webhooks in a sqlite database, surviving restarts
+#[derive(Debug, Clone)]
+pub struct SqliteWebhookStore {
+    /// The webhook database
+    database: Database,
+}
+
+impl SqliteWebhookStore {
+    /// Open, and create if needed, the webhook database at the path
+    ///
+    /// # Errors
+    /// If the database could not be opened or setup
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            database: Database::open(path, SCHEMA)?,
+        })
+    }
+}
+
+/// Read a webhook from a row made up of `COLUMNS`
+///
+/// Events are stored as a comma separated list.
+fn webhook_from_row(row: &Row<'_>) -> rusqlite::Result<Webhook> {
+    let events: String = row.get(4)?;
+
+    Ok(Webhook {
+        id: row.get(0)?,
+        guild_id: GuildId(from_sql_integer(row.get(1)?)),
+        url: row.get(2)?,
+        secret: row.get(3)?,
+        events: events
+            .split(',')
+            .filter_map(|event| event.parse().ok())
+            .collect(),
+        created_by: UserId(from_sql_integer(row.get(5)?)),
+        created_at: from_sql_integer(row.get(6)?),
+    })
+}
+
+/// Read a delivery from a row made up of `DELIVERY_COLUMNS`
+fn delivery_from_row(row: &Row<'_>) -> rusqlite::Result<WebhookDelivery> {
+    let event: String = row.get(2)?;
+
+    Ok(WebhookDelivery {
+        id: row.get(0)?,
+        webhook_id: row.get(1)?,
+        event: event.parse().map_err(|error: anyhow::Error| {
+            rusqlite::Error::FromSqlConversionFailure(2, Type::Text, error.into())
+        })?,
+        payload: row.get(3)?,
+        attempts: row.get(4)?,
+        status: row.get(5)?,
+        error: row.get(6)?,
+        succeeded: row.get(7)?,
+        created_at: from_sql_integer(row.get(8)?),
+        last_attempt_at: row.get::<_, Option<i64>>(9)?.map(from_sql_integer),
+    })
+}
+
+#[rocket::async_trait]
+impl WebhookStore for SqliteWebhookStore {
+    async fn insert(&self, webhook: Webhook) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                let events: Vec<&str> = webhook.events.iter().map(|event| event.as_str()).collect();
+
+                connection.execute(
+                    &format!(
+                        "INSERT INTO webhooks ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                        COLUMNS
+                    ),
+                    params![
+                        webhook.id,
+                        to_sql_integer(webhook.guild_id.0),
+                        webhook.url,
+                        webhook.secret,
+                        events.join(","),
+                        to_sql_integer(webhook.created_by.0),
+                        to_sql_integer(webhook.created_at),
+                    ],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn guild_webhooks(&self, guild_id: GuildId) -> anyhow::Result<Vec<Webhook>> {
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .prepare(&format!(
+                        "SELECT {} FROM webhooks WHERE guild_id = ?1 ORDER BY created_at",
+                        COLUMNS
+                    ))?
+                    .query_map(params![to_sql_integer(guild_id.0)], webhook_from_row)?
+                    .collect()
+            })
+            .await
+    }
+
+    async fn remove(&self, guild_id: GuildId, id: &str) -> anyhow::Result<bool> {
+        let id = id.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                let removed = connection.execute(
+                    "DELETE FROM webhooks WHERE guild_id = ?1 AND id = ?2",
+                    params![to_sql_integer(guild_id.0), id],
+                )? > 0;
+
+                if removed {
+                    connection.execute(
+                        "DELETE FROM webhook_deliveries WHERE webhook_id = ?1",
+                        params![id],
+                    )?;
+                }
+
+                Ok(removed)
+            })
+            .await
+    }
+
+    async fn record_delivery(&self, delivery: WebhookDelivery) -> anyhow::Result<()> {
+        self.database
+            .with_connection(move |connection| {
+                connection.execute(
+                    &format!(
+                        "INSERT OR REPLACE INTO webhook_deliveries ({}) \
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                        DELIVERY_COLUMNS
+                    ),
+                    params![
+                        delivery.id,
+                        delivery.webhook_id,
+                        delivery.event.as_str(),
+                        delivery.payload,
+                        delivery.attempts,
+                        delivery.status,
+                        delivery.error,
+                        delivery.succeeded,
+                        to_sql_integer(delivery.created_at),
+                        delivery.last_attempt_at.map(to_sql_integer),
+                    ],
+                )?;
+
+                // Only keep the newest deliveries so that the log of a busy webhook stays small
+                connection.execute(
+                    "DELETE FROM webhook_deliveries WHERE webhook_id = ?1 AND id NOT IN (
+                        SELECT id FROM webhook_deliveries WHERE webhook_id = ?1
+                        ORDER BY created_at DESC LIMIT ?2
+                    )",
+                    params![
+                        delivery.webhook_id,
+                        to_sql_integer(DELIVERY_LOG_SIZE as u64)
+                    ],
+                )?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn deliveries(&self, webhook_id: &str) -> anyhow::Result<Vec<WebhookDelivery>> {
+        let webhook_id = webhook_id.to_owned();
+
+        self.database
+            .with_connection(move |connection| {
+                connection
+                    .prepare(&format!(
+                        "SELECT {} FROM webhook_deliveries WHERE webhook_id = ?1 \
+                         ORDER BY created_at DESC",
+                        DELIVERY_COLUMNS
+                    ))?
+                    .query_map(params![webhook_id], delivery_from_row)?
+                    .collect()
+            })
+            .await
+    }
+}
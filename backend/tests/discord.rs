@@ -0,0 +1,321 @@
+//! End to end tests of the backend, talking to a mock of discord instead of the real thing
+
+mod mock_discord;
+mod test_app;
+
+use mock_discord::{
+    ADMIN_CODE, ADMIN_ID, GUILD_ID, LISTENER_ID, LOUD_CHANNEL_ID, MEMBER_CODE, MEMBER_ID,
+    MUTED_CHANNEL_ID, MUTED_ID, UNJOINED_GUILD_ID,
+};
+use reqwest::{header, StatusCode};
+use serde_json::{json, Value};
+use std::{env, fs, process};
+use test_app::TestApp;
+
+/// Sort the ids in a list of ids returned by a mutation
+fn sorted_ids(ids: &Value) -> Vec<String> {
+    let mut ids: Vec<String> = ids
+        .as_array()
+        .expect("Expected a list of ids")
+        .iter()
+        .map(|id| id.as_str().expect("Expected an id").to_owned())
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[async_std::test]
+async fn login_redirects_to_discord() {
+    let app = TestApp::start().await;
+
+    let response = app
+        .client
+        .get(&app.url("/oauth/login?from=%2F"))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(response.status().is_redirection());
+
+    let location = response.headers()[header::LOCATION].to_str().unwrap();
+    assert!(location.starts_with("https://discord.com/api/oauth2/authorize"));
+    assert!(location.contains("client_id=100"));
+}
+
+#[async_std::test]
+async fn login_creates_a_session() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = app.graphql(&cookie, "{ me { id name } }").await;
+
+    assert_eq!(response["data"]["me"]["id"], ADMIN_ID.to_string());
+    assert_eq!(response["data"]["me"]["name"], "Admin");
+}
+
+#[async_std::test]
+async fn sessions_are_stored_without_their_secret() {
+    let database = env::temp_dir().join(format!("stfu-sessions-{}.sqlite", process::id()));
+    let app = TestApp::start_with_env(&[("SESSION_DATABASE", database.to_str().unwrap())]).await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = app.graphql(&cookie, "{ me { id } }").await;
+    assert_eq!(response["data"]["me"]["id"], ADMIN_ID.to_string());
+
+    let secret = cookie.splitn(2, '=').nth(1).unwrap();
+    let stored = fs::read(&database).unwrap();
+    fs::remove_file(&database).ok();
+
+    assert!(!stored
+        .windows(secret.len())
+        .any(|window| window == secret.as_bytes()));
+}
+
+#[async_std::test]
+async fn graphql_requires_login() {
+    let app = TestApp::start().await;
+
+    let response = app
+        .client
+        .post(&app.url("/graphql"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(json!({ "query": "{ me { id } }" }).to_string())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[async_std::test]
+async fn shared_guilds_only_include_guilds_with_the_bot() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            "{ sharedGuilds { id name } manageableGuilds { id inviteUrl } }",
+        )
+        .await;
+
+    assert_eq!(
+        response["data"]["sharedGuilds"],
+        json!([{ "id": GUILD_ID.to_string(), "name": "Mock Guild" }])
+    );
+
+    let manageable = &response["data"]["manageableGuilds"];
+    assert_eq!(manageable[0]["id"], UNJOINED_GUILD_ID.to_string());
+    assert!(manageable[0]["inviteUrl"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("guild_id={}", UNJOINED_GUILD_ID)));
+}
+
+#[async_std::test]
+async fn mute_mutes_everyone_but_bots() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ mute(guildId: "{}", channelId: "{}") }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+    assert_eq!(
+        sorted_ids(&response["data"]["mute"]),
+        vec![MEMBER_ID.to_string(), LISTENER_ID.to_string()]
+    );
+
+    let mut updates = app.discord.member_updates();
+    updates.sort_by_key(|update| update.user_id);
+
+    assert_eq!(updates.len(), 2);
+    for (update, user_id) in updates.iter().zip(&[MEMBER_ID, LISTENER_ID]) {
+        assert_eq!(update.guild_id, GUILD_ID);
+        assert_eq!(update.user_id, *user_id);
+        assert_eq!(update.body["mute"], true);
+    }
+}
+
+#[async_std::test]
+async fn unmute_unmutes_muted_users() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ unmute(guildId: "{}", channelId: "{}") }}"#,
+                GUILD_ID, MUTED_CHANNEL_ID
+            ),
+        )
+        .await;
+
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+    assert_eq!(
+        sorted_ids(&response["data"]["unmute"]),
+        vec![MUTED_ID.to_string()]
+    );
+
+    let updates = app.discord.member_updates();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].user_id, MUTED_ID);
+    assert_eq!(updates[0].body["mute"], false);
+}
+
+#[async_std::test]
+async fn mute_is_denied_without_permissions() {
+    let app = TestApp::start().await;
+    let cookie = app.login(MEMBER_CODE).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ mute(guildId: "{}", channelId: "{}") }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+
+    let error = &response["errors"][0];
+    assert!(error["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Permission denied"));
+    assert!(error["extensions"]["missing_permissions"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("MUTE_MEMBERS")));
+
+    assert!(app.discord.member_updates().is_empty());
+}
+
+#[async_std::test]
+async fn graphql_accepts_batches_and_bare_queries() {
+    let app = TestApp::start().await;
+    let cookie = &app.login(ADMIN_CODE).await;
+    let app = &app;
+
+    let post = move |content_type: &'static str, body: String| async move {
+        let body = app
+            .client
+            .post(&app.url("/graphql"))
+            .header(header::COOKIE, cookie)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        serde_json::from_str::<Value>(&body).unwrap()
+    };
+
+    let response = post(
+        "application/json",
+        json!([
+            { "query": "{ me { id } }" },
+            { "query": "{ sharedGuilds { id } }" },
+        ])
+        .to_string(),
+    )
+    .await;
+    assert_eq!(
+        response,
+        json!([
+            { "data": { "me": { "id": ADMIN_ID.to_string() } } },
+            { "data": { "sharedGuilds": [{ "id": GUILD_ID.to_string() }] } },
+        ])
+    );
+
+    let response = post("application/graphql", "{ me { id } }".to_owned()).await;
+    assert_eq!(
+        response,
+        json!({ "data": { "me": { "id": ADMIN_ID.to_string() } } })
+    );
+}
+
+#[async_std::test]
+async fn batches_share_the_query_limits() {
+    // Every `{ me { id } }` costs 2
+    let app =
+        TestApp::start_with_env(&[("MAX_QUERY_COMPLEXITY", "5"), ("MAX_BATCH_SIZE", "3")]).await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let post = |batch: Value| {
+        let request = app
+            .client
+            .post(&app.url("/graphql"))
+            .header(header::COOKIE, &cookie)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(batch.to_string())
+            .send();
+
+        async move {
+            let body = request.await.unwrap().text().await.unwrap();
+            serde_json::from_str::<Value>(&body).unwrap()
+        }
+    };
+
+    let query = json!({ "query": "{ me { id } }" });
+
+    let response = post(json!([query, query])).await;
+    assert_eq!(response[1]["data"]["me"]["id"], ADMIN_ID.to_string());
+
+    let response = post(json!([query, query, query])).await;
+    assert_eq!(
+        response["errors"][0]["extensions"]["code"], "QUERY_TOO_COMPLEX",
+        "{}",
+        response
+    );
+
+    let response = post(json!([query, query, query, query])).await;
+    assert_eq!(
+        response["errors"][0]["extensions"]["code"], "BATCH_TOO_LARGE",
+        "{}",
+        response
+    );
+}
+
+#[async_std::test]
+async fn api_tokens_are_limited_to_the_guilds_of_the_user() {
+    let app = TestApp::start_with_env(&[("MUTATION_USER_BURST", "100")]).await;
+    let cookie = app.login(MEMBER_CODE).await;
+
+    let create = |guild_id: u64| {
+        format!(
+            r#"mutation {{
+                createApiToken(name: "bot", guildIds: ["{}"], actions: [READ]) {{ token }}
+            }}"#,
+            guild_id
+        )
+    };
+
+    let response = app.graphql(&cookie, &create(UNJOINED_GUILD_ID)).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        format!("You are not in the guild {}", UNJOINED_GUILD_ID)
+    );
+
+    for _ in 0..25 {
+        let response = app.graphql(&cookie, &create(GUILD_ID)).await;
+        assert_eq!(response["errors"], Value::Null, "{}", response);
+    }
+
+    let response = app.graphql(&cookie, &create(GUILD_ID)).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "A user can have at most 25 api tokens"
+    );
+}
@@ -0,0 +1,76 @@
+//! Runs graphql queries against the json fixture of discord data, without connecting to discord
+//!
+//! The query command is only built in debug mode, so these tests are too.
+#![cfg(debug_assertions)]
+
+use serde_json::{json, Value};
+use std::process::Command;
+
+/// The path of the fixture
+const FIXTURE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/guild.json");
+
+/// The admin of the fixture guild, with the permissions to mute others
+const ADMIN_ID: &str = "10";
+/// A member of the fixture guild without the permissions to mute others
+const MEMBER_ID: &str = "11";
+
+/// Run a query against the fixture as the user, returning the response and the printed updates
+fn query(user: &str, query: &str) -> (Value, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_stfu-backend"))
+        .args(&["query", "--fixture", FIXTURE_PATH, "--user", user, query])
+        .output()
+        .expect("Failed to run the query command");
+
+    (
+        serde_json::from_slice(&output.stdout).expect("The response was not json"),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn guilds_are_read_from_the_fixture() {
+    let (response, _) = query(
+        ADMIN_ID,
+        r#"{ sharedGuilds { name voiceChannels { name states { totalCount } } } }"#,
+    );
+
+    let guilds = response["data"]["sharedGuilds"].as_array().unwrap();
+    assert_eq!(guilds.len(), 1);
+    assert_eq!(guilds[0]["name"], "Fixture Guild");
+
+    let mut channels = guilds[0]["voiceChannels"].as_array().unwrap().clone();
+    channels.sort_by_key(|channel| channel["name"].as_str().unwrap().to_owned());
+
+    assert_eq!(
+        channels,
+        vec![
+            json!({ "name": "Loud", "states": { "totalCount": 2 } }),
+            json!({ "name": "Muted", "states": { "totalCount": 1 } }),
+        ]
+    );
+}
+
+#[test]
+fn mute_updates_the_fixture() {
+    let (response, updates) = query(
+        ADMIN_ID,
+        r#"mutation { mute(guildId: "1", channelId: "20") }"#,
+    );
+
+    assert_eq!(response["data"]["mute"], json!([MEMBER_ID]));
+    assert!(updates.contains("Muted user 11 in guild 1"));
+}
+
+#[test]
+fn mute_is_denied_without_permissions() {
+    let (response, updates) = query(
+        MEMBER_ID,
+        r#"mutation { mute(guildId: "1", channelId: "20") }"#,
+    );
+
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Permission denied"));
+    assert!(!updates.contains("Muted user"));
+}
@@ -0,0 +1,815 @@
+//! A local stand-in for the discord rest api and gateway, serving a single fixture guild
+//!
+//! The backend sends every rest request to the mock as an http proxy, so requests arrive with
+//! absolute urls such as `http://discord.com/api/v8/users/@me`. The gateway url handed out by
+//! `/gateway/bot` points at a websocket served by the mock, which identifies the bot and sends
+//! the fixture guild. Member updates that change the mute of a user in a voice channel are
+//! followed by a `VOICE_STATE_UPDATE` on every gateway session, like discord does.
+
+#![allow(dead_code)]
+
+use async_std::{
+    io::{prelude::*, BufReader},
+    net::{TcpListener, TcpStream},
+    task,
+};
+use async_tungstenite::tungstenite::{self, Message};
+use flate2::{Compress, Compression, FlushCompress};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    stream, SinkExt, StreamExt,
+};
+use serde_json::{json, Value};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The token the bot logs in with
+pub const BOT_TOKEN: &str = "mock-bot-token";
+/// The id of the bot user and its application
+pub const BOT_ID: u64 = 100;
+/// The guild that both the bot and the users are in
+pub const GUILD_ID: u64 = 1;
+/// A guild that the admin owns, but the bot is not in
+pub const UNJOINED_GUILD_ID: u64 = 2;
+/// A voice channel with two unmuted users and the bot in it
+pub const LOUD_CHANNEL_ID: u64 = 20;
+/// A voice channel with one server muted user in it
+pub const MUTED_CHANNEL_ID: u64 = 21;
+/// The text channel of the fixture guild
+pub const TEXT_CHANNEL_ID: u64 = 22;
+/// A user with the permissions to mute others
+pub const ADMIN_ID: u64 = 10;
+/// A user without the permissions to mute others
+pub const MEMBER_ID: u64 = 11;
+/// Another unmuted user in the loud channel
+pub const LISTENER_ID: u64 = 13;
+/// The server muted user in the muted channel
+pub const MUTED_ID: u64 = 14;
+/// The role of the moderators, allowed to mute and deafen members
+const MODERATOR_ROLE_ID: u64 = 30;
+
+/// The oauth code that logs in as the admin
+pub const ADMIN_CODE: &str = "admin";
+/// The oauth code that logs in as the member
+pub const MEMBER_CODE: &str = "member";
+
+/// A member update that the backend sent to discord
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberUpdate {
+    /// The guild of the member
+    pub guild_id: u64,
+    /// The updated user
+    pub user_id: u64,
+    /// The body of the request
+    pub body: Value,
+}
+
+/// A message that the backend posted to discord, with the edits applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostedMessage {
+    /// The id given to the message
+    pub id: u64,
+    /// The channel the message was posted to
+    pub channel_id: u64,
+    /// The embeds of the message
+    pub embeds: Value,
+    /// The amount of times the message was edited
+    pub edits: usize,
+}
+
+/// The state shared between the connections to the mock
+#[derive(Debug, Default)]
+struct State {
+    /// The address of the gateway websocket
+    gateway_addr: Option<SocketAddr>,
+    /// If the fixture guild has been sent over the gateway
+    guild_sent: AtomicBool,
+    /// The member updates received
+    member_updates: Mutex<Vec<MemberUpdate>>,
+    /// The messages posted
+    messages: Mutex<Vec<PostedMessage>>,
+    /// The senders of the dispatch events of each gateway session
+    dispatches: Mutex<Vec<UnboundedSender<(&'static str, Value)>>>,
+}
+
+impl State {
+    /// Send a dispatch event to every gateway session
+    fn dispatch(&self, event: &'static str, data: Value) {
+        self.dispatches
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.unbounded_send((event, data.clone())).is_ok());
+    }
+}
+
+/// Something for a gateway session to act on
+enum GatewayInput {
+    /// A message from the shard
+    Message(Result<Message, tungstenite::Error>),
+    /// A dispatch event to send to the shard
+    Dispatch(&'static str, Value),
+}
+
+/// A running mock of discord
+#[derive(Debug, Clone)]
+pub struct MockDiscord {
+    /// The address of the rest api
+    api_addr: SocketAddr,
+    /// The shared state
+    state: Arc<State>,
+}
+
+impl MockDiscord {
+    /// Start serving the rest api and gateway on free local ports
+    pub async fn start() -> Self {
+        let api = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind the mock api");
+        let gateway = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind the mock gateway");
+
+        let state = Arc::new(State {
+            gateway_addr: Some(gateway.local_addr().unwrap()),
+            ..State::default()
+        });
+
+        let mock = Self {
+            api_addr: api.local_addr().unwrap(),
+            state,
+        };
+
+        {
+            let state = mock.state.clone();
+            task::spawn(async move {
+                while let Some(Ok(stream)) = api.incoming().next().await {
+                    task::spawn(serve_api(stream, state.clone()));
+                }
+            });
+        }
+
+        {
+            let state = mock.state.clone();
+            task::spawn(async move {
+                while let Some(Ok(stream)) = gateway.incoming().next().await {
+                    task::spawn(serve_gateway(stream, state.clone()));
+                }
+            });
+        }
+
+        mock
+    }
+
+    /// The url to configure as the discord api url of the backend
+    pub fn api_url(&self) -> String {
+        format!("http://{}", self.api_addr)
+    }
+
+    /// If the fixture guild has been sent over the gateway
+    pub fn guild_sent(&self) -> bool {
+        self.state.guild_sent.load(Ordering::SeqCst)
+    }
+
+    /// The member updates that the backend has made
+    pub fn member_updates(&self) -> Vec<MemberUpdate> {
+        self.state.member_updates.lock().unwrap().clone()
+    }
+
+    /// The messages that the backend has posted
+    pub fn messages(&self) -> Vec<PostedMessage> {
+        self.state.messages.lock().unwrap().clone()
+    }
+
+    /// Have a user of the fixture guild join a voice channel, sending the new voice state to
+    /// every gateway session
+    pub fn join_voice_channel(&self, user_id: u64, channel_id: u64, mute: bool) {
+        self.state
+            .dispatch("VOICE_STATE_UPDATE", voice_state(user_id, channel_id, mute));
+    }
+
+    /// Have a user leave the fixture guild, sending the removal to every gateway session
+    pub fn leave_guild(&self, user_id: u64) {
+        self.state.dispatch(
+            "GUILD_MEMBER_REMOVE",
+            json!({
+                "guild_id": GUILD_ID.to_string(),
+                "user": user(user_id, &format!("user-{}", user_id), false),
+            }),
+        );
+    }
+}
+
+/// An http request read from a connection
+#[derive(Debug)]
+struct Request {
+    /// The method of the request
+    method: String,
+    /// The path of the request, without the scheme and host of proxied requests
+    path: String,
+    /// The authorization header
+    authorization: Option<String>,
+    /// The body of the request
+    body: Vec<u8>,
+}
+
+/// Read a request from a connection
+async fn read_request(reader: &mut BufReader<&TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let target = parts.next()?;
+
+    // Proxied requests have an absolute url as their target
+    let path = match target.find("://") {
+        Some(scheme_end) => {
+            let rest = &target[scheme_end + 3..];
+            rest.find('/').map_or("/", |path_start| &rest[path_start..])
+        }
+        None => target,
+    };
+    let path = path.split('?').next().unwrap_or(path).to_owned();
+
+    let mut authorization = None;
+    let mut content_length = 0;
+
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.ok()?;
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(colon) = header.find(':') {
+            let (name, value) = (&header[..colon], header[colon + 1..].trim());
+
+            match name.to_ascii_lowercase().as_str() {
+                "authorization" => authorization = Some(value.to_owned()),
+                "content-length" => content_length = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+
+    Some(Request {
+        method,
+        path,
+        authorization,
+        body,
+    })
+}
+
+/// Serve rest requests on a connection, one at a time
+async fn serve_api(stream: TcpStream, state: Arc<State>) {
+    let mut reader = BufReader::new(&stream);
+
+    while let Some(request) = read_request(&mut reader).await {
+        let (status, body) = respond(&request, &state);
+
+        let response = match body {
+            Some(body) => {
+                let body = body.to_string();
+                format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+            }
+            None => format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status),
+        };
+
+        if (&stream).write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The user that the authorization header of a request belongs to
+fn authorized_user(request: &Request) -> Option<u64> {
+    match request.authorization.as_deref()? {
+        authorization if authorization.ends_with(BOT_TOKEN) => Some(BOT_ID),
+        "Bearer token-admin" => Some(ADMIN_ID),
+        "Bearer token-member" => Some(MEMBER_ID),
+        _ => None,
+    }
+}
+
+/// Create the response to a rest request
+fn respond(request: &Request, state: &State) -> (&'static str, Option<Value>) {
+    let path = request.path.trim_start_matches("/api");
+    let path = path
+        .strip_prefix("/v8")
+        .or_else(|| path.strip_prefix("/v7"))
+        .unwrap_or(path);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    if let ("POST", ["oauth2", "token"]) = (request.method.as_str(), segments.as_slice()) {
+        let body = String::from_utf8_lossy(&request.body);
+        let code = body
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("code="))
+            .unwrap_or_default();
+
+        let access_token = match code {
+            ADMIN_CODE => "token-admin",
+            MEMBER_CODE => "token-member",
+            _ => return ("400 Bad Request", Some(json!({ "error": "invalid_grant" }))),
+        };
+
+        return (
+            "200 OK",
+            Some(json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": 604_800,
+                "refresh_token": format!("refresh-{}", access_token),
+                "scope": "identify guilds",
+            })),
+        );
+    }
+
+    let user_id = match authorized_user(request) {
+        Some(user_id) => user_id,
+        None => {
+            return (
+                "401 Unauthorized",
+                Some(json!({ "message": "401: Unauthorized", "code": 0 })),
+            )
+        }
+    };
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["gateway", "bot"]) => (
+            "200 OK",
+            Some(json!({
+                "url": format!("ws://{}", state.gateway_addr.unwrap()),
+                "shards": 1,
+                "session_start_limit": {
+                    "total": 1000,
+                    "remaining": 1000,
+                    "reset_after": 0,
+                    "max_concurrency": 1,
+                },
+            })),
+        ),
+        ("GET", ["oauth2", "applications", "@me"]) => ("200 OK", Some(application())),
+        ("GET", ["users", "@me"]) => ("200 OK", Some(current_user(user_id))),
+        ("GET", ["users", "@me", "guilds"]) => ("200 OK", Some(current_user_guilds(user_id))),
+        ("PATCH", ["guilds", guild_id, "members", member_id]) if user_id == BOT_ID => {
+            let update = MemberUpdate {
+                guild_id: guild_id.parse().unwrap_or_default(),
+                user_id: member_id.parse().unwrap_or_default(),
+                body: serde_json::from_slice(&request.body).unwrap_or(Value::Null),
+            };
+
+            if let (Some(mute), Some(channel_id)) = (
+                update.body["mute"].as_bool(),
+                voice_channel_of(update.user_id),
+            ) {
+                state.dispatch(
+                    "VOICE_STATE_UPDATE",
+                    voice_state(update.user_id, channel_id, mute),
+                );
+            }
+
+            state.member_updates.lock().unwrap().push(update);
+
+            ("204 No Content", None)
+        }
+        ("POST", ["channels", channel_id, "messages"]) if user_id == BOT_ID => {
+            let body: Value = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+            let mut messages = state.messages.lock().unwrap();
+
+            let posted = PostedMessage {
+                id: 1000 + messages.len() as u64,
+                channel_id: channel_id.parse().unwrap_or_default(),
+                embeds: embeds_of(&body),
+                edits: 0,
+            };
+            messages.push(posted.clone());
+
+            ("200 OK", Some(message(&posted)))
+        }
+        ("PATCH", ["channels", channel_id, "messages", message_id]) if user_id == BOT_ID => {
+            let body: Value = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+            let mut messages = state.messages.lock().unwrap();
+
+            let posted = messages.iter_mut().find(|posted| {
+                posted.channel_id.to_string() == *channel_id && posted.id.to_string() == *message_id
+            });
+
+            match posted {
+                Some(posted) => {
+                    posted.embeds = embeds_of(&body);
+                    posted.edits += 1;
+
+                    ("200 OK", Some(message(posted)))
+                }
+                None => (
+                    "404 Not Found",
+                    Some(json!({ "message": "Unknown Message", "code": 10008 })),
+                ),
+            }
+        }
+        _ => (
+            "404 Not Found",
+            Some(json!({ "message": "404: Not Found", "code": 0 })),
+        ),
+    }
+}
+
+/// Accept a websocket connection to the gateway and act out a session
+async fn serve_gateway(stream: TcpStream, state: Arc<State>) {
+    let socket = match async_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    let (mut sink, incoming) = socket.split();
+
+    let (dispatch_sender, dispatches) = mpsc::unbounded();
+    state.dispatches.lock().unwrap().push(dispatch_sender);
+
+    let mut inputs = stream::select(
+        incoming.map(GatewayInput::Message),
+        dispatches.map(|(event, data)| GatewayInput::Dispatch(event, data)),
+    );
+
+    // Shards ask for a zlib stream, so every payload is compressed with one shared context
+    let mut compress = Compress::new(Compression::fast(), true);
+    let mut sequence = 0;
+    let mut send = |op: u8, event: Option<&str>, data: Value| {
+        let payload = if op == 0 {
+            sequence += 1;
+            json!({ "op": op, "t": event, "s": sequence, "d": data })
+        } else {
+            json!({ "op": op, "t": null, "s": null, "d": data })
+        }
+        .to_string();
+
+        let mut compressed = Vec::with_capacity(payload.len() + 64);
+        compress
+            .compress_vec(payload.as_bytes(), &mut compressed, FlushCompress::Sync)
+            .expect("Failed to compress a gateway payload");
+
+        Message::Binary(compressed)
+    };
+
+    if sink
+        .send(send(10, None, json!({ "heartbeat_interval": 41_250 })))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    while let Some(input) = inputs.next().await {
+        let message = match input {
+            GatewayInput::Message(Ok(message)) => message,
+            GatewayInput::Message(Err(_)) => return,
+            GatewayInput::Dispatch(event, data) => {
+                if sink.send(send(0, Some(event), data)).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        let payload: Value = match message {
+            Message::Text(text) => serde_json::from_str(&text).unwrap_or(Value::Null),
+            Message::Binary(bytes) => serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+            Message::Close(_) => return,
+            _ => continue,
+        };
+
+        let replies = match payload["op"].as_u64() {
+            // Heartbeat
+            Some(1) => vec![send(11, None, Value::Null)],
+            // Identify
+            Some(2) => vec![
+                send(0, Some("READY"), ready()),
+                send(0, Some("GUILD_CREATE"), guild()),
+            ],
+            _ => Vec::new(),
+        };
+        let identified = payload["op"].as_u64() == Some(2);
+
+        for reply in replies {
+            if sink.send(reply).await.is_err() {
+                return;
+            }
+        }
+
+        if identified {
+            state.guild_sent.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A user object
+fn user(id: u64, name: &str, bot: bool) -> Value {
+    json!({
+        "id": id.to_string(),
+        "username": name,
+        "discriminator": format!("{:04}", id),
+        "avatar": null,
+        "bot": bot,
+        "public_flags": 0,
+    })
+}
+
+/// The current user object of the user
+fn current_user(id: u64) -> Value {
+    let name = match id {
+        BOT_ID => "Mock Bot",
+        ADMIN_ID => "Admin",
+        _ => "Member",
+    };
+
+    json!({
+        "id": id.to_string(),
+        "username": name,
+        "discriminator": format!("{:04}", id),
+        "avatar": null,
+        "bot": id == BOT_ID,
+        "mfa_enabled": false,
+        "locale": "en-US",
+        "verified": true,
+        "email": null,
+        "flags": 0,
+        "premium_type": 0,
+        "public_flags": 0,
+    })
+}
+
+/// The application of the bot
+fn application() -> Value {
+    json!({
+        "id": BOT_ID.to_string(),
+        "name": "Mock Bot",
+        "icon": null,
+        "description": "",
+        "summary": "",
+        "rpc_origins": [],
+        "bot_public": true,
+        "bot_require_code_grant": false,
+        "owner": user(ADMIN_ID, "Admin", false),
+        "verify_key": "",
+        "team": null,
+        "flags": 0,
+    })
+}
+
+/// The guilds that the user is in, as seen through oauth
+fn current_user_guilds(user_id: u64) -> Value {
+    match user_id {
+        ADMIN_ID => json!([
+            {
+                "id": GUILD_ID.to_string(),
+                "name": "Mock Guild",
+                "icon": null,
+                "owner": true,
+                "permissions": "2147483647",
+                "features": [],
+            },
+            {
+                "id": UNJOINED_GUILD_ID.to_string(),
+                "name": "Unjoined Guild",
+                "icon": null,
+                "owner": true,
+                "permissions": "2147483647",
+                "features": [],
+            },
+        ]),
+        MEMBER_ID => json!([
+            {
+                "id": GUILD_ID.to_string(),
+                "name": "Mock Guild",
+                "icon": null,
+                "owner": false,
+                "permissions": "1049600",
+                "features": [],
+            },
+        ]),
+        _ => json!([]),
+    }
+}
+
+/// The ready event for the bot
+fn ready() -> Value {
+    json!({
+        "v": 8,
+        "user": current_user(BOT_ID),
+        "guilds": [{ "id": GUILD_ID.to_string(), "unavailable": true }],
+        "session_id": "mock-session",
+        "shard": [0, 1],
+        "application": { "id": BOT_ID.to_string(), "flags": 0 },
+        "private_channels": [],
+        "relationships": [],
+    })
+}
+
+/// A member object of the fixture guild
+fn member(id: u64, name: &str, bot: bool, roles: &[u64]) -> Value {
+    json!({
+        "user": user(id, name, bot),
+        "nick": null,
+        "roles": roles.iter().map(u64::to_string).collect::<Vec<_>>(),
+        "joined_at": "2020-01-01T00:00:00.000000+00:00",
+        "premium_since": null,
+        "deaf": false,
+        "mute": id == MUTED_ID,
+        "pending": false,
+    })
+}
+
+/// The voice channel that a user of the fixture guild is in
+fn voice_channel_of(user_id: u64) -> Option<u64> {
+    match user_id {
+        BOT_ID | MEMBER_ID | LISTENER_ID => Some(LOUD_CHANNEL_ID),
+        MUTED_ID => Some(MUTED_CHANNEL_ID),
+        _ => None,
+    }
+}
+
+/// A voice state in the fixture guild
+fn voice_state(user_id: u64, channel_id: u64, mute: bool) -> Value {
+    json!({
+        "guild_id": GUILD_ID.to_string(),
+        "channel_id": channel_id.to_string(),
+        "user_id": user_id.to_string(),
+        "session_id": format!("voice-session-{}", user_id),
+        "deaf": false,
+        "mute": mute,
+        "self_deaf": false,
+        "self_mute": false,
+        "self_stream": false,
+        "self_video": false,
+        "suppress": false,
+        "token": null,
+    })
+}
+
+/// The embeds of a message create or edit body, which can send one embed or a list of them
+fn embeds_of(body: &Value) -> Value {
+    match (&body["embeds"], &body["embed"]) {
+        (Value::Array(embeds), _) => Value::Array(embeds.clone()),
+        (_, Value::Null) => json!([]),
+        (_, embed) => json!([embed]),
+    }
+}
+
+/// A message object of a message posted by the bot
+fn message(posted: &PostedMessage) -> Value {
+    json!({
+        "id": posted.id.to_string(),
+        "channel_id": posted.channel_id.to_string(),
+        "guild_id": GUILD_ID.to_string(),
+        "author": user(BOT_ID, "Mock Bot", true),
+        "content": "",
+        "timestamp": "2020-01-01T00:00:00.000000+00:00",
+        "edited_timestamp": if posted.edits > 0 {
+            json!("2020-01-01T00:01:00.000000+00:00")
+        } else {
+            Value::Null
+        },
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "mention_channels": [],
+        "attachments": [],
+        "embeds": posted.embeds,
+        "reactions": [],
+        "pinned": false,
+        "type": 0,
+        "flags": 0,
+    })
+}
+
+/// A text channel in the fixture guild
+fn text_channel(id: u64, name: &str, position: u64) -> Value {
+    json!({
+        "id": id.to_string(),
+        "type": 0,
+        "guild_id": GUILD_ID.to_string(),
+        "name": name,
+        "position": position,
+        "permission_overwrites": [],
+        "topic": null,
+        "last_message_id": null,
+        "rate_limit_per_user": 0,
+        "parent_id": null,
+        "nsfw": false,
+    })
+}
+
+/// A voice channel in the fixture guild
+fn voice_channel(id: u64, name: &str, position: u64) -> Value {
+    json!({
+        "id": id.to_string(),
+        "type": 2,
+        "guild_id": GUILD_ID.to_string(),
+        "name": name,
+        "position": position,
+        "permission_overwrites": [],
+        "bitrate": 64000,
+        "user_limit": 0,
+        "parent_id": null,
+        "nsfw": false,
+    })
+}
+
+/// A role in the fixture guild
+fn role(id: u64, name: &str, permissions: u64, position: u64) -> Value {
+    json!({
+        "id": id.to_string(),
+        "name": name,
+        "color": 0,
+        "hoist": false,
+        "position": position,
+        "permissions": permissions.to_string(),
+        "managed": false,
+        "mentionable": false,
+    })
+}
+
+/// The fixture guild, sent when the bot identifies
+fn guild() -> Value {
+    // View channel and connect
+    const EVERYONE_PERMISSIONS: u64 = 0x400 | 0x10_0000;
+    // Mute and deafen members
+    const MODERATOR_PERMISSIONS: u64 = 0x40_0000 | 0x80_0000;
+
+    json!({
+        "id": GUILD_ID.to_string(),
+        "name": "Mock Guild",
+        "icon": null,
+        "splash": null,
+        "discovery_splash": null,
+        "banner": null,
+        "description": null,
+        "owner_id": ADMIN_ID.to_string(),
+        "region": "us-east",
+        "afk_channel_id": null,
+        "afk_timeout": 300,
+        "verification_level": 0,
+        "default_message_notifications": 0,
+        "explicit_content_filter": 0,
+        "mfa_level": 0,
+        "application_id": null,
+        "system_channel_id": null,
+        "system_channel_flags": 0,
+        "rules_channel_id": null,
+        "public_updates_channel_id": null,
+        "widget_enabled": false,
+        "widget_channel_id": null,
+        "vanity_url_code": null,
+        "premium_tier": 0,
+        "premium_subscription_count": 0,
+        "preferred_locale": "en-US",
+        "max_members": 100_000,
+        "max_presences": null,
+        "max_video_channel_users": 25,
+        "features": [],
+        "emojis": [],
+        "joined_at": "2020-01-01T00:00:00.000000+00:00",
+        "large": false,
+        "unavailable": false,
+        "member_count": 5,
+        "roles": [
+            role(GUILD_ID, "@everyone", EVERYONE_PERMISSIONS, 0),
+            role(MODERATOR_ROLE_ID, "Moderators", MODERATOR_PERMISSIONS, 1),
+        ],
+        "channels": [
+            voice_channel(LOUD_CHANNEL_ID, "Loud", 0),
+            voice_channel(MUTED_CHANNEL_ID, "Muted", 1),
+            text_channel(TEXT_CHANNEL_ID, "general", 2),
+        ],
+        "members": [
+            member(BOT_ID, "Mock Bot", true, &[MODERATOR_ROLE_ID]),
+            member(ADMIN_ID, "Admin", false, &[MODERATOR_ROLE_ID]),
+            member(MEMBER_ID, "Member", false, &[]),
+            member(LISTENER_ID, "Listener", false, &[]),
+            member(MUTED_ID, "Muted", false, &[]),
+        ],
+        "voice_states": [
+            voice_state(BOT_ID, LOUD_CHANNEL_ID, false),
+            voice_state(MEMBER_ID, LOUD_CHANNEL_ID, false),
+            voice_state(LISTENER_ID, LOUD_CHANNEL_ID, false),
+            voice_state(MUTED_ID, MUTED_CHANNEL_ID, true),
+        ],
+        "presences": [],
+    })
+}
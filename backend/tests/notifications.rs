@@ -0,0 +1,216 @@
+//! End to end tests of the messages posted when a voice channel is mass muted
+
+mod mock_discord;
+mod test_app;
+
+use async_std::{future::timeout, task};
+use mock_discord::{
+    PostedMessage, ADMIN_CODE, GUILD_ID, LOUD_CHANNEL_ID, MEMBER_CODE, TEXT_CHANNEL_ID,
+};
+use serde_json::Value;
+use std::time::Duration;
+use test_app::TestApp;
+
+/// Wait until the mock has a posted message that matches the condition
+async fn wait_for_message(
+    app: &TestApp,
+    condition: impl Fn(&PostedMessage) -> bool,
+) -> PostedMessage {
+    timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(message) = app.discord.messages().into_iter().find(|m| condition(m)) {
+                return message;
+            }
+
+            task::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The message was not posted in time")
+}
+
+/// The value of the field of the first embed of a message with the name
+fn field<'a>(message: &'a PostedMessage, name: &str) -> &'a Value {
+    message.embeds[0]["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|field| field["name"] == name)
+        .map_or(&Value::Null, |field| &field["value"])
+}
+
+/// Mute or unmute the loud channel of the fixture guild
+async fn set_muted(app: &TestApp, cookie: &str, muted: bool) {
+    let response = app
+        .graphql(
+            cookie,
+            &format!(
+                r#"mutation {{ {}(guildId: "{}", channelId: "{}") }}"#,
+                if muted { "mute" } else { "unmute" },
+                GUILD_ID,
+                LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+}
+
+#[async_std::test]
+async fn mutes_are_posted_to_the_voice_chat_and_edited_when_lifted() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{
+                    setNotificationChannel(guildId: "{}", channelId: null) {{ channelId updatedBy }}
+                }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+    assert_eq!(
+        response["data"]["setNotificationChannel"]["channelId"],
+        Value::Null
+    );
+
+    set_muted(&app, &cookie, true).await;
+
+    let posted = wait_for_message(&app, |_| true).await;
+    assert_eq!(posted.channel_id, LOUD_CHANNEL_ID);
+    assert_eq!(posted.embeds[0]["title"], "Voice channel muted");
+    assert_eq!(field(&posted, "Members muted"), "2");
+
+    set_muted(&app, &cookie, false).await;
+
+    let edited = wait_for_message(&app, |message| message.edits > 0).await;
+    assert_eq!(edited.id, posted.id);
+    assert_eq!(edited.embeds[0]["title"], "Voice channel unmuted");
+    assert_eq!(field(&edited, "Members muted"), "2");
+    assert_eq!(field(&edited, "Members unmuted"), "2");
+    assert_eq!(app.discord.messages().len(), 1);
+}
+
+#[async_std::test]
+async fn unmutes_right_after_a_mute_edit_its_message() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ setNotificationChannel(guildId: "{}", channelId: null) {{ channelId }} }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    // The unmute is made before the message about the mute could have been posted
+    set_muted(&app, &cookie, true).await;
+    set_muted(&app, &cookie, false).await;
+
+    let edited = wait_for_message(&app, |message| message.edits > 0).await;
+    assert_eq!(edited.embeds[0]["title"], "Voice channel unmuted");
+    assert_eq!(app.discord.messages().len(), 1);
+}
+
+#[async_std::test]
+async fn mutes_are_posted_to_the_chosen_text_channel() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    // Voice channels can not be chosen
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{
+                    setNotificationChannel(guildId: "{}", channelId: "{}") {{ channelId }}
+                }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "Text channel does not exist on the guild"
+    );
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{
+                    setNotificationChannel(guildId: "{}", channelId: "{}") {{ channelId }}
+                }}"#,
+                GUILD_ID, TEXT_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["data"]["setNotificationChannel"]["channelId"],
+        TEXT_CHANNEL_ID.to_string(),
+        "{}",
+        response
+    );
+
+    set_muted(&app, &cookie, true).await;
+
+    let posted = wait_for_message(&app, |_| true).await;
+    assert_eq!(posted.channel_id, TEXT_CHANNEL_ID);
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ disableNotifications(guildId: "{}") }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["data"]["disableNotifications"], true,
+        "{}",
+        response
+    );
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"{{ notificationSettings(guildId: "{}") {{ channelId }} }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+    assert_eq!(response["data"]["notificationSettings"], Value::Null);
+}
+
+#[async_std::test]
+async fn notification_settings_need_the_manage_guild_permission() {
+    let app = TestApp::start().await;
+    let cookie = app.login(MEMBER_CODE).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{
+                    setNotificationChannel(guildId: "{}", channelId: null) {{ channelId }}
+                }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Permission denied"));
+}
@@ -0,0 +1,195 @@
+//! End to end tests of an api server and a gateway worker talking through redis, with the
+//! worker filling the shared cache and making the member updates of the server
+//!
+//! These need a local redis server, at `STFU_TEST_REDIS_URL` or `redis://127.0.0.1/`, so they
+//! are ignored by default. Run them with `cargo test --test redis_cache -- --ignored`.
+
+mod mock_discord;
+mod test_app;
+
+use async_std::{future::timeout, task};
+use mock_discord::{ADMIN_CODE, BOT_ID, GUILD_ID, LISTENER_ID, LOUD_CHANNEL_ID, MEMBER_ID};
+use serde_json::{json, Value};
+use std::{env, time::Duration};
+use test_app::TestApp;
+
+/// How long the gateway worker has to write the fixture guild to redis
+const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Start an api server and a gateway worker talking through redis
+async fn start() -> TestApp {
+    let redis_url =
+        env::var("STFU_TEST_REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_owned());
+
+    TestApp::start_with_redis(&redis_url).await
+}
+
+/// Run a query until its response passes the check, as changes reach the server through the
+/// cache some time after they are made
+async fn query_until(
+    app: &TestApp,
+    cookie: &str,
+    query: &str,
+    check: impl Fn(&Value) -> bool,
+) -> Value {
+    timeout(SYNC_TIMEOUT, async {
+        loop {
+            let response = app.graphql(cookie, query).await;
+
+            if check(&response) {
+                return response;
+            }
+
+            task::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("The change never reached the server")
+}
+
+/// Run a query until the server has read the fixture guild from the cache
+async fn query_synced(app: &TestApp, cookie: &str, query: &str) -> Value {
+    query_until(app, cookie, query, |response| {
+        response["data"]["sharedGuilds"]
+            .as_array()
+            .map_or(false, |guilds| !guilds.is_empty())
+    })
+    .await
+}
+
+#[async_std::test]
+#[ignore]
+async fn server_reads_guilds_from_the_cache() {
+    let app = start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = query_synced(
+        &app,
+        &cookie,
+        "{ sharedGuilds { id name voiceChannels { id states { totalCount } } } }",
+    )
+    .await;
+
+    let guild = &response["data"]["sharedGuilds"][0];
+    assert_eq!(guild["id"], GUILD_ID.to_string());
+    assert_eq!(guild["name"], "Mock Guild");
+
+    let loud = guild["voiceChannels"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|channel| channel["id"] == LOUD_CHANNEL_ID.to_string())
+        .expect("The loud channel is missing");
+    assert_eq!(loud["states"]["totalCount"], 3);
+}
+
+#[async_std::test]
+#[ignore]
+async fn mutes_are_made_by_the_gateway_worker() {
+    let app = start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    query_synced(&app, &cookie, "{ sharedGuilds { id } }").await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ mute(guildId: "{}", channelId: "{}") }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    let mut muted: Vec<_> = response["data"]["mute"].as_array().unwrap().clone();
+    muted.sort_by_key(|id| id.as_str().unwrap().to_owned());
+    assert_eq!(
+        muted,
+        vec![json!(MEMBER_ID.to_string()), json!(LISTENER_ID.to_string())]
+    );
+
+    let mut updates = app.discord.member_updates();
+    updates.sort_by_key(|update| update.user_id);
+    assert_eq!(
+        updates
+            .iter()
+            .map(|update| (update.user_id, update.body["mute"].clone()))
+            .collect::<Vec<_>>(),
+        vec![(MEMBER_ID, json!(true)), (LISTENER_ID, json!(true))]
+    );
+
+    // The voice state updates sent to the worker come back to the server through the cache
+    let channel_states = format!(
+        r#"{{ sharedGuilds {{ voiceChannel(id: "{}") {{ states {{ nodes {{ id mute }} }} }} }} }}"#,
+        LOUD_CHANNEL_ID
+    );
+    query_until(&app, &cookie, &channel_states, |response| {
+        response["data"]["sharedGuilds"][0]["voiceChannel"]["states"]["nodes"]
+            .as_array()
+            .map_or(false, |states| {
+                states
+                    .iter()
+                    .all(|state| state["mute"] == (state["id"] != BOT_ID.to_string()))
+            })
+    })
+    .await;
+}
+
+#[async_std::test]
+#[ignore]
+async fn commands_reuse_their_connections() {
+    let app = start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    query_synced(&app, &cookie, "{ sharedGuilds { id } }").await;
+
+    let channel_states = format!(
+        r#"{{ sharedGuilds {{ voiceChannel(id: "{}") {{ states {{ nodes {{ id mute }} }} }} }} }}"#,
+        LOUD_CHANNEL_ID
+    );
+
+    // The unmute runs on the connections that the mute left idle
+    for &mute in &[true, false] {
+        let response = app
+            .graphql(
+                &cookie,
+                &format!(
+                    r#"mutation {{ {}(guildId: "{}", channelId: "{}") }}"#,
+                    if mute { "mute" } else { "unmute" },
+                    GUILD_ID,
+                    LOUD_CHANNEL_ID
+                ),
+            )
+            .await;
+
+        assert_eq!(response["errors"], Value::Null, "{}", response);
+
+        query_until(&app, &cookie, &channel_states, |response| {
+            response["data"]["sharedGuilds"][0]["voiceChannel"]["states"]["nodes"]
+                .as_array()
+                .map_or(false, |states| {
+                    states.iter().all(|state| {
+                        state["id"] == BOT_ID.to_string() || state["mute"] == json!(mute)
+                    })
+                })
+        })
+        .await;
+    }
+
+    let mut updates = app.discord.member_updates();
+    updates.sort_by_key(|update| (update.body["mute"] == json!(false), update.user_id));
+    assert_eq!(
+        updates
+            .iter()
+            .map(|update| (update.user_id, update.body["mute"].clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (MEMBER_ID, json!(true)),
+            (LISTENER_ID, json!(true)),
+            (MEMBER_ID, json!(false)),
+            (LISTENER_ID, json!(false)),
+        ]
+    );
+}
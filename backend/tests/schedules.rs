@@ -0,0 +1,323 @@
+//! End to end tests of scheduled mutes and the timezones they are evaluated in
+
+mod mock_discord;
+mod test_app;
+
+use async_std::{future::timeout, task};
+use mock_discord::{
+    ADMIN_CODE, ADMIN_ID, GUILD_ID, LISTENER_ID, LOUD_CHANNEL_ID, MEMBER_CODE, MEMBER_ID,
+};
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use test_app::TestApp;
+
+/// Schedule an action on the loud channel, with the arguments that say when it runs
+async fn create_schedule(app: &TestApp, cookie: &str, action: &str, when: &str) -> Value {
+    app.graphql(
+        cookie,
+        &format!(
+            r#"mutation {{
+                createSchedule(guildId: "{}", channelId: "{}", action: {}, {}) {{
+                    id
+                    action
+                    cron
+                    runAt
+                    nextRunAt
+                    createdBy
+                }}
+            }}"#,
+            GUILD_ID, LOUD_CHANNEL_ID, action, when
+        ),
+    )
+    .await
+}
+
+/// The schedules of the fixture guild
+async fn schedules(app: &TestApp, cookie: &str) -> Value {
+    let response = app
+        .graphql(
+            cookie,
+            &format!(
+                r#"{{ schedules(guildId: "{}") {{ id cron nextRunAt lastRunAt }} }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+    response["data"]["schedules"].clone()
+}
+
+/// The current time in seconds since the unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[async_std::test]
+async fn one_off_schedules_mute_the_channel_when_due() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let run_at = now().to_string();
+    let response = create_schedule(&app, &cookie, "MUTE", &format!(r#"runAt: "{}""#, run_at)).await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    let schedule = &response["data"]["createSchedule"];
+    assert_eq!(schedule["action"], "MUTE");
+    assert_eq!(schedule["cron"], Value::Null);
+    assert_eq!(schedule["runAt"], run_at);
+    assert_eq!(schedule["nextRunAt"], run_at);
+    assert_eq!(schedule["createdBy"], ADMIN_ID.to_string());
+
+    timeout(Duration::from_secs(10), async {
+        while app.discord.member_updates().len() < 2 {
+            task::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The schedule did not run in time");
+
+    let mut mutes: Vec<_> = app
+        .discord
+        .member_updates()
+        .into_iter()
+        .map(|update| (update.user_id, update.body["mute"].as_bool().unwrap()))
+        .collect();
+    mutes.sort();
+    assert_eq!(mutes, vec![(MEMBER_ID, true), (LISTENER_ID, true)]);
+
+    // The schedule ran once, so it is never due again
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let schedules = schedules(&app, &cookie).await;
+
+            if schedules[0]["lastRunAt"] != Value::Null {
+                assert_eq!(schedules[0]["nextRunAt"], Value::Null);
+                return;
+            }
+
+            task::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The run of the schedule was not recorded in time");
+}
+
+#[async_std::test]
+async fn recurring_schedules_follow_the_timezone_of_the_guild() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = create_schedule(&app, &cookie, "MUTE", r#"cron: "0 22 * * MON-FRI""#).await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+    let id = response["data"]["createSchedule"]["id"].clone();
+    let utc_run: u64 = response["data"]["createSchedule"]["nextRunAt"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(utc_run > now());
+    assert_eq!(utc_run % 3600, 0);
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ setTimezone(guildId: "{}", timezone: "Not/AZone") }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "Unknown timezone Not/AZone"
+    );
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ setTimezone(guildId: "{}", timezone: "Asia/Kolkata") }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["data"]["setTimezone"], "Asia/Kolkata",
+        "{}",
+        response
+    );
+
+    // India is five and a half hours ahead of UTC, so the run moves off the full hour
+    let schedules = schedules(&app, &cookie).await;
+    assert_eq!(schedules[0]["id"], id);
+    let local_run: u64 = schedules[0]["nextRunAt"].as_str().unwrap().parse().unwrap();
+    assert_eq!(local_run % 3600, 1800);
+
+    let response = app.graphql(&cookie, "{ sharedGuilds { timezone } }").await;
+    assert_eq!(
+        response["data"]["sharedGuilds"][0]["timezone"], "Asia/Kolkata",
+        "{}",
+        response
+    );
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ deleteSchedule(guildId: "{}", id: {}) }}"#,
+                GUILD_ID, id
+            ),
+        )
+        .await;
+    assert_eq!(response["data"]["deleteSchedule"], true, "{}", response);
+    assert_eq!(schedules(&app, &cookie).await, json!([]));
+}
+
+#[async_std::test]
+async fn invalid_schedules_are_rejected() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = create_schedule(&app, &cookie, "MUTE", r#"cron: "61 * * * *""#).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "Invalid minute: 61 is not between 0 and 59"
+    );
+
+    let response = create_schedule(&app, &cookie, "UNMUTE", r#"cron: "0 7 * *""#).await;
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("A cron expression needs 5 fields"));
+
+    let response = create_schedule(
+        &app,
+        &cookie,
+        "MUTE",
+        r#"cron: "@daily", runAt: "1700000000""#,
+    )
+    .await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "Exactly one of cron and runAt has to be given"
+    );
+
+    assert_eq!(schedules(&app, &cookie).await, json!([]));
+}
+
+#[async_std::test]
+async fn schedules_need_the_manage_guild_permission() {
+    let app = TestApp::start().await;
+    let cookie = app.login(MEMBER_CODE).await;
+
+    let response = create_schedule(&app, &cookie, "MUTE", r#"cron: "@daily""#).await;
+
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Permission denied"));
+}
+
+#[async_std::test]
+async fn schedules_stop_once_their_creator_can_not_run_them() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let run_at = format!(r#"runAt: "{}""#, now() + 2);
+    let response = create_schedule(&app, &cookie, "MUTE", &run_at).await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    app.discord.leave_guild(ADMIN_ID);
+
+    timeout(Duration::from_secs(10), async {
+        while schedules(&app, &cookie).await[0]["nextRunAt"] != Value::Null {
+            task::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The schedule was never stopped");
+
+    assert!(app.discord.member_updates().is_empty());
+    assert_eq!(schedules(&app, &cookie).await[0]["lastRunAt"], Value::Null);
+}
+
+#[async_std::test]
+async fn schedules_are_limited() {
+    let app = TestApp::start_with_env(&[
+        ("MUTATION_USER_BURST", "100"),
+        ("MUTATION_GUILD_BURST", "100"),
+    ])
+    .await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = create_schedule(&app, &cookie, "MUTE", r#"cron: "* * * * *""#).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "A recurring schedule can run at most once every 15 minutes"
+    );
+
+    let response = create_schedule(&app, &cookie, "MUTE", r#"cron: "0,59 0,23 * * *""#).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "A recurring schedule can run at most once every 15 minutes"
+    );
+
+    let response = create_schedule(&app, &cookie, "MUTE", r#"cron: "0 0 30 2 *""#).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "The cron expression never matches"
+    );
+
+    for _ in 0..25 {
+        let response = create_schedule(&app, &cookie, "MUTE", r#"cron: "@daily""#).await;
+        assert_eq!(response["errors"], Value::Null, "{}", response);
+    }
+
+    let response = create_schedule(&app, &cookie, "MUTE", r#"cron: "@daily""#).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "A guild can have at most 25 schedules"
+    );
+}
+
+#[async_std::test]
+async fn guild_settings_can_not_be_changed_after_leaving_the_guild() {
+    let app = TestApp::start_with_env(&[
+        ("MUTATION_USER_BURST", "100"),
+        ("MUTATION_GUILD_BURST", "100"),
+    ])
+    .await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    // Load the guild list of the user into the cache, where it still has the guild after leaving
+    assert_eq!(schedules(&app, &cookie).await, json!([]));
+
+    app.discord.leave_guild(ADMIN_ID);
+
+    let set_timezone = format!(
+        r#"mutation {{ setTimezone(guildId: "{}", timezone: "Asia/Kolkata") }}"#,
+        GUILD_ID
+    );
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let response = app.graphql(&cookie, &set_timezone).await;
+
+            if let Some(message) = response["errors"][0]["message"].as_str() {
+                assert!(message.starts_with("Permission denied"), "{}", response);
+                return;
+            }
+
+            task::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The timezone could still be set after leaving the guild");
+
+    // Reading the settings goes by the cached guild list
+    assert_eq!(schedules(&app, &cookie).await, json!([]));
+}
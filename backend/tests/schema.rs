@@ -0,0 +1,35 @@
+//! Checks that the checked in graphql schema matches the schema of the backend
+
+use std::{env, fs, process::Command};
+
+/// The path of the checked in schema
+const SCHEMA_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/schema.graphql");
+
+#[test]
+fn checked_in_schema_is_up_to_date() {
+    let output = Command::new(env!("CARGO_BIN_EXE_stfu-backend"))
+        .args(&["schema", "--format", "sdl"])
+        .output()
+        .expect("Failed to run the schema command");
+
+    assert!(
+        output.status.success(),
+        "The schema command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let generated = String::from_utf8(output.stdout).expect("The schema was not valid utf-8");
+
+    if env::var_os("UPDATE_SCHEMA").is_some() {
+        fs::write(SCHEMA_PATH, &generated).expect("Failed to update the checked in schema");
+        return;
+    }
+
+    let checked_in = fs::read_to_string(SCHEMA_PATH).expect("Failed to read the checked in schema");
+
+    assert!(
+        checked_in == generated,
+        "schema.graphql is out of date, update it with `cargo run -- schema --out schema.graphql` \
+         or `UPDATE_SCHEMA=1 cargo test`"
+    );
+}
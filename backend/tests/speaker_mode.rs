@@ -0,0 +1,346 @@
+//! End to end tests of speaker mode, where only the members holding the floor can talk, and of
+//! the speaking queues that members wait in for the floor
+
+mod mock_discord;
+mod test_app;
+
+use async_std::{future::timeout, task};
+use mock_discord::{
+    ADMIN_CODE, ADMIN_ID, GUILD_ID, LISTENER_ID, LOUD_CHANNEL_ID, MEMBER_CODE, MEMBER_ID, MUTED_ID,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+use test_app::TestApp;
+
+/// Give the floor of the loud channel to the members
+async fn give_floor(app: &TestApp, cookie: &str, speaker_ids: &[u64]) -> Value {
+    app.graphql(
+        cookie,
+        &format!(
+            r#"mutation {{
+                giveFloor(guildId: "{}", channelId: "{}", speakerIds: {}) {{
+                    speakerIds
+                    givenBy
+                }}
+            }}"#,
+            GUILD_ID,
+            LOUD_CHANNEL_ID,
+            json!(speaker_ids
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>())
+        ),
+    )
+    .await
+}
+
+/// The mute updates made to members since the given amount of updates, sorted by member
+fn mutes_since(app: &TestApp, skip: usize) -> Vec<(u64, bool)> {
+    let mut mutes: Vec<_> = app
+        .discord
+        .member_updates()
+        .into_iter()
+        .skip(skip)
+        .map(|update| (update.user_id, update.body["mute"].as_bool().unwrap()))
+        .collect();
+    mutes.sort();
+    mutes
+}
+
+/// Wait until the backend has seen the voice state of the member change to the mute status
+async fn wait_for_mute(app: &TestApp, cookie: &str, user_id: u64, mute: bool) {
+    let query = format!(
+        r#"{{ sharedGuilds {{ voiceChannel(id: "{}") {{ states {{ nodes {{ id mute }} }} }} }} }}"#,
+        LOUD_CHANNEL_ID
+    );
+
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let response = app.graphql(cookie, &query).await;
+            let states = &response["data"]["sharedGuilds"][0]["voiceChannel"]["states"]["nodes"];
+
+            if states
+                .as_array()
+                .unwrap()
+                .contains(&json!({ "id": user_id.to_string(), "mute": mute }))
+            {
+                return;
+            }
+
+            task::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The voice state was not updated in time")
+}
+
+#[async_std::test]
+async fn the_floor_can_be_given_passed_and_released() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = give_floor(&app, &cookie, &[MEMBER_ID]).await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+    assert_eq!(
+        response["data"]["giveFloor"],
+        json!({ "speakerIds": [MEMBER_ID.to_string()], "givenBy": ADMIN_ID.to_string() })
+    );
+
+    // The speaker was already unmuted, so only the listener is updated
+    assert_eq!(mutes_since(&app, 0), vec![(LISTENER_ID, true)]);
+    wait_for_mute(&app, &cookie, LISTENER_ID, true).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"{{ sharedGuilds {{ voiceChannel(id: "{}") {{ floor {{ speakerIds }} }} }} }}"#,
+                LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["data"]["sharedGuilds"][0]["voiceChannel"]["floor"]["speakerIds"],
+        json!([MEMBER_ID.to_string()])
+    );
+
+    // Passing the floor swaps who is muted
+    let response = give_floor(&app, &cookie, &[LISTENER_ID]).await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+    assert_eq!(
+        mutes_since(&app, 1),
+        vec![(MEMBER_ID, true), (LISTENER_ID, false)]
+    );
+    wait_for_mute(&app, &cookie, MEMBER_ID, true).await;
+    wait_for_mute(&app, &cookie, LISTENER_ID, false).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ releaseFloor(guildId: "{}", channelId: "{}") }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["data"]["releaseFloor"],
+        json!([MEMBER_ID.to_string()]),
+        "{}",
+        response
+    );
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"{{ sharedGuilds {{ voiceChannel(id: "{}") {{ floor {{ speakerIds }} }} }} }}"#,
+                LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["data"]["sharedGuilds"][0]["voiceChannel"]["floor"],
+        Value::Null
+    );
+}
+
+#[async_std::test]
+async fn members_joining_a_channel_in_speaker_mode_are_muted() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = give_floor(&app, &cookie, &[MEMBER_ID]).await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+    assert_eq!(mutes_since(&app, 0), vec![(LISTENER_ID, true)]);
+
+    app.discord
+        .join_voice_channel(ADMIN_ID, LOUD_CHANNEL_ID, false);
+
+    timeout(Duration::from_secs(10), async {
+        while mutes_since(&app, 1) != vec![(ADMIN_ID, true)] {
+            task::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The member who joined was not muted");
+}
+
+#[async_std::test]
+async fn the_floor_can_only_be_given_to_members_in_the_channel() {
+    let app = TestApp::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let response = give_floor(&app, &cookie, &[MUTED_ID]).await;
+
+    assert_eq!(
+        response["errors"][0]["message"],
+        "Speaker is not in the voice channel"
+    );
+    assert!(app.discord.member_updates().is_empty());
+}
+
+#[async_std::test]
+async fn giving_the_floor_is_denied_without_permissions() {
+    let app = TestApp::start().await;
+    let cookie = app.login(MEMBER_CODE).await;
+
+    let response = give_floor(&app, &cookie, &[MEMBER_ID]).await;
+
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Permission denied"));
+    assert!(app.discord.member_updates().is_empty());
+}
+
+#[async_std::test]
+async fn advancing_the_queue_gives_the_floor_to_the_next_member() {
+    let app = TestApp::start().await;
+    let admin = app.login(ADMIN_CODE).await;
+    let member = app.login(MEMBER_CODE).await;
+
+    let raise_hand = format!(
+        r#"mutation {{ raiseHand(guildId: "{}", channelId: "{}") {{ id }} }}"#,
+        GUILD_ID, LOUD_CHANNEL_ID
+    );
+    let advance_queue = format!(
+        r#"mutation {{ advanceQueue(guildId: "{}", channelId: "{}") {{ speakerIds }} }}"#,
+        GUILD_ID, LOUD_CHANNEL_ID
+    );
+    let queue = format!(
+        r#"{{ sharedGuilds {{ voiceChannel(id: "{}") {{ queue {{ id member {{ name }} }} }} }} }}"#,
+        LOUD_CHANNEL_ID
+    );
+
+    // Only members in the channel can join its queue
+    let response = app.graphql(&admin, &raise_hand).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "You have to be in the voice channel to join its speaking queue"
+    );
+
+    // Raising a hand twice keeps one place in the queue
+    for _ in 0..2 {
+        let response = app.graphql(&member, &raise_hand).await;
+        assert_eq!(
+            response["data"]["raiseHand"],
+            json!([{ "id": MEMBER_ID.to_string() }]),
+            "{}",
+            response
+        );
+    }
+
+    let response = app.graphql(&admin, &queue).await;
+    assert_eq!(
+        response["data"]["sharedGuilds"][0]["voiceChannel"]["queue"],
+        json!([{ "id": MEMBER_ID.to_string(), "member": { "name": "Member" } }])
+    );
+
+    let response = app.graphql(&admin, &advance_queue).await;
+    assert_eq!(
+        response["data"]["advanceQueue"],
+        json!({ "speakerIds": [MEMBER_ID.to_string()] }),
+        "{}",
+        response
+    );
+    assert_eq!(mutes_since(&app, 0), vec![(LISTENER_ID, true)]);
+
+    let response = app.graphql(&admin, &queue).await;
+    assert_eq!(
+        response["data"]["sharedGuilds"][0]["voiceChannel"]["queue"],
+        json!([])
+    );
+
+    let response = app.graphql(&admin, &advance_queue).await;
+    assert_eq!(
+        response["errors"][0]["message"],
+        "Nobody in the voice channel is waiting to speak"
+    );
+}
+
+#[async_std::test]
+async fn members_can_lower_their_hands() {
+    let app = TestApp::start().await;
+    let member = app.login(MEMBER_CODE).await;
+
+    app.graphql(
+        &member,
+        &format!(
+            r#"mutation {{ raiseHand(guildId: "{}", channelId: "{}") {{ id }} }}"#,
+            GUILD_ID, LOUD_CHANNEL_ID
+        ),
+    )
+    .await;
+
+    let lower_hand = format!(
+        r#"mutation {{ lowerHand(guildId: "{}", channelId: "{}") }}"#,
+        GUILD_ID, LOUD_CHANNEL_ID
+    );
+
+    let response = app.graphql(&member, &lower_hand).await;
+    assert_eq!(response["data"]["lowerHand"], true, "{}", response);
+
+    let response = app.graphql(&member, &lower_hand).await;
+    assert_eq!(response["data"]["lowerHand"], false, "{}", response);
+
+    // Removing someone else from the queue needs the permissions of a moderator
+    let response = app
+        .graphql(
+            &member,
+            &format!(
+                r#"mutation {{ lowerHand(guildId: "{}", channelId: "{}", userId: "{}") }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID, LISTENER_ID
+            ),
+        )
+        .await;
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Permission denied"));
+}
+
+#[async_std::test]
+async fn raising_hands_does_not_use_up_the_guild_rate_limit() {
+    let app = TestApp::start_with_env(&[
+        ("MUTATION_GUILD_BURST", "1"),
+        ("MUTATION_GUILD_PER_MINUTE", "1"),
+    ])
+    .await;
+    let admin = app.login(ADMIN_CODE).await;
+    let member = app.login(MEMBER_CODE).await;
+
+    // More mutations than the guild allows, which only count against the member
+    for (mutation, selection) in &[
+        ("raiseHand", "{ id }"),
+        ("lowerHand", ""),
+        ("raiseHand", "{ id }"),
+    ] {
+        let response = app
+            .graphql(
+                &member,
+                &format!(
+                    r#"mutation {{ {}(guildId: "{}", channelId: "{}") {} }}"#,
+                    mutation, GUILD_ID, LOUD_CHANNEL_ID, selection
+                ),
+            )
+            .await;
+        assert_eq!(response["errors"], Value::Null, "{}", response);
+    }
+
+    let response = app
+        .graphql(
+            &admin,
+            &format!(
+                r#"mutation {{ advanceQueue(guildId: "{}", channelId: "{}") {{ speakerIds }} }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(
+        response["data"]["advanceQueue"],
+        json!({ "speakerIds": [MEMBER_ID.to_string()] }),
+        "{}",
+        response
+    );
+}
@@ -0,0 +1,225 @@
+//! A backend process connected to a mock of discord, for the end to end tests
+
+#![allow(dead_code)]
+
+use super::mock_discord::{MockDiscord, BOT_TOKEN};
+use async_std::{future::timeout, task};
+use reqwest::{header, redirect::Policy, Client};
+use serde_json::{json, Value};
+use std::{
+    env,
+    net::TcpListener,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+/// The name of the auth cookie the backend is configured with
+const AUTH_COOKIE_NAME: &str = "stfu-auth";
+
+/// How long the backend has to start and receive the fixture guild
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The databases that api servers have to be given, named after their config keys without the
+/// `_DATABASE` suffix
+const SHARED_DATABASES: &[&str] = &[
+    "session",
+    "api_token",
+    "webhook",
+    "notification",
+    "floor",
+    "schedule",
+];
+
+/// A backend process connected to a mock of discord, killed when dropped
+#[derive(Debug)]
+pub struct TestApp {
+    /// The mock of discord
+    pub discord: MockDiscord,
+    /// The backend process serving the api
+    backend: Child,
+    /// The gateway worker filling the redis cache, if the backend reads from one
+    gateway: Option<Child>,
+    /// The url the backend is listening on
+    url: String,
+    /// The client to make requests to the backend with, which does not follow redirects
+    pub client: Client,
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        for process in self.gateway.iter_mut().chain(Some(&mut self.backend)) {
+            process.kill().ok();
+            process.wait().ok();
+        }
+    }
+}
+
+/// Find a free local port
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to find a free port")
+        .port()
+}
+
+/// A command to run the backend binary, configured to talk to the mock of discord
+fn backend_command(discord: &MockDiscord, subcommand: &str) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_stfu-backend"));
+
+    command
+        .arg(subcommand)
+        // Keep .env files and config files of the developer out of the tests
+        .current_dir(env::temp_dir())
+        .env("STFU_CONFIG", "stfu-integration-tests-missing.toml")
+        .env("STFU_PROFILE", "test")
+        .env("DISCORD_TOKEN", BOT_TOKEN)
+        .env("CLIENT_SECRET", "mock-client-secret")
+        .env("REDIRECT_URL", "http://localhost:8000/oauth/authorize")
+        .env("AUTH_COOKIE_NAME", AUTH_COOKIE_NAME)
+        .env("AUTH_COOKIE_DOMAIN", "127.0.0.1")
+        .env("DISCORD_API_URL", discord.api_url())
+        // The webhook receivers of the tests listen on loopback
+        .env("WEBHOOK_INTERNAL_ADDRESSES", "true")
+        .env("RUST_LOG", "off")
+        .stdout(Stdio::null());
+
+    command
+}
+
+impl TestApp {
+    /// Start a mock of discord and a backend connected to it, waiting until the bot has received
+    /// the fixture guild
+    pub async fn start() -> Self {
+        let discord = MockDiscord::start().await;
+        let backend = backend_command(&discord, "serve");
+
+        Self::launch(discord, backend, None).await
+    }
+
+    /// Start a mock of discord and a backend connected to it with extra environment variables,
+    /// which take precedence over those of the tests
+    pub async fn start_with_env(vars: &[(&str, &str)]) -> Self {
+        let discord = MockDiscord::start().await;
+        let mut backend = backend_command(&discord, "serve");
+        backend.envs(vars.iter().copied());
+
+        Self::launch(discord, backend, None).await
+    }
+
+    /// Start a mock of discord, a gateway worker connected to the redis server at the url, and
+    /// an api server talking to the worker through redis, waiting until the worker has received
+    /// the fixture guild
+    ///
+    /// The keys are prefixed uniquely, so tests can share the redis server.
+    pub async fn start_with_redis(redis_url: &str) -> Self {
+        let discord = MockDiscord::start().await;
+        let prefix = format!("stfu-test-{}", free_port());
+        let databases: Vec<_> = SHARED_DATABASES
+            .iter()
+            .map(|name| {
+                let path = env::temp_dir().join(format!("{}-{}.sqlite", prefix, name));
+
+                (format!("{}_DATABASE", name.to_uppercase()), path)
+            })
+            .collect();
+
+        let gateway = backend_command(&discord, "gateway")
+            .env("REDIS_URL", redis_url)
+            .env("REDIS_KEY_PREFIX", &prefix)
+            .envs(databases.iter().cloned())
+            .spawn()
+            .expect("Failed to start the gateway worker");
+
+        let mut backend = backend_command(&discord, "api");
+        backend
+            .env("REDIS_URL", redis_url)
+            .env("REDIS_KEY_PREFIX", &prefix)
+            .envs(databases);
+
+        Self::launch(discord, backend, Some(gateway)).await
+    }
+
+    /// Start the backend on a free port and wait for it to come up
+    async fn launch(discord: MockDiscord, mut backend: Command, gateway: Option<Child>) -> Self {
+        let port = free_port();
+
+        let backend = backend
+            .env("ROCKET_ADDRESS", "127.0.0.1")
+            .env("ROCKET_PORT", port.to_string())
+            .spawn()
+            .expect("Failed to start the backend");
+
+        let app = Self {
+            discord,
+            backend,
+            gateway,
+            url: format!("http://127.0.0.1:{}", port),
+            client: Client::builder()
+                .redirect(Policy::none())
+                .no_proxy()
+                .build()
+                .expect("Failed to create the http client"),
+        };
+
+        timeout(STARTUP_TIMEOUT, async {
+            while !app.discord.guild_sent()
+                || app.client.get(&app.url("/graphql")).send().await.is_err()
+            {
+                task::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("The backend did not start in time");
+
+        app
+    }
+
+    /// The url of a path on the backend
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+
+    /// Log in through the oauth flow with the code, returning the auth cookie
+    pub async fn login(&self, code: &str) -> String {
+        let response = self
+            .client
+            .get(&self.url(&format!("/oauth/authorize?code={}&state=%2F", code)))
+            .send()
+            .await
+            .expect("Failed to make the authorize request");
+
+        assert!(
+            response.status().is_success(),
+            "Logging in failed with {}",
+            response.status()
+        );
+
+        response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|cookie| cookie.to_str().ok())
+            .find(|cookie| cookie.starts_with(AUTH_COOKIE_NAME))
+            .and_then(|cookie| cookie.split(';').next())
+            .expect("Logging in did not set the auth cookie")
+            .to_owned()
+    }
+
+    /// Run a graphql query as the user with the auth cookie, returning the response body
+    pub async fn graphql(&self, cookie: &str, query: &str) -> Value {
+        let body = self
+            .client
+            .post(&self.url("/graphql"))
+            .header(header::COOKIE, cookie)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(json!({ "query": query }).to_string())
+            .send()
+            .await
+            .expect("Failed to make the graphql request")
+            .text()
+            .await
+            .expect("Failed to read the graphql response");
+
+        serde_json::from_str(&body).expect("The graphql response was not json")
+    }
+}
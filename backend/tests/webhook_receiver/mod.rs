@@ -0,0 +1,163 @@
+//! A local http server standing in for a webhook, recording every event posted to it
+
+#![allow(dead_code)]
+
+use async_std::{
+    future::timeout,
+    io::{prelude::*, BufReader},
+    net::{TcpListener, TcpStream},
+    task,
+};
+use futures::StreamExt;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// How long to wait for an event to arrive
+const EVENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An event posted to the webhook
+#[derive(Debug, Clone)]
+pub struct ReceivedEvent {
+    /// The headers of the request, with lowercase names
+    pub headers: HashMap<String, String>,
+    /// The body of the request
+    pub body: String,
+}
+
+/// The state shared between the connections to the webhook
+#[derive(Debug)]
+struct State {
+    /// The status line to respond with
+    status: &'static str,
+    /// The events received
+    events: Mutex<Vec<ReceivedEvent>>,
+}
+
+/// A running webhook receiver
+#[derive(Debug, Clone)]
+pub struct WebhookReceiver {
+    /// The address of the receiver
+    addr: SocketAddr,
+    /// The shared state
+    state: Arc<State>,
+}
+
+impl WebhookReceiver {
+    /// Start a receiver that accepts every event
+    pub async fn start() -> Self {
+        Self::responding_with("204 No Content").await
+    }
+
+    /// Start a receiver that responds to every event with the status line
+    pub async fn responding_with(status: &'static str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind the webhook receiver");
+
+        let receiver = Self {
+            addr: listener.local_addr().unwrap(),
+            state: Arc::new(State {
+                status,
+                events: Mutex::default(),
+            }),
+        };
+
+        {
+            let state = receiver.state.clone();
+            task::spawn(async move {
+                while let Some(Ok(stream)) = listener.incoming().next().await {
+                    task::spawn(serve(stream, state.clone()));
+                }
+            });
+        }
+
+        receiver
+    }
+
+    /// The url to register as the webhook
+    pub fn url(&self) -> String {
+        format!("http://{}/hook", self.addr)
+    }
+
+    /// The events received so far
+    pub fn events(&self) -> Vec<ReceivedEvent> {
+        self.state.events.lock().unwrap().clone()
+    }
+
+    /// Wait until the receiver has received the amount of events
+    pub async fn wait_for_events(&self, count: usize) -> Vec<ReceivedEvent> {
+        timeout(EVENT_TIMEOUT, async {
+            loop {
+                let events = self.events();
+
+                if events.len() >= count {
+                    return events;
+                }
+
+                task::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("The webhook did not receive the events in time")
+    }
+}
+
+/// Read an event from a connection
+async fn read_event(reader: &mut BufReader<&TcpStream>) -> Option<ReceivedEvent> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    let mut headers = HashMap::new();
+
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.ok()?;
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(colon) = header.find(':') {
+            headers.insert(
+                header[..colon].to_ascii_lowercase(),
+                header[colon + 1..].trim().to_owned(),
+            );
+        }
+    }
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+
+    Some(ReceivedEvent {
+        headers,
+        body: String::from_utf8(body).ok()?,
+    })
+}
+
+/// Record the events posted on a connection, one at a time
+async fn serve(stream: TcpStream, state: Arc<State>) {
+    let mut reader = BufReader::new(&stream);
+
+    while let Some(event) = read_event(&mut reader).await {
+        state.events.lock().unwrap().push(event);
+
+        let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", state.status);
+
+        if (&stream).write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
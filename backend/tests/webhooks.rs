@@ -0,0 +1,349 @@
+//! End to end tests of the webhooks that events in a guild are sent to
+
+mod mock_discord;
+mod test_app;
+mod webhook_receiver;
+
+use async_std::{future::timeout, task};
+use hmac::{Hmac, Mac, NewMac};
+use mock_discord::{
+    ADMIN_CODE, ADMIN_ID, GUILD_ID, LISTENER_ID, LOUD_CHANNEL_ID, MEMBER_CODE, MEMBER_ID,
+};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::time::Duration;
+use test_app::TestApp;
+use webhook_receiver::{ReceivedEvent, WebhookReceiver};
+
+/// Register a webhook for the events in the fixture guild, returning its id and secret
+async fn create_webhook(app: &TestApp, cookie: &str, url: &str, events: &str) -> (String, String) {
+    let response = app
+        .graphql(
+            cookie,
+            &format!(
+                r#"mutation {{
+                    createWebhook(guildId: "{}", url: "{}", events: [{}]) {{
+                        secret
+                        webhook {{ id url events }}
+                    }}
+                }}"#,
+                GUILD_ID, url, events
+            ),
+        )
+        .await;
+
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    let created = &response["data"]["createWebhook"];
+    assert_eq!(created["webhook"]["url"], url);
+
+    (
+        created["webhook"]["id"].as_str().unwrap().to_owned(),
+        created["secret"].as_str().unwrap().to_owned(),
+    )
+}
+
+/// Check that the event was signed with the secret
+fn assert_signed(event: &ReceivedEvent, secret: &str) {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", event.headers["x-stfu-timestamp"], event.body).as_bytes());
+
+    assert_eq!(
+        event.headers["x-stfu-signature"],
+        format!("sha256={:x}", mac.finalize().into_bytes())
+    );
+}
+
+#[async_std::test]
+async fn mutes_are_sent_to_webhooks_signed() {
+    let app = TestApp::start().await;
+    let receiver = WebhookReceiver::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let (id, secret) = create_webhook(&app, &cookie, &receiver.url(), "MUTE").await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ mute(guildId: "{}", channelId: "{}") }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    let events = receiver.wait_for_events(1).await;
+    let event = &events[0];
+
+    assert_eq!(event.headers["x-stfu-event"], "mute");
+    assert_signed(event, &secret);
+
+    let body: Value = serde_json::from_str(&event.body).unwrap();
+    assert_eq!(body["id"], event.headers["x-stfu-delivery"]);
+    assert_eq!(body["event"], "mute");
+    assert_eq!(body["guild_id"], GUILD_ID.to_string());
+    assert_eq!(body["data"]["channel_id"], LOUD_CHANNEL_ID.to_string());
+    assert_eq!(body["data"]["actor_id"], ADMIN_ID.to_string());
+
+    let mut member_ids: Vec<_> = body["data"]["member_ids"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|id| id.as_str().unwrap().to_owned())
+        .collect();
+    member_ids.sort();
+    assert_eq!(
+        member_ids,
+        vec![MEMBER_ID.to_string(), LISTENER_ID.to_string()]
+    );
+
+    // Unmutes were not subscribed to
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ unmute(guildId: "{}", channelId: "{}") }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    // The delivery is logged after the receiver responds, so it can take a moment to show up
+    let query = format!(
+        r#"{{ webhooks(guildId: "{}") {{ id deliveries {{ event succeeded status attempts }} }} }}"#,
+        GUILD_ID
+    );
+    let webhooks = timeout(Duration::from_secs(10), async {
+        loop {
+            let response = app.graphql(&cookie, &query).await;
+            let webhooks = response["data"]["webhooks"].clone();
+
+            if webhooks[0]["deliveries"][0]["succeeded"] == true {
+                return webhooks;
+            }
+
+            task::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The delivery was not logged in time");
+
+    assert_eq!(
+        webhooks,
+        json!([{
+            "id": id,
+            "deliveries": [{ "event": "MUTE", "succeeded": true, "status": 204, "attempts": 1 }],
+        }])
+    );
+    assert_eq!(receiver.events().len(), 1);
+}
+
+#[async_std::test]
+async fn test_deliveries_report_failures() {
+    let app = TestApp::start().await;
+    let receiver = WebhookReceiver::responding_with("500 Internal Server Error").await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let (id, secret) = create_webhook(&app, &cookie, &receiver.url(), "UNMUTE").await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{
+                    testWebhook(guildId: "{}", id: "{}") {{ event succeeded status attempts error }}
+                }}"#,
+                GUILD_ID, id
+            ),
+        )
+        .await;
+
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    let delivery = &response["data"]["testWebhook"];
+    assert_eq!(delivery["event"], "TEST");
+    assert_eq!(delivery["succeeded"], false);
+    assert_eq!(delivery["status"], 500);
+    assert_eq!(delivery["attempts"], 1);
+    // Only the status is reported, not what the receiver said
+    assert_eq!(delivery["error"], "The webhook did not accept the event");
+
+    let events = receiver.events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].headers["x-stfu-event"], "test");
+    assert_signed(&events[0], &secret);
+}
+
+#[async_std::test]
+async fn scheduled_runs_are_sent_to_webhooks() {
+    let app = TestApp::start().await;
+    let receiver = WebhookReceiver::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    create_webhook(&app, &cookie, &receiver.url(), "SCHEDULE").await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{
+                    createSchedule(guildId: "{}", channelId: "{}", action: MUTE, runAt: "0") {{ id }}
+                }}"#,
+                GUILD_ID, LOUD_CHANNEL_ID
+            ),
+        )
+        .await;
+    assert_eq!(response["errors"], Value::Null, "{}", response);
+
+    let events = receiver.wait_for_events(1).await;
+
+    let body: Value = serde_json::from_str(&events[0].body).unwrap();
+    assert_eq!(body["event"], "schedule");
+    assert_eq!(
+        body["data"],
+        json!({
+            "schedule_id": response["data"]["createSchedule"]["id"],
+            "channel_id": LOUD_CHANNEL_ID.to_string(),
+            "action": "mute",
+            "actor_id": ADMIN_ID.to_string(),
+            "outcome": "ran",
+            "error": null,
+        })
+    );
+}
+
+#[async_std::test]
+async fn webhooks_can_be_deleted() {
+    let app = TestApp::start().await;
+    let receiver = WebhookReceiver::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    let (id, _) = create_webhook(&app, &cookie, &receiver.url(), "MUTE, UNMUTE").await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{ deleteWebhook(guildId: "{}", id: "{}") }}"#,
+                GUILD_ID, id
+            ),
+        )
+        .await;
+    assert_eq!(response["data"]["deleteWebhook"], true, "{}", response);
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(r#"{{ webhooks(guildId: "{}") {{ id }} }}"#, GUILD_ID),
+        )
+        .await;
+    assert_eq!(response["data"]["webhooks"], json!([]));
+}
+
+#[async_std::test]
+async fn webhooks_need_the_manage_guild_permission() {
+    let app = TestApp::start().await;
+    let cookie = app.login(MEMBER_CODE).await;
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{
+                    createWebhook(guildId: "{}", url: "http://127.0.0.1/hook", events: [MUTE]) {{
+                        secret
+                    }}
+                }}"#,
+                GUILD_ID
+            ),
+        )
+        .await;
+
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Permission denied"));
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(r#"{{ webhooks(guildId: "{}") {{ id }} }}"#, GUILD_ID),
+        )
+        .await;
+
+    assert!(response["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Permission denied"));
+}
+
+#[async_std::test]
+async fn webhooks_can_not_reach_internal_addresses() {
+    let app = TestApp::start_with_env(&[("WEBHOOK_INTERNAL_ADDRESSES", "false")]).await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    for url in &[
+        "http://127.0.0.1:8080/hook",
+        "http://localhost/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://10.0.0.1/hook",
+        "http://[::1]/hook",
+        "http://[::ffff:192.168.0.1]/hook",
+        "http://[64:ff9b::a9fe:a9fe]/hook",
+        "http://198.18.0.1/hook",
+        "ftp://example.com/hook",
+    ] {
+        let response = app
+            .graphql(
+                &cookie,
+                &format!(
+                    r#"mutation {{
+                        createWebhook(guildId: "{}", url: "{}", events: [MUTE]) {{ secret }}
+                    }}"#,
+                    GUILD_ID, url
+                ),
+            )
+            .await;
+
+        assert_eq!(response["data"], Value::Null, "{} was accepted", url);
+    }
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(r#"{{ webhooks(guildId: "{}") {{ id }} }}"#, GUILD_ID),
+        )
+        .await;
+    assert_eq!(response["data"]["webhooks"], json!([]));
+}
+
+#[async_std::test]
+async fn guilds_have_a_limited_number_of_webhooks() {
+    let app = TestApp::start().await;
+    let receiver = WebhookReceiver::start().await;
+    let cookie = app.login(ADMIN_CODE).await;
+
+    for _ in 0..10 {
+        create_webhook(&app, &cookie, &receiver.url(), "MUTE").await;
+    }
+
+    let response = app
+        .graphql(
+            &cookie,
+            &format!(
+                r#"mutation {{
+                    createWebhook(guildId: "{}", url: "{}", events: [MUTE]) {{ secret }}
+                }}"#,
+                GUILD_ID,
+                receiver.url()
+            ),
+        )
+        .await;
+
+    assert_eq!(
+        response["errors"][0]["message"],
+        "A guild can have at most 10 webhooks"
+    );
+}
@@ -1,7 +1,7 @@
 const { cwd } = require("process")
 
 // Fix to get around the problem of different directories
-const localSchemaFile = cwd().endsWith("frontend") ? '../backend/target/graphql.json' : 'backend/target/graphql.json';
+const localSchemaFile = cwd().endsWith("frontend") ? '../backend/schema.graphql' : 'backend/schema.graphql';
 
 module.exports = {
     client: {
@@ -0,0 +1,25 @@
+// Convert the operations extracted by `apollo client:extract` into the persisted query registry
+// that the backend loads, a json object of the sha256 hash of each query to the query itself.
+//
+// The queries are printed and hashed the same way that the apollo client does, so that the
+// hashes sent by the client line up with the registry.
+const { createHash } = require("crypto");
+const { readFileSync, writeFileSync } = require("fs");
+const { parse, print } = require("graphql");
+const { addTypenameToDocument } = require("@apollo/client/utilities");
+
+const [input = "build/operations.json", output = "build/persisted-queries.json"] = process.argv.slice(2);
+
+const { operations } = JSON.parse(readFileSync(input, "utf8"));
+
+const queries = {};
+
+for (const { document } of operations) {
+    const query = print(addTypenameToDocument(parse(document)));
+
+    queries[createHash("sha256").update(query).digest("hex")] = query;
+}
+
+writeFileSync(output, JSON.stringify(queries, undefined, 4));
+
+console.log(`Wrote ${Object.keys(queries).length} persisted queries to ${output}`);
@@ -8,5 +8,5 @@ export const BACKEND_DOMAIN = IS_PROD ? "stfu-backend.dusterthefirst.com" : "dev
 /** The backend graphql url */
 export const BACKEND_GRAPHQL_URL = `http${IS_HTTPS ? "s" : ""}://${BACKEND_DOMAIN}/graphql`;
 
-/** The CDN for accessing images or content from discord */
-export const DISCORD_CDN = "https://cdn.discordapp.com/";
+/** How often to poll the floor and speaking queue of a voice channel, in milliseconds */
+export const FLOOR_POLL_INTERVAL = 5000;
@@ -1,4 +1,5 @@
-import { ApolloClient, ApolloProvider, InMemoryCache } from "@apollo/client";
+import { ApolloClient, ApolloProvider, createHttpLink, InMemoryCache } from "@apollo/client";
+import { createPersistedQueryLink } from "@apollo/client/link/persisted-queries";
 import hash from "object-hash";
 import React from "react";
 import ReactDOM from "react-dom";
@@ -7,17 +8,28 @@ import { BACKEND_GRAPHQL_URL } from "./constants";
 import * as serviceWorker from "./serviceWorker";
 import { GlobalStyle } from "./style";
 
+/** Hash a query into the hex encoded sha256 that the backend uses for persisted queries */
+async function sha256(query: string) {
+    const digest = await crypto.subtle.digest("SHA-256", new TextEncoder().encode(query));
+
+    return Array.from(new Uint8Array(digest))
+        .map(byte => byte.toString(16).padStart(2, "0"))
+        .join("");
+}
+
 /** The client to use for apollo */
 const CLIENT = new ApolloClient({
     cache: new InMemoryCache({
         dataIdFromObject: o => hash(o), // tslint:disable-line: no-unnecessary-callback-wrapper
     }),
-    credentials: "include",
-    headers: {
-        Accept: "application/json"
-    },
-    name: "stfu",
-    uri: BACKEND_GRAPHQL_URL
+    link: createPersistedQueryLink({ sha256 }).concat(createHttpLink({
+        credentials: "include",
+        headers: {
+            Accept: "application/json"
+        },
+        uri: BACKEND_GRAPHQL_URL
+    })),
+    name: "stfu"
 });
 
 ReactDOM.render(
@@ -4,9 +4,15 @@ import { Link, useParams } from "react-router-dom";
 import ErrorScreen from "../components/Error";
 import LazyAnimateImage from "../components/LazyAnimateImage";
 import { LoadingIcon, LoadingScreen } from "../components/Loading";
-import { getAvatar } from "../utils";
+import { FLOOR_POLL_INTERVAL } from "../constants";
+import { AdvanceQueue, AdvanceQueueVariables } from "./__generated__/AdvanceQueue";
 import { GetChannel, GetChannelVariables, GetChannel_guild, GetChannel_guild_voiceChannel } from "./__generated__/GetChannel";
+import { GetFloor, GetFloorVariables } from "./__generated__/GetFloor";
+import { GiveFloor, GiveFloorVariables } from "./__generated__/GiveFloor";
+import { LowerHand, LowerHandVariables } from "./__generated__/LowerHand";
 import { MuteAll, MuteAllVariables } from "./__generated__/MuteAll";
+import { RaiseHand, RaiseHandVariables } from "./__generated__/RaiseHand";
+import { ReleaseFloor, ReleaseFloorVariables } from "./__generated__/ReleaseFloor";
 import { UnmuteAll, UnmuteAllVariables } from "./__generated__/UnmuteAll";
 
 /** The graphql query to get the specific channel */
@@ -26,23 +32,27 @@ const GET_CHANNEL = gql`
                 id
                 name
                 position
-                states {
-                    channelId
-                    deaf
-                    mute
-                    selfDeaf
-                    selfMute
-                    id
-                    member {
-                        avatar
-                        bot
-                        color
-                        discriminator
-                        id
-                        joinedAt
+                states(first: 100) {
+                    totalCount
+                    nodes {
+                        channelId
+                        deaf
                         mute
-                        name
-                        nick
+                        selfDeaf
+                        selfMute
+                        id
+                        member {
+                            avatarUrl(size: 64)
+                            stillAvatarUrl: avatarUrl(size: 64, format: PNG)
+                            bot
+                            color
+                            discriminator
+                            id
+                            joinedAt
+                            mute
+                            name
+                            nick
+                        }
                     }
                 }
                 userLimit
@@ -65,6 +75,81 @@ const UNMUTE_ALL = gql`
     }
 `;
 
+/**
+ * The graphql query to get the floor and speaking queue of the channel, polled on its own
+ * since they change far more often than the rest of the channel
+ */
+const GET_FLOOR = gql`
+    query GetFloor($guild_id: String!, $channel_id: String!) {
+        guild(id: $guild_id) {
+            id
+            voiceChannel(id: $channel_id) {
+                id
+                floor {
+                    speakerIds
+                    speakers {
+                        id
+                        name
+                        discriminator
+                    }
+                    givenBy
+                    givenAt
+                }
+                queue {
+                    id
+                    member {
+                        id
+                        name
+                        discriminator
+                    }
+                    joinedAt
+                }
+            }
+        }
+    }
+`;
+
+/** The graphql query to give the floor of a channel to members */
+const GIVE_FLOOR = gql`
+    mutation GiveFloor($channel_id: String!, $guild_id: String!, $speaker_ids: [String!]!) {
+        giveFloor(channelId: $channel_id, guildId: $guild_id, speakerIds: $speaker_ids) {
+            speakerIds
+        }
+    }
+`;
+
+/** The graphql query to take a channel out of speaker mode */
+const RELEASE_FLOOR = gql`
+    mutation ReleaseFloor($channel_id: String!, $guild_id: String!) {
+        releaseFloor(channelId: $channel_id, guildId: $guild_id)
+    }
+`;
+
+/** The graphql query to join the speaking queue of a channel */
+const RAISE_HAND = gql`
+    mutation RaiseHand($channel_id: String!, $guild_id: String!) {
+        raiseHand(channelId: $channel_id, guildId: $guild_id) {
+            id
+        }
+    }
+`;
+
+/** The graphql query to leave the speaking queue of a channel, or remove someone else from it */
+const LOWER_HAND = gql`
+    mutation LowerHand($channel_id: String!, $guild_id: String!, $user_id: String) {
+        lowerHand(channelId: $channel_id, guildId: $guild_id, userId: $user_id)
+    }
+`;
+
+/** The graphql query to give the floor to the next member in the speaking queue */
+const ADVANCE_QUEUE = gql`
+    mutation AdvanceQueue($channel_id: String!, $guild_id: String!) {
+        advanceQueue(channelId: $channel_id, guildId: $guild_id) {
+            speakerIds
+        }
+    }
+`;
+
 /** The parameters for the channel */
 interface IParams {
     /** The parent guild's id */
@@ -139,6 +224,7 @@ export default function Channel() {
             <>
                 {loading ? <LoadingIcon /> : undefined}
                 <ChannelInfo guild={guild} channel={channel} refetch={refetch_no_await} muteAll={muteAll} unmuteAll={unmuteAll} />
+                <SpeakerMode guild_id={guild_id} channel={channel} />
                 <pre>
                     {JSON.stringify(data, undefined, 4)}
                 </pre>
@@ -194,7 +280,7 @@ function ChannelInfo({ guild, channel, refetch, muteAll, unmuteAll }: IChannelIn
                         <td>{channel.botMissingPermissions?.join(", ")}</td>
                         <td>{channel.userMissingPermissions?.join(", ")}</td>
                         <td>{channel.userLimit}</td>
-                        <td>{channel.states.length}</td>
+                        <td>{channel.states.totalCount}</td>
                         <td>
                             <table>
                                 <thead>
@@ -209,8 +295,8 @@ function ChannelInfo({ guild, channel, refetch, muteAll, unmuteAll }: IChannelIn
                                     </tr>
                                 </thead>
                                 <tbody>
-                                    {channel.states.map((s, i) => {
-                                        const source = (hover: boolean) => `${getAvatar(s.member, hover)}?size=64`;
+                                    {channel.states.nodes.map((s, i) => {
+                                        const source = (hover: boolean) => hover ? s.member.avatarUrl : s.member.stillAvatarUrl;
 
                                         return (
                                             <tr key={i}>
@@ -233,3 +319,89 @@ function ChannelInfo({ guild, channel, refetch, muteAll, unmuteAll }: IChannelIn
         </div >
     );
 }
+
+/** The props for the SpeakerMode component */
+interface ISpeakerModeProps {
+    /** The id of the guild of the channel */
+    guild_id: string;
+    /** The voice channel to follow speaker mode in */
+    channel: GetChannel_guild_voiceChannel;
+}
+
+/** The floor and speaking queue of a channel, kept up to date by polling */
+function SpeakerMode({ guild_id, channel }: ISpeakerModeProps) {
+    const variables = { channel_id: channel.id, guild_id };
+    const { error, data, refetch } = useQuery<GetFloor, GetFloorVariables>(GET_FLOOR, {
+        pollInterval: FLOOR_POLL_INTERVAL,
+        variables,
+    });
+
+    // Every change to the floor or queue is shown right away instead of on the next poll
+    const options = { refetchQueries: [{ query: GET_FLOOR, variables }], variables };
+    const [giveFloor] = useMutation<GiveFloor, GiveFloorVariables>(GIVE_FLOOR, options);
+    const [releaseFloor] = useMutation<ReleaseFloor, ReleaseFloorVariables>(RELEASE_FLOOR, options);
+    const [raiseHand] = useMutation<RaiseHand, RaiseHandVariables>(RAISE_HAND, options);
+    const [lowerHand] = useMutation<LowerHand, LowerHandVariables>(LOWER_HAND, options);
+    const [advanceQueue] = useMutation<AdvanceQueue, AdvanceQueueVariables>(ADVANCE_QUEUE, options);
+
+    const report = (promise: Promise<unknown>) => { promise.catch((e) => console.error(e)); };
+
+    if (error !== undefined) {
+        return (
+            <ErrorScreen error={error} refetch={() => report(refetch())} />
+        );
+    }
+
+    const state = data?.guild?.voiceChannel;
+
+    if (state === undefined || state === null) {
+        return null;
+    }
+
+    const operable = channel.botMissingPermissions === null && channel.userMissingPermissions === null;
+
+    return (
+        <div>
+            <h2>Speaker Mode</h2>
+            {state.floor === null
+                ? <div>The channel is not in speaker mode</div>
+                : <div>
+                    Speaking: {state.floor.speakers.map((m) => `${m.name}#${m.discriminator}`).join(", ")}
+                    {" "}(given by {state.floor.givenBy} at {new Date(parseInt(state.floor.givenAt, 10) * 1000).toLocaleTimeString()})
+                    <button onClick={() => report(releaseFloor())} disabled={!operable}>Release Floor</button>
+                </div>}
+            <div>
+                Give the floor to:
+                {channel.states.nodes.map((s) => (
+                    <button key={s.id} onClick={() => report(giveFloor({ variables: { ...variables, speaker_ids: [s.member.id] } }))} disabled={!operable}>{s.member.name}#{s.member.discriminator}</button>
+                ))}
+            </div>
+            <div>
+                <button onClick={() => report(raiseHand())}>Raise Hand</button>
+                <button onClick={() => report(lowerHand())}>Lower Hand</button>
+                <button onClick={() => report(advanceQueue())} disabled={!operable || state.queue.length === 0}>Next Speaker</button>
+            </div>
+            <table>
+                <thead>
+                    <tr>
+                        <th>Queue</th>
+                        <th>Waiting Since</th>
+                        <th />
+                    </tr>
+                </thead>
+                <tbody>
+                    {state.queue.map((q) => (
+                        <tr key={q.id}>
+                            <td>{q.member === null ? q.id : `${q.member.name}#${q.member.discriminator}`}</td>
+                            <td>{new Date(parseInt(q.joinedAt, 10) * 1000).toLocaleTimeString()}</td>
+                            <td>
+                                <button onClick={() => report(giveFloor({ variables: { ...variables, speaker_ids: [q.id] } }))} disabled={!operable}>Give Floor</button>
+                                <button onClick={() => report(lowerHand({ variables: { ...variables, user_id: q.id } }))} disabled={!operable}>Remove</button>
+                            </td>
+                        </tr>
+                    ))}
+                </tbody>
+            </table>
+        </div>
+    );
+}
@@ -3,7 +3,6 @@ import React, { useMemo } from "react";
 import { Link, useParams } from "react-router-dom";
 import ErrorScreen from "../components/Error";
 import { LoadingIcon, LoadingScreen } from "../components/Loading";
-import { getGuildIcon } from "../utils";
 import {
     GetGuild, GetGuildVariables, GetGuild_guild, GetGuild_guild_voiceChannels as VoiceChannel, GetGuild_guild_voiceChannels_category as ChannelCategory
 } from "./__generated__/GetGuild";
@@ -14,7 +13,7 @@ const GET_GUILD = gql`
         guild(id: $guild_id) {
             name,
             id,
-            icon
+            iconUrl
             banner
             owner {
                 id
@@ -36,19 +35,25 @@ const GET_GUILD = gql`
                 id
                 name
                 position
-                states {
-                    deaf
-                    id
-                    channelId
-                    member {
+                states(first: 100) {
+                    totalCount
+                    pageInfo {
+                        hasNextPage
+                    }
+                    nodes {
+                        deaf
                         id
-                        name
-                        avatar
-                        color
-                        nick
-                        discriminator
+                        channelId
+                        member {
+                            id
+                            name
+                            avatar
+                            color
+                            nick
+                            discriminator
+                        }
+                        mute
                     }
-                    mute
                 }
                 userLimit
             }
@@ -200,8 +205,11 @@ function GuildInfo({ guild, refetch }: IGuildInfoProps) {
                                                                 <td>{vc.botMissingPermissions?.join(", ")}</td>
                                                                 <td>{vc.userMissingPermissions?.join(", ")}</td>
                                                                 <td>{vc.userLimit}</td>
-                                                                <td>{vc.states.length}</td>
-                                                                <td>{vc.states.map(s => `${s.member.name}#${s.member.discriminator}`).join(", ")}</td>
+                                                                <td>{vc.states.totalCount}</td>
+                                                                <td>
+                                                                    {vc.states.nodes.map(s => `${s.member.name}#${s.member.discriminator}`).join(", ")}
+                                                                    {vc.states.pageInfo.hasNextPage ? <> and <Link to={`/${guild.id}/${vc.id}`}>{vc.states.totalCount - vc.states.nodes.length} more</Link></> : undefined}
+                                                                </td>
                                                             </tr>
                                                         ))}
 
@@ -216,7 +224,7 @@ function GuildInfo({ guild, refetch }: IGuildInfoProps) {
                     </tr>
                     <tr>
                         <td>Guild icon:</td>
-                        <td><img src={getGuildIcon(guild)} alt="Guild icon" /></td>
+                        <td><img src={guild.iconUrl ?? undefined} alt="Guild icon" /></td>
                     </tr>
                 </tbody>
             </table>
@@ -3,14 +3,13 @@ import React from "react";
 import { Link } from "react-router-dom";
 import ErrorScreen from "../components/Error";
 import { LoadingIcon, LoadingScreen } from "../components/Loading";
-import { getGuildIcon } from "../utils";
 import { GetSharedGuilds, GetSharedGuilds_sharedGuilds } from "./__generated__/GetSharedGuilds";
 
 /** The graphql query to get information about the shared guilds between the user and bot */
 const GET_SHARED_GUILDS = gql`
     query GetSharedGuilds {
         sharedGuilds {
-            icon
+            iconUrl
             id
             name
             owner {
@@ -95,7 +94,7 @@ function GuildsInfo({ guilds, refetch }: IGuildsInfoProps) {
                     {[...guilds].sort((a, b) => a.name.localeCompare(b.name)).map((guild) => (
                         <tr key={guild.id}>
                             <td><Link to={`/${guild.id}`}>{guild.name}</Link></td>
-                            <td><img src={getGuildIcon(guild)} alt="Guild icon" /></td>
+                            <td><img src={guild.iconUrl ?? undefined} alt="Guild icon" /></td>
                             <td>{guild.id}</td>
                             <td style={{ color: `#${guild.owner.color === null ? "000000" : guild.owner.color.toString(16)}`, fontWeight: "bold" }}>{guild.owner.nick === null ? guild.owner.name : guild.owner.nick}#{guild.owner.discriminator}</td>
                             <td style={{ color: `#${guild.me.color === null ? "000000" : guild.me.color.toString(16)}`, fontWeight: "bold" }}>{guild.me.nick === null ? guild.me.name : guild.me.nick}#{guild.me.discriminator}</td>